@@ -0,0 +1,95 @@
+//! Timings of the paths every scan goes through, with `cargo bench`, or `cargo bench -- NAME`
+//! for the ones whose names match NAME.  Criterion keeps the last run's results and reports how
+//! each benchmark has changed since, to compare before and after a change to the engine.
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use jtag_taps::statemachine::JtagSM;
+use svfplayer::bits::{self, BitOrder, DontCare, Rng};
+use svfplayer::cable::sim::Sim;
+use svfplayer::cable::{AdapterBox, ShiftCable};
+use svfplayer::engine::{tdo_matches, Sticky};
+use svfplayer::{run_svf, tune, Svf};
+
+const CHAIN: &str = "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n";
+
+fn jtag() -> JtagSM<AdapterBox> {
+    JtagSM::new(AdapterBox(Box::new(ShiftCable(Sim::parse(CHAIN).unwrap()))))
+}
+
+fn play(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, text: &str) {
+    run_svf(sm, svf, &mut text.as_bytes(), None).unwrap();
+}
+
+/// A file like a flash programming one: the IDCODE checked, then pages of data, each followed by
+/// a status poll
+fn synthetic(pages: usize, page_bits: u32) -> String {
+    let mut text = "SIR 4 TDI (e);\nSDR 32 TDI (00000000) TDO (12345679);\nSIR 4 TDI (f);\n".to_string();
+    for page in 0..pages {
+        let data: String = (0..page_bits / 4).map(|i| char::from_digit((i + page as u32) % 16, 16).unwrap()).collect();
+        text += &format!("SDR {} TDI ({});\nRUNTEST 10 TCK;\nSDR 8 TDI (00) TDO (00) MASK (00);\n", page_bits, data);
+    }
+    text
+}
+
+/// TDI of `length` bits with random SMASK, the way the player packs it for the cable
+fn pack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pack");
+    let mut rng = Rng::new(0);
+    for length in [32, 4096] {
+        let mut sticky = Sticky::default();
+        sticky.update("SDR", length, Some(rng.bytes(bits::byte_len(length))), None,
+                      Some(bits::fit(rng.bytes(bits::byte_len(length)), length))).unwrap();
+        for (dont_care, name) in [(DontCare::Zero, "0"), (DontCare::Previous, "previous"), (DontCare::Random, "random")] {
+            let mut buf = vec![];
+            group.bench_function(format!("{}-bit/dont-care-{}", length, name), |b| b.iter(|| {
+                sticky.drive_into(dont_care, &mut rng, &mut buf);
+                buf = bits::to_cable(std::mem::take(&mut buf), length, BitOrder::LsbFirst);
+                black_box(&buf);
+            }));
+        }
+        let mut buf = vec![];
+        group.bench_function(format!("{}-bit/msb-first", length), |b| b.iter(|| {
+            sticky.drive_into(DontCare::Zero, &mut rng, &mut buf);
+            buf = bits::to_cable(std::mem::take(&mut buf), length, BitOrder::MsbFirst);
+            black_box(&buf);
+        }));
+    }
+    group.finish();
+}
+
+/// Checking what came back on TDO against the expected TDO under a MASK
+fn compare(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compare");
+    let mut rng = Rng::new(0);
+    for length in [32, 4096] {
+        let read = rng.bytes(bits::byte_len(length));
+        let mask = bits::fit(rng.bytes(bits::byte_len(length)), length);
+        group.bench_function(format!("{}-bit", length), |b| b.iter(|| {
+            black_box(tdo_matches(black_box(&read), black_box(&read), black_box(&mask)));
+        }));
+    }
+    group.finish();
+}
+
+/// Whole files played on the simulated chain
+fn playback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("play");
+    for chunk_size in [8, tune::DEFAULT_CHUNK_SIZE] {
+        let text = synthetic(1, 1024);
+        let mut sm = jtag();
+        let mut svf = Svf::new();
+        svf.chunk_size = chunk_size;
+        group.bench_function(format!("sim/1024-bit/chunk-{}-bytes", chunk_size), |b| {
+            b.iter(|| play(&mut sm, &mut svf, &text))
+        });
+    }
+    let text = synthetic(64, 256);
+    let mut sm = jtag();
+    let mut svf = Svf::new();
+    group.bench_function("sim/64-pages", |b| b.iter(|| play(&mut sm, &mut svf, &text)));
+    group.finish();
+}
+
+criterion_group!(benches, pack, compare, playback);
+criterion_main!(benches);
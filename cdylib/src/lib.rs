@@ -0,0 +1,4 @@
+//! The shared library build of svfplayer.  The exported functions are the ones the player crate
+//! defines under its `cdylib` and `pyo3` features; this crate only links them into a cdylib, which
+//! the player itself can't be without std.
+pub use player::*;
@@ -0,0 +1,32 @@
+/* C API of svfplayer, built with `cargo build --release --manifest-path cdylib/Cargo.toml` */
+#ifndef SVFPLAYER_H
+#define SVFPLAYER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct svfplayer svfplayer;
+
+/* Called after every command; total is 0 because it isn't known before the file is read */
+typedef void (*svfplayer_progress)(size_t done, size_t total, void *user);
+
+/* Open a cable by its --cable spec, e.g. "jtagkey" or "xvc:host:2542".  NULL on failure. */
+svfplayer *svfplayer_open(const char *cable, uint32_t baud);
+
+/* Play an SVF file.  0 on success, -1 on failure, TDO mismatches included. */
+int svfplayer_play(svfplayer *player, const char *path, svfplayer_progress progress, void *user);
+
+/* Why the last failing call on this thread failed */
+const char *svfplayer_last_error(void);
+
+void svfplayer_close(svfplayer *player);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,116 @@
+//! Who may use the network servers, which hand out control of the cable to whoever connects:
+//! `--allow` limits clients to the given addresses and networks, and `--token-file` makes them
+//! present a shared secret first.  How the token is presented depends on the protocol, see
+//! `daemon`, `http` and `xvc_server`.
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Network {
+    addr: IpAddr,
+    bits: u8,
+}
+
+impl Network {
+    /// An address, or a network as ADDRESS/BITS
+    pub fn parse(text: &str) -> Result<Network, String> {
+        let (addr, bits) = text.split_once('/').map_or((text, None), |(addr, bits)| (addr, Some(bits)));
+        let addr: IpAddr = addr.parse().map_err(|_| format!("{} is not an IP address", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let bits = match bits {
+            Some(bits) => bits.parse().ok().filter(|bits| *bits <= max)
+                .ok_or_else(|| format!("{} is not a prefix length of up to {} bits", bits, max))?,
+            None => max,
+        };
+        Ok(Network { addr, bits })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let prefix = |ip: u128, width: u32| ip.checked_shr(width - self.bits as u32).unwrap_or(0);
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix(u32::from(net) as u128, 32) == prefix(u32::from(ip) as u128, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix(u128::from(net), 128) == prefix(u128::from(ip), 128),
+            _ => false,
+        }
+    }
+}
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct Access {
+    /// Only accept clients from this address or network, e.g. 10.0.0.0/8.  May be repeated; by
+    /// default anyone who can connect is accepted.
+    #[arg(long = "allow", value_name = "ADDRESS[/BITS]", value_parser = Network::parse)]
+    pub allow: Vec<Network>,
+    /// File holding a token clients have to present before anything else
+    #[arg(long, value_name = "PATH")]
+    pub token_file: Option<PathBuf>,
+}
+
+impl Access {
+    /// The checks to make of clients, with the token read from its file
+    pub fn gate(&self) -> Result<Gate, String> {
+        let token = match &self.token_file {
+            Some(path) => {
+                let token = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let token = token.trim().to_string();
+                if token.is_empty() {
+                    return Err(format!("{}: the token is empty", path.display()));
+                }
+                Some(token)
+            }
+            None => None,
+        };
+        Ok(Gate { allow: self.allow.clone(), token })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Gate {
+    allow: Vec<Network>,
+    token: Option<String>,
+}
+
+impl Gate {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+
+    pub fn needs_token(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Whether `presented` is the token, compared in time that doesn't depend on where they differ
+    pub fn accepts(&self, presented: &[u8]) -> bool {
+        match &self.token {
+            Some(token) => {
+                token.len() == presented.len()
+                    && token.bytes().zip(presented).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_are_checked_against_networks_and_the_token() {
+        let gate = Gate {
+            allow: vec![Network::parse("10.1.0.0/16").unwrap(), Network::parse("::1").unwrap()],
+            token: Some("s3cret".into()),
+        };
+        assert!(gate.allows("10.1.200.3".parse().unwrap()));
+        assert!(gate.allows("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(gate.allows("::1".parse().unwrap()));
+        assert!(!gate.allows("10.2.0.1".parse().unwrap()));
+        assert!(!gate.allows("127.0.0.1".parse().unwrap()));
+        assert!(Gate::default().allows("192.0.2.1".parse().unwrap()));
+        assert!(Network::parse("0.0.0.0/0").unwrap().contains("192.0.2.1".parse().unwrap()));
+        assert!(Network::parse("10.0.0.0/33").is_err());
+        assert!(gate.accepts(b"s3cret"));
+        assert!(!gate.accepts(b"s3cre"));
+        assert!(!gate.accepts(b"s3creT"));
+    }
+}
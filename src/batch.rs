@@ -0,0 +1,214 @@
+//! Command queueing for cables.  Every call into a jtag_taps cable is a separate USB transfer, so
+//! SVF files made of many short scans spend most of their time waiting on latency.
+//! `BatchingCable` holds on to TMS sequences and TDI-only writes and coalesces consecutive ones
+//! into a single call.  Anything that returns TDO flushes the queue first, so the order of
+//! operations on the wire is unchanged.
+use jtag_taps::cable::Cable;
+
+use crate::cable::{Adapter, Capabilities};
+
+/// Upper bound on the number of bytes held back before the queue is flushed anyway
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+enum Pending {
+    Tms { tms: Vec<usize>, tdi: bool },
+    Write { data: Vec<u8>, bits: u8, pause_after: bool },
+}
+
+pub struct BatchingCable {
+    inner: Box<dyn Adapter>,
+    queue: Vec<Pending>,
+    pending_bytes: usize,
+}
+
+impl BatchingCable {
+    pub fn new(inner: Box<dyn Adapter>) -> Self {
+        BatchingCable {
+            inner,
+            queue: vec![],
+            pending_bytes: 0,
+        }
+    }
+
+    pub fn flush(&mut self) {
+        // A failing write leaves nothing behind, the queue is gone either way
+        self.pending_bytes = 0;
+        for op in self.queue.drain(..) {
+            match op {
+                Pending::Tms { tms, tdi } => self.inner.change_mode(&tms, tdi),
+                Pending::Write { data, bits, pause_after } => self.inner.write_data(&data, bits, pause_after),
+            }
+        }
+    }
+
+    fn queued(&mut self, bytes: usize) {
+        self.pending_bytes += bytes;
+        if self.pending_bytes >= MAX_PENDING_BYTES {
+            self.flush();
+        }
+    }
+}
+
+/// Append the low `n` bits of `byte` to a bit vector whose last byte holds `last` valid bits
+fn push_bits(buf: &mut Vec<u8>, last: &mut u8, byte: u8, n: u8) {
+    let byte = if n == 8 { byte } else { byte & ((1 << n) - 1) };
+    if buf.is_empty() || *last == 8 {
+        buf.push(byte);
+        *last = n;
+        return;
+    }
+
+    let free = 8 - *last;
+    let idx = buf.len() - 1;
+    buf[idx] |= byte << *last;
+    if n > free {
+        buf.push(byte >> free);
+        *last = n - free;
+    } else {
+        *last += n;
+    }
+}
+
+impl Cable for BatchingCable {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        if tms.is_empty() {
+            return;
+        }
+        if let Some(Pending::Tms { tms: pending, tdi }) = self.queue.last_mut() {
+            if *tdi == tdo {
+                pending.extend_from_slice(tms);
+                self.queued(tms.len() / 8);
+                return;
+            }
+        }
+        self.queue.push(Pending::Tms { tms: tms.to_vec(), tdi: tdo });
+        self.queued(tms.len() / 8);
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        self.flush();
+        self.inner.read_data(bits)
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        if let Some(Pending::Write { data: pending, bits: last, pause_after: paused }) = self.queue.last_mut() {
+            if !*paused {
+                // Still in Shift-xR, so this write simply continues the previous one
+                for (i, byte) in data.iter().enumerate() {
+                    let n = if i == data.len() - 1 { bits } else { 8 };
+                    push_bits(pending, last, *byte, n);
+                }
+                *paused = pause_after;
+                self.queued(data.len());
+                return;
+            }
+        }
+        self.queue.push(Pending::Write { data: data.to_vec(), bits, pause_after });
+        self.queued(data.len());
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        self.flush();
+        self.inner.read_write_data(data, bits, pause_after)
+    }
+}
+
+impl Adapter for BatchingCable {
+    fn capabilities(&mut self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        // Queued operations were issued at the old frequency
+        self.flush();
+        self.inner.set_frequency(hz)
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.flush();
+        self.inner.set_trst(asserted)
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.flush();
+        self.inner.set_srst(asserted)
+    }
+
+    fn flush(&mut self) {
+        BatchingCable::flush(self);
+        self.inner.flush();
+    }
+}
+
+impl Drop for BatchingCable {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cable::script::Call::*;
+    use crate::cable::script::Script;
+
+    fn batch(calls: Vec<crate::cable::script::Call>) -> BatchingCable {
+        BatchingCable::new(Box::new(Script::new(calls)))
+    }
+
+    #[test]
+    fn partial_bytes_of_one_shift_are_packed_together() {
+        // 101, then 1100110 carrying on from the fourth bit
+        let mut cable = batch(vec![Write { data: vec![0x35, 0x03], bits: 2, pause_after: true }]);
+        cable.write_data(&[0x05], 3, false);
+        cable.write_data(&[0x66], 7, true);
+        assert_eq!(cable.queue.len(), 1);
+    }
+
+    #[test]
+    fn tms_runs_coalesce_while_tdi_is_the_same() {
+        let mut cable = batch(vec![
+            ChangeMode(vec![1, 0, 0, 1], true),
+            ChangeMode(vec![1, 1], false),
+            Write { data: vec![0xab], bits: 8, pause_after: true },
+            Write { data: vec![0xcd], bits: 8, pause_after: true },
+        ]);
+        cable.change_mode(&[1, 0], true);
+        cable.change_mode(&[], false);
+        cable.change_mode(&[0, 1], true);
+        cable.change_mode(&[1, 1], false);
+        // A write that left Shift-xR isn't continued by the next one
+        cable.write_data(&[0xab], 8, true);
+        cable.write_data(&[0xcd], 8, true);
+    }
+
+    #[test]
+    fn reads_flush_what_is_queued_first() {
+        let mut cable = batch(vec![
+            ChangeMode(vec![1, 0, 0], true),
+            Write { data: vec![0x0f], bits: 4, pause_after: false },
+            ReadWrite { data: vec![0x01], bits: 4, pause_after: true, tdo: vec![0x09] },
+            Read { bits: 8, tdo: vec![0x5a] },
+        ]);
+        cable.change_mode(&[1, 0, 0], true);
+        cable.write_data(&[0x0f], 4, false);
+        assert_eq!(cable.read_write_data(&[0x01], 4, true), [0x09]);
+        assert!(cable.queue.is_empty());
+        assert_eq!(cable.read_data(8), [0x5a]);
+    }
+
+    #[test]
+    fn a_full_queue_is_flushed_without_a_read() {
+        let chunk = vec![0xa5; 1024];
+        let mut cable = batch(vec![
+            Write { data: vec![0xa5; MAX_PENDING_BYTES], bits: 8, pause_after: false },
+            Write { data: vec![0xa5], bits: 8, pause_after: true },
+        ]);
+        for _ in 0..MAX_PENDING_BYTES / chunk.len() {
+            cable.write_data(&chunk, 8, false);
+        }
+        assert!(cable.queue.is_empty());
+        assert_eq!(cable.pending_bytes, 0);
+        cable.write_data(&[0xa5], 8, true);
+    }
+}
@@ -0,0 +1,283 @@
+//! Scan vectors the way they are shifted: `length.div_ceil(8)` bytes, least significant first,
+//! with the pad bits above `length` in the last byte clear.
+//!
+//! SVF writes hex most significant digit first and shifts the least significant bit first, which
+//! is also the order jtag_taps and the cables in this crate shift bytes in.  `--bit-order
+//! msb-first` is for cables or targets that expect the vector the other way around.
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "std", derive(serde::Deserialize, clap::ValueEnum), serde(rename_all = "kebab-case"))]
+pub enum BitOrder {
+    /// Bit 0 of the SVF vector is shifted first, as the SVF spec says
+    #[default]
+    LsbFirst,
+    /// The highest bit of the SVF vector is shifted first
+    MsbFirst,
+}
+
+/// What TDI carries in bits that SMASK marks as don't-care
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "std", derive(serde::Deserialize, clap::ValueEnum), serde(rename_all = "lowercase"))]
+pub enum DontCare {
+    #[default]
+    #[cfg_attr(feature = "std", value(name = "0"), serde(rename = "0"))]
+    Zero,
+    #[cfg_attr(feature = "std", value(name = "1"), serde(rename = "1"))]
+    One,
+    /// Whatever the previous scan of the same register and length drove there, else 0
+    Previous,
+    /// Fresh random bits for every scan, to shake out devices that care about padding values
+    Random,
+}
+
+/// xorshift64*, plenty to scatter don't-care bits without pulling in a crate for it
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // The state must never be zero, which xorshift would never leave, so the one seed that
+        // lands on it gets the state of seed 0
+        match seed ^ 0x9e37_79b9_7f4a_7c15 {
+            0 => Rng(0x9e37_79b9_7f4a_7c15),
+            state => Rng(state),
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        self.fill(&mut bytes);
+        bytes
+    }
+
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+pub fn byte_len(length: u32) -> usize {
+    length.div_ceil(8) as usize
+}
+
+/// Number of bits of the last byte that are part of the scan, as jtag_taps takes them
+pub fn last_bits(length: u32) -> u8 {
+    match length % 8 {
+        0 => 8,
+        n => n as u8,
+    }
+}
+
+/// Whether every set bit of `data` is below `length`
+pub fn fits(data: &[u8], length: u32) -> bool {
+    data.iter().enumerate().rev()
+        .find(|(_, b)| **b != 0)
+        .map(|(i, b)| i * 8 + (8 - b.leading_zeros() as usize) <= length as usize)
+        .unwrap_or(true)
+}
+
+/// Pad or truncate `data` to exactly `length` bits
+pub fn fit(mut data: Vec<u8>, length: u32) -> Vec<u8> {
+    data.resize(byte_len(length), 0);
+    clear_pad(&mut data, length);
+    data
+}
+
+/// Clear the bits of the last byte of `data` above `length`
+pub fn clear_pad(data: &mut [u8], length: u32) {
+    if let Some(last) = data.last_mut() {
+        *last &= 0xff >> (8 - last_bits(length));
+    }
+}
+
+/// Reverse the `length` bits of `data` where they are: the bytes and the bits in each, then down
+/// by the pad bits, which have come out at the bottom
+fn reverse(data: &mut [u8], length: u32) {
+    data.reverse();
+    for byte in data.iter_mut() {
+        *byte = byte.reverse_bits();
+    }
+    let shift = (8 - last_bits(length)) as u32;
+    if shift != 0 {
+        for i in 0..data.len() {
+            let above = data.get(i + 1).map_or(0, |byte| byte << (8 - shift));
+            data[i] = data[i] >> shift | above;
+        }
+    }
+}
+
+/// Convert a vector of exactly `length` bits from SVF order to the order the cable shifts
+pub fn to_cable(mut data: Vec<u8>, length: u32, order: BitOrder) -> Vec<u8> {
+    if order == BitOrder::MsbFirst {
+        reverse(&mut data, length);
+    }
+    data
+}
+
+/// Convert `length` bits captured by the cable back to SVF order
+pub fn from_cable(data: Vec<u8>, length: u32, order: BitOrder) -> Vec<u8> {
+    // Both conversions are the same permutation, which is its own inverse
+    to_cable(fit(data, length), length, order)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn boundary_lengths() {
+        for (length, bytes, last) in [(1, 1, 1), (7, 1, 7), (8, 1, 8), (9, 2, 1), (31, 4, 7), (33, 5, 1)] {
+            assert_eq!(byte_len(length), bytes, "{} bits", length);
+            assert_eq!(last_bits(length), last, "{} bits", length);
+        }
+    }
+
+    #[test]
+    fn fit_clears_pad_bits() {
+        assert_eq!(fit(vec![0xff], 1), [0x01]);
+        assert_eq!(fit(vec![0xff], 7), [0x7f]);
+        assert_eq!(fit(vec![0xff], 8), [0xff]);
+        assert_eq!(fit(vec![0xff, 0xff], 9), [0xff, 0x01]);
+        assert_eq!(fit(vec![0xff; 4], 31), [0xff, 0xff, 0xff, 0x7f]);
+        assert_eq!(fit(vec![0xff; 5], 33), [0xff, 0xff, 0xff, 0xff, 0x01]);
+    }
+
+    #[test]
+    fn fit_pads_short_vectors() {
+        // The parser strips leading zeros, so (0001) over 33 bits arrives as a single byte
+        assert_eq!(fit(vec![0x01], 33), [0x01, 0, 0, 0, 0]);
+        assert_eq!(fit(vec![], 9), [0, 0]);
+    }
+
+    /// The bits of `data` in the order the cable shifts them
+    fn shifted(data: &[u8], length: u32) -> Vec<bool> {
+        (0..length as usize).map(|i| crate::cable::bit(data, i)).collect()
+    }
+
+    #[test]
+    fn svf_hex_is_shifted_lsb_first() {
+        let commands = svf::parse_complete("SDR 12 TDI (ABC);").unwrap();
+        let svf::Command::SDR(pattern) = &commands[0] else { panic!() };
+        let tdi = pattern.tdi.clone().unwrap();
+        assert_eq!(tdi, [0xbc, 0x0a]);
+        // 0xABC = 1010 1011 1100, so the first bit out is the last digit of the hex
+        let expected = [0, 0, 1, 1, 1, 1, 0, 1, 0, 1, 0, 1].map(|b| b == 1);
+        assert_eq!(shifted(&to_cable(tdi, 12, BitOrder::LsbFirst), 12), expected);
+    }
+
+    #[test]
+    fn msb_first_reverses_the_vector() {
+        assert_eq!(to_cable(vec![0x01], 1, BitOrder::MsbFirst), [0x01]);
+        assert_eq!(to_cable(vec![0x01], 7, BitOrder::MsbFirst), [0x40]);
+        assert_eq!(to_cable(vec![0x01], 8, BitOrder::MsbFirst), [0x80]);
+        assert_eq!(to_cable(vec![0x01, 0x00], 9, BitOrder::MsbFirst), [0x00, 0x01]);
+        assert_eq!(to_cable(vec![0xbc, 0x0a], 12, BitOrder::MsbFirst), [0xd5, 0x03]);
+        assert_eq!(to_cable(vec![0x01, 0, 0, 0, 0], 33, BitOrder::MsbFirst), [0, 0, 0, 0, 0x01]);
+    }
+
+    #[test]
+    fn every_seed_gives_random_bits() {
+        for seed in [0, 1, 0x9e37_79b9_7f4a_7c15] {
+            assert_ne!(super::Rng::new(seed).bytes(16), [0; 16], "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        for order in [BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            for length in [1, 7, 8, 9, 31, 33] {
+                let data = fit(vec![0x5a, 0xc3, 0x96, 0x3c, 0xe1], length);
+                let cable = to_cable(data.clone(), length, order);
+                assert_eq!(from_cable(cable, length, order), data, "{:?} {} bits", order, length);
+            }
+        }
+    }
+
+    #[test]
+    fn from_cable_drops_pad_bits() {
+        assert_eq!(from_cable(vec![0xff], 3, BitOrder::LsbFirst), [0x07]);
+        assert_eq!(from_cable(vec![0xfe], 3, BitOrder::MsbFirst), [0x03]);
+    }
+
+    #[test]
+    fn fits_rejects_bits_beyond_length() {
+        assert!(fits(&[0x01], 1));
+        assert!(!fits(&[0x02], 1));
+        assert!(fits(&[0x7f], 7));
+        assert!(!fits(&[0x80], 7));
+        assert!(fits(&[0xff], 8));
+        assert!(!fits(&[0xff, 0x01], 8));
+        assert!(fits(&[0xff, 0x01], 9));
+        assert!(!fits(&[0xff, 0x02], 9));
+        assert!(fits(&[0xff, 0xff, 0xff, 0x7f], 31));
+        assert!(!fits(&[0xff, 0xff, 0xff, 0xff], 31));
+        assert!(fits(&[0, 0, 0, 0, 0x01], 33));
+        assert!(!fits(&[0, 0, 0, 0, 0x02], 33));
+        // Zero bytes above the length are only padding
+        assert!(fits(&[0x01, 0, 0], 1));
+    }
+
+    /// `bits`, first shifted first, written as SVF hex
+    pub fn hex(bits: &[bool]) -> String {
+        bits.chunks(4).rev()
+            .map(|nibble| nibble.iter().rev().fold(0, |v, b| v << 1 | *b as u32))
+            .map(|digit| format!("{:X}", digit))
+            .collect()
+    }
+
+    fn order() -> impl Strategy<Value = BitOrder> {
+        prop_oneof![Just(BitOrder::LsbFirst), Just(BitOrder::MsbFirst)]
+    }
+
+    proptest! {
+        #[test]
+        fn hex_is_shifted_as_written(bits in prop::collection::vec(any::<bool>(), 1..100)) {
+            let length = bits.len() as u32;
+            let commands = svf::parse_complete(&format!("SDR {} TDI ({});", length, hex(&bits))).unwrap();
+            let svf::Command::SDR(pattern) = &commands[0] else { panic!() };
+            let tdi = fit(pattern.tdi.clone().unwrap(), length);
+            prop_assert!(fits(&tdi, length));
+            prop_assert_eq!(tdi.len(), byte_len(length));
+            prop_assert_eq!(shifted(&to_cable(tdi, length, BitOrder::LsbFirst), length), bits);
+        }
+
+        #[test]
+        fn msb_first_shifts_the_bits_backwards(bits in prop::collection::vec(any::<bool>(), 1..100)) {
+            let length = bits.len() as u32;
+            let reversed: Vec<bool> = bits.iter().rev().copied().collect();
+            prop_assert_eq!(shifted(&to_cable(crate::cable::pack(&bits), length, BitOrder::MsbFirst), length), reversed);
+        }
+
+        #[test]
+        fn cable_order_round_trips(data in prop::collection::vec(any::<u8>(), 0..16), length in 1u32..128,
+                                   order in order()) {
+            let data = fit(data, length);
+            prop_assert_eq!(from_cable(to_cable(data.clone(), length, order), length, order), data);
+        }
+
+        #[test]
+        fn fit_keeps_exactly_the_bits_below_length(data in prop::collection::vec(any::<u8>(), 0..16),
+                                                   length in 1u32..128) {
+            let fitted = fit(data.clone(), length);
+            prop_assert_eq!(fitted.len(), byte_len(length));
+            for i in 0..fitted.len() * 8 {
+                let expected = i < length as usize && i < data.len() * 8 && crate::cable::bit(&data, i);
+                prop_assert_eq!(crate::cable::bit(&fitted, i), expected, "bit {}", i);
+            }
+            let set_below = (0..data.len() * 8).all(|i| i < length as usize || !crate::cable::bit(&data, i));
+            prop_assert_eq!(fits(&data, length), set_below);
+        }
+    }
+}
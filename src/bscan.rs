@@ -0,0 +1,203 @@
+//! `svfplayer bscan`: read and drive the pins of a BSDL-described device through its boundary
+//! register, with every other device on the chain in BYPASS.
+use jtag_taps::statemachine::{JtagSM, JtagState, Register};
+
+use crate::bsdl::{Bsdl, Function};
+use crate::cable::{bit, pack, AdapterBox};
+use crate::Svf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Drive {
+    Low,
+    High,
+    HighZ,
+}
+
+/// Parse `PORT=0`, `PORT=1` or `PORT=z`
+pub fn parse_drive(s: &str) -> Result<(String, Drive), String> {
+    let (port, value) = s.split_once('=').ok_or_else(|| format!("{} should look like PORT=0|1|z", s))?;
+    let drive = match value {
+        "0" => Drive::Low,
+        "1" => Drive::High,
+        "z" | "Z" => Drive::HighZ,
+        _ => return Err(format!("{}: drive 0, 1 or z", s)),
+    };
+    Ok((port.to_string(), drive))
+}
+
+pub struct Target<'a> {
+    /// Instruction lengths of the whole chain, nearest TDO first
+    pub chain: Vec<usize>,
+    /// The described devices and their positions in `chain`, in chain order
+    pub devices: Vec<(usize, &'a Bsdl)>,
+}
+
+/// A port's value as captured by its cell
+pub struct Pin {
+    pub port: String,
+    pub function: Function,
+    pub value: bool,
+}
+
+/// Set `cells` of `bsdl` up to drive `port` as `value`, returning its output cell
+pub fn drive(bsdl: &Bsdl, cells: &mut [bool], port: &str, value: Drive) -> Result<usize, String> {
+    let (num, cell) = bsdl.cells.iter().enumerate()
+        .find(|(_, cell)| cell.function.drives() && cell.port.as_deref() == Some(port))
+        .ok_or_else(|| format!("{} has no output cell for {}", bsdl.entity, port))?;
+    match (value, cell.control) {
+        (Drive::HighZ, None) => return Err(format!("{} can't be tristated", port)),
+        (Drive::HighZ, Some((control, disable))) => cells[control] = disable,
+        (_, control) => {
+            cells[num] = value == Drive::High;
+            if let Some((control, disable)) = control {
+                cells[control] = !disable;
+            }
+        }
+    }
+    Ok(num)
+}
+
+/// The cell capturing what is on `port`, if it has one
+pub fn receiver(bsdl: &Bsdl, port: &str) -> Option<usize> {
+    bsdl.cells.iter().position(|cell| {
+        cell.port.as_deref() == Some(port)
+            && matches!(cell.function, Function::Input | Function::Bidir | Function::Clock | Function::ObserveOnly)
+    })
+}
+
+impl<'a> Target<'a> {
+    /// `bsdl` at `position` in a chain of `chain` instruction lengths, or alone if that's empty
+    pub fn new(bsdl: &'a Bsdl, chain: Vec<usize>, position: usize) -> Result<Self, String> {
+        let chain = if chain.is_empty() { vec![bsdl.ir_length] } else { chain };
+        Self::with_devices(chain, vec![(position, bsdl)])
+    }
+
+    /// Several described devices in one chain
+    pub fn with_devices(chain: Vec<usize>, mut devices: Vec<(usize, &'a Bsdl)>) -> Result<Self, String> {
+        devices.sort_by_key(|(position, _)| *position);
+        for (position, bsdl) in &devices {
+            match chain.get(*position) {
+                Some(len) if *len == bsdl.ir_length => (),
+                Some(len) => return Err(format!("{} has a {} bit IR, not {} as the chain says", bsdl.entity,
+                                                bsdl.ir_length, len)),
+                None => return Err(format!("position {} is beyond the {} device chain", position, chain.len())),
+            }
+        }
+        if devices.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err("two devices at the same position".into());
+        }
+        Ok(Target { chain, devices })
+    }
+
+    fn bsdl_at(&self, position: usize) -> Option<&'a Bsdl> {
+        self.devices.iter().find(|(p, _)| *p == position).map(|(_, bsdl)| *bsdl)
+    }
+
+    /// Load `name` into every described device and BYPASS into the rest
+    fn instruction(&self, svf: &mut Svf, sm: &mut JtagSM<AdapterBox>, name: &str) -> Result<(), String> {
+        let mut bits = vec![];
+        for (i, len) in self.chain.iter().enumerate() {
+            let code = match self.bsdl_at(i) {
+                Some(bsdl) => bsdl.opcode(name).ok_or_else(|| format!("{} has no {} instruction", bsdl.entity, name))?,
+                None => u64::MAX,
+            };
+            bits.extend((0..*len).map(|b| code >> b & 1 != 0));
+        }
+        svf.scan(sm, Register::Instruction, pack(&bits), bits.len() as u32, JtagState::Idle);
+        Ok(())
+    }
+
+    /// Shift `cells` into the described devices' boundary registers, returning what they captured
+    pub fn exchange(&self, svf: &mut Svf, sm: &mut JtagSM<AdapterBox>, cells: &[Vec<bool>]) -> Vec<Vec<bool>> {
+        // Each device in BYPASS adds a bit, the ones nearer TDO coming out first
+        let mut bits = vec![];
+        let mut offsets = vec![];
+        let mut described = cells.iter();
+        for position in 0..self.chain.len() {
+            if self.bsdl_at(position).is_some() {
+                let cells = described.next().unwrap();
+                offsets.push((bits.len(), cells.len()));
+                bits.extend_from_slice(cells);
+            } else {
+                bits.push(false);
+            }
+        }
+        let read = svf.scan(sm, Register::Data, pack(&bits), bits.len() as u32, JtagState::Idle);
+        offsets.into_iter().map(|(start, len)| (start..start + len).map(|i| bit(&read, i)).collect()).collect()
+    }
+
+    /// Safe values for every described device's cells
+    pub fn safe(&self) -> Vec<Vec<bool>> {
+        self.devices.iter()
+            .map(|(_, bsdl)| bsdl.cells.iter().map(|cell| cell.safe.unwrap_or(false)).collect())
+            .collect()
+    }
+
+    /// Take over the pins with EXTEST, PRELOADing `cells` first so the pins start out with these
+    /// values rather than whatever was there
+    pub fn enter_extest(&self, svf: &mut Svf, sm: &mut JtagSM<AdapterBox>, cells: &[Vec<bool>])
+                        -> Result<(), String> {
+        let preload = if self.devices.iter().all(|(_, bsdl)| bsdl.opcode("PRELOAD").is_some()) {
+            "PRELOAD"
+        } else {
+            "SAMPLE"
+        };
+        self.instruction(svf, sm, preload)?;
+        self.exchange(svf, sm, cells);
+        self.instruction(svf, sm, "EXTEST")
+    }
+
+    fn pins(&self, captured: &[bool]) -> Vec<Pin> {
+        self.devices[0].1.cells.iter().zip(captured)
+            .filter_map(|(cell, value)| {
+                let port = cell.port.clone()?;
+                (cell.function != Function::Internal).then_some(Pin { port, function: cell.function, value: *value })
+            })
+            .collect()
+    }
+
+    /// Capture every pin of the first described device without disturbing it
+    pub fn sample(&self, svf: &mut Svf, sm: &mut JtagSM<AdapterBox>) -> Result<Vec<Pin>, String> {
+        self.instruction(svf, sm, "SAMPLE")?;
+        let captured = self.exchange(svf, sm, &self.safe());
+        Ok(self.pins(&captured[0]))
+    }
+
+    /// EXTEST on the first described device, driving `drive` and leaving the rest at their safe
+    /// values, and capture every pin
+    pub fn extest(&self, svf: &mut Svf, sm: &mut JtagSM<AdapterBox>, drive: &[(String, Drive)])
+                  -> Result<Vec<Pin>, String> {
+        let mut cells = self.safe();
+        for (port, value) in drive {
+            self::drive(self.devices[0].1, &mut cells[0], port, *value)?;
+        }
+        self.enter_extest(svf, sm, &cells)?;
+        let captured = self.exchange(svf, sm, &cells);
+        Ok(self.pins(&captured[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdl::tests::BSDL;
+    use crate::cable::sim::Sim;
+    use crate::cable::ShiftCable;
+
+    #[test]
+    fn extest_values_are_captured_back() {
+        let bsdl = crate::bsdl::parse(BSDL).unwrap();
+        let sim = Sim::parse("[[device]]\nirlen = 4\nidcode = 1\nbsr = 6\n\
+                              instructions = { idcode = 0xe, sample = 0x1, extest = 0x0 }\n").unwrap();
+        let mut sm = JtagSM::new(AdapterBox(Box::new(ShiftCable(sim))));
+        let mut svf = Svf::new();
+        let target = Target::new(&bsdl, vec![], 0).unwrap();
+        let drive = [parse_drive("Y=1").unwrap(), parse_drive("IO(1)=1").unwrap(), parse_drive("IO(0)=z").unwrap()];
+        let pins = target.extest(&mut svf, &mut sm, &drive).unwrap();
+        let values: Vec<_> = pins.iter().map(|pin| (pin.port.as_str(), pin.value)).collect();
+        assert_eq!(values, [("A", false), ("Y", true), ("IO(0)", false), ("IO(1)", true)]);
+        // The simulated cells hold what EXTEST loaded, which SAMPLE then reads
+        assert!(target.sample(&mut svf, &mut sm).unwrap()[1].value);
+        assert!(Target::new(&bsdl, vec![5], 0).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Just enough of BSDL (IEEE 1149.1 boundary scan description) to drive a device's boundary
+//! register: the instruction length and opcodes, and the boundary cells with their ports,
+//! functions, safe values and control cells.
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Input,
+    Output2,
+    Output3,
+    Bidir,
+    Control,
+    ControlR,
+    Internal,
+    Clock,
+    ObserveOnly,
+}
+
+impl Function {
+    fn parse(s: &str) -> Result<Function, String> {
+        Ok(match s.to_lowercase().as_str() {
+            "input" => Function::Input,
+            "output2" => Function::Output2,
+            "output3" => Function::Output3,
+            "bidir" => Function::Bidir,
+            "control" => Function::Control,
+            "controlr" => Function::ControlR,
+            "internal" => Function::Internal,
+            "clock" => Function::Clock,
+            "observe_only" => Function::ObserveOnly,
+            _ => return Err(format!("unknown cell function {}", s)),
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Input => "input",
+            Function::Output2 => "output2",
+            Function::Output3 => "output3",
+            Function::Bidir => "bidir",
+            Function::Control => "control",
+            Function::ControlR => "controlr",
+            Function::Internal => "internal",
+            Function::Clock => "clock",
+            Function::ObserveOnly => "observe_only",
+        }
+    }
+
+    /// Whether the cell can drive its port
+    pub fn drives(self) -> bool {
+        matches!(self, Function::Output2 | Function::Output3 | Function::Bidir)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+    /// Port the cell belongs to, as written in the BSDL, e.g. `IO(3)`.  None for `*`.
+    pub port: Option<String>,
+    pub function: Function,
+    /// Value to load when nothing else is wanted, None for `X`
+    pub safe: Option<bool>,
+    /// Cell enabling this one's output buffer, and the value that disables it
+    pub control: Option<(usize, bool)>,
+}
+
+#[derive(Debug)]
+pub struct Bsdl {
+    pub entity: String,
+    pub ir_length: usize,
+    /// Opcodes by instruction name, upper case
+    pub opcodes: HashMap<String, Vec<u64>>,
+    /// Cells by number, 0 being nearest TDO
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => (),
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_word(token: Option<&Token>, word: &str) -> bool {
+    matches!(token, Some(Token::Word(w)) if w.eq_ignore_ascii_case(word))
+}
+
+/// Split at commas that aren't inside parentheses
+fn split_top(s: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(s[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+/// `NAME (parts)` items, as used by INSTRUCTION_OPCODE and BOUNDARY_REGISTER
+fn items(s: &str) -> Result<Vec<(&str, Vec<&str>)>, String> {
+    split_top(s).into_iter().map(|item| {
+        let open = item.find('(').ok_or_else(|| format!("expected ( in {}", item))?;
+        let inner = item[open + 1..].strip_suffix(')').ok_or_else(|| format!("expected ) in {}", item))?;
+        Ok((item[..open].trim(), split_top(inner)))
+    }).collect()
+}
+
+fn number(s: &str, what: &str) -> Result<usize, String> {
+    s.trim().parse().map_err(|_| format!("bad {} {}", what, s))
+}
+
+fn bit(s: &str) -> Result<Option<bool>, String> {
+    match s.trim() {
+        "0" => Ok(Some(false)),
+        "1" => Ok(Some(true)),
+        "X" | "x" => Ok(None),
+        s => Err(format!("bad cell value {}", s)),
+    }
+}
+
+pub fn parse(text: &str) -> Result<Bsdl, String> {
+    let tokens = tokenize(text)?;
+    let mut entity = None;
+    let mut attributes: HashMap<String, String> = HashMap::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if is_word(tokens.get(i), "entity") && entity.is_none() {
+            if let Some(Token::Word(name)) = tokens.get(i + 1) {
+                entity = Some(name.clone());
+            }
+        }
+        // attribute NAME of ENTITY : entity is VALUE ;
+        if is_word(tokens.get(i), "attribute") && is_word(tokens.get(i + 2), "of")
+            && tokens.get(i + 4) == Some(&Token::Punct(':')) && is_word(tokens.get(i + 6), "is")
+        {
+            let Some(Token::Word(name)) = tokens.get(i + 1) else {
+                return Err("attribute without a name".into());
+            };
+            let mut value = String::new();
+            i += 7;
+            while i < tokens.len() && tokens[i] != Token::Punct(';') {
+                match &tokens[i] {
+                    Token::Str(s) | Token::Word(s) => value.push_str(s),
+                    Token::Punct(_) => (),
+                }
+                i += 1;
+            }
+            attributes.insert(name.to_uppercase(), value);
+        }
+        i += 1;
+    }
+
+    let get = |name: &str| attributes.get(name).ok_or_else(|| format!("no {} attribute", name));
+    let ir_length = number(get("INSTRUCTION_LENGTH")?, "INSTRUCTION_LENGTH")?;
+    let mut opcodes = HashMap::new();
+    for (name, codes) in items(get("INSTRUCTION_OPCODE")?)? {
+        let codes = codes.iter()
+            .map(|code| u64::from_str_radix(code, 2).map_err(|_| format!("bad opcode {} for {}", code, name)))
+            .collect::<Result<_, _>>()?;
+        opcodes.insert(name.to_uppercase(), codes);
+    }
+
+    let length = number(get("BOUNDARY_LENGTH")?, "BOUNDARY_LENGTH")?;
+    let mut cells = vec![None; length];
+    for (num, fields) in items(get("BOUNDARY_REGISTER")?)? {
+        let num = number(num, "cell number")?;
+        if fields.len() < 4 {
+            return Err(format!("cell {} needs at least 4 fields", num));
+        }
+        let control = match fields.get(4..6) {
+            Some([ccell, disval]) => Some((number(ccell, "control cell")?,
+                                           bit(disval)?.ok_or(format!("cell {} has no disable value", num))?)),
+            _ => None,
+        };
+        let cell = Cell {
+            port: (fields[1] != "*").then(|| fields[1].replace(' ', "")),
+            function: Function::parse(fields[2])?,
+            safe: bit(fields[3])?,
+            control,
+        };
+        *cells.get_mut(num).ok_or(format!("cell {} is beyond BOUNDARY_LENGTH", num))? = Some(cell);
+    }
+    let cells = cells.into_iter().enumerate()
+        .map(|(i, cell)| cell.ok_or(format!("cell {} isn't described", i)))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Bsdl {
+        entity: entity.ok_or("no entity")?,
+        ir_length,
+        opcodes,
+        cells,
+    })
+}
+
+impl Bsdl {
+    pub fn load(path: &str) -> Result<Bsdl, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// First opcode of `instruction`
+    pub fn opcode(&self, instruction: &str) -> Option<u64> {
+        self.opcodes.get(instruction).and_then(|codes| codes.first().copied())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const BSDL: &str = r#"
+        entity TINY is
+          generic (PHYSICAL_PIN_MAP : string := "QFN");
+          port (A: in bit; Y: out bit; IO: inout bit_vector(0 to 1));
+          attribute INSTRUCTION_LENGTH of TINY : entity is 4;
+          attribute INSTRUCTION_OPCODE of TINY : entity is
+            "EXTEST (0000)," &
+            "SAMPLE (0001, 1001)," &  -- SAMPLE/PRELOAD
+            "IDCODE (1110)," &
+            "BYPASS (1111)";
+          attribute BOUNDARY_LENGTH of TINY : entity is 6;
+          attribute BOUNDARY_REGISTER of TINY : entity is
+            "0 (BC_1, A, input, X)," &
+            "1 (BC_1, Y, output2, 0)," &
+            "2 (BC_1, *, control, 0)," &
+            "3 (BC_7, IO(0), bidir, X, 2, 0, Z)," &
+            "4 (BC_1, *, control, 0)," &
+            "5 (BC_7, IO(1), bidir, X, 4, 0, Z)";
+        end TINY;
+    "#;
+
+    #[test]
+    fn parses_opcodes_and_cells() {
+        let bsdl = parse(BSDL).unwrap();
+        assert_eq!(bsdl.entity, "TINY");
+        assert_eq!(bsdl.ir_length, 4);
+        assert_eq!(bsdl.opcodes["SAMPLE"], [0b0001, 0b1001]);
+        assert_eq!(bsdl.opcode("IDCODE"), Some(0b1110));
+        assert_eq!(bsdl.cells.len(), 6);
+        assert_eq!(bsdl.cells[3], Cell {
+            port: Some("IO(0)".into()),
+            function: Function::Bidir,
+            safe: None,
+            control: Some((2, false)),
+        });
+        assert_eq!(bsdl.cells[2].port, None);
+    }
+}
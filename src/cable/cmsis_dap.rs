@@ -0,0 +1,326 @@
+//! CMSIS-DAP probes (DAPLink and friends), selected with `--cable cmsis-dap` or
+//! `cmsis-dap:vid=0d28,pid=0204,serial=...`.  Without vid/pid any device whose product string
+//! contains "CMSIS-DAP" is a candidate.  The v2 bulk interface is preferred over v1 HID.  Bulk
+//! is driven through libusb everywhere, and so is HID on Linux, where the kernel driver can be
+//! detached; macOS and Windows keep HID interfaces to themselves, so there v1 probes are reached
+//! through the operating system's HID API instead.  Scans become `DAP_JTAG_Sequence` commands,
+//! packed up to the packet size the probe reports.
+use std::time::Duration;
+
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType, UsbContext};
+
+use super::{bit, pack, parse_id, Capabilities, Shifter};
+
+const DAP_INFO: u8 = 0x00;
+const DAP_CONNECT: u8 = 0x02;
+const DAP_DISCONNECT: u8 = 0x03;
+const DAP_SWJ_PINS: u8 = 0x10;
+const DAP_SWJ_CLOCK: u8 = 0x11;
+const DAP_JTAG_SEQUENCE: u8 = 0x14;
+
+const INFO_PACKET_SIZE: u8 = 0xff;
+const PORT_JTAG: u8 = 2;
+const PIN_N_RESET: u8 = 1 << 7;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether libusb can claim a HID interface from the operating system
+const HID_OVER_LIBUSB: bool = cfg!(target_os = "linux");
+
+/// How commands reach the probe
+enum Link {
+    Usb { handle: DeviceHandle<Context>, ep_out: u8, ep_in: u8 },
+    Hid(HidDevice),
+}
+
+pub struct CmsisDap {
+    link: Link,
+    /// v1 probes use HID reports, which always carry a full packet
+    hid: bool,
+    packet_size: usize,
+}
+
+/// The USB interface CMSIS-DAP commands are exchanged on
+struct Interface {
+    number: u8,
+    ep_out: u8,
+    ep_in: u8,
+    packet_size: usize,
+    hid: bool,
+}
+
+fn find_interface(device: &Device<Context>) -> Option<Interface> {
+    let config = device.active_config_descriptor().ok()?;
+    let mut found: Option<Interface> = None;
+    for iface in config.interfaces() {
+        let Some(desc) = iface.descriptors().next() else {
+            continue;
+        };
+        let hid = match desc.class_code() {
+            0x03 if HID_OVER_LIBUSB => true,
+            0xff => false,
+            _ => continue,
+        };
+        let wanted = if hid { TransferType::Interrupt } else { TransferType::Bulk };
+        let mut ep_out = None;
+        let mut ep_in = None;
+        let mut packet_size = 64;
+        for ep in desc.endpoint_descriptors() {
+            if ep.transfer_type() != wanted {
+                continue;
+            }
+            match ep.direction() {
+                Direction::Out if ep_out.is_none() => {
+                    ep_out = Some(ep.address());
+                    packet_size = ep.max_packet_size() as usize;
+                }
+                Direction::In if ep_in.is_none() => ep_in = Some(ep.address()),
+                _ => (),
+            }
+        }
+        let (Some(ep_out), Some(ep_in)) = (ep_out, ep_in) else {
+            continue;
+        };
+        if found.as_ref().is_none_or(|f| f.hid && !hid) {
+            found = Some(Interface {
+                number: desc.interface_number(),
+                ep_out,
+                ep_in,
+                packet_size,
+                hid,
+            });
+        }
+    }
+    found
+}
+
+fn product(handle: &DeviceHandle<Context>, device: &Device<Context>) -> Option<(String, String)> {
+    let desc = device.device_descriptor().ok()?;
+    let product = handle.read_product_string_ascii(&desc).ok()?;
+    let serial = handle.read_serial_number_string_ascii(&desc).unwrap_or_default();
+    Some((product, serial))
+}
+
+/// The v1 probes that have to be reached through the HID API
+fn hid_probes(api: &HidApi, vid: Option<u16>, pid: Option<u16>, serial: Option<&str>) -> Vec<DeviceInfo> {
+    let mut found: Vec<DeviceInfo> = vec![];
+    for info in api.device_list() {
+        let product = info.product_string().unwrap_or_default();
+        if vid.is_some_and(|vid| vid != info.vendor_id()) || pid.is_some_and(|pid| pid != info.product_id())
+            || (vid.is_none() && pid.is_none() && !product.contains("CMSIS-DAP"))
+            || serial.is_some_and(|serial| Some(serial) != info.serial_number()) {
+            continue;
+        }
+        // A probe with several HID interfaces is listed once per interface
+        if !found.iter().any(|f| f.path() == info.path()) {
+            found.push(info.clone());
+        }
+    }
+    found
+}
+
+/// Every attached CMSIS-DAP probe, as a cable spec and description
+pub fn list() -> Vec<(String, String)> {
+    let mut found = vec![];
+    if let Ok(devices) = Context::new().and_then(|ctx| ctx.devices()) {
+        for device in devices.iter() {
+            let Ok(handle) = device.open() else {
+                continue;
+            };
+            if let Some((product, serial)) = product(&handle, &device) {
+                if product.contains("CMSIS-DAP") && find_interface(&device).is_some() {
+                    found.push((format!("cmsis-dap:serial={}", serial), product));
+                }
+            }
+        }
+    }
+    if let Some(Ok(api)) = (!HID_OVER_LIBUSB).then(HidApi::new) {
+        for info in hid_probes(&api, None, None, None) {
+            let serial = info.serial_number().unwrap_or_default();
+            if !found.iter().any(|(spec, _)| spec.ends_with(&format!("={}", serial))) {
+                found.push((format!("cmsis-dap:serial={}", serial), info.product_string().unwrap_or_default().into()));
+            }
+        }
+    }
+    found
+}
+
+impl CmsisDap {
+    pub fn open(params: &str, clock: u32) -> Result<Self, String> {
+        let params = super::parse_params(params)?;
+        let vid = params.contains_key("vid").then(|| parse_id(&params, "vid", 0)).transpose()?;
+        let pid = params.contains_key("pid").then(|| parse_id(&params, "pid", 0)).transpose()?;
+        let serial = params.get("serial").copied();
+
+        let devices = Context::new().and_then(|ctx| ctx.devices()).map_err(|e| e.to_string())?;
+        let mut found = vec![];
+        for device in devices.iter() {
+            let Ok(desc) = device.device_descriptor() else {
+                continue;
+            };
+            if vid.is_some_and(|vid| vid != desc.vendor_id()) || pid.is_some_and(|pid| pid != desc.product_id()) {
+                continue;
+            }
+            let Ok(handle) = device.open() else {
+                continue;
+            };
+            let Some((product, sn)) = product(&handle, &device) else {
+                continue;
+            };
+            if (vid.is_none() && pid.is_none() && !product.contains("CMSIS-DAP"))
+                || serial.is_some_and(|serial| serial != sn) {
+                continue;
+            }
+            if let Some(iface) = find_interface(&device) {
+                found.push((handle, iface, sn));
+            }
+        }
+
+        // Where libusb can claim HID interfaces it already found the v1 probes
+        let api = (!HID_OVER_LIBUSB).then(HidApi::new).and_then(Result::ok);
+        let hid_found = api.as_ref().map_or(vec![], |api| hid_probes(api, vid, pid, serial));
+
+        let mut dap = match (found.len(), hid_found.as_slice()) {
+            (0, []) => return Err("no matching CMSIS-DAP probe found".into()),
+            (1, []) => {
+                let (handle, iface, _) = found.pop().unwrap();
+                // Only matters for HID, where the kernel driver owns the interface
+                let _ = handle.set_auto_detach_kernel_driver(true);
+                handle.claim_interface(iface.number).map_err(|e| e.to_string())?;
+                CmsisDap {
+                    link: Link::Usb { handle, ep_out: iface.ep_out, ep_in: iface.ep_in },
+                    hid: iface.hid,
+                    packet_size: iface.packet_size,
+                }
+            }
+            (0, [info]) => {
+                let device = info.open_device(api.as_ref().unwrap()).map_err(|e| e.to_string())?;
+                CmsisDap { link: Link::Hid(device), hid: true, packet_size: 64 }
+            }
+            _ => {
+                let serials: Vec<_> = found.iter().map(|(_, _, sn)| sn.as_str())
+                    .chain(hid_found.iter().map(|info| info.serial_number().unwrap_or_default()))
+                    .collect();
+                return Err(format!("several CMSIS-DAP probes match, add serial= to pick one of: {}",
+                                   serials.join(" ")));
+            }
+        };
+
+        let info = dap.transfer(&[DAP_INFO, INFO_PACKET_SIZE])?;
+        if info.len() >= 4 && info[1] == 2 {
+            dap.packet_size = u16::from_le_bytes([info[2], info[3]]) as usize;
+        }
+        let resp = dap.transfer(&[DAP_CONNECT, PORT_JTAG])?;
+        if resp.get(1) != Some(&PORT_JTAG) {
+            return Err("CMSIS-DAP probe doesn't support JTAG".into());
+        }
+        if clock != 0 {
+            dap.swj_clock(clock)?;
+        }
+        Ok(dap)
+    }
+
+    /// Send one command and return its response
+    fn transfer(&mut self, req: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = req.to_vec();
+        if self.hid {
+            out.resize(self.packet_size, 0);
+        }
+        let mut resp = vec![0; self.packet_size.max(64)];
+        let n = match &self.link {
+            Link::Usb { handle, ep_out, ep_in } if self.hid => {
+                handle.write_interrupt(*ep_out, &out, TIMEOUT).map_err(|e| e.to_string())?;
+                handle.read_interrupt(*ep_in, &mut resp, TIMEOUT).map_err(|e| e.to_string())?
+            }
+            Link::Usb { handle, ep_out, ep_in } => {
+                handle.write_bulk(*ep_out, &out, TIMEOUT).map_err(|e| e.to_string())?;
+                handle.read_bulk(*ep_in, &mut resp, TIMEOUT).map_err(|e| e.to_string())?
+            }
+            Link::Hid(device) => {
+                // The HID API takes the report ID, which CMSIS-DAP doesn't use, ahead of the report
+                device.write(&[&[0], &out[..]].concat()).map_err(|e| e.to_string())?;
+                device.read_timeout(&mut resp, TIMEOUT.as_millis() as i32).map_err(|e| e.to_string())?
+            }
+        };
+        resp.truncate(n);
+        if resp.first() != Some(&req[0]) {
+            return Err(format!("no response to CMSIS-DAP command {:#04x}", req[0]));
+        }
+        Ok(resp)
+    }
+
+    fn swj_clock(&mut self, hz: u32) -> Result<(), String> {
+        let mut req = vec![DAP_SWJ_CLOCK];
+        req.extend_from_slice(&hz.to_le_bytes());
+        match self.transfer(&req)?.get(1) {
+            Some(0) => Ok(()),
+            _ => Err(format!("CMSIS-DAP probe refused a {} Hz clock", hz)),
+        }
+    }
+}
+
+impl Shifter for CmsisDap {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities { max_transfer: Some(self.packet_size), srst: true, read_skip: true, ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], read: bool) -> Vec<bool> {
+        let mut tdo = Vec::with_capacity(tms.len());
+        let mut i = 0;
+        while i < tms.len() {
+            // Each sequence is up to 64 clocks with a constant TMS
+            let mut req = vec![DAP_JTAG_SEQUENCE, 0];
+            let mut resp_len = 2;
+            let mut lengths = vec![];
+            while i < tms.len() && req[1] < u8::MAX {
+                let mut n = 1;
+                while i + n < tms.len() && n < 64 && tms[i + n] == tms[i] {
+                    n += 1;
+                }
+                let bytes = n.div_ceil(8);
+                if req.len() + 1 + bytes > self.packet_size || (read && resp_len + bytes > self.packet_size) {
+                    break;
+                }
+                req.push((n % 64) as u8 | (tms[i] as u8) << 6 | (read as u8) << 7);
+                req.extend_from_slice(&pack(&tdi[i..i + n]));
+                if read {
+                    resp_len += bytes;
+                }
+                lengths.push(n);
+                req[1] += 1;
+                i += n;
+            }
+
+            let resp = self.transfer(&req).expect("cmsis-dap transfer");
+            assert_eq!(resp.get(1), Some(&0), "DAP_JTAG_Sequence failed");
+            if read {
+                let mut offset = 2;
+                for n in lengths {
+                    tdo.extend((0..n).map(|j| bit(&resp[offset..], j)));
+                    offset += n.div_ceil(8);
+                }
+            }
+        }
+        if !read {
+            tdo.resize(tms.len(), false);
+        }
+        tdo
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.swj_clock(hz as u32).expect("cmsis-dap clock");
+        Some(hz)
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        let output = if asserted { 0 } else { PIN_N_RESET };
+        self.transfer(&[DAP_SWJ_PINS, output, PIN_N_RESET, 0, 0, 0, 0]).is_ok()
+    }
+}
+
+impl Drop for CmsisDap {
+    fn drop(&mut self) {
+        let _ = self.transfer(&[DAP_DISCONNECT]);
+    }
+}
@@ -0,0 +1,201 @@
+//! MPSSE cables selected by USB VID:PID, serial number and interface, e.g.
+//! `ftdi:vid=0403,pid=6010,serial=FTX1A2B,interface=A`.  This uses the same pinout as the
+//! jtag_taps "jtagkey" cable, but doesn't depend on the device description string, so it can pick
+//! one probe out of several identical ones.
+use std::time::Duration;
+
+use jtag_taps::cable::Cable;
+use jtag_taps::cable::mpsse::Mpsse;
+use libftd2xx::{DeviceInfo, DeviceType, Ft2232h, Ft232h, Ft4232h, FtStatus, Ftdi, FtdiCommon,
+                FtdiMpsse, MpsseCmdExecutor, MpsseSettings, TimeoutError};
+use libftd2xx_ffi::FT_HANDLE;
+
+use super::parse_id;
+
+// Lower pins
+const PIN_TMS: u8 = 1 << 3;
+const LOWER_OUTPUT_PINS: u8 = 0x1b;
+
+// Upper pins
+const PIN_N_TRST: u8 = 1;
+const PIN_N_SRST: u8 = 1 << 1;
+const UPPER_OUTPUT_PINS: u8 = 0x0f;
+
+/// Wraps an MPSSE capable FTDI device so the jtagkey pin setup happens as part of
+/// `Mpsse::new`, since the device isn't reachable once it has been handed over
+struct JtagKeyPins<T>(T);
+
+impl<T: FtdiCommon> FtdiCommon for JtagKeyPins<T> {
+    const DEVICE_TYPE: DeviceType = T::DEVICE_TYPE;
+
+    fn handle(&mut self) -> FT_HANDLE {
+        self.0.handle()
+    }
+
+    fn device_type(&mut self) -> Result<DeviceType, FtStatus> {
+        Ok(Self::DEVICE_TYPE)
+    }
+}
+
+impl<T: FtdiMpsse> FtdiMpsse for JtagKeyPins<T> {
+    fn initialize_mpsse_default(&mut self) -> Result<(), TimeoutError> {
+        self.initialize_mpsse(&MpsseSettings::default())?;
+        self.set_latency_timer(Duration::from_millis(2))?;
+        self.set_gpio_upper(PIN_N_TRST | PIN_N_SRST, UPPER_OUTPUT_PINS)?;
+        self.set_gpio_lower(PIN_TMS, LOWER_OUTPUT_PINS)
+    }
+}
+
+impl<T: FtdiMpsse> MpsseCmdExecutor for JtagKeyPins<T> {
+    type Error = TimeoutError;
+
+    fn init(&mut self, settings: &MpsseSettings) -> Result<(), Self::Error> {
+        self.initialize_mpsse(settings)
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.write_all(data)
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_all(data)
+    }
+}
+
+struct Selector<'a> {
+    vid: u16,
+    pid: u16,
+    serial: Option<&'a str>,
+    interface: Option<&'a str>,
+}
+
+impl Selector<'_> {
+    /// D2XX reports each interface of a multi-channel chip as its own device, with the channel
+    /// letter appended to the serial number
+    fn matches(&self, dev: &DeviceInfo) -> bool {
+        if dev.vendor_id != self.vid || dev.product_id != self.pid {
+            return false;
+        }
+        let sn = dev.serial_number.as_str();
+        if dev.device_type == DeviceType::FT232H {
+            // The one channel, whose serial number has no letter appended
+            return self.serial.is_none_or(|serial| sn == serial)
+                && self.interface.is_none_or(|iface| iface == "A");
+        }
+        match (self.serial, self.interface) {
+            (Some(serial), Some(iface)) => sn == format!("{}{}", serial, iface),
+            (Some(serial), None) => sn == serial || sn == format!("{}A", serial),
+            (None, Some(iface)) => sn.ends_with(iface),
+            (None, None) => sn.is_empty() || sn.ends_with('A'),
+        }
+    }
+}
+
+pub fn open(params: &str, clock: u32) -> Result<Box<dyn Cable>, String> {
+    let params = super::parse_params(params)?;
+    let selector = Selector {
+        vid: parse_id(&params, "vid", 0x0403)?,
+        pid: parse_id(&params, "pid", 0x6010)?,
+        serial: params.get("serial").copied(),
+        interface: params.get("interface").copied(),
+    };
+
+    #[cfg(unix)]
+    libftd2xx::set_vid_pid(selector.vid, selector.pid).map_err(|e| e.to_string())?;
+
+    let devices = libftd2xx::list_devices().map_err(|e| e.to_string())?;
+    let found: Vec<_> = devices.into_iter().filter(|dev| selector.matches(dev)).collect();
+    let dev = match found.as_slice() {
+        [dev] => dev,
+        [] => return Err("no matching FTDI device found".into()),
+        _ => {
+            let serials: Vec<_> = found.iter().map(|dev| dev.serial_number.as_str()).collect();
+            return Err(format!("several FTDI devices match, add serial= to pick one of: {}",
+                               serials.join(" ")));
+        }
+    };
+
+    let ft = if dev.serial_number.is_empty() {
+        Ftdi::with_description(&dev.description)
+    } else {
+        Ftdi::with_serial_number(&dev.serial_number)
+    }.map_err(|e| if cfg!(target_os = "macos") {
+        // Apple's driver attaches to FTDI chips as serial ports, keeping D2XX out
+        format!("{} (if the system FTDI serial driver has claimed the device, unload it)", e)
+    } else {
+        e.to_string()
+    })?;
+
+    let cable: Box<dyn Cable> = match dev.device_type {
+        DeviceType::FT2232H => {
+            let ft = Ft2232h::try_from(ft).map_err(|e| e.to_string())?;
+            Box::new(Mpsse::new(JtagKeyPins(ft), clock))
+        }
+        DeviceType::FT4232H => {
+            let ft = Ft4232h::try_from(ft).map_err(|e| e.to_string())?;
+            Box::new(Mpsse::new(JtagKeyPins(ft), clock))
+        }
+        DeviceType::FT232H => {
+            let ft = Ft232h::try_from(ft).map_err(|e| e.to_string())?;
+            Box::new(Mpsse::new(JtagKeyPins(ft), clock))
+        }
+        other => return Err(format!("{:?} has no MPSSE", other)),
+    };
+    Ok(cable)
+}
+
+/// Cable specs for every FTDI device D2XX can see
+pub fn list() -> Result<Vec<(String, String)>, String> {
+    let devices = libftd2xx::list_devices().map_err(|e| e.to_string())?;
+    Ok(devices.into_iter().map(|dev| {
+        let mut spec = format!("ftdi:vid={:04x},pid={:04x}", dev.vendor_id, dev.product_id);
+        let sn = &dev.serial_number;
+        match sn.chars().last() {
+            Some(iface @ 'A'..='D') if dev.device_type != DeviceType::FT232H => {
+                spec += &format!(",serial={},interface={}", &sn[..sn.len() - 1], iface);
+            }
+            Some(_) => spec += &format!(",serial={}", sn),
+            None => (),
+        }
+        let busy = if dev.port_open { " (in use)" } else { "" };
+        (spec, format!("{}{}", dev.description, busy))
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(device_type: DeviceType, pid: u16, serial: &str) -> DeviceInfo {
+        DeviceInfo {
+            port_open: false,
+            speed: None,
+            device_type,
+            vendor_id: 0x0403,
+            product_id: pid,
+            serial_number: serial.to_string(),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn selectors_pick_channels_by_serial_and_interface() {
+        let channel_a = device(DeviceType::FT2232H, 0x6010, "FTX1A2BA");
+        let channel_b = device(DeviceType::FT2232H, 0x6010, "FTX1A2BB");
+        let ft232h = device(DeviceType::FT232H, 0x6014, "FT9Z8Y7X");
+        let selector = |pid, serial, interface| Selector { vid: 0x0403, pid, serial, interface };
+
+        let any = selector(0x6010, None, None);
+        assert!(any.matches(&channel_a));
+        assert!(!any.matches(&channel_b));
+        assert!(selector(0x6010, Some("FTX1A2B"), Some("B")).matches(&channel_b));
+        assert!(selector(0x6010, Some("FTX1A2B"), None).matches(&channel_a));
+        assert!(!selector(0x6010, None, None).matches(&ft232h));
+
+        assert!(selector(0x6014, None, None).matches(&ft232h));
+        assert!(selector(0x6014, Some("FT9Z8Y7X"), None).matches(&ft232h));
+        assert!(selector(0x6014, None, Some("A")).matches(&ft232h));
+        assert!(!selector(0x6014, None, Some("B")).matches(&ft232h));
+        assert!(!selector(0x6014, Some("FT9Z8Y7"), None).matches(&ft232h));
+    }
+}
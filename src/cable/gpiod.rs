@@ -0,0 +1,140 @@
+//! Bit-banged JTAG on Linux GPIO lines through the character device, selected with
+//! `--cable gpiod:chip=/dev/gpiochip0,tck=11,tms=25,tdi=10,tdo=9`.  Line numbers are offsets on
+//! the chip.  Each half TCK period is padded with a busy wait, so the clock never runs faster
+//! than requested; it can run slower, since every edge is a separate ioctl.
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use super::{Capabilities, Shifter};
+
+const GPIOHANDLES_MAX: usize = 64;
+const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+
+/// `struct gpiohandle_request` from linux/gpio.h
+#[repr(C)]
+struct HandleRequest {
+    lineoffsets: [u32; GPIOHANDLES_MAX],
+    flags: u32,
+    default_values: [u8; GPIOHANDLES_MAX],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: libc::c_int,
+}
+
+/// `struct gpiohandle_data` from linux/gpio.h
+#[repr(C)]
+struct HandleData {
+    values: [u8; GPIOHANDLES_MAX],
+}
+
+const fn iowr<T>(nr: u64) -> libc::Ioctl {
+    ((3 << 30) | ((std::mem::size_of::<T>() as u64) << 16) | (0xb4 << 8) | nr) as libc::Ioctl
+}
+
+const GPIO_GET_LINEHANDLE_IOCTL: libc::Ioctl = iowr::<HandleRequest>(0x03);
+const GPIOHANDLE_GET_LINE_VALUES_IOCTL: libc::Ioctl = iowr::<HandleData>(0x08);
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: libc::Ioctl = iowr::<HandleData>(0x09);
+
+fn request_lines(chip: &File, offsets: &[u32], flags: u32) -> Result<OwnedFd, String> {
+    let mut req = HandleRequest {
+        lineoffsets: [0; GPIOHANDLES_MAX],
+        flags,
+        default_values: [0; GPIOHANDLES_MAX],
+        consumer_label: [0; 32],
+        lines: offsets.len() as u32,
+        fd: -1,
+    };
+    req.lineoffsets[..offsets.len()].copy_from_slice(offsets);
+    req.consumer_label[..9].copy_from_slice(b"svfplayer");
+    if unsafe { libc::ioctl(chip.as_raw_fd(), GPIO_GET_LINEHANDLE_IOCTL, &mut req) } < 0 {
+        return Err(format!("lines {:?}: {}", offsets, std::io::Error::last_os_error()));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(req.fd) })
+}
+
+pub struct Gpiod {
+    /// TCK, TMS and TDI, in that order
+    outputs: OwnedFd,
+    tdo: OwnedFd,
+    half_period: Duration,
+    last_edge: Instant,
+}
+
+fn half_period(hz: f64) -> Duration {
+    if hz > 0.0 {
+        Duration::from_secs_f64(0.5 / hz)
+    } else {
+        Duration::ZERO
+    }
+}
+
+impl Gpiod {
+    pub fn open(params: &str, clock: u32) -> Result<Self, String> {
+        let params = super::parse_params(params)?;
+        let line = |name: &str| -> Result<u32, String> {
+            let value = params.get(name).ok_or_else(|| format!("gpiod cable needs {}=", name))?;
+            value.parse().map_err(|_| format!("bad {} line {}", name, value))
+        };
+        let chip = params.get("chip").copied().unwrap_or("/dev/gpiochip0");
+        let chip = File::open(chip).map_err(|e| format!("{}: {}", chip, e))?;
+        let outputs = request_lines(&chip, &[line("tck")?, line("tms")?, line("tdi")?], GPIOHANDLE_REQUEST_OUTPUT)?;
+        let tdo = request_lines(&chip, &[line("tdo")?], GPIOHANDLE_REQUEST_INPUT)?;
+        Ok(Gpiod {
+            outputs,
+            tdo,
+            half_period: half_period(clock as f64),
+            last_edge: Instant::now(),
+        })
+    }
+
+    fn set(&mut self, tck: bool, tms: bool, tdi: bool) {
+        let mut data = HandleData { values: [0; GPIOHANDLES_MAX] };
+        data.values[..3].copy_from_slice(&[tck as u8, tms as u8, tdi as u8]);
+        // Hold the previous level for at least half a period
+        while self.last_edge.elapsed() < self.half_period {
+            std::hint::spin_loop();
+        }
+        if unsafe { libc::ioctl(self.outputs.as_raw_fd(), GPIOHANDLE_SET_LINE_VALUES_IOCTL, &mut data) } < 0 {
+            panic!("gpiod set: {}", std::io::Error::last_os_error());
+        }
+        self.last_edge = Instant::now();
+    }
+
+    fn tdo(&mut self) -> bool {
+        let mut data = HandleData { values: [0; GPIOHANDLES_MAX] };
+        if unsafe { libc::ioctl(self.tdo.as_raw_fd(), GPIOHANDLE_GET_LINE_VALUES_IOCTL, &mut data) } < 0 {
+            panic!("gpiod get: {}", std::io::Error::last_os_error());
+        }
+        data.values[0] != 0
+    }
+}
+
+impl Shifter for Gpiod {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities { read_skip: true, ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], read: bool) -> Vec<bool> {
+        let mut tdo = vec![false; tms.len()];
+        for (i, (tms, tdi)) in tms.iter().zip(tdi).enumerate() {
+            self.set(false, *tms, *tdi);
+            if read {
+                // The target updates TDO on the falling edge, so it is stable until TCK rises
+                tdo[i] = self.tdo();
+            }
+            self.set(true, *tms, *tdi);
+        }
+        // Leave TCK low between calls
+        if let (Some(tms), Some(tdi)) = (tms.last(), tdi.last()) {
+            self.set(false, *tms, *tdi);
+        }
+        tdo
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.half_period = half_period(hz);
+        Some(hz)
+    }
+}
@@ -0,0 +1,86 @@
+//! SEGGER J-Link probes through the jaylink crate, selected with `--cable jlink` or
+//! `jlink:SERIAL` when more than one is attached.
+use jaylink::{Interface, JayLink, SpeedConfig};
+
+use super::{Capabilities, Shifter};
+
+/// Largest JTAG transfer the probe accepts in one command
+const MAX_BITS: usize = 65535;
+
+pub struct JLink {
+    probe: JayLink,
+}
+
+/// Every attached J-Link, as a cable spec and description
+pub fn list() -> Vec<(String, String)> {
+    // jaylink uses the global libusb context, which panics if libusb can't start
+    if rusb::Context::new().is_err() {
+        return vec![];
+    }
+    let Ok(devices) = jaylink::scan_usb() else {
+        return vec![];
+    };
+    let mut found = vec![];
+    for device in devices {
+        let Ok(probe) = device.open() else {
+            continue;
+        };
+        let hardware = probe.read_hardware_version().map(|v| v.to_string()).unwrap_or_default();
+        let firmware = probe.read_firmware_version().unwrap_or_default();
+        found.push((format!("jlink:{}", probe.serial_string()),
+                    format!("{} hardware {}, firmware {}", probe.product_string(), hardware, firmware.trim())));
+    }
+    found
+}
+
+/// The probe takes its speed in whole kHz
+fn khz(hz: f64) -> u16 {
+    (hz / 1000.0).clamp(1.0, 0xfffe as f64) as u16
+}
+
+impl JLink {
+    pub fn open(serial: &str, clock: u32) -> Result<Self, String> {
+        if rusb::Context::new().is_err() {
+            return Err("USB is not available".into());
+        }
+        let serial = (!serial.is_empty()).then_some(serial);
+        let mut probe = JayLink::open_by_serial(serial).map_err(|e| e.to_string())?;
+        probe.select_interface(Interface::Jtag).map_err(|e| e.to_string())?;
+        if clock != 0 {
+            probe.set_speed(SpeedConfig::khz(khz(clock as f64)).unwrap()).map_err(|e| e.to_string())?;
+        }
+        // Release both resets in case a previous session left them asserted
+        probe.set_trst(true).map_err(|e| e.to_string())?;
+        probe.set_reset(true).map_err(|e| e.to_string())?;
+        Ok(JLink { probe })
+    }
+}
+
+impl Shifter for JLink {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities { max_transfer: Some(MAX_BITS / 8), trst: true, srst: true, ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], _read: bool) -> Vec<bool> {
+        let mut tdo = Vec::with_capacity(tms.len());
+        for (tms, tdi) in tms.chunks(MAX_BITS).zip(tdi.chunks(MAX_BITS)) {
+            let bits = self.probe.jtag_io(tms.iter().copied(), tdi.iter().copied()).expect("jlink io");
+            tdo.extend(bits);
+        }
+        tdo
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        let khz = khz(hz);
+        self.probe.set_speed(SpeedConfig::khz(khz).unwrap()).expect("jlink speed");
+        Some(khz as f64 * 1000.0)
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.probe.set_trst(!asserted).is_ok()
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.probe.set_reset(!asserted).is_ok()
+    }
+}
@@ -0,0 +1,119 @@
+//! Client for the jtag_vpi socket protocol used by OpenOCD to talk to HDL testbenches, selected
+//! with `--cable jtag_vpi:host:port`.  Commands are the raw `struct vpi_cmd` from OpenOCD's
+//! jtag_vpi driver; scans are answered by the simulator echoing the struct with `buffer_in`
+//! filled in.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use jtag_taps::cable::Cable;
+
+use super::Adapter;
+
+pub const DEFAULT_PORT: u16 = 5555;
+
+const XFERT_MAX_SIZE: usize = 512;
+const CMD_SIZE: usize = 4 + 2 * XFERT_MAX_SIZE + 4 + 4;
+
+const CMD_TMS_SEQ: u32 = 1;
+const CMD_SCAN_CHAIN: u32 = 2;
+const CMD_SCAN_CHAIN_FLIP_TMS: u32 = 3;
+const CMD_STOP_SIMU: u32 = 4;
+
+pub struct JtagVpi {
+    stream: TcpStream,
+}
+
+impl JtagVpi {
+    pub fn connect(addr: &str) -> Result<Self, String> {
+        let addr = if addr.contains(':') {
+            addr.to_string()
+        } else {
+            format!("{}:{}", addr, DEFAULT_PORT)
+        };
+        let stream = TcpStream::connect(&addr).map_err(|e| format!("{}: {}", addr, e))?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        Ok(JtagVpi { stream })
+    }
+
+    fn try_send(&mut self, cmd: u32, data: &[u8], nb_bits: usize) -> std::io::Result<()> {
+        let mut buf = [0; CMD_SIZE];
+        buf[..4].copy_from_slice(&cmd.to_le_bytes());
+        buf[4..4 + data.len()].copy_from_slice(data);
+        let tail = 4 + 2 * XFERT_MAX_SIZE;
+        buf[tail..tail + 4].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        buf[tail + 4..].copy_from_slice(&(nb_bits as u32).to_le_bytes());
+        self.stream.write_all(&buf)
+    }
+
+    fn send(&mut self, cmd: u32, data: &[u8], nb_bits: usize) {
+        self.try_send(cmd, data, nb_bits).expect("jtag_vpi write");
+    }
+
+    fn receive(&mut self) -> Vec<u8> {
+        let mut buf = [0; CMD_SIZE];
+        self.stream.read_exact(&mut buf).expect("jtag_vpi read");
+        buf[4 + XFERT_MAX_SIZE..4 + 2 * XFERT_MAX_SIZE].to_vec()
+    }
+
+    /// Scan `nbits` of `data` with TMS low, raising TMS on the very last bit if `exit`
+    fn scan(&mut self, data: &[u8], nbits: usize, exit: bool) -> Vec<u8> {
+        let mut tdo = Vec::with_capacity(data.len());
+        let chunk_bits = XFERT_MAX_SIZE * 8;
+        let mut done = 0;
+        while done < nbits {
+            let bits = (nbits - done).min(chunk_bits);
+            let chunk = &data[done / 8..(done + bits).div_ceil(8)];
+            let last = done + bits == nbits;
+            let cmd = if last && exit { CMD_SCAN_CHAIN_FLIP_TMS } else { CMD_SCAN_CHAIN };
+            self.send(cmd, chunk, bits);
+            tdo.extend_from_slice(&self.receive()[..chunk.len()]);
+            done += bits;
+        }
+        tdo
+    }
+
+    fn shift_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        let nbits = (data.len() - 1) * 8 + bits as usize;
+        let tdo = self.scan(data, nbits, pause_after);
+        if pause_after {
+            self.change_mode(&[0], false);
+        }
+        tdo
+    }
+}
+
+impl Cable for JtagVpi {
+    fn change_mode(&mut self, tms: &[usize], _tdo: bool) {
+        for chunk in tms.chunks(XFERT_MAX_SIZE * 8) {
+            let mut buf = vec![0; chunk.len().div_ceil(8)];
+            for (i, x) in chunk.iter().enumerate() {
+                if *x != 0 {
+                    buf[i / 8] |= 1 << (i % 8);
+                }
+            }
+            self.send(CMD_TMS_SEQ, &buf, chunk.len());
+        }
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        let ones = vec![0xff; bits.div_ceil(8)];
+        self.scan(&ones, bits, false)
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        self.shift_data(data, bits, pause_after);
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        self.shift_data(data, bits, pause_after)
+    }
+}
+
+impl Adapter for JtagVpi {}
+
+impl Drop for JtagVpi {
+    /// Like OpenOCD, end the simulation when the cable is closed
+    fn drop(&mut self) {
+        let _ = self.try_send(CMD_STOP_SIMU, &[], 0);
+    }
+}
@@ -0,0 +1,393 @@
+//! Cable selection.  A spec names a `Backend`, either one built into this crate, which include
+//! the jtag_taps cables through `jtag_taps::cable::new_from_string`, or one another crate added
+//! with `register`.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use jtag_taps::cable::Cable;
+use rusb::UsbContext;
+
+use crate::color;
+
+pub mod cmsis_dap;
+pub mod ftdi;
+#[cfg(target_os = "linux")]
+pub mod gpiod;
+pub mod jlink;
+pub mod jtag_vpi;
+#[cfg(feature = "probe-rs")]
+pub mod probe_rs;
+pub mod remote_bitbang;
+#[cfg(test)]
+pub mod script;
+pub mod session;
+pub mod sim;
+pub mod xvc;
+
+/// What a cable can do, so the player works within it rather than finding out by trying.  The
+/// default claims nothing: no known TCK limit or transfer size, no TRST or SRST line, and TDO
+/// read back whether it is wanted or not.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Capabilities {
+    /// Fastest TCK the cable runs
+    pub max_hz: Option<f64>,
+    /// Most bytes of a scan worth handing the cable in one call
+    pub max_transfer: Option<usize>,
+    pub trst: bool,
+    pub srst: bool,
+    /// Whether a write that doesn't need TDO skips reading it back, making it cheaper than a read
+    pub read_skip: bool,
+}
+
+/// Controls beyond what the jtag_taps `Cable` trait offers.  Every method has a default so a
+/// backend only implements what its hardware supports.
+pub trait Adapter: Cable {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Change TCK, returning the frequency now in effect, or None if this cable's clock is fixed
+    /// once it is opened
+    fn set_frequency(&mut self, _hz: f64) -> Option<f64> {
+        None
+    }
+
+    /// Drive nTRST, low when `asserted`.  Returns false if the cable has no TRST line.
+    fn set_trst(&mut self, _asserted: bool) -> bool {
+        false
+    }
+
+    /// Drive nSRST, the target's system reset, low when `asserted`.  Returns false if the cable
+    /// has no SRST line.
+    fn set_srst(&mut self, _asserted: bool) -> bool {
+        false
+    }
+
+    /// Return once everything issued so far has reached the hardware
+    fn flush(&mut self) {}
+}
+
+/// Owns the cable the player drives.  `JtagSM` only needs the `Cable` side, the rest of the
+/// `Adapter` controls are reached through `.0`.
+pub struct AdapterBox(pub Box<dyn Adapter>);
+
+impl std::ops::Deref for AdapterBox {
+    type Target = dyn Cable;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl std::ops::DerefMut for AdapterBox {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.0
+    }
+}
+
+/// A cable from jtag_taps, which has no controls of its own
+pub struct Stock(pub Box<dyn Cable>);
+
+impl Cable for Stock {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        self.0.change_mode(tms, tdo)
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        self.0.read_data(bits)
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        self.0.write_data(data, bits, pause_after)
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        self.0.read_write_data(data, bits, pause_after)
+    }
+}
+
+impl Adapter for Stock {}
+
+/// Backends that can clock an arbitrary sequence of TMS/TDI pairs.  `ShiftCable` builds the
+/// `Cable` operations on top of this.  When `read` is false the TDO values aren't needed and
+/// may be left as zeros.
+pub trait Shifter {
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], read: bool) -> Vec<bool>;
+
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn set_frequency(&mut self, _hz: f64) -> Option<f64> {
+        None
+    }
+
+    fn set_trst(&mut self, _asserted: bool) -> bool {
+        false
+    }
+
+    fn set_srst(&mut self, _asserted: bool) -> bool {
+        false
+    }
+}
+
+pub struct ShiftCable<T>(pub T);
+
+/// Pack bits LSB first
+pub fn pack(bits: &[bool]) -> Vec<u8> {
+    let mut out = vec![0; bits.len().div_ceil(8)];
+    for (i, bit) in bits.iter().enumerate() {
+        if *bit {
+            out[i / 8] |= 1 << (i % 8);
+        }
+    }
+    out
+}
+
+pub fn bit(data: &[u8], i: usize) -> bool {
+    data[i / 8] & (1 << (i % 8)) != 0
+}
+
+impl<T: Shifter> ShiftCable<T> {
+    /// Shift `data` through Shift-xR, optionally leaving through Exit1 to Pause
+    fn shift_data(&mut self, data: &[u8], bits: u8, pause_after: bool, read: bool) -> Vec<u8> {
+        let nbits = (data.len() - 1) * 8 + bits as usize;
+        let mut tms = vec![false; nbits];
+        let mut tdi: Vec<bool> = (0..nbits).map(|i| bit(data, i)).collect();
+        if pause_after {
+            tms[nbits - 1] = true;
+            tms.push(false);
+            tdi.push(false);
+        }
+        let tdo = self.0.shift(&tms, &tdi, read);
+        if read {
+            pack(&tdo[..nbits])
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl<T: Shifter> Cable for ShiftCable<T> {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        let tms: Vec<bool> = tms.iter().map(|x| *x != 0).collect();
+        let tdi = vec![tdo; tms.len()];
+        self.0.shift(&tms, &tdi, false);
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        let tms = vec![false; bits];
+        let tdi = vec![true; bits];
+        pack(&self.0.shift(&tms, &tdi, true))
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        self.shift_data(data, bits, pause_after, false);
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        self.shift_data(data, bits, pause_after, true)
+    }
+}
+
+impl<T: Shifter> Adapter for ShiftCable<T> {
+    fn capabilities(&mut self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.0.set_frequency(hz)
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.0.set_trst(asserted)
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.0.set_srst(asserted)
+    }
+}
+
+/// Parse `key=value,key=value` cable parameters
+pub fn parse_params(params: &str) -> Result<HashMap<&str, &str>, String> {
+    let mut map = HashMap::new();
+    for param in params.split(',').filter(|p| !p.is_empty()) {
+        let (key, value) = param.split_once('=')
+            .ok_or_else(|| format!("cable parameter {} should look like key=value", param))?;
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
+/// Parse a hexadecimal USB vendor or product ID parameter
+pub fn parse_id(params: &HashMap<&str, &str>, key: &str, default: u16) -> Result<u16, String> {
+    match params.get(key) {
+        Some(v) => u16::from_str_radix(v.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("bad {} {}", key, v)),
+        None => Ok(default),
+    }
+}
+
+/// A kind of cable, selected with `--cable NAME` or `--cable NAME:PARAMS`.  The backends in this
+/// crate are built in; another crate makes its own selectable with `register`.
+pub trait Backend: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Open the cable with TCK at `clock` Hz, `params` being whatever followed the colon
+    fn open(&self, params: &str, clock: u32) -> Result<Box<dyn Adapter>, String>;
+
+    /// Attached adapters this backend could open, each with a spec that selects it and a
+    /// description
+    fn detect(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+}
+
+type Open = fn(&str, u32) -> Result<Box<dyn Adapter>, String>;
+
+struct Builtin {
+    name: &'static str,
+    open: Open,
+}
+
+impl Backend for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn open(&self, params: &str, clock: u32) -> Result<Box<dyn Adapter>, String> {
+        (self.open)(params, clock)
+    }
+}
+
+fn builtins() -> Vec<Builtin> {
+    let mut builtins = vec![
+        Builtin { name: "cmsis-dap", open: |params, clock| Ok(Box::new(ShiftCable(cmsis_dap::CmsisDap::open(params, clock)?))) },
+        Builtin { name: "ftdi", open: |params, clock| Ok(Box::new(Stock(ftdi::open(params, clock)?))) },
+        Builtin { name: "xvc", open: |params, clock| Ok(Box::new(ShiftCable(xvc::Xvc::connect(params, clock)?))) },
+        Builtin { name: "jlink", open: |params, clock| Ok(Box::new(ShiftCable(jlink::JLink::open(params, clock)?))) },
+        Builtin { name: "jtag_vpi", open: |params, _| Ok(Box::new(jtag_vpi::JtagVpi::connect(params)?)) },
+        Builtin { name: "remote_bitbang", open: |params, _| Ok(Box::new(ShiftCable(remote_bitbang::RemoteBitbang::connect(params)?))) },
+        Builtin { name: "sim", open: |params, _| Ok(Box::new(ShiftCable(sim::Sim::open(params)?))) },
+        Builtin { name: "replay", open: |params, _| Ok(Box::new(session::Replay::open(params)?)) },
+        // The jtag_taps cables this crate has no backend of its own for
+        Builtin { name: "jtagkey", open: |_, clock| Ok(Box::new(Stock(jtag_taps::cable::new_from_string("jtagkey", clock)?))) },
+        Builtin { name: "ef3", open: |_, clock| Ok(Box::new(Stock(jtag_taps::cable::new_from_string("ef3", clock)?))) },
+        Builtin { name: "usbblaster", open: |_, clock| Ok(Box::new(Stock(jtag_taps::cable::new_from_string("usbblaster", clock)?))) },
+    ];
+    #[cfg(target_os = "linux")]
+    builtins.push(Builtin { name: "gpiod", open: |params, clock| Ok(Box::new(ShiftCable(gpiod::Gpiod::open(params, clock)?))) });
+    #[cfg(feature = "probe-rs")]
+    builtins.push(Builtin { name: "probe-rs", open: |params, clock| Ok(Box::new(ShiftCable(probe_rs::ProbeRs::open(params, clock)?))) });
+    builtins
+}
+
+/// Backends added with `register`, the latest last
+static REGISTERED: Mutex<Vec<Arc<dyn Backend>>> = Mutex::new(Vec::new());
+
+/// Make `backend` selectable by its name, ahead of any backend already of that name
+pub fn register(backend: impl Backend + 'static) {
+    REGISTERED.lock().unwrap().push(Arc::new(backend));
+}
+
+/// Every backend, in the order a name is looked up: the registered ones latest first, then the
+/// built-in ones
+pub fn backends() -> Vec<Arc<dyn Backend>> {
+    let mut backends: Vec<Arc<dyn Backend>> = REGISTERED.lock().unwrap().iter().rev().cloned().collect();
+    backends.extend(builtins().into_iter().map(|builtin| Arc::new(builtin) as Arc<dyn Backend>));
+    backends
+}
+
+pub fn open(spec: &str, clock: u32) -> Result<Box<dyn Adapter>, String> {
+    let (kind, params) = spec.split_once(':').unwrap_or((spec, ""));
+    let backends = backends();
+    match backends.iter().find(|backend| backend.name() == kind) {
+        Some(backend) => backend.open(params, clock),
+        None => {
+            let mut names: Vec<_> = backends.iter().map(|backend| backend.name()).collect();
+            names.sort();
+            names.dedup();
+            Err(format!("unknown cable type: {}, expected one of {}", kind, names.join(", ")))
+        }
+    }
+}
+
+/// USB devices that jtag_taps can drive, but only by opening the first one attached
+const KNOWN_USB: &[(u16, u16, &str)] = &[
+    (0x09fb, 0x6001, "usbblaster"),
+];
+
+/// Every attached adapter together with a cable spec that selects it.  Problems enumerating a
+/// bus are reported as warnings and otherwise skipped.
+pub fn detect() -> Vec<(String, String)> {
+    let mut found = match ftdi::list() {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("{} unable to list FTDI devices: {}", color::warning(), e);
+            vec![]
+        }
+    };
+    found.extend(jlink::list());
+    found.extend(cmsis_dap::list());
+    #[cfg(feature = "probe-rs")]
+    found.extend(probe_rs::list());
+    for backend in REGISTERED.lock().unwrap().iter() {
+        found.extend(backend.detect());
+    }
+
+    // An explicit context reports a missing USB stack as an error instead of panicking
+    let devices = match rusb::Context::new().and_then(|ctx| ctx.devices()) {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("{} unable to list USB devices: {}", color::warning(), e);
+            return found;
+        }
+    };
+    for device in devices.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        for (vid, pid, name) in KNOWN_USB {
+            if desc.vendor_id() == *vid && desc.product_id() == *pid {
+                found.push((name.to_string(), format!("{:04x}:{:04x} on bus {} address {}", vid, pid,
+                                                      device.bus_number(), device.address())));
+            }
+        }
+    }
+    found
+}
+
+/// Print every attached adapter together with a cable spec that selects it
+pub fn list() {
+    for (spec, description) in detect() {
+        println!("{:<50} {}", spec, description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::script::{Call, Script};
+    use super::*;
+
+    struct Scripted;
+
+    impl Backend for Scripted {
+        fn name(&self) -> &str {
+            "scripted"
+        }
+
+        fn open(&self, params: &str, _clock: u32) -> Result<Box<dyn Adapter>, String> {
+            let hz = params.parse().map_err(|_| format!("bad frequency {}", params))?;
+            Ok(Box::new(Script::new(vec![Call::Frequency(hz)])))
+        }
+    }
+
+    #[test]
+    fn registered_backends_are_opened_by_name() {
+        assert!(open("scripted:1000", 1000).err().unwrap().contains("expected one of cmsis-dap, ef3, ftdi"));
+        register(Scripted);
+        let mut cable = open("scripted:1000", 1000).unwrap();
+        cable.set_frequency(1000.0);
+        assert!(open("scripted:fast", 1000).err().unwrap().contains("bad frequency"));
+    }
+}
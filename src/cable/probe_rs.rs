@@ -0,0 +1,84 @@
+//! Any probe-rs probe with raw JTAG access, selected with `--cable probe-rs` or
+//! `probe-rs:VID:PID[:SERIAL]` using the same selector syntax as the probe-rs tools.  Built with
+//! the `probe-rs` feature.
+use bitvec::vec::BitVec;
+use ::probe_rs::probe::list::Lister;
+use ::probe_rs::probe::{DebugProbeSelector, JtagSequence, Probe, WireProtocol};
+
+use super::{Capabilities, Shifter};
+
+pub struct ProbeRs {
+    probe: Probe,
+}
+
+/// Every probe probe-rs can see, as a cable spec and description
+pub fn list() -> Vec<(String, String)> {
+    Lister::new().list_all().iter()
+        .map(|info| (format!("probe-rs:{}", DebugProbeSelector::from(info)), info.identifier.clone()))
+        .collect()
+}
+
+impl ProbeRs {
+    pub fn open(selector: &str, clock: u32) -> Result<Self, String> {
+        let lister = Lister::new();
+        let probe = if selector.is_empty() {
+            match lister.list_all().as_slice() {
+                [info] => info.open(),
+                [] => return Err("no probe-rs probe found".into()),
+                _ => return Err("several probes found, select one with probe-rs:VID:PID:SERIAL".into()),
+            }
+        } else {
+            let selector: DebugProbeSelector = selector.parse().map_err(|e| format!("{}", e))?;
+            lister.open(selector)
+        }.map_err(|e| e.to_string())?;
+        Self::new(probe, clock)
+    }
+
+    /// Take over a probe that was already opened and configured through probe-rs.  The probe is
+    /// switched to JTAG and attached without a target, after which it is only driven with raw
+    /// sequences.
+    pub fn new(mut probe: Probe, clock: u32) -> Result<Self, String> {
+        probe.select_protocol(WireProtocol::Jtag).map_err(|e| e.to_string())?;
+        if clock != 0 {
+            probe.set_speed(clock.div_ceil(1000)).map_err(|e| e.to_string())?;
+        }
+        probe.attach_to_unspecified().map_err(|e| e.to_string())?;
+        if probe.try_as_jtag_probe().is_none() {
+            return Err(format!("{} has no raw JTAG access", probe.get_name()));
+        }
+        Ok(ProbeRs { probe })
+    }
+}
+
+impl Shifter for ProbeRs {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities { read_skip: true, ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], read: bool) -> Vec<bool> {
+        let jtag = self.probe.try_as_jtag_probe().unwrap();
+        let mut tdo = Vec::with_capacity(tms.len());
+        let mut i = 0;
+        while i < tms.len() {
+            // Each sequence holds TMS constant
+            let mut n = 1;
+            while i + n < tms.len() && tms[i + n] == tms[i] {
+                n += 1;
+            }
+            let sequence = JtagSequence {
+                tdo_capture: read,
+                tms: tms[i],
+                data: tdi[i..i + n].iter().collect::<BitVec>(),
+            };
+            let captured = jtag.shift_raw_sequence(sequence).expect("probe-rs shift");
+            if read {
+                tdo.extend(captured.iter().by_vals().take(n));
+            }
+            i += n;
+        }
+        if !read {
+            tdo.resize(tms.len(), false);
+        }
+        tdo
+    }
+}
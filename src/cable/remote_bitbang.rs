@@ -0,0 +1,66 @@
+//! OpenOCD remote_bitbang client, selected with `--cable remote_bitbang:host:port`.  Each cable
+//! call is sent as one burst of ASCII commands, with an `R` before every rising edge whose TDO
+//! is wanted.
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+
+use super::{Capabilities, Shifter};
+
+pub struct RemoteBitbang {
+    stream: BufReader<TcpStream>,
+}
+
+impl RemoteBitbang {
+    pub fn connect(addr: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("{}: {}", addr, e))?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        Ok(RemoteBitbang {
+            stream: BufReader::new(stream),
+        })
+    }
+}
+
+fn pins(tck: bool, tms: bool, tdi: bool) -> u8 {
+    b'0' + ((tck as u8) << 2 | (tms as u8) << 1 | tdi as u8)
+}
+
+impl Shifter for RemoteBitbang {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities { srst: true, read_skip: true, ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], read: bool) -> Vec<bool> {
+        let mut req = Vec::with_capacity(tms.len() * 3 + 1);
+        for (tms, tdi) in tms.iter().zip(tdi) {
+            req.push(pins(false, *tms, *tdi));
+            if read {
+                req.push(b'R');
+            }
+            req.push(pins(true, *tms, *tdi));
+        }
+        // Leave TCK low between calls
+        if let (Some(tms), Some(tdi)) = (tms.last(), tdi.last()) {
+            req.push(pins(false, *tms, *tdi));
+        }
+        self.stream.get_mut().write_all(&req).expect("remote_bitbang write");
+
+        if !read {
+            return vec![false; tms.len()];
+        }
+        let mut resp = vec![0; tms.len()];
+        self.stream.read_exact(&mut resp).expect("remote_bitbang read");
+        resp.iter().map(|b| *b == b'1').collect()
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        // 's' asserts SRST and 'r' releases it, both with TRST released
+        let command = if asserted { b"s" } else { b"r" };
+        self.stream.get_mut().write_all(command).is_ok()
+    }
+}
+
+impl Drop for RemoteBitbang {
+    fn drop(&mut self) {
+        let _ = self.stream.get_mut().write_all(b"Q");
+    }
+}
@@ -0,0 +1,258 @@
+//! A cable for tests that plays back a script of the exact calls the player is expected to make,
+//! failing on the first that differs, and answering reads with the TDO the script gives.
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+use jtag_taps::cable::Cable;
+use jtag_taps::statemachine::JtagSM;
+
+use super::{Adapter, AdapterBox, Capabilities};
+use crate::{run_svf, Svf};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call {
+    /// TMS sequence, and the TDI held during it
+    ChangeMode(Vec<usize>, bool),
+    Read { bits: usize, tdo: Vec<u8> },
+    Write { data: Vec<u8>, bits: u8, pause_after: bool },
+    ReadWrite { data: Vec<u8>, bits: u8, pause_after: bool, tdo: Vec<u8> },
+    Frequency(f64),
+    Trst(bool),
+    Srst(bool),
+}
+
+/// TMS path from Run-Test/Idle to Shift-IR
+pub const IDLE_TO_SHIFT_IR: [usize; 4] = [1, 1, 0, 0];
+/// TMS path from Run-Test/Idle to Shift-DR
+pub const IDLE_TO_SHIFT_DR: [usize; 3] = [1, 0, 0];
+/// TMS path from Pause-IR or Pause-DR back to Run-Test/Idle
+pub const PAUSE_TO_IDLE: [usize; 3] = [1, 1, 0];
+
+/// The scripted cable has every control and reads TDO only when asked
+const CAPABLE: Capabilities = Capabilities { max_hz: None, max_transfer: None, trst: true, srst: true, read_skip: true };
+
+pub struct Script {
+    calls: VecDeque<Call>,
+    /// Calls made so far, to show where things went wrong
+    made: usize,
+    pub capabilities: Capabilities,
+}
+
+impl Script {
+    pub fn new(calls: Vec<Call>) -> Script {
+        Script { calls: calls.into(), made: 0, capabilities: CAPABLE }
+    }
+
+    /// Take the next call, which has to be `call` apart from the TDO it answers with
+    fn expect(&mut self, call: Call) -> Vec<u8> {
+        self.made += 1;
+        let expected = self.calls.pop_front()
+            .unwrap_or_else(|| panic!("call {} wasn't in the script: {:?}", self.made, call));
+        let (expected, tdo) = match expected {
+            Call::Read { bits, tdo } => (Call::Read { bits, tdo: vec![] }, tdo),
+            Call::ReadWrite { data, bits, pause_after, tdo } => {
+                (Call::ReadWrite { data, bits, pause_after, tdo: vec![] }, tdo)
+            }
+            call => (call, vec![]),
+        };
+        assert_eq!(call, expected, "call {} differs from the script", self.made);
+        tdo
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            assert!(self.calls.is_empty(), "the player stopped after {} calls, short of {:?}", self.made, self.calls);
+        }
+    }
+}
+
+impl Cable for Script {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        self.expect(Call::ChangeMode(tms.to_vec(), tdo));
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        self.expect(Call::Read { bits, tdo: vec![] })
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        self.expect(Call::Write { data: data.to_vec(), bits, pause_after });
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        self.expect(Call::ReadWrite { data: data.to_vec(), bits, pause_after, tdo: vec![] })
+    }
+}
+
+impl Adapter for Script {
+    fn capabilities(&mut self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.expect(Call::Frequency(hz));
+        Some(hz)
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.expect(Call::Trst(asserted));
+        true
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.expect(Call::Srst(asserted));
+        true
+    }
+}
+
+/// Play `svf` with `player` on a scripted cable, which after the reset `JtagSM` starts with has
+/// to see exactly `calls`
+pub fn play_with(player: &mut Svf, svf: &str, calls: Vec<Call>) {
+    play_on(player, svf, CAPABLE, calls)
+}
+
+/// `play_with` on a cable that reports `capabilities`
+pub fn play_on(player: &mut Svf, svf: &str, capabilities: Capabilities, calls: Vec<Call>) {
+    let script = [vec![Call::ChangeMode(vec![1, 1, 1, 1, 1, 0], true)], calls].concat();
+    let mut cable = Script::new(script);
+    cable.capabilities = capabilities;
+    let mut jtag = JtagSM::new(AdapterBox(Box::new(cable)));
+    let mut input: &mut dyn BufRead = &mut svf.as_bytes();
+    run_svf(&mut jtag, player, &mut input, None).unwrap();
+}
+
+pub fn play(svf: &str, calls: Vec<Call>) {
+    play_with(&mut Svf::new(), svf, calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Call::*;
+    use super::*;
+
+    #[test]
+    fn sir_without_tdo_only_writes() {
+        play("SIR 4 TDI (e);\n", vec![
+            ChangeMode([&[0], &IDLE_TO_SHIFT_IR[..]].concat(), true),
+            Write { data: vec![0x0e], bits: 4, pause_after: true },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    fn sdr_with_tdo_reads_and_checks() {
+        play("SDR 12 TDI (abc) TDO (123) MASK (0ff);\nSDR 12 TDO (023);\n", vec![
+            ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true),
+            ReadWrite { data: vec![0xbc, 0x0a], bits: 4, pause_after: true, tdo: vec![0x23, 0x0f] },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+            ChangeMode(IDLE_TO_SHIFT_DR.to_vec(), true),
+            ReadWrite { data: vec![0xbc, 0x0a], bits: 4, pause_after: true, tdo: vec![0x23, 0x00] },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tdo_mismatch_fails() {
+        play("SDR 8 TDI (00) TDO (5a);\n", vec![
+            ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true),
+            ReadWrite { data: vec![0x00], bits: 8, pause_after: true, tdo: vec![0x5b] },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    fn tdo_mismatch_can_only_warn() {
+        let mut player = Svf::new();
+        player.on_mismatch = crate::config::OnMismatch::Warn;
+        play_with(&mut player, "SDR 8 TDI (00) TDO (5a);\n", vec![
+            ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true),
+            ReadWrite { data: vec![0x00], bits: 8, pause_after: true, tdo: vec![0x5b] },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    fn end_states_runtest_and_frequency() {
+        play("FREQUENCY 1E6 HZ;\nENDDR DRPAUSE;\nSDR 8 TDI (81);\nRUNTEST IDLE 5 TCK ENDSTATE IDLE;\nSTATE RESET;\n", vec![
+            Frequency(1e6),
+            ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true),
+            Write { data: vec![0x81], bits: 8, pause_after: true },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+            ChangeMode(vec![0; 5], true),
+            ChangeMode(vec![1, 1, 1], true),
+        ]);
+    }
+
+    #[test]
+    fn scans_from_pause_update_and_capture_before_shifting() {
+        // Exit2, Update, Select-DR, (Select-IR,) Capture, Exit1, Pause; then Exit2 into Shift
+        let to_dr = vec![ChangeMode(vec![1, 1, 1, 0, 1, 0, 1, 0], true)];
+        let to_ir = vec![ChangeMode(vec![1, 1, 1, 1, 0, 1, 0, 1, 0], true)];
+        let write = |data: u8, bits: u8| Write { data: vec![data], bits, pause_after: true };
+        play("ENDDR DRPAUSE;\nSDR 8 TDI (81);\nSDR 8 TDI (42);\nENDIR IRPAUSE;\nSIR 4 TDI (e);\n\
+              ENDDR IDLE;\nSDR 8 TDI (24);\n", [
+            vec![ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true), write(0x81, 8)],
+            to_dr.clone(),
+            vec![write(0x42, 8)],
+            to_ir,
+            vec![write(0x0e, 4)],
+            to_dr,
+            vec![write(0x24, 8), ChangeMode(PAUSE_TO_IDLE.to_vec(), true)],
+        ].concat());
+    }
+
+    #[test]
+    fn scans_and_trst_keep_within_the_cable_capabilities() {
+        let mut player = Svf::new();
+        player.ignore_unsupported = true;
+        let capabilities = Capabilities { max_transfer: Some(1), ..Capabilities::default() };
+        play_on(&mut player, "TRST ON;\nSDR 12 TDI (abc);\nTRST OFF;\n", capabilities, vec![
+            ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true),
+            Write { data: vec![0xbc], bits: 8, pause_after: false },
+            Write { data: vec![0x0a], bits: 4, pause_after: true },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    fn state_paths_are_clocked_as_written() {
+        play("STATE IDLE DRSELECT DRCAPTURE DREXIT1 DRUPDATE IDLE;\nSTATE DRPAUSE;\n", vec![
+            ChangeMode(vec![0, 1, 0, 1, 1, 0], true),
+            ChangeMode(vec![1, 0, 1, 0], true),
+        ]);
+    }
+
+    #[test]
+    fn prologue_carries_the_settings_in_effect() {
+        let mut player = Svf::new();
+        play_with(&mut player, "FREQUENCY 1E6 HZ;\nENDDR DRPAUSE;\nRUNTEST DRPAUSE 0 TCK;\n", vec![
+            Frequency(1e6),
+            ChangeMode(vec![0, 1, 0, 1, 0], true),
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+        assert_eq!(player.prologue(), "FREQUENCY 1E6 HZ;\nHIR 0;\nHDR 0;\nTIR 0;\nTDR 0;\nENDIR IDLE;\n\
+                                       ENDDR DRPAUSE;\nRUNTEST DRPAUSE 0 TCK ENDSTATE IDLE;\n");
+    }
+
+    #[test]
+    fn empty_scans_only_move_to_the_end_state() {
+        play("SIR 0;\nSDR 0;\nSDR 8 TDI (81);\n", vec![
+            ChangeMode(vec![0], true),
+            ChangeMode(IDLE_TO_SHIFT_DR.to_vec(), true),
+            Write { data: vec![0x81], bits: 8, pause_after: true },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    fn trst_is_driven() {
+        play("TRST ON;\nTRST OFF;\n", vec![
+            Trst(true),
+            ChangeMode(vec![1, 1, 1, 1, 1, 0], true),
+            Trst(false),
+        ]);
+    }
+}
@@ -0,0 +1,305 @@
+//! `--record-session` and `--replay-session`: every call the player makes into the cable, with
+//! what the cable answered and when, one JSON object per line after a header line describing
+//! the cable.  Replaying a recording answers the player the way the cable did, so a failure in
+//! the field can be played again at a desk, without the board, as often as it takes.  A replay
+//! fails at the first call that isn't the one recorded.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::time::Instant;
+
+use jtag_taps::cable::Cable;
+use serde::{Deserialize, Serialize};
+
+use super::{Adapter, Capabilities};
+
+/// The first line of a recording
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub cable: String,
+    pub baud: u32,
+    pub capabilities: Capabilities,
+    /// Seed of the random don't-care bits, which a replay has to drive again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// A call into the cable and what it returned, vectors in hex in the order the cable shifts
+/// their bytes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "call", rename_all = "snake_case")]
+pub enum Call {
+    ChangeMode { tms: String, tdi: bool },
+    Read { bits: usize, tdo: String },
+    Write { data: String, bits: u8, pause_after: bool },
+    ReadWrite { data: String, bits: u8, pause_after: bool, tdo: String },
+    Frequency { hz: f64, set: Option<f64> },
+    Trst { asserted: bool, driven: bool },
+    Srst { asserted: bool, driven: bool },
+    Flush,
+}
+
+impl Call {
+    /// The call without its answer, to compare with the one the player makes
+    fn request(&self) -> Call {
+        match self.clone() {
+            Call::Read { bits, .. } => Call::Read { bits, tdo: String::new() },
+            Call::ReadWrite { data, bits, pause_after, .. } => {
+                Call::ReadWrite { data, bits, pause_after, tdo: String::new() }
+            }
+            Call::Frequency { hz, .. } => Call::Frequency { hz, set: None },
+            Call::Trst { asserted, .. } => Call::Trst { asserted, driven: false },
+            Call::Srst { asserted, .. } => Call::Srst { asserted, driven: false },
+            call => call,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Microseconds since the cable was opened
+    us: u64,
+    #[serde(flatten)]
+    call: Call,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("?"), 16)
+            .unwrap_or_else(|_| panic!("replay: bad hex {}", hex)))
+        .collect()
+}
+
+fn tms(tms: &[usize]) -> String {
+    tms.iter().map(|&bit| if bit != 0 { '1' } else { '0' }).collect()
+}
+
+/// Passes everything through to the cable it wraps, writing each call to the recording
+pub struct Recorder {
+    inner: Box<dyn Adapter>,
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Record into a new file at `path`, `header` saying which cable this is and how it was
+    /// opened; the capabilities are asked of the cable itself
+    pub fn create(path: &str, mut header: Header, mut inner: Box<dyn Adapter>) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        header.capabilities = inner.capabilities();
+        let mut out = BufWriter::new(file);
+        writeln!(out, "{}", serde_json::to_string(&header).unwrap()).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Recorder { inner, out, start: Instant::now() })
+    }
+
+    fn record(&mut self, call: Call) {
+        let entry = Entry { us: self.start.elapsed().as_micros() as u64, call };
+        // The recording is for debugging, so a full disk shouldn't stop the run
+        let _ = writeln!(self.out, "{}", serde_json::to_string(&entry).unwrap());
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+impl Cable for Recorder {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        self.inner.change_mode(tms, tdo);
+        self.record(Call::ChangeMode { tms: self::tms(tms), tdi: tdo });
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        let tdo = self.inner.read_data(bits);
+        self.record(Call::Read { bits, tdo: hex(&tdo) });
+        tdo
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        self.inner.write_data(data, bits, pause_after);
+        self.record(Call::Write { data: hex(data), bits, pause_after });
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        let tdo = self.inner.read_write_data(data, bits, pause_after);
+        self.record(Call::ReadWrite { data: hex(data), bits, pause_after, tdo: hex(&tdo) });
+        tdo
+    }
+}
+
+impl Adapter for Recorder {
+    fn capabilities(&mut self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        let set = self.inner.set_frequency(hz);
+        self.record(Call::Frequency { hz, set });
+        set
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        let driven = self.inner.set_trst(asserted);
+        self.record(Call::Trst { asserted, driven });
+        driven
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        let driven = self.inner.set_srst(asserted);
+        self.record(Call::Srst { asserted, driven });
+        driven
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+        self.record(Call::Flush);
+        // Whatever ran up to a flush is worth having if the run dies after it
+        let _ = self.out.flush();
+    }
+}
+
+/// The header of the recording at `path`
+pub fn header(path: &str) -> Result<Header, String> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?).read_line(&mut line)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&line).map_err(|e| format!("{}: header: {}", path, e))
+}
+
+/// A cable that answers from a recording, selected with `--cable replay:PATH` or
+/// `--replay-session PATH`
+pub struct Replay {
+    capabilities: Capabilities,
+    calls: VecDeque<Call>,
+    /// Calls made so far, to show where things went wrong
+    made: usize,
+}
+
+impl Replay {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let header = header(path)?;
+        let file = BufReader::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?);
+        let calls = file.lines().skip(1).enumerate().map(|(i, line)| {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            let entry: Entry = serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path, i + 2, e))?;
+            Ok(entry.call)
+        }).collect::<Result<_, String>>()?;
+        Ok(Replay { capabilities: header.capabilities, calls, made: 0 })
+    }
+
+    /// Take the next recorded call, which has to be `call` apart from its answer
+    fn expect(&mut self, call: Call) -> Call {
+        self.made += 1;
+        let recorded = self.calls.pop_front()
+            .unwrap_or_else(|| panic!("replay: call {} is past the end of the recording: {:?}", self.made, call));
+        if recorded.request() != call {
+            panic!("replay: call {} differs from the recording: {:?}, recorded {:?}", self.made, call, recorded);
+        }
+        recorded
+    }
+}
+
+impl Drop for Replay {
+    fn drop(&mut self) {
+        if !std::thread::panicking() && !self.calls.is_empty() {
+            eprintln!("replay: the player stopped after {} calls, {} short of the recording", self.made,
+                      self.calls.len());
+        }
+    }
+}
+
+impl Cable for Replay {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        self.expect(Call::ChangeMode { tms: self::tms(tms), tdi: tdo });
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        match self.expect(Call::Read { bits, tdo: String::new() }) {
+            Call::Read { tdo, .. } => unhex(&tdo),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        self.expect(Call::Write { data: hex(data), bits, pause_after });
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        match self.expect(Call::ReadWrite { data: hex(data), bits, pause_after, tdo: String::new() }) {
+            Call::ReadWrite { tdo, .. } => unhex(&tdo),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Adapter for Replay {
+    fn capabilities(&mut self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        match self.expect(Call::Frequency { hz, set: None }) {
+            Call::Frequency { set, .. } => set,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        match self.expect(Call::Trst { asserted, driven: false }) {
+            Call::Trst { driven, .. } => driven,
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        match self.expect(Call::Srst { asserted, driven: false }) {
+            Call::Srst { driven, .. } => driven,
+            _ => unreachable!(),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.expect(Call::Flush);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jtag_taps::statemachine::JtagSM;
+
+    use super::*;
+    use crate::cable::sim::Sim;
+    use crate::cable::{AdapterBox, ShiftCable};
+    use crate::{run_svf, Svf};
+
+    const CHAIN: &str = "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n";
+
+    fn play(cable: Box<dyn Adapter>, svf: &str) {
+        let mut jtag = JtagSM::new(AdapterBox(cable));
+        run_svf(&mut jtag, &mut Svf::new(), &mut svf.as_bytes(), None).unwrap();
+    }
+
+    #[test]
+    fn a_recording_replays_without_the_chain() {
+        let path = std::env::temp_dir().join(format!("svfplayer-session-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let svf = "SIR 4 TDI (e);\nSDR 32 TDI (0) TDO (12345679);\nRUNTEST 4 TCK;\n";
+        let header = Header { cable: "sim".to_string(), baud: 0, capabilities: Capabilities::default(), seed: None };
+        let sim = Box::new(ShiftCable(Sim::parse(CHAIN).unwrap()));
+        play(Box::new(Recorder::create(path, header.clone(), sim).unwrap()), svf);
+        assert_eq!(super::header(path).unwrap().cable, header.cable);
+
+        play(Box::new(Replay::open(path).unwrap()), svf);
+        let differs = std::panic::catch_unwind(|| {
+            play(Box::new(Replay::open(path).unwrap()), "SIR 4 TDI (f);\n");
+        });
+        std::fs::remove_file(path).unwrap();
+        assert!(differs.is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! A simulated chain, selected with `--cable sim:chain.toml`, for checking a file's IR opcodes and
+//! expected TDO without any hardware.  Each device has an instruction register, BYPASS, and
+//! optionally an IDCODE and a boundary scan register reached through the instructions named in
+//! the description.  Devices are listed nearest TDO first, like `svfplayer scan` prints them.
+//!
+//! ```toml
+//! [[device]]
+//! irlen = 4
+//! idcode = 0x4ba00477
+//! instructions = { idcode = 0xe }
+//!
+//! [[device]]
+//! name = "fpga"
+//! irlen = 6
+//! idcode = 0x0362d093
+//! bsr = 200
+//! instructions = { idcode = 0x09, sample = 0x01, extest = 0x26 }
+//! ```
+//!
+//! An instruction the description doesn't name selects BYPASS, with a warning.
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+
+use super::{Capabilities, Shifter};
+use crate::color;
+use crate::engine::{self, TapState};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Chain {
+    device: Vec<DeviceSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceSpec {
+    name: Option<String>,
+    irlen: usize,
+    idcode: Option<u32>,
+    /// Boundary scan register length
+    #[serde(default)]
+    bsr: usize,
+    /// Opcodes of `idcode`, `sample`, `preload`, `extest` and `bypass`
+    #[serde(default)]
+    instructions: HashMap<String, u64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Selected {
+    Bypass,
+    Idcode,
+    Boundary,
+}
+
+struct Device {
+    name: String,
+    irlen: usize,
+    idcode: Option<u32>,
+    instructions: HashMap<u64, Selected>,
+    /// None after a reset that selected IDCODE without the description giving its opcode
+    ir: Option<u64>,
+    /// Whatever register is being shifted, LSB nearest TDO
+    shift: Vec<bool>,
+    /// What the boundary scan cells last latched, which SAMPLE also reads back
+    boundary: Vec<bool>,
+    unknown: HashSet<u64>,
+}
+
+impl Device {
+    fn new(index: usize, spec: DeviceSpec) -> Result<Self, String> {
+        let name = spec.name.unwrap_or_else(|| format!("device {}", index));
+        if spec.irlen < 2 || spec.irlen > 64 {
+            return Err(format!("{}: irlen must be between 2 and 64", name));
+        }
+        let mut instructions = HashMap::new();
+        let bypass = u64::MAX >> (64 - spec.irlen);
+        instructions.insert(bypass, Selected::Bypass);
+        for (instruction, opcode) in spec.instructions {
+            let selected = match instruction.to_lowercase().as_str() {
+                "bypass" => Selected::Bypass,
+                "idcode" if spec.idcode.is_some() => Selected::Idcode,
+                "idcode" => return Err(format!("{}: an idcode instruction needs an idcode", name)),
+                "sample" | "preload" | "extest" => Selected::Boundary,
+                _ => return Err(format!("{}: unknown instruction {}", name, instruction)),
+            };
+            if opcode > bypass {
+                return Err(format!("{}: {} opcode doesn't fit in {} bits", name, instruction, spec.irlen));
+            }
+            instructions.insert(opcode, selected);
+        }
+        let mut device = Device {
+            name,
+            irlen: spec.irlen,
+            idcode: spec.idcode,
+            instructions,
+            ir: None,
+            shift: vec![],
+            boundary: vec![false; spec.bsr],
+            unknown: HashSet::new(),
+        };
+        device.reset();
+        Ok(device)
+    }
+
+    /// Test-Logic-Reset selects IDCODE, or BYPASS for a device without one
+    fn reset(&mut self) {
+        let idcode = self.instructions.iter().find(|(_, s)| **s == Selected::Idcode).map(|(opcode, _)| *opcode);
+        self.ir = match self.idcode {
+            Some(_) => idcode,
+            None => Some(u64::MAX >> (64 - self.irlen)),
+        };
+    }
+
+    fn selected(&self) -> Selected {
+        match self.ir {
+            Some(ir) => self.instructions.get(&ir).copied().unwrap_or(Selected::Bypass),
+            None => Selected::Idcode,
+        }
+    }
+
+    fn capture_dr(&mut self) {
+        self.shift = match self.selected() {
+            Selected::Bypass => vec![false],
+            Selected::Idcode => (0..32).map(|i| self.idcode.unwrap() & 1 << i != 0).collect(),
+            Selected::Boundary => self.boundary.clone(),
+        };
+        if self.shift.is_empty() {
+            // A boundary instruction on a device described without a BSR
+            self.shift = vec![false];
+        }
+    }
+
+    fn capture_ir(&mut self) {
+        self.shift = (0..self.irlen).map(|i| i == 0).collect();
+    }
+
+    fn update_dr(&mut self) {
+        if self.selected() == Selected::Boundary && self.shift.len() == self.boundary.len() {
+            self.boundary = self.shift.clone();
+        }
+    }
+
+    fn update_ir(&mut self) {
+        let ir = self.shift.iter().enumerate().fold(0, |ir, (i, bit)| ir | (*bit as u64) << i);
+        if !self.instructions.contains_key(&ir) && self.unknown.insert(ir) {
+            eprintln!("{} sim: {}: instruction {:0width$b} isn't described, selecting BYPASS", color::warning(),
+                      self.name, ir, width = self.irlen);
+        }
+        self.ir = Some(ir);
+    }
+
+    /// Clock `tdi` in at the TDI end, returning the bit that was at the TDO end
+    fn shift_bit(&mut self, tdi: bool) -> bool {
+        let tdo = self.shift.remove(0);
+        self.shift.push(tdi);
+        tdo
+    }
+}
+
+pub struct Sim {
+    state: TapState,
+    devices: Vec<Device>,
+}
+
+impl Sim {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// A chain from the text of its description
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let chain: Chain = toml::from_str(text).map_err(|e| e.to_string())?;
+        let devices = chain.device.into_iter().enumerate()
+            .map(|(i, spec)| Device::new(i, spec))
+            .collect::<Result<_, _>>()?;
+        Ok(Sim { state: TapState::Reset, devices })
+    }
+
+    fn clock(&mut self, tms: bool, tdi: bool) -> bool {
+        let mut tdo = true;
+        match self.state {
+            TapState::CaptureDR => self.devices.iter_mut().for_each(Device::capture_dr),
+            TapState::CaptureIR => self.devices.iter_mut().for_each(Device::capture_ir),
+            TapState::ShiftDR | TapState::ShiftIR => {
+                tdo = self.devices.iter_mut().rev().fold(tdi, |bit, device| device.shift_bit(bit));
+            }
+            _ => (),
+        }
+        self.state = engine::next(self.state, tms as usize);
+        match self.state {
+            TapState::Reset => self.devices.iter_mut().for_each(Device::reset),
+            TapState::UpdateDR => self.devices.iter_mut().for_each(Device::update_dr),
+            TapState::UpdateIR => self.devices.iter_mut().for_each(Device::update_ir),
+            _ => (),
+        }
+        tdo
+    }
+}
+
+impl Shifter for Sim {
+    fn capabilities(&mut self) -> Capabilities {
+        Capabilities { trst: true, srst: true, ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], _read: bool) -> Vec<bool> {
+        tms.iter().zip(tdi).map(|(tms, tdi)| self.clock(*tms, *tdi)).collect()
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        if asserted {
+            self.state = TapState::Reset;
+            self.devices.iter_mut().for_each(Device::reset);
+        }
+        true
+    }
+
+    fn set_srst(&mut self, _asserted: bool) -> bool {
+        // System reset leaves the TAPs alone
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jtag_taps::statemachine::JtagSM;
+
+    use super::*;
+    use crate::cable::{AdapterBox, ShiftCable};
+    use crate::{chain, run_svf, Svf};
+
+    const CHAIN: &str = "[[device]]\nirlen = 4\nidcode = 0x4ba00477\ninstructions = { idcode = 0xe }\n\n\
+                         [[device]]\nirlen = 6\n\n\
+                         [[device]]\nirlen = 5\nidcode = 0x0362d093\nbsr = 8\n\
+                         instructions = { idcode = 0x09, sample = 0x01, extest = 0x06 }\n";
+
+    fn jtag() -> JtagSM<AdapterBox> {
+        JtagSM::new(AdapterBox(Box::new(ShiftCable(Sim::parse(CHAIN).unwrap()))))
+    }
+
+    #[test]
+    fn chain_scan_finds_the_idcodes() {
+        assert_eq!(chain::idcodes(&mut jtag()), [Some(0x4ba00477), None, Some(0x0362d093)]);
+    }
+
+    #[test]
+    fn ir_lengths_are_detected() {
+        let database = crate::devices::Database::default();
+        assert_eq!(chain::ir_lengths(&mut jtag(), &database).unwrap(), [4, 6, 5]);
+    }
+
+    #[test]
+    fn boundary_register_reads_back_what_was_loaded() {
+        // IR is 4 and 6 bits of BYPASS, then SAMPLE for the last device; DR is 1 + 1 + 8 bits
+        let svf = "SIR 15 TDI (07ff) TDO (0411);\nSDR 10 TDI (168);\nSDR 10 TDI (000) TDO (168) MASK (3fc);\n\
+                   SIR 15 TDI (1bff);\nSDR 10 TDI (000) TDO (000) MASK (3fc);\n";
+        let mut svf_input = svf.as_bytes();
+        let mut player = Svf::new();
+        run_svf(&mut jtag(), &mut player, &mut svf_input, None).unwrap();
+    }
+}
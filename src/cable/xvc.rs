@@ -0,0 +1,84 @@
+//! Xilinx Virtual Cable client, selected with `--cable xvc:host:port`.  Every cable operation
+//! turns into one or more `shift:` requests, split to the vector size the server advertises.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use super::{bit, pack, Capabilities, Shifter};
+
+pub const DEFAULT_PORT: u16 = 2542;
+
+pub struct Xvc {
+    stream: BufReader<TcpStream>,
+    /// Largest vector, in bytes, the server accepts in a single shift
+    max_bytes: usize,
+}
+
+impl Xvc {
+    pub fn connect(addr: &str, clock: u32) -> Result<Self, String> {
+        let addr = if addr.contains(':') {
+            addr.to_string()
+        } else {
+            format!("{}:{}", addr, DEFAULT_PORT)
+        };
+        let stream = TcpStream::connect(&addr).map_err(|e| format!("{}: {}", addr, e))?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut xvc = Xvc {
+            stream: BufReader::new(stream),
+            max_bytes: 0,
+        };
+
+        xvc.stream.get_mut().write_all(b"getinfo:").map_err(|e| e.to_string())?;
+        let mut info = String::new();
+        xvc.stream.read_line(&mut info).map_err(|e| e.to_string())?;
+        let max = info.trim().strip_prefix("xvcServer_v1.0:")
+            .ok_or_else(|| format!("unexpected XVC server info: {}", info.trim()))?;
+        // The advertised size covers both the TMS and TDI vectors
+        xvc.max_bytes = max.parse::<usize>().map_err(|e| e.to_string())? / 2;
+        if xvc.max_bytes == 0 {
+            return Err("XVC server doesn't accept any data".into());
+        }
+
+        if clock != 0 {
+            xvc.settck(clock as f64).map_err(|e| e.to_string())?;
+        }
+        Ok(xvc)
+    }
+
+    /// Ask the server for a TCK period, returning the frequency it actually uses
+    fn settck(&mut self, hz: f64) -> std::io::Result<f64> {
+        let period = (1e9 / hz).clamp(1.0, u32::MAX as f64) as u32;
+        let mut req = b"settck:".to_vec();
+        req.extend_from_slice(&period.to_le_bytes());
+        self.stream.get_mut().write_all(&req)?;
+        let mut actual = [0; 4];
+        self.stream.read_exact(&mut actual)?;
+        Ok(1e9 / u32::from_le_bytes(actual).max(1) as f64)
+    }
+}
+
+impl Shifter for Xvc {
+    fn capabilities(&mut self) -> Capabilities {
+        // Every shift: command returns TDO
+        Capabilities { max_transfer: Some(self.max_bytes), ..Capabilities::default() }
+    }
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool], _read: bool) -> Vec<bool> {
+        let mut tdo = Vec::with_capacity(tms.len());
+        for (tms, tdi) in tms.chunks(self.max_bytes * 8).zip(tdi.chunks(self.max_bytes * 8)) {
+            let mut req = b"shift:".to_vec();
+            req.extend_from_slice(&(tms.len() as u32).to_le_bytes());
+            req.extend_from_slice(&pack(tms));
+            req.extend_from_slice(&pack(tdi));
+            self.stream.get_mut().write_all(&req).expect("xvc write");
+
+            let mut resp = vec![0; tms.len().div_ceil(8)];
+            self.stream.read_exact(&mut resp).expect("xvc read");
+            tdo.extend((0..tms.len()).map(|i| bit(&resp, i)));
+        }
+        tdo
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        Some(self.settck(hz).expect("xvc settck"))
+    }
+}
@@ -0,0 +1,134 @@
+//! Rough models of common adapters, for `stats --cable-profile` to predict how long a file would
+//! take to play on each.  A profile knows the fastest TCK the adapter runs, how many bytes it
+//! sends per clock, how much it buffers per USB or network transfer and what each transfer costs
+//! in latency.  Every scan that checks TDO and every timed RUNTEST also waits for a round trip,
+//! as the player does.  The figures are typical rather than measured on any one unit.
+use crate::parse_frequency;
+use crate::stats::Estimate;
+
+#[derive(Debug)]
+pub struct Profile {
+    pub name: &'static str,
+    pub max_hz: f64,
+    /// Bytes sent per TCK in a scan or RUNTEST, and per TCK of a state change
+    pub scan_bytes: f64,
+    pub tms_bytes: f64,
+    /// Bytes a transfer carries, and the time each takes to go and come back
+    pub buffer: u64,
+    pub latency: f64,
+}
+
+pub const PROFILES: &[Profile] = &[
+    // FT2232H MPSSE, high-speed USB
+    Profile { name: "ft2232", max_hz: 30e6, scan_bytes: 1.0 / 8.0, tms_bytes: 3.0 / 7.0, buffer: 4096, latency: 125e-6 },
+    // FT232H MPSSE, high-speed USB
+    Profile { name: "ft232h", max_hz: 30e6, scan_bytes: 1.0 / 8.0, tms_bytes: 3.0 / 7.0, buffer: 1024, latency: 125e-6 },
+    // FT2232D MPSSE, full-speed USB (e.g. Amontec JTAGkey)
+    Profile { name: "ft2232d", max_hz: 6e6, scan_bytes: 1.0 / 8.0, tms_bytes: 3.0 / 7.0, buffer: 384, latency: 1e-3 },
+    // Altera USB-Blaster, full-speed USB
+    Profile { name: "usbblaster", max_hz: 6e6, scan_bytes: 1.0 / 8.0, tms_bytes: 2.0, buffer: 64, latency: 1e-3 },
+    // SEGGER J-Link, high-speed USB
+    Profile { name: "jlink", max_hz: 15e6, scan_bytes: 2.0 / 8.0, tms_bytes: 2.0 / 8.0, buffer: 2048, latency: 125e-6 },
+    // CMSIS-DAP v2 bulk, high-speed USB
+    Profile { name: "cmsis-dap", max_hz: 10e6, scan_bytes: 1.0 / 8.0, tms_bytes: 1.0, buffer: 512, latency: 125e-6 },
+    // CMSIS-DAP v1 HID, full-speed USB
+    Profile { name: "cmsis-dap-hid", max_hz: 10e6, scan_bytes: 1.0 / 8.0, tms_bytes: 1.0, buffer: 64, latency: 1e-3 },
+    // Xilinx Virtual Cable over a LAN
+    Profile { name: "xvc", max_hz: 30e6, scan_bytes: 2.0 / 8.0, tms_bytes: 2.0 / 8.0, buffer: 32768, latency: 300e-6 },
+    // OpenOCD remote_bitbang over localhost
+    Profile { name: "remote_bitbang", max_hz: 2e6, scan_bytes: 2.0, tms_bytes: 2.0, buffer: 4096, latency: 50e-6 },
+    // Linux GPIO character device bit-banging
+    Profile { name: "gpiod", max_hz: 200e3, scan_bytes: 0.0, tms_bytes: 0.0, buffer: u64::MAX, latency: 0.0 },
+];
+
+/// A profile as `NAME[@FREQUENCY]`, running TCK at the frequency given or else as fast as it can
+pub fn parse(text: &str) -> Result<(&'static Profile, f64), String> {
+    let (name, hz) = text.split_once('@').map_or((text, None), |(name, hz)| (name, Some(hz)));
+    let profile = PROFILES.iter().find(|profile| profile.name == name).ok_or_else(|| {
+        let names: Vec<_> = PROFILES.iter().map(|profile| profile.name).collect();
+        format!("unknown cable profile {}, expected one of {}", name, names.join(", "))
+    })?;
+    let hz = match hz {
+        Some(hz) => parse_frequency(hz)?,
+        None => profile.max_hz,
+    };
+    if hz > profile.max_hz {
+        return Err(format!("{} runs TCK at most at {} MHz", profile.name, profile.max_hz / 1e6));
+    }
+    Ok((profile, hz))
+}
+
+#[derive(Debug, Default)]
+pub struct Prediction {
+    pub clock_seconds: f64,
+    pub transfer_seconds: f64,
+    pub wait_seconds: f64,
+    pub transfers: u64,
+}
+
+impl Prediction {
+    pub fn total_seconds(&self) -> f64 {
+        self.clock_seconds + self.transfer_seconds + self.wait_seconds
+    }
+}
+
+impl Profile {
+    /// How long `est` would take with TCK at `hz`, or slower where the file's FREQUENCY asks
+    pub fn predict(&self, est: &Estimate, hz: f64) -> Prediction {
+        let mut prediction = Prediction::default();
+        // Bytes queued since the last transfer went out
+        let mut queued = 0.0;
+        for step in &est.steps {
+            let hz = step.hz.map_or(hz, |declared| declared.min(hz));
+            let clocks = step.scan_bits + step.run_clocks + step.state_clocks;
+            prediction.clock_seconds += clocks as f64 / hz;
+            prediction.wait_seconds += (step.min_time - step.run_clocks as f64 / hz).max(0.0);
+            queued += (step.scan_bits + step.run_clocks) as f64 * self.scan_bytes
+                + step.state_clocks as f64 * self.tms_bytes;
+            let full = (queued / self.buffer as f64).floor();
+            prediction.transfers += full as u64;
+            queued -= full * self.buffer as f64;
+            if step.round_trip && queued > 0.0 {
+                prediction.transfers += 1;
+                queued = 0.0;
+            }
+        }
+        if queued > 0.0 {
+            prediction.transfers += 1;
+        }
+        prediction.transfer_seconds = prediction.transfers as f64 * self.latency;
+        prediction
+    }
+}
+
+/// Print the prediction for each of `profiles`
+pub fn report(est: &Estimate, profiles: &[(&Profile, f64)]) {
+    println!("{:<16} {:>10} {:>10} {:>12}", "cable profile", "TCK MHz", "transfers", "predicted");
+    for (profile, hz) in profiles {
+        let prediction = profile.predict(est, *hz);
+        println!("{:<16} {:>10.3} {:>10} {:>10.3} s  ({:.3} s clocking, {:.3} s in transfers, {:.3} s waiting)",
+                 profile.name, hz / 1e6, prediction.transfers, prediction.total_seconds(),
+                 prediction.clock_seconds, prediction.transfer_seconds, prediction.wait_seconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::estimate;
+
+    #[test]
+    fn slow_round_trips_dominate_checked_scans() {
+        let text = "SDR 32 TDI (0) TDO (0);\n".repeat(100);
+        let est = estimate(&mut text.as_bytes(), 1e6).unwrap();
+        let (ft2232, hz) = parse("ft2232@10MHz").unwrap();
+        assert_eq!(hz, 10e6);
+        let fast = ft2232.predict(&est, hz);
+        assert_eq!(fast.transfers, 100);
+        let (hid, hz) = parse("cmsis-dap-hid").unwrap();
+        let slow = hid.predict(&est, hz);
+        assert!(slow.total_seconds() > 0.1 && slow.total_seconds() > fast.total_seconds() * 5.0);
+        assert!(parse("ft2232@60MHz").is_err());
+        assert!(parse("nosuch").unwrap_err().contains("ft2232"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Finding out what is on the chain.  After Test-Logic-Reset every TAP selects IDCODE, or BYPASS
+//! if it has none, so shifting DR lists the devices without knowing anything about them.
+use jtag_taps::statemachine::{JtagSM, JtagState, Register};
+
+use crate::cable::{bit, pack, AdapterBox};
+use crate::devices::Database;
+use crate::tune::DEFAULT_CHUNK_SIZE;
+use crate::{read_write_reg, Svf};
+
+/// Longest chain `idcodes` looks for
+pub const MAX_DEVICES: usize = 32;
+
+/// Most instruction register bits, over the whole chain, `ir_lengths` looks for
+pub const MAX_IR_BITS: usize = 1024;
+
+/// The IDCODE of every device on the chain, nearest TDO first.  A device without one, only
+/// offering BYPASS after reset, shows up as `None`.
+pub fn idcodes(sm: &mut JtagSM<AdapterBox>) -> Vec<Option<u32>> {
+    sm.mode_reset();
+    // Ones shifted in behind the chain read back as an all-ones IDCODE, which is never valid
+    let bits = (MAX_DEVICES + 1) * 32;
+    let read = read_write_reg(sm, Register::Data, &vec![0xff; bits / 8], 8, DEFAULT_CHUNK_SIZE);
+    sm.change_mode(JtagState::Idle);
+    let mut devices = vec![];
+    let mut pos = 0;
+    while pos + 32 <= bits && devices.len() < MAX_DEVICES {
+        if !bit(&read, pos) {
+            devices.push(None);
+            pos += 1;
+            continue;
+        }
+        let idcode = (0..32).fold(0u32, |id, i| id | (bit(&read, pos + i) as u32) << i);
+        if idcode == u32::MAX {
+            break;
+        }
+        devices.push(Some(idcode));
+        pos += 32;
+    }
+    devices
+}
+
+/// Parse an instruction opcode as hex, or `-` for a device to leave in BYPASS
+pub fn parse_opcode(s: &str) -> Result<Option<u64>, String> {
+    if s == "-" {
+        return Ok(None);
+    }
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    u64::from_str_radix(digits, 16).map(Some).map_err(|_| format!("bad opcode {}", s))
+}
+
+/// Load `opcodes` into the devices of a chain with `irlens` (both nearest TDO first), BYPASS
+/// where there's no opcode, and read the 32 bit register each opcode selects
+pub fn read_register(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, irlens: &[usize], opcodes: &[Option<u64>])
+                     -> Result<Vec<Option<u32>>, String> {
+    if opcodes.len() != irlens.len() {
+        return Err(format!("{} opcodes for a chain of {} devices", opcodes.len(), irlens.len()));
+    }
+    let mut ir = vec![];
+    for (len, opcode) in irlens.iter().zip(opcodes) {
+        let opcode = opcode.unwrap_or(u64::MAX);
+        if *len < 64 && opcode != u64::MAX && opcode >> len != 0 {
+            return Err(format!("opcode {:x} doesn't fit in {} bits", opcode, len));
+        }
+        ir.extend((0..*len).map(|i| opcode >> i & 1 != 0));
+    }
+    svf.scan(sm, Register::Instruction, pack(&ir), ir.len() as u32, JtagState::Idle);
+    let length: usize = opcodes.iter().map(|opcode| if opcode.is_some() { 32 } else { 1 }).sum();
+    let read = svf.scan(sm, Register::Data, vec![0; length.div_ceil(8)], length as u32, JtagState::Idle);
+    let mut pos = 0;
+    Ok(opcodes.iter().map(|opcode| {
+        if opcode.is_none() {
+            pos += 1;
+            return None;
+        }
+        let value = (0..32).fold(0u32, |v, i| v | (bit(&read, pos + i) as u32) << i);
+        pos += 32;
+        Some(value)
+    }).collect())
+}
+
+/// Shift zeros and then ones through `reg`, returning what came out before the first of the
+/// ones did, or None if none of them came out
+fn flush(sm: &mut JtagSM<AdapterBox>, reg: Register, max: usize) -> Option<Vec<bool>> {
+    let data: Vec<u8> = [vec![0; max / 8], vec![0xff; max / 8]].concat();
+    let read = read_write_reg(sm, reg, &data, 8, DEFAULT_CHUNK_SIZE);
+    sm.change_mode(JtagState::Idle);
+    let length = (max..2 * max).find(|i| bit(&read, *i))? - max;
+    Some((0..length).map(|i| bit(&read, i)).collect())
+}
+
+/// Split the IR capture of a whole chain into `count` instruction registers.  Each one captures
+/// 1 then 0 in its two bits nearest TDO, but other bits may look the same, so `known` lengths
+/// (by position) rule out splits, and the split has to be the only one left.
+fn split_ir(captured: &[bool], count: usize, known: &[Option<usize>]) -> Result<Vec<usize>, String> {
+    let total = captured.len();
+    let starts: Vec<usize> = (0..total)
+        .filter(|p| captured[*p] && !captured.get(p + 1).copied().unwrap_or(true))
+        .chain([total])
+        .collect();
+    let pattern: String = captured.iter().rev().map(|b| if *b { '1' } else { '0' }).collect();
+    if starts[0] != 0 {
+        return Err(format!("the IR capture {} doesn't end in 01 as 1149.1 requires", pattern));
+    }
+    // ways[device][start] counts the ways, up to 2, that devices from `device` on can start at `start`
+    let mut ways = vec![vec![0u8; starts.len()]; count + 1];
+    ways[count][starts.len() - 1] = 1;
+    for device in (0..count).rev() {
+        for (i, start) in starts.iter().enumerate() {
+            ways[device][i] = starts.iter().enumerate().skip(i + 1)
+                .filter(|(_, next)| *next - start >= 2 && known[device].is_none_or(|len| len == *next - start))
+                .map(|(j, _)| ways[device + 1][j])
+                .fold(0, |a, b| (a + b).min(2));
+        }
+    }
+    match ways[0][0] {
+        0 => return Err(format!("{} devices can't have captured IR {}", count, pattern)),
+        1 => (),
+        _ => return Err(format!("IR lengths are ambiguous from the capture {}; give them with --chain", pattern)),
+    }
+    let mut lengths = vec![];
+    let mut i = 0;
+    for device in 0..count {
+        let j = (i + 1..starts.len())
+            .find(|j| {
+                let len = starts[*j] - starts[i];
+                len >= 2 && known[device].is_none_or(|known| known == len) && ways[device + 1][*j] > 0
+            })
+            .unwrap();
+        lengths.push(starts[j] - starts[i]);
+        i = j;
+    }
+    Ok(lengths)
+}
+
+/// Find the instruction length of every device on the chain, nearest TDO first, from how long
+/// the chain's IR and BYPASS paths are and what the IRs capture.  Parts in `database` help
+/// where the capture alone doesn't settle it.
+pub fn ir_lengths(sm: &mut JtagSM<AdapterBox>, database: &Database) -> Result<Vec<usize>, String> {
+    let idcodes = idcodes(sm);
+    sm.mode_reset();
+    let captured = flush(sm, Register::Instruction, MAX_IR_BITS)
+        .ok_or("no ones came back through IR; is TDO stuck low?")?;
+    if captured.is_empty() {
+        return Err("no devices found".into());
+    }
+    // The IRs are all ones now, which is BYPASS for every device
+    let count = flush(sm, Register::Data, MAX_DEVICES * 8).ok_or("more devices than expected in BYPASS")?.len();
+    sm.mode_reset();
+    let known: Vec<Option<usize>> = match idcodes.len() == count {
+        true => idcodes.iter().map(|id| id.and_then(|id| database.part(id)).map(|part| part.irlen)).collect(),
+        false => vec![None; count],
+    };
+    split_ir(&captured, count, &known)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(s: &str) -> Vec<bool> {
+        s.chars().rev().map(|c| c == '1').collect()
+    }
+
+    #[test]
+    fn ir_capture_splits_into_devices() {
+        assert_eq!(split_ir(&bits("0000010001"), 2, &[None, None]).unwrap(), [4, 6]);
+        // The 6 bit IR also captures 01 in its middle, which could start a third register
+        let captured = bits("0100010001");
+        assert!(split_ir(&captured, 2, &[None, None]).unwrap_err().contains("ambiguous"));
+        assert_eq!(split_ir(&captured, 2, &[Some(4), None]).unwrap(), [4, 6]);
+        assert!(split_ir(&bits("0010"), 1, &[None]).is_err());
+    }
+}
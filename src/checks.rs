@@ -0,0 +1,98 @@
+//! `--soft-check` and `--hard-check`: pick out SDR TDO checks by their command number in the
+//! file (`12`, `40-60`) or by the instruction the last SIR loaded (`ir=0x05`).  A soft check,
+//! such as a status poll, is retried --retries times and then only warned about; a hard one,
+//! such as an IDCODE or a final verify, stops playback whatever --on-mismatch says.  Where both
+//! kinds match a check it is hard.
+use crate::config::OnMismatch;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Soft,
+    Hard,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    /// Commands `first..=last`, numbered from 1 in each file
+    Commands(usize, usize),
+    /// SDRs after an SIR shifting this instruction, the target's own with --target
+    Opcode(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub severity: Severity,
+    pub selector: Selector,
+}
+
+pub fn parse_selector(s: &str) -> Result<Selector, String> {
+    if let Some(opcode) = s.strip_prefix("ir=") {
+        let opcode = match opcode.strip_prefix("0x").or_else(|| opcode.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => opcode.parse(),
+        };
+        return opcode.map(Selector::Opcode).map_err(|_| format!("bad instruction in {}", s));
+    }
+    let number = |n: &str| match n.parse::<usize>() {
+        Ok(0) => Err("commands are numbered from 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("expected N, N-M or ir=OPCODE, not {}", s)),
+    };
+    let (first, last) = match s.split_once('-') {
+        Some((first, last)) => (number(first)?, number(last)?),
+        None => (number(s)?, number(s)?),
+    };
+    if last < first {
+        return Err(format!("{} ends before it starts", s));
+    }
+    Ok(Selector::Commands(first, last))
+}
+
+/// An instruction as `Selector::Opcode` compares it, from the TDI of an SIR
+pub fn opcode(tdi: &[u8]) -> u64 {
+    tdi.iter().take(8).rev().fold(0, |opcode, &byte| opcode << 8 | byte as u64)
+}
+
+impl Selector {
+    fn matches(&self, index: usize, opcode: Option<u64>) -> bool {
+        match *self {
+            Selector::Commands(first, last) => (first..=last).contains(&index),
+            Selector::Opcode(wanted) => opcode == Some(wanted),
+        }
+    }
+}
+
+/// How a check of command `index`, after an SIR of `opcode`, is treated, if any rule says
+pub fn severity(rules: &[Rule], index: usize, opcode: Option<u64>) -> Option<Severity> {
+    let matching = rules.iter().filter(|rule| rule.selector.matches(index, opcode));
+    matching.map(|rule| rule.severity).max_by_key(|severity| *severity == Severity::Hard)
+}
+
+/// What a mismatch does under `severity`, or `default` without one
+pub fn on_mismatch(severity: Option<Severity>, default: OnMismatch) -> OnMismatch {
+    match severity {
+        Some(Severity::Soft) => OnMismatch::Warn,
+        Some(Severity::Hard) => OnMismatch::Abort,
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_rules_win_over_soft_ones() {
+        let rule = |severity, selector| Rule { severity, selector: parse_selector(selector).unwrap() };
+        let rules = [rule(Severity::Soft, "10-20"), rule(Severity::Hard, "15"), rule(Severity::Soft, "ir=0x05")];
+        assert_eq!(severity(&rules, 12, None), Some(Severity::Soft));
+        assert_eq!(severity(&rules, 15, Some(5)), Some(Severity::Hard));
+        assert_eq!(severity(&rules, 30, Some(opcode(&[0x05]))), Some(Severity::Soft));
+        assert_eq!(severity(&rules, 30, Some(9)), None);
+        assert_eq!(on_mismatch(None, OnMismatch::Prompt), OnMismatch::Prompt);
+        assert_eq!(parse_selector("ir=9").unwrap(), Selector::Opcode(9));
+        assert!(parse_selector("20-10").is_err());
+        assert!(parse_selector("0").is_err());
+        assert!(parse_selector("ir=0xzz").is_err());
+    }
+}
@@ -0,0 +1,1420 @@
+//! The `svfplayer` command line.  The binary only calls `main`; a crate with cable backends of
+//! its own registers them with `cable::register` and then calls `main` from its binary, giving
+//! the same command line with those cables selectable by name.
+use std::io::{BufRead, IsTerminal};
+use std::iter::zip;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use jtag_taps::statemachine::JtagSM;
+use svf::ParseError;
+
+use crate::access::{Access, Gate};
+use crate::batch::BatchingCable;
+use crate::bits::{BitOrder, DontCare, Rng};
+use crate::bscan::{self, Drive};
+use crate::bsdl::Bsdl;
+use crate::cable::session::{self, Header, Recorder};
+use crate::cable::{self, Adapter, AdapterBox, Capabilities};
+use crate::checks::{self, Rule, Selector, Severity};
+use crate::color;
+use crate::config::{Config, LogLevel, OnMismatch};
+use crate::devices::{Database, Part};
+use crate::diff::{self, Difference};
+use crate::dump::Dump;
+use crate::explain::Explainer;
+use crate::fields::{self, Capture, CaptureObserver};
+use crate::include::Includes;
+use crate::limits::Limits;
+use crate::preflight::Preflight;
+use crate::manifest::{self, Manifest};
+use crate::template::{self, Template};
+use crate::patch::{self, Patch};
+use crate::pipeline::PipelinedCable;
+use crate::postmortem::{self, Postmortem};
+use crate::profile::{Profiler, ProfilingCable};
+use crate::session_log::SessionLog;
+use crate::status::{self, StatusLine};
+use crate::telemetry::Telemetry;
+use crate::target::{self, Padding};
+use crate::tune::{self, ChunkSize};
+use crate::watchdog::{self, WatchdogCable};
+use crate::{cable_profile, chain, compiled, daemon, hooks, http, input, interconnect, lint, pacing, phases, repl, stapl, stats, svf_writer, watch, xvc_server};
+use crate::{panic_message, parse_frequency, run_svf, Svf};
+
+fn play_files(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, args: &Play, inputs: &mut [Box<dyn BufRead>],
+              mut profiler: Option<&mut Profiler>) -> Result<(), ParseError> {
+    if let Some(deadline) = args.deadline {
+        svf.set_deadline(deadline);
+    }
+    for (i, (name, input)) in zip(&args.input, inputs).enumerate() {
+        if i > 0 && args.reset_between {
+            svf.reset();
+            jtag.mode_reset();
+        }
+        svf.log(format!("Playing {}", name));
+        if let Some(status) = &mut svf.status {
+            status.finish();
+        }
+        if args.input.len() > 1 && svf.log_level >= LogLevel::Info {
+            eprintln!("Playing {}", name);
+        }
+        if let Some(status) = &mut svf.status {
+            // Counted from a second read of the file, which a pipe can't give
+            let total = (name != "-").then(|| open_input(name, args).ok().and_then(|mut input| status::count(&mut input)));
+            status.start(total.flatten());
+        }
+        run_svf(jtag, svf, input, profiler.as_deref_mut())?;
+        if svf.stopped {
+            break;
+        }
+    }
+    if let Some(status) = &mut svf.status {
+        status.finish();
+    }
+    Ok(())
+}
+
+/// Why a run failed, if it did, naming the phase it failed in; the phase playing ends either way
+fn outcome(svf: &mut Svf, result: &std::thread::Result<Result<(), ParseError>>) -> Option<String> {
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("svf: {}", e)),
+        Err(e) => Some(format!("error: {}", panic_message(&**e))),
+    };
+    let error = match (error, svf.phases.current()) {
+        (Some(error), Some(phase)) => Some(format!("{} (in phase {})", error, phase)),
+        (error, _) => error,
+    };
+    if error.is_some() {
+        svf.phases.fail();
+    } else {
+        svf.phases.end();
+    }
+    error
+}
+
+/// Play the files `--repeat` times, or until a time fails with `--until-failure`, for soak
+/// testing, reporting how each time went and the TDO mismatches over all of them
+fn soak(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, args: &Play, inputs: &mut Vec<Box<dyn BufRead>>,
+        mut profiler: Option<&mut Profiler>) -> std::thread::Result<Result<(), ParseError>> {
+    // Telemetry counts the mismatches, retried ones included
+    svf.telemetry.get_or_insert_with(Telemetry::new);
+    let mismatches = |svf: &Svf| svf.telemetry.as_ref().map_or(0, |t| t.tdo_mismatches);
+    let (mut iterations, mut failed) = (0, 0);
+    while args.repeat.is_none_or(|n| iterations < n) {
+        iterations += 1;
+        if iterations > 1 {
+            *inputs = args.input.iter().map(|input| open_input(input, args).expect("read")).collect();
+            svf.reset();
+            jtag.mode_reset();
+        }
+        let before = mismatches(svf);
+        srst_before(jtag, svf, args);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            play_files(jtag, svf, args, inputs, profiler.as_deref_mut())
+        }));
+        let error = outcome(svf, &result);
+        if error.is_some() {
+            abort(jtag, svf, args);
+            failed += 1;
+        }
+        srst_after(jtag, svf, args);
+        let status = error.as_deref().map_or("passed".to_string(), |e| format!("failed, {}", e));
+        let line = format!("Iteration {}: {} ({} TDO mismatches)", iterations, status, mismatches(svf) - before);
+        svf.log(&line);
+        println!("{}", line);
+        if error.is_some() && args.until_failure {
+            break;
+        }
+    }
+    println!("{} iterations: {} passed, {} failed, {} TDO mismatches", iterations, iterations - failed, failed,
+             mismatches(svf));
+    if failed == 0 {
+        return Ok(Ok(()));
+    }
+    let error = format!("{} of {} iterations failed", failed, iterations);
+    eprintln!("{}", error);
+    Err(Box::new(error))
+}
+
+/// What to do with the target's system reset around playback
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+enum Srst {
+    /// Assert SRST and release it again before playback
+    Pulse,
+    /// Hold SRST asserted through playback and leave it asserted
+    Assert,
+    /// Hold SRST asserted through playback and release it afterwards, failed or not
+    Release,
+}
+
+/// Drive SRST before playback as `args.srst` says
+fn srst_before(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, args: &Play) {
+    let Some(mode) = args.srst else {
+        return;
+    };
+    if !jtag.cable.0.capabilities().srst || !jtag.cable.0.set_srst(true) {
+        eprintln!("--srst: the cable has no SRST line");
+        std::process::exit(1);
+    }
+    jtag.cable.0.flush();
+    svf.log("SRST asserted");
+    std::thread::sleep(args.srst_delay);
+    if mode == Srst::Pulse {
+        srst_release(jtag, svf, args);
+    }
+}
+
+/// Drive SRST after playback as `args.srst` says
+fn srst_after(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, args: &Play) {
+    if args.srst == Some(Srst::Release) {
+        srst_release(jtag, svf, args);
+    }
+}
+
+fn srst_release(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, args: &Play) {
+    // Let the last of the scans reach the target before it comes out of reset
+    jtag.cable.0.flush();
+    jtag.cable.0.set_srst(false);
+    jtag.cable.0.flush();
+    svf.log("SRST released");
+    std::thread::sleep(args.srst_settle);
+}
+
+/// Steps run on the target when playback fails
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+enum AbortStep {
+    /// Clock TMS high into Test-Logic-Reset
+    Reset,
+    /// Assert and release TRST
+    Trst,
+    /// Leave the TAP where it is
+    None,
+}
+
+/// Leave the target somewhere defined after a failed run, instead of wherever the error struck
+fn abort(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, args: &Play) {
+    svf.abandon();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        for step in &args.on_error {
+            match step {
+                AbortStep::Reset => jtag.mode_reset(),
+                AbortStep::None => (),
+                AbortStep::Trst => {
+                    if jtag.cable.0.capabilities().trst && jtag.cable.0.set_trst(true) {
+                        jtag.cable.0.flush();
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                        jtag.cable.0.set_trst(false);
+                        jtag.mode_reset();
+                    } else if svf.log_level >= LogLevel::Warn {
+                        eprintln!("{} cable has no TRST, skipping it in the abort sequence", color::warning());
+                    }
+                }
+            }
+        }
+        if let Some(path) = &args.abort_svf {
+            svf.reset();
+            svf.log(format!("Playing abort sequence {}", path));
+            let mut input = input::open(path).expect("read abort SVF");
+            run_svf(jtag, svf, &mut input, None).expect("abort SVF");
+        }
+        jtag.cable.0.flush();
+    }));
+    if let Err(e) = result {
+        svf.log(format!("abort sequence failed: {}", panic_message(&*e)));
+        eprintln!("abort sequence failed: {}", panic_message(&*e));
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Play SVF files on the cable
+    Play(Box<Play>),
+    /// Estimate TCK count and playback time without touching any hardware
+    Stats {
+        /// TCK frequency assumed until the file declares a FREQUENCY
+        #[arg(long, default_value_t = 1_000_000.0, value_name = "HZ")]
+        assume_freq: f64,
+        /// Also predict playback on a model of an adapter, e.g. ft2232@30MHz, or on every model
+        /// with "all".  May be repeated.
+        #[arg(long = "cable-profile", value_name = "NAME[@FREQUENCY]")]
+        cable_profiles: Vec<String>,
+        /// SVF file to analyze, or "-" for standard input
+        input: String,
+    },
+    /// Report SVF that parses but is likely wrong, such as unreachable STATE paths or TDO without
+    /// a MASK
+    #[command(alias = "lint")]
+    Check {
+        /// SVF file to check, or "-" for standard input
+        input: String,
+    },
+    /// Rewrite an SVF file with uniform formatting and without repeated sticky parameters
+    Convert {
+        /// SVF file to read, or "-" for standard input
+        input: String,
+    },
+    /// Rewrite an SVF file like convert, also merging RUNTESTs and dropping STATEs that change nothing
+    Optimize {
+        /// SVF file to read, or "-" for standard input
+        input: String,
+    },
+    /// Parse an SVF file once into a binary form that play reads without parsing again
+    Compile {
+        /// SVF file to read, or "-" for standard input
+        input: String,
+        /// File to write the compiled SVF to, conventionally ending in .svfc
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Compare what two SVF files do, ignoring formatting, comments and re-stated sticky
+    /// parameters, and show where they first differ
+    Diff {
+        a: String,
+        b: String,
+    },
+    /// Print the IDCODE of every device on the chain, nearest TDO first
+    Scan {
+        #[command(flatten)]
+        cable: CableArgs,
+    },
+    /// Print the IDCODE of every device, loading the IDCODE instruction if --opcode gives it
+    Idcode {
+        #[command(flatten)]
+        register: RegisterArgs,
+    },
+    /// Print the USERCODE of the devices --opcode, or the known-device database, gives the
+    /// USERCODE instruction of
+    Usercode {
+        #[command(flatten)]
+        register: RegisterArgs,
+    },
+    /// Sample or drive the pins of a BSDL-described device through its boundary register
+    Bscan {
+        #[command(flatten)]
+        cable: CableArgs,
+        #[arg(long, value_name = "PATH")]
+        bsdl: String,
+        /// Instruction lengths of every device on the chain, nearest TDO first, when the described
+        /// device isn't alone
+        #[arg(long, value_delimiter = ',', value_name = "IRLENS")]
+        chain: Vec<usize>,
+        /// Position of the described device in --chain, counting from 0
+        #[arg(long, default_value_t = 0)]
+        position: usize,
+        #[command(subcommand)]
+        operation: BscanOperation,
+    },
+    /// Test the nets between BSDL-described devices for opens and shorts by walking a one across them
+    Interconnect {
+        #[command(flatten)]
+        cable: CableArgs,
+        /// Instruction lengths of every device on the chain, nearest TDO first
+        #[arg(long, required = true, value_delimiter = ',', value_name = "IRLENS")]
+        chain: Vec<usize>,
+        /// A described device, its name in the netlist, position in --chain and BSDL file
+        #[arg(long = "device", required = true, value_parser = parse_device, value_name = "NAME=POSITION:BSDL")]
+        devices: Vec<(String, usize, String)>,
+        /// File listing a net per line: its name, then its pins as DEVICE.PORT
+        netlist: String,
+    },
+    /// List attached adapters along with the --cable spec that selects each one
+    ListCables,
+    /// Play the jobs in a TOML manifest at the same time, each with its own cable and files
+    Jobs {
+        /// TOML file with a [[job]] table for each cable
+        manifest: std::path::PathBuf,
+    },
+    /// Expose the local cable to XVC clients such as Vivado
+    ServeXvc {
+        #[command(flatten)]
+        cable: CableArgs,
+        #[arg(long, default_value = "0.0.0.0")]
+        address: String,
+        #[arg(long, default_value_t = xvc_server_port())]
+        port: u16,
+        #[command(flatten)]
+        access: Access,
+    },
+    /// Type SVF commands at a prompt and see their TDO immediately
+    Repl {
+        #[command(flatten)]
+        cable: CableArgs,
+        /// Write the commands that ran to this file, to replay the session later
+        #[arg(long, value_name = "PATH")]
+        record: Option<String>,
+        #[command(flatten)]
+        dump: Dump,
+    },
+    /// Run an ACTION of a STAPL (.jam) file on the cable
+    Stapl {
+        #[command(flatten)]
+        cable: CableArgs,
+        /// ACTION to run; without it the file's actions are listed
+        #[arg(long)]
+        action: Option<String>,
+        /// STAPL source file
+        input: String,
+    },
+    /// Accept SVF jobs over TCP and play them one at a time on the cable; one daemon serves one
+    /// cable
+    Serve {
+        #[command(flatten)]
+        cable: CableArgs,
+        #[arg(long, default_value = "0.0.0.0:7777", value_name = "ADDRESS:PORT")]
+        listen: String,
+        /// Also serve the REST API (POST /jobs, GET /jobs/{id}, DELETE /jobs/{id}, GET /cables) on this address
+        #[arg(long, value_name = "ADDRESS:PORT")]
+        http: Option<String>,
+        /// Fail a job that has a command the player would stop at before it touches the cable
+        #[arg(long)]
+        preflight: bool,
+        /// Fail a job once it has taken longer than this
+        #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION")]
+        deadline: Option<Duration>,
+        #[command(flatten)]
+        access: Access,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct RegisterArgs {
+    #[command(flatten)]
+    cable: CableArgs,
+    /// Instruction lengths of every device on the chain, nearest TDO first
+    #[arg(long, value_delimiter = ',', value_name = "IRLENS", requires = "opcode")]
+    chain: Vec<usize>,
+    /// Opcode of the instruction for every device in --chain, in hex, or - to leave it in BYPASS
+    #[arg(long, value_delimiter = ',', value_parser = chain::parse_opcode, value_name = "OPCODES",
+          requires = "chain")]
+    opcode: Vec<Option<u64>>,
+}
+
+#[derive(Subcommand, Debug)]
+enum BscanOperation {
+    /// Print every pin without disturbing the device
+    Sample,
+    /// Drive pins with EXTEST, the rest staying at their safe values, and print every pin
+    Extest {
+        #[arg(value_parser = bscan::parse_drive, value_name = "PORT=0|1|z")]
+        drive: Vec<(String, Drive)>,
+    },
+}
+
+fn parse_device(s: &str) -> Result<(String, usize, String), String> {
+    let (name, rest) = s.split_once('=').ok_or("expected NAME=POSITION:BSDL")?;
+    let (position, path) = rest.split_once(':').ok_or("expected NAME=POSITION:BSDL")?;
+    let position = position.parse().map_err(|_| format!("bad position {}", position))?;
+    Ok((name.to_string(), position, path.to_string()))
+}
+
+fn xvc_server_port() -> u16 {
+    cable::xvc::DEFAULT_PORT
+}
+
+#[derive(clap::Args, Debug)]
+struct CableArgs {
+    /// Defaults file to use instead of ~/.config/svfplayer.toml
+    #[arg(long, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+    /// Cable type (e.g. jtagkey, usbblaster) or a spec such as
+    /// ftdi:vid=0403,pid=6010,serial=FTX1A2B,interface=A, cmsis-dap:serial=..., jlink:SERIAL,
+    /// xvc:host:2542, remote_bitbang:host:port, jtag_vpi:host:port,
+    /// gpiod:chip=/dev/gpiochip0,tck=11,tms=25,tdi=10,tdo=9, probe-rs:VID:PID:SERIAL or
+    /// sim:chain.toml for a simulated chain, or replay:session.jsonl to answer from a
+    /// --record-session recording.  Without one here or in the config file the
+    /// attached adapters are probed, and the only one found is used.
+    #[arg(short, long)]
+    cable: Option<String>,
+    #[arg(short, long)]
+    baud: Option<u32>,
+}
+
+impl CableArgs {
+    /// Combine the command line with the config file, detecting the cable if neither gives one
+    /// and exiting if there is no baud either way
+    fn resolve(&self) -> (Config, String, u32) {
+        let config = self.config();
+        let Some(baud) = self.baud.or(config.baud) else {
+            eprintln!("--baud must be given on the command line or in the config file");
+            std::process::exit(1);
+        };
+        let cable = match self.cable.clone().or(config.cable.clone()) {
+            Some(cable) => cable,
+            None => detect_cable(config.log_level.unwrap_or_default()),
+        };
+        (config, cable, baud)
+    }
+
+    fn config(&self) -> Config {
+        Config::load(self.config.as_deref()).unwrap_or_else(|e| {
+            eprintln!("config: {}", e);
+            std::process::exit(1);
+        })
+    }
+}
+
+/// The spec of the one adapter attached, exiting with the candidates if there isn't exactly one
+fn detect_cable(log_level: LogLevel) -> String {
+    let mut found = cable::detect();
+    match found.len() {
+        0 => {
+            eprintln!("no --cable given and no adapter found attached");
+            std::process::exit(1);
+        }
+        1 => {
+            let (spec, description) = found.remove(0);
+            if log_level >= LogLevel::Info {
+                println!("Cable: {} ({})", spec, description);
+            }
+            spec
+        }
+        _ => {
+            eprintln!("no --cable given and {} adapters are attached, pick one:", found.len());
+            for (spec, description) in found {
+                eprintln!("  --cable {:<50} {}", spec, description);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Without a subcommand the arguments are those of `play`
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    action: Option<Action>,
+    #[command(flatten)]
+    play: Play,
+}
+
+#[derive(clap::Args, Debug)]
+struct Play {
+    #[command(flatten)]
+    cable: CableArgs,
+    /// Re-shift an SDR whose TDO doesn't match up to this many times before failing
+    #[arg(long)]
+    retries: Option<u32>,
+    /// What a TDO mismatch does: stop, re-shift the SDR first, just warn, or ask
+    #[arg(long, value_enum)]
+    on_mismatch: Option<OnMismatch>,
+    /// Treat the TDO checks of these SDRs, by command number (N or N-M) or the instruction
+    /// loaded before them (ir=OPCODE), as soft: retried, then only warned about; may be repeated
+    #[arg(long, value_parser = checks::parse_selector, value_name = "SELECTOR")]
+    soft_check: Vec<Selector>,
+    /// Stop at the first mismatch of these SDRs' TDO checks whatever --on-mismatch says,
+    /// selected the same way; may be repeated
+    #[arg(long, value_parser = checks::parse_selector, value_name = "SELECTOR")]
+    hard_check: Vec<Selector>,
+    #[arg(long, value_enum)]
+    log_level: Option<LogLevel>,
+    /// Raise the log level a step: -v echoes every command instead of the status line, -vv
+    /// also prints TDO
+    #[arg(short, action = clap::ArgAction::Count)]
+    verbose: u8,
+    #[command(flatten)]
+    dump: Dump,
+    /// Order scan vectors are shifted in, for cables or targets that don't follow the SVF
+    /// convention of least significant bit first
+    #[arg(long, value_enum)]
+    bit_order: Option<BitOrder>,
+    /// Value driven on TDI for bits that SMASK marks as don't-care.  "random" draws new bits for
+    /// every scan, so each --repeat iteration drives different ones.
+    #[arg(long, value_enum, value_name = "0|1|previous|random")]
+    dont_care_bits: Option<DontCare>,
+    /// Seed for --dont-care-bits random, to drive the same bits again; one is picked and printed
+    /// otherwise
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+    /// Run TCK at this rate (e.g. 6MHz) whatever FREQUENCY the files ask for
+    #[arg(long, value_parser = parse_frequency, value_name = "HZ")]
+    freq: Option<f64>,
+    /// Clamp FREQUENCY commands to this rate
+    #[arg(long, value_parser = parse_frequency, value_name = "HZ")]
+    max_freq: Option<f64>,
+    /// Bytes of scan data per transfer, or "auto" to measure the cable's best transfer size at
+    /// startup
+    #[arg(long, value_parser = tune::parse_chunk_size, value_name = "BYTES|auto")]
+    chunk_size: Option<ChunkSize>,
+    /// Append a timestamped line for every command, retry and mismatch to this file
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+    /// Record every call into the cable, with what it answered and when, to play the run again
+    /// offline with --replay-session
+    #[arg(long, value_name = "PATH")]
+    record_session: Option<String>,
+    /// Answer from a --record-session recording instead of a cable, failing where the player
+    /// does other than it did then
+    #[arg(long, value_name = "PATH", conflicts_with = "record_session")]
+    replay_session: Option<String>,
+    /// When a TDO mismatch stops playback, write the commands before it, the failing scan in
+    /// full and the TAP state to this file, for a bug report
+    #[arg(long, value_name = "PATH")]
+    postmortem: Option<String>,
+    /// How many commands before the mismatch --postmortem keeps
+    #[arg(long, value_name = "N", default_value_t = postmortem::DEFAULT_COMMANDS, requires = "postmortem")]
+    postmortem_commands: usize,
+    /// Write a JSON summary of the run (bits shifted, throughput, retries, errors) to this file
+    #[arg(long, value_name = "PATH")]
+    summary: Option<String>,
+    /// Emit a tracing span per command (kind, length, end state, duration) on standard error
+    #[arg(long, value_name = "FORMAT")]
+    trace: Option<TraceFormat>,
+    /// Shell command run before the cable is opened; playback is skipped if it fails
+    #[arg(long, value_name = "COMMAND")]
+    pre_cmd: Option<String>,
+    /// Shell command run after playback, with SVFPLAYER_RESULT and SVFPLAYER_ERROR set
+    #[arg(long, value_name = "COMMAND")]
+    post_cmd: Option<String>,
+    /// What to do to the target when playback fails, in order
+    #[arg(long, value_delimiter = ',', default_value = "reset", value_name = "STEPS")]
+    on_error: Vec<AbortStep>,
+    /// SVF file played after the --on-error steps when playback fails
+    #[arg(long, value_name = "PATH")]
+    abort_svf: Option<String>,
+    /// Read every file through and check that all of its commands can be played before the
+    /// cable is opened
+    #[arg(long)]
+    preflight: bool,
+    /// Print every command as it would be played, with the TDI, MASK and SMASK it inherits,
+    /// --target padding and the states it moves through, without opening the cable
+    #[arg(long)]
+    explain: bool,
+    /// Warn about and skip commands the player can't execute, such as PIO or RUNTEST on SCK,
+    /// instead of failing
+    #[arg(long)]
+    ignore_unsupported: bool,
+    /// Refuse any SIR or SDR longer than this many bits, checked with --preflight
+    #[arg(long, value_name = "BITS")]
+    max_shift_bits: Option<u32>,
+    /// Refuse a run that would clock TCK more than this many times, LOOPs counted in full,
+    /// checked with --preflight
+    #[arg(long, value_name = "CYCLES")]
+    max_total_tck: Option<u64>,
+    /// Drive the target's system reset line around playback
+    #[arg(long, value_enum)]
+    srst: Option<Srst>,
+    /// How long SRST is held before playback starts, or before a pulse ends
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "100ms", value_name = "DURATION")]
+    srst_delay: Duration,
+    /// How long to wait after SRST is released
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s", value_name = "DURATION")]
+    srst_settle: Duration,
+    /// Fail playback, running the --on-error steps, once it has taken longer than this (e.g.
+    /// 120s); with --repeat, each time round gets this long
+    #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION")]
+    deadline: Option<Duration>,
+    /// Fail playback once one pass through a phase marked in the files takes longer than this;
+    /// may be repeated
+    #[arg(long, value_parser = phases::parse_budget, value_name = "PHASE=DURATION")]
+    phase_budget: Vec<(String, Duration)>,
+    /// Idle after each SIR and SDR so scanning takes at most this share of the time (e.g. 25%),
+    /// limiting the average current a bus-powered adapter draws
+    #[arg(long, value_parser = pacing::parse_duty, value_name = "N%")]
+    max_duty: Option<f64>,
+    /// A --max-duty of its own for a phase marked in the files; may be repeated
+    #[arg(long, value_parser = pacing::parse_phase_duty, value_name = "PHASE=N%")]
+    phase_duty: Vec<(String, f64)>,
+    /// Give up on a cable transaction that takes longer than this (e.g. 5s) and reopen the cable
+    #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION")]
+    cable_timeout: Option<Duration>,
+    /// Times the cable may be reopened after it times out or fails before playback fails, 3 by
+    /// default with --cable-timeout.  Playback resumes from the command that was interrupted,
+    /// except with --pipeline.
+    #[arg(long, value_name = "N")]
+    reconnects: Option<u32>,
+    /// Print time, USB round trips and bytes transferred per command type when done
+    #[arg(long)]
+    profile: bool,
+    /// Issue every TMS sequence and TDI write to the cable immediately instead of coalescing them
+    #[arg(long)]
+    no_batch: bool,
+    /// Run the cable on a worker thread and allow up to DEPTH SDR checks to be outstanding while
+    /// later commands are issued.  Batching is not used in this mode, and a mismatching SDR can't
+    /// be retried.
+    #[arg(long, value_name = "DEPTH")]
+    pipeline: Option<usize>,
+    /// Write the files back out to PATH with what every scan read filled in as its expected TDO,
+    /// to verify other boards against this one
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["pipeline", "watch"])]
+    capture_tdo: Option<String>,
+    /// TOML file naming fields of the TDO of particular scans, printed decoded when the run ends
+    #[arg(long, value_name = "PATH")]
+    fields: Option<std::path::PathBuf>,
+    /// Overwrite LENGTH bits of the TDI of the Nth SDR played from bit OFFSET up, with hex
+    /// (0x...) or the bytes of a file, least significant bit first; may be repeated
+    #[arg(long, value_parser = patch::parse_patch, value_name = "N:OFFSET:LENGTH:DATA")]
+    patch: Vec<Patch>,
+    /// Refuse anything the SVF specification doesn't allow, rather than tolerating common
+    /// generator quirks such as bits set beyond a vector's length, or Lattice's LOOP
+    #[arg(long, conflicts_with_all = ["includes", "define"])]
+    strict: bool,
+    /// Play the file named by each `! include "file.svf"` comment in its place
+    #[arg(long)]
+    includes: bool,
+    /// Replace ${NAME} in the files with VALUE; may be repeated
+    #[arg(long, value_parser = template::parse_define, value_name = "NAME=VALUE")]
+    define: Vec<(String, String)>,
+    /// Play files written for a single device against this one of the chain, by position
+    /// (nearest TDO first, from 0) or part name, keeping the others in BYPASS
+    #[arg(long, value_name = "POSITION|NAME")]
+    target: Option<String>,
+    /// Instruction lengths of every device on the chain, nearest TDO first, for --target instead
+    /// of detecting them
+    #[arg(long, value_delimiter = ',', value_name = "IRLENS", requires = "target")]
+    chain: Vec<usize>,
+    /// Reset the TAP and forget ENDIR/ENDDR and remembered vectors between input files, instead
+    /// of carrying them over
+    #[arg(long)]
+    reset_between: bool,
+    /// Pause before every command and wait for confirmation
+    #[arg(long)]
+    step: bool,
+    /// Pause before command N of each file (counting from 1); may be repeated
+    #[arg(long, value_name = "N")]
+    break_at: Vec<usize>,
+    /// Stop playback after command N, counting from 1 in each file as --break-at does
+    #[arg(long, value_name = "N")]
+    stop_command: Option<usize>,
+    /// When playback stops early, on an error or at --stop-command, write the ENDIR, ENDDR,
+    /// FREQUENCY, RUNTEST states and empty headers and trailers in effect to this file as SVF, to
+    /// put in front of the rest of the file for a later run
+    #[arg(long, value_name = "PATH")]
+    prologue: Option<String>,
+    /// Keep running and play the files again whenever one of them is rewritten
+    #[arg(long)]
+    watch: bool,
+    /// Play the files N times over, carrying on after failures, and report how each time went
+    #[arg(long, value_name = "N", conflicts_with = "watch")]
+    repeat: Option<u32>,
+    /// Play the files over and over until one time fails, or --repeat times
+    #[arg(long, conflicts_with = "watch")]
+    until_failure: bool,
+    /// SVF files to play, in order, over a single cable session.  "-" reads standard input.
+    /// gzip and zstd compressed files are decompressed on the fly.
+    #[arg(required = true)]
+    input: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum TraceFormat {
+    Text,
+    Json,
+}
+
+fn open_cable(name: &str, baud: u32, timeout: Option<Duration>, reconnects: Option<u32>) -> Box<dyn Adapter> {
+    if timeout.is_none() && reconnects.is_none() {
+        return cable::open(name, baud).expect("cable");
+    }
+    let name = name.to_string();
+    let open: watchdog::Open = Arc::new(move || cable::open(&name, baud));
+    Box::new(WatchdogCable::spawn(open, timeout, reconnects.unwrap_or(3)).expect("cable"))
+}
+
+/// `cable`, recording every call into it if `recording` gives a path and header
+fn record_session(cable: Box<dyn Adapter>, recording: &Option<(String, Header)>) -> Box<dyn Adapter> {
+    match recording {
+        Some((path, header)) => Box::new(Recorder::create(path, header.clone(), cable)
+            .unwrap_or_else(|e| panic!("--record-session: {}", e))),
+        None => cable,
+    }
+}
+
+fn load_devices(config: &Config) -> Database {
+    Database::load(config.devices.as_deref()).unwrap_or_else(|e| {
+        eprintln!("devices: {}", e);
+        std::process::exit(1);
+    })
+}
+
+
+/// Read the register `name` of every device that has it, with the chain and opcodes from
+/// `args`, or else from the known-device database, `opcode` picking the part's opcode
+fn read_register(name: &str, args: RegisterArgs, opcode: fn(&Part) -> Option<u64>) {
+    let fail = |e: String| -> ! {
+        eprintln!("{}: {}", name, e);
+        std::process::exit(1);
+    };
+    let (config, cable, baud) = args.cable.resolve();
+    let mut svf = Svf::new();
+    svf.log_level = config.log_level.unwrap_or_default();
+    let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+    let (irlens, opcodes) = if args.chain.is_empty() {
+        let database = load_devices(&config);
+        let idcodes = chain::idcodes(&mut jtag);
+        let parts = database.chain(&idcodes).unwrap_or_else(|e| fail(format!("{}; give --chain and --opcode", e)));
+        let irlens = chain::ir_lengths(&mut jtag, &database).unwrap_or_else(|e| fail(e));
+        if irlens.iter().zip(&parts).any(|(len, part)| *len != part.irlen) {
+            fail(format!("the chain's IR lengths {:?} aren't those of the parts it identifies as", irlens));
+        }
+        (irlens, parts.iter().map(|part| opcode(part)).collect())
+    } else {
+        (args.chain, args.opcode)
+    };
+    jtag.mode_reset();
+    let values = chain::read_register(&mut jtag, &mut svf, &irlens, &opcodes).unwrap_or_else(|e| fail(e));
+    jtag.mode_reset();
+    for (position, value) in values.iter().enumerate() {
+        match value {
+            Some(value) => println!("{}: {:08x}", position, value),
+            None => println!("{}: -", position),
+        }
+    }
+}
+
+/// Padding for `target` on a chain with `irlens`, or the detected chain if that's empty
+fn select_target(jtag: &mut JtagSM<AdapterBox>, target: &str, irlens: &[usize], config: &Config) -> Padding {
+    let fail = |e: String| -> ! {
+        eprintln!("target: {}", e);
+        std::process::exit(1);
+    };
+    let database = load_devices(config);
+    let idcodes = chain::idcodes(jtag);
+    let irlens = match irlens {
+        [] => chain::ir_lengths(jtag, &database).unwrap_or_else(|e| fail(format!("{}; give --chain", e))),
+        irlens => irlens.to_vec(),
+    };
+    let position = target::find(target, &idcodes, &database).unwrap_or_else(|e| fail(e));
+    jtag.mode_reset();
+    Padding::new(&irlens, position).unwrap_or_else(|e| fail(e))
+}
+
+/// Read the files through before the cable is touched, exiting if any of them has a command the
+/// player would stop at or goes past the --max-shift-bits and --max-total-tck limits
+fn preflight(args: &Play, limits: Option<Limits>) {
+    let mut preflight = Preflight::new(limits);
+    preflight.ignore_unsupported = args.ignore_unsupported;
+    preflight.strict = args.strict;
+    // Standard input can only be read once, so it is only checked as it plays
+    for path in args.input.iter().filter(|path| *path != "-") {
+        let mut input = open_input(path, args).expect("read");
+        if let Err(e) = preflight.check(&mut input) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print the files as --explain resolves them.  --target needs its position and --chain, as
+/// there's no cable to find them on.
+fn explain(args: &Play) {
+    let mut explainer = Explainer::new(std::io::stdout().lock());
+    explainer.patches = args.patch.clone();
+    if let Some(target) = &args.target {
+        let padding = target.parse().map_err(|_| "--explain needs --target as a position".to_string())
+            .and_then(|position| Padding::new(&args.chain, position));
+        explainer.padding = Some(padding.unwrap_or_else(|e| {
+            eprintln!("target: {}", e);
+            std::process::exit(1);
+        }));
+    }
+    for path in &args.input {
+        let mut input = open_input(path, args).expect("read");
+        if args.input.len() > 1 {
+            println!("! {}", path);
+        }
+        if let Err(e) = explainer.explain(&mut input) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn open_input(path: &str, args: &Play) -> std::io::Result<Box<dyn BufRead>> {
+    let mut input = input::open(path)?;
+    // --includes and --define work a line of text at a time, which would mangle compiled SVF;
+    // it has neither includes nor placeholders left to expand anyway
+    if compiled::is_compiled(&mut input) {
+        return Ok(input);
+    }
+    let input: Box<dyn BufRead> = if args.includes { Box::new(Includes::new(path, input)?) } else { input };
+    if args.define.is_empty() {
+        return Ok(input);
+    }
+    Ok(Box::new(Template::new(input, args.define.iter().cloned().collect())))
+}
+
+fn convert(input: &str, optimize: bool) {
+    let mut input = input::open(input).expect("read");
+    if let Err(e) = svf_writer::rewrite(&mut input, std::io::stdout().lock(), optimize) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// The checks a server makes of its clients, exiting if the token can't be read
+fn gate(access: &Access) -> Gate {
+    access.gate().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Parse the command line and run it, exiting with a failure status when it fails
+pub fn main() {
+    let args = Args::parse();
+    match args.action {
+        Some(Action::Stats { assume_freq, cable_profiles, input }) => {
+            let mut profiles = vec![];
+            for text in &cable_profiles {
+                if text == "all" {
+                    profiles.extend(cable_profile::PROFILES.iter().map(|profile| (profile, profile.max_hz)));
+                    continue;
+                }
+                profiles.push(cable_profile::parse(text).unwrap_or_else(|e| {
+                    eprintln!("--cable-profile: {}", e);
+                    std::process::exit(2);
+                }));
+            }
+            let mut input = input::open(&input).expect("read");
+            let est = stats::estimate(&mut input, assume_freq).expect("svf");
+            est.report();
+            if !profiles.is_empty() {
+                println!();
+                cable_profile::report(&est, &profiles);
+            }
+        }
+        Some(Action::Check { input }) => {
+            let mut input = input::open(&input).expect("read");
+            let issues = lint::lint(&mut input).expect("read");
+            for issue in &issues {
+                println!("line {}: {}", issue.line, issue.message);
+            }
+            std::process::exit(if issues.is_empty() { 0 } else { 1 });
+        }
+        Some(Action::Convert { input }) => convert(&input, false),
+        Some(Action::Optimize { input }) => convert(&input, true),
+        Some(Action::Compile { input, output }) => {
+            let mut svf = input::open(&input).expect("read");
+            let out = std::fs::File::create(&output).unwrap_or_else(|e| {
+                eprintln!("{}: {}", output, e);
+                std::process::exit(1);
+            });
+            if let Err(e) = compiled::compile(&mut svf, out) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Action::Diff { a, b }) => {
+            let (same, difference) = diff::diff(&a, &b).unwrap_or_else(|e| {
+                eprintln!("diff: {}", e);
+                std::process::exit(1);
+            });
+            let differs = difference.is_some();
+            match difference {
+                None => println!("{} and {} are equivalent ({} commands)", a, b, same),
+                Some(Difference::Step { a: step_a, b: step_b, field }) => {
+                    println!("{} differs after {} matching commands, at command {} of {} and {} of {}:",
+                             field, same, step_a.index, a, step_b.index, b);
+                    println!("< {}", step_a);
+                    println!("> {}", step_b);
+                }
+                Some(Difference::Ended { a_ended, next }) => {
+                    let (ended, other, mark) = if a_ended { (&a, &b, '>') } else { (&b, &a, '<') };
+                    println!("{} ends after {} matching commands, {} goes on at command {}:", ended, same, other,
+                             next.index);
+                    println!("{} {}", mark, next);
+                }
+            }
+            if differs {
+                std::process::exit(1);
+            }
+        }
+        Some(Action::Scan { cable }) => {
+            let (config, cable, baud) = cable.resolve();
+            let database = load_devices(&config);
+            let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+            let idcodes = chain::idcodes(&mut jtag);
+            if idcodes.is_empty() {
+                eprintln!("scan: no devices found");
+                std::process::exit(1);
+            }
+            let irlens = chain::ir_lengths(&mut jtag, &database).unwrap_or_else(|e| {
+                eprintln!("{} IR lengths not found: {}", color::warning(), e);
+                vec![]
+            });
+            for (position, idcode) in idcodes.iter().enumerate() {
+                let mut line = match idcode {
+                    Some(idcode) => format!("{}: {:08x}", position, idcode),
+                    None => format!("{}: BYPASS  ", position),
+                };
+                if let Some(irlen) = irlens.get(position).filter(|_| irlens.len() == idcodes.len()) {
+                    line += &format!(" IR {:<2}", irlen);
+                }
+                if let Some(description) = idcode.and_then(|idcode| database.describe(idcode)) {
+                    line += &format!(" {}", description);
+                }
+                println!("{}", line.trim_end());
+            }
+        }
+        Some(Action::Idcode { register }) if register.opcode.is_empty() => {
+            let (config, cable, baud) = register.cable.resolve();
+            let database = load_devices(&config);
+            let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+            let idcodes = chain::idcodes(&mut jtag);
+            for (position, idcode) in idcodes.iter().enumerate() {
+                match (idcode, idcode.and_then(|idcode| database.describe(idcode))) {
+                    (Some(idcode), Some(description)) => println!("{}: {:08x} {}", position, idcode, description),
+                    (Some(idcode), None) => println!("{}: {:08x}", position, idcode),
+                    (None, _) => println!("{}: BYPASS", position),
+                }
+            }
+        }
+        Some(Action::Idcode { register }) => read_register("idcode", register, |part| part.idcode_opcode),
+        Some(Action::Usercode { register }) => read_register("usercode", register, |part| part.usercode_opcode),
+        Some(Action::Bscan { cable, bsdl, chain, position, operation }) => {
+            let bsdl = Bsdl::load(&bsdl).unwrap_or_else(|e| {
+                eprintln!("bscan: {}", e);
+                std::process::exit(1);
+            });
+            let target = bscan::Target::new(&bsdl, chain, position).unwrap_or_else(|e| {
+                eprintln!("bscan: {}", e);
+                std::process::exit(1);
+            });
+            let (config, cable, baud) = cable.resolve();
+            let mut svf = Svf::new();
+            svf.log_level = config.log_level.unwrap_or_default();
+            let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+            jtag.mode_reset();
+            let pins = match operation {
+                BscanOperation::Sample => target.sample(&mut svf, &mut jtag),
+                BscanOperation::Extest { drive } => target.extest(&mut svf, &mut jtag, &drive),
+            };
+            match pins {
+                Ok(pins) => {
+                    for pin in pins {
+                        println!("{:<16} {:<12} {}", pin.port, pin.function.name(), pin.value as u8);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("bscan: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Action::Interconnect { cable, chain, devices, netlist }) => {
+            let fail = |e: String| -> ! {
+                eprintln!("interconnect: {}", e);
+                std::process::exit(1);
+            };
+            let nets = std::fs::read_to_string(&netlist)
+                .map_err(|e| format!("{}: {}", netlist, e))
+                .and_then(|text| interconnect::parse_netlist(&text).map_err(|e| format!("{}: {}", netlist, e)))
+                .unwrap_or_else(|e| fail(e));
+            let mut devices: Vec<_> = devices.into_iter()
+                .map(|(name, position, path)| (name, position, Bsdl::load(&path).unwrap_or_else(|e| fail(e))))
+                .collect();
+            // The target keeps its devices in chain order
+            devices.sort_by_key(|(_, position, _)| *position);
+            let names: Vec<_> = devices.iter().map(|(name, _, _)| name.clone()).collect();
+            let described = devices.iter().map(|(_, position, bsdl)| (*position, bsdl)).collect();
+            let target = bscan::Target::with_devices(chain, described).unwrap_or_else(|e| fail(e));
+            let (config, cable, baud) = cable.resolve();
+            let mut svf = Svf::new();
+            svf.log_level = config.log_level.unwrap_or_default();
+            let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+            jtag.mode_reset();
+            let faults = interconnect::test(&target, &names, &nets, &mut svf, &mut jtag).unwrap_or_else(|e| fail(e));
+            jtag.mode_reset();
+            for net in &nets {
+                let prefix = format!("{}: ", net.name);
+                let found: Vec<_> = faults.iter().map(|f| f.to_string()).filter(|f| f.starts_with(&prefix)).collect();
+                if found.is_empty() {
+                    println!("{}ok", prefix);
+                }
+                for fault in found {
+                    println!("{}", fault);
+                }
+            }
+            if !faults.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Some(Action::ListCables) => cable::list(),
+        Some(Action::Jobs { manifest }) => {
+            let manifest = Manifest::load(&manifest).unwrap_or_else(|e| {
+                eprintln!("jobs: {}", e);
+                std::process::exit(1);
+            });
+            std::process::exit(if manifest::run(&manifest) { 0 } else { 1 });
+        }
+        Some(Action::ServeXvc { cable, address, port, access }) => {
+            let gate = gate(&access);
+            let (_, cable_name, baud) = cable.resolve();
+            let listener = std::net::TcpListener::bind((address.as_str(), port)).expect("listen");
+            let mut cable = cable::open(&cable_name, baud).expect("cable");
+            xvc_server::serve(listener, &mut *cable, baud, &gate);
+        }
+        Some(Action::Repl { cable, record, dump }) => {
+            let (config, cable, baud) = cable.resolve();
+            let mut svf = Svf::new();
+            svf.dump = dump;
+            svf.retries = config.retries.unwrap_or(0);
+            svf.log_level = config.log_level.unwrap_or_default();
+            svf.bit_order = config.bit_order.unwrap_or_default();
+            svf.dont_care = config.dont_care_bits.unwrap_or_default();
+            if let Some(record) = record {
+                let file = std::fs::File::create(record).expect("create recording");
+                svf.recorder = Some(svf_writer::Writer::new(Box::new(file)));
+            }
+            let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+            repl::run(&mut jtag, &mut svf);
+        }
+        Some(Action::Stapl { cable, action, input }) => {
+            if input.ends_with(".jbc") {
+                eprintln!("stapl: {}: byte-code files aren't supported, only .jam source", input);
+                std::process::exit(1);
+            }
+            let text = std::fs::read_to_string(&input).expect("read input");
+            let program = stapl::parse(&text).unwrap_or_else(|e| {
+                eprintln!("stapl: {}", e);
+                std::process::exit(1);
+            });
+            let Some(action) = action else {
+                for (name, _) in &program.actions {
+                    println!("{}", name);
+                }
+                return;
+            };
+            let (config, cable, baud) = cable.resolve();
+            let mut svf = Svf::new();
+            svf.log_level = config.log_level.unwrap_or_default();
+            let mut jtag = JtagSM::new(AdapterBox(cable::open(&cable, baud).expect("cable")));
+            match stapl::Interpreter::new(&program).run(&action, &mut jtag, &mut svf) {
+                Ok(0) => (),
+                Ok(code) => {
+                    eprintln!("stapl: {} exited with code {}", action, code);
+                    std::process::exit(code as i32);
+                }
+                Err(e) => {
+                    eprintln!("stapl: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Action::Serve { cable, listen, http, preflight, deadline, access }) => {
+            let gate = gate(&access);
+            let (config, cable, baud) = cable.resolve();
+            // Clients are only let in once there is a cable to play their jobs on
+            let queue = daemon::Queue::spawn(daemon::Player {
+                cable,
+                baud,
+                retries: config.retries.unwrap_or(0),
+                log_level: config.log_level.unwrap_or_default(),
+                bit_order: config.bit_order.unwrap_or_default(),
+                dont_care: config.dont_care_bits.unwrap_or_default(),
+                preflight,
+                deadline,
+            }).unwrap_or_else(|e| {
+                eprintln!("serve: {}", e);
+                std::process::exit(1);
+            });
+            let listener = std::net::TcpListener::bind(&listen).expect("listen");
+            if let Some(http) = http {
+                let server = tiny_http::Server::http(&http).expect("listen");
+                let queue = queue.clone();
+                let gate = gate.clone();
+                std::thread::spawn(move || http::serve(server, queue, gate));
+            }
+            daemon::serve(listener, queue, gate);
+        }
+        Some(Action::Play(args)) => play(*args),
+        None => play(args.play),
+    }
+}
+
+fn play(args: Play) {
+    if let Some(format) = args.trace {
+        let subscriber = tracing_subscriber::fmt()
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .with_writer(std::io::stderr);
+        match format {
+            TraceFormat::Text => subscriber.init(),
+            TraceFormat::Json => subscriber.json().init(),
+        }
+    }
+    if args.explain {
+        explain(&args);
+        return;
+    }
+    let replaying = args.replay_session.as_ref().map(|path| {
+        let header = session::header(path).unwrap_or_else(|e| {
+            eprintln!("--replay-session: {}", e);
+            std::process::exit(1);
+        });
+        (path, header)
+    });
+    let (config, cable_name, baud) = match &replaying {
+        Some((path, header)) => (args.cable.config(), format!("replay:{}", path), header.baud),
+        None => args.cable.resolve(),
+    };
+    if args.input.iter().filter(|input| *input == "-").count() > 1 {
+        eprintln!("standard input can only be played once");
+        std::process::exit(1);
+    }
+    if (args.step || !args.break_at.is_empty()) && args.input.iter().any(|input| input == "-") {
+        eprintln!("--step and --break-at read answers from standard input, so it can't be played");
+        std::process::exit(1);
+    }
+    if (args.repeat.is_some() || args.until_failure) && args.input.iter().any(|input| input == "-") {
+        eprintln!("--repeat and --until-failure play the files more than once, so standard input can't be played");
+        std::process::exit(1);
+    }
+    if args.watch && args.input.iter().any(|input| input == "-") {
+        eprintln!("--watch needs files, standard input can't be watched");
+        std::process::exit(1);
+    }
+    // Open everything up front so a typo in the last file name is caught before touching the cable
+    let mut inputs: Vec<Box<dyn BufRead>> = args.input.iter()
+        .map(|input| open_input(input, &args).expect("read"))
+        .collect();
+    let mut svf = Svf::new();
+    if args.max_shift_bits.is_some() || args.max_total_tck.is_some() {
+        svf.limits = Some(Limits::new(args.max_shift_bits, args.max_total_tck));
+    }
+    if args.preflight || svf.limits.is_some() {
+        preflight(&args, svf.limits.clone());
+    }
+    svf.on_mismatch = args.on_mismatch.or(config.on_mismatch).unwrap_or_default();
+    let retries = if svf.on_mismatch == OnMismatch::Retry { 3 } else { 0 };
+    svf.retries = args.retries.or(config.retries).unwrap_or(retries);
+    if args.pipeline.is_some() && svf.retries > 0 {
+        eprintln!("--pipeline checks TDO after later commands have been issued, so it can't retry an SDR; \
+                   drop --retries and --on-mismatch retry");
+        std::process::exit(1);
+    }
+    let rules = |severity, selectors: &[Selector]| -> Vec<Rule> {
+        selectors.iter().map(|selector| Rule { severity, selector: selector.clone() }).collect()
+    };
+    svf.phases.budgets = args.phase_budget.clone();
+    svf.phases.duties = args.phase_duty.clone();
+    svf.max_duty = args.max_duty;
+    svf.checks = [rules(Severity::Soft, &args.soft_check), rules(Severity::Hard, &args.hard_check)].concat();
+    svf.log_level = args.log_level.or(config.log_level).unwrap_or_default();
+    for _ in 0..args.verbose {
+        svf.log_level = match svf.log_level {
+            LogLevel::Error => LogLevel::Warn,
+            LogLevel::Warn => LogLevel::Info,
+            LogLevel::Info => LogLevel::Verbose,
+            LogLevel::Verbose | LogLevel::Debug => LogLevel::Debug,
+        };
+    }
+    if svf.log_level == LogLevel::Info && std::io::stderr().is_terminal() {
+        svf.status = Some(StatusLine::new());
+    }
+    svf.dump = args.dump;
+    svf.bit_order = args.bit_order.or(config.bit_order).unwrap_or_default();
+    svf.dont_care = args.dont_care_bits.or(config.dont_care_bits).unwrap_or_default();
+    svf.step = args.step;
+    svf.ignore_unsupported = args.ignore_unsupported;
+    svf.strict = args.strict;
+    svf.breakpoints = args.break_at.clone();
+    svf.stop_after = args.stop_command;
+    svf.patches = args.patch.clone();
+    let capture = args.fields.as_ref().map(|path| {
+        let capture = Capture::new(fields::load(path).unwrap_or_else(|e| {
+            eprintln!("fields: {}", e);
+            std::process::exit(1);
+        }));
+        svf.observer = Some(Box::new(CaptureObserver(capture.clone())));
+        capture
+    });
+    if let Some(path) = &args.log_file {
+        svf.log_file = Some(SessionLog::open(path).expect("open log file"));
+    }
+    svf.postmortem = args.postmortem.clone().map(|path| Postmortem::new(path, args.postmortem_commands));
+    let mut seed = None;
+    if svf.dont_care == DontCare::Random {
+        let recorded = replaying.as_ref().and_then(|(_, header)| header.seed);
+        let picked = args.seed.or(recorded).unwrap_or_else(|| {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            now.as_nanos() as u64
+        });
+        eprintln!("Don't-care seed: {}", picked);
+        svf.log(format!("Don't-care seed {}", picked));
+        svf.rng = Rng::new(picked);
+        seed = Some(picked);
+    }
+    if let Some(path) = &args.capture_tdo {
+        let file = std::fs::File::create(path).expect("create --capture-tdo file");
+        svf.recorder = Some(svf_writer::Writer::new(Box::new(file)));
+        svf.capture_tdo = true;
+    }
+    if let Some(pre) = &args.pre_cmd {
+        let status = hooks::pre(pre, &args.input).expect("run --pre-cmd");
+        if !status.success() {
+            let error = format!("--pre-cmd failed with {}", status);
+            svf.log(&error);
+            eprintln!("{}", error);
+            if let Some(post) = &args.post_cmd {
+                hooks::post(post, &args.input, Some(&error)).expect("run --post-cmd");
+            }
+            std::process::exit(1);
+        }
+    }
+    let recording = args.record_session.clone().map(|path| {
+        let header = Header { cable: cable_name.clone(), baud, capabilities: Capabilities::default(), seed };
+        (path, header)
+    });
+    let mut cable = if let Some(depth) = args.pipeline {
+        let name = cable_name.clone();
+        let (timeout, reconnects) = (args.cable_timeout, args.reconnects);
+        let cable = PipelinedCable::spawn(move || {
+            record_session(open_cable(&name, baud, timeout, reconnects), &recording)
+        });
+        svf.pipeline = Some(cable.handle());
+        svf.pipeline_depth = depth;
+        Box::new(cable)
+    } else {
+        svf.resume = args.cable_timeout.is_some() || args.reconnects.is_some();
+        record_session(open_cable(&cable_name, baud, args.cable_timeout, args.reconnects), &recording)
+    };
+    let mut profiler = args.profile.then(Profiler::new);
+    if let Some(profiler) = &profiler {
+        cable = profiler.wrap(cable);
+    }
+    if args.summary.is_some() {
+        let telemetry = Telemetry::new();
+        cable = Box::new(ProfilingCable::new(cable, telemetry.cable.clone()));
+        svf.telemetry = Some(telemetry);
+    }
+    if !args.no_batch && args.pipeline.is_none() {
+        cable = Box::new(BatchingCable::new(cable));
+    }
+    let mut jtag = JtagSM::new(AdapterBox(cable));
+    if let Some(target) = &args.target {
+        svf.padding = Some(select_target(&mut jtag, target, &args.chain, &config));
+    }
+    svf.freq_override = args.freq;
+    svf.max_freq = args.max_freq;
+    if let Some(hz) = args.freq {
+        svf.set_frequency(&mut jtag, hz);
+    }
+    match args.chunk_size {
+        Some(ChunkSize::Bytes(bytes)) => svf.chunk_size = bytes,
+        Some(ChunkSize::Auto) => {
+            svf.chunk_size = tune::measure(&mut jtag);
+            if svf.log_level >= LogLevel::Info {
+                println!("Chunk size: {} bytes", svf.chunk_size);
+            }
+        }
+        None => (),
+    }
+    if args.watch {
+        loop {
+            srst_before(&mut jtag, &mut svf, &args);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                play_files(&mut jtag, &mut svf, &args, &mut inputs, profiler.as_mut())
+            }));
+            match result {
+                Ok(Ok(())) => svf.log("Passed"),
+                Ok(Err(e)) => {
+                    svf.log(format!("svf: {}", e));
+                    eprintln!("svf: {}", e);
+                    abort(&mut jtag, &mut svf, &args);
+                }
+                Err(e) => {
+                    svf.log(format!("error: {}", panic_message(&*e)));
+                    eprintln!("error: {}", panic_message(&*e));
+                    abort(&mut jtag, &mut svf, &args);
+                }
+            }
+            srst_after(&mut jtag, &mut svf, &args);
+            jtag.cable.0.flush();
+            eprintln!("Watching for changes");
+            inputs = loop {
+                watch::wait_for_change(&args.input);
+                match args.input.iter().map(|input| open_input(input, &args)).collect() {
+                    Ok(inputs) => break inputs,
+                    Err(e) => eprintln!("read: {}", e),
+                }
+            };
+            svf.reset();
+            jtag.mode_reset();
+        }
+    }
+    let repeating = args.repeat.is_some() || args.until_failure;
+    let result = if repeating {
+        soak(&mut jtag, &mut svf, &args, &mut inputs, profiler.as_mut())
+    } else {
+        srst_before(&mut jtag, &mut svf, &args);
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            play_files(&mut jtag, &mut svf, &args, &mut inputs, profiler.as_mut())
+        }))
+    };
+    let error = outcome(&mut svf, &result);
+    if svf.stopped {
+        let line = format!("Stopped after command {}", args.stop_command.unwrap_or(0));
+        eprintln!("{}", line);
+        svf.log(line);
+    }
+    svf.log(error.as_deref().unwrap_or("Passed"));
+    if let Some(path) = args.prologue.as_ref().filter(|_| error.is_some() || svf.stopped) {
+        std::fs::write(path, svf.prologue()).expect("write prologue");
+    }
+    if !svf.phases.phases.is_empty() && svf.log_level >= LogLevel::Info {
+        for line in svf.phases.report() {
+            println!("{}", line);
+        }
+    }
+    if let Some(capture) = &capture {
+        for line in capture.borrow().report() {
+            println!("{}", line);
+        }
+    }
+    // Each time round was already cleaned up after
+    if !repeating {
+        if error.is_some() {
+            abort(&mut jtag, &mut svf, &args);
+        }
+        srst_after(&mut jtag, &mut svf, &args);
+    }
+    if let Some(post) = &args.post_cmd {
+        // Let the target see the last of the traffic before the hook acts on it
+        jtag.cable.0.flush();
+        let status = hooks::post(post, &args.input, error.as_deref()).expect("run --post-cmd");
+        if !status.success() && svf.log_level >= LogLevel::Warn {
+            eprintln!("{} --post-cmd failed with {}", color::warning(), status);
+        }
+    }
+    if let (Some(path), Some(telemetry)) = (&args.summary, &svf.telemetry) {
+        telemetry.write(path, error.as_deref(), &svf.phases).expect("write summary");
+    }
+    // A failed run is profiled too, often where it's most wanted
+    if let Some(profiler) = &profiler {
+        profiler.report();
+    }
+    match result {
+        Ok(Ok(())) => (),
+        Ok(Err(e)) => panic!("svf: {:?}", e),
+        Err(e) => std::panic::resume_unwind(e),
+    }
+    // Dropping the state machine flushes anything still queued in the cable
+    drop(jtag);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cable::sim::Sim;
+    use crate::cable::ShiftCable;
+
+    #[test]
+    fn compiled_files_play_through_define_and_includes() {
+        let path = std::env::temp_dir().join(format!("svfplayer-cli-{}.svfc", std::process::id()));
+        let mut compiled = vec![];
+        compiled::compile(&mut "SIR 4 TDI (e);\nSDR 32 TDI (fffefdfc) TDO (12345679);\n".as_bytes(), &mut compiled).unwrap();
+        std::fs::write(&path, compiled).unwrap();
+        let path = path.to_str().unwrap();
+        let args = Args::try_parse_from(["svfplayer", "--define", "ID=12345679", "--includes", path]).unwrap();
+        let mut input = open_input(path, &args.play).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let chain = "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n";
+        let mut jtag = JtagSM::new(AdapterBox(Box::new(ShiftCable(Sim::parse(chain).unwrap()))));
+        run_svf(&mut jtag, &mut Svf::new(), &mut input, None).unwrap();
+    }
+}
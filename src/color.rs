@@ -0,0 +1,88 @@
+//! Colored diagnostics on a terminal, left plain when stderr isn't one or `NO_COLOR` is set.  A
+//! TDO mismatch is shown with the differing nibbles highlighted, or marked underneath without
+//! color, so the bad bits of a long vector can be found by eye.
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+const RED: &str = "\x1b[1;31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether to color what goes to stderr
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stderr().is_terminal()
+    })
+}
+
+fn paint(text: &str, color: &str) -> String {
+    if enabled() {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// The `Warning:` that starts a warning
+pub fn warning() -> String {
+    paint("Warning:", YELLOW)
+}
+
+pub fn error(text: &str) -> String {
+    paint(text, RED)
+}
+
+/// Nibbles of `data`, most significant first as SVF writes them
+fn nibbles(data: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    data.iter().rev().flat_map(|b| [b >> 4, b & 0xf])
+}
+
+/// `read`, `expected` and `mask` as lines of hex aligned under each other, the nibbles that
+/// differ under the mask highlighted
+pub fn tdo_mismatch(read: &[u8], expected: &[u8], mask: &[u8]) -> String {
+    let bad: Vec<bool> = nibbles(read).zip(nibbles(expected)).zip(nibbles(mask))
+        .map(|((r, e), m)| (r ^ e) & m != 0)
+        .collect();
+    let line = |data: &[u8], color: Option<&str>| -> String {
+        nibbles(data).zip(&bad)
+            .map(|(n, bad)| match color.filter(|_| *bad) {
+                Some(color) => paint(&format!("{:X}", n), color),
+                None => format!("{:X}", n),
+            })
+            .collect()
+    };
+    let mut text = format!("{}\n  read     {}\n  expected {}\n  mask     {}", error("TDO mismatch:"),
+                           line(read, Some(RED)), line(expected, Some(GREEN)), line(mask, None));
+    if !enabled() {
+        let marks: String = bad.iter().map(|bad| if *bad { '^' } else { ' ' }).collect();
+        text += &format!("\n           {}", marks.trim_end());
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differing_nibbles_are_picked_out() {
+        let text = tdo_mismatch(&[0x79, 0x56], &[0x19, 0x56], &[0xff, 0x0f]);
+        // Without the escapes, whether or not the tests run on a terminal
+        let mut plain = String::new();
+        let mut escape = false;
+        for c in text.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                c if !escape => plain.push(c),
+                _ => (),
+            }
+        }
+        assert!(plain.contains("read     5679\n  expected 5619\n  mask     0FFF"));
+        if !enabled() {
+            assert!(plain.ends_with("\n             ^"));
+        }
+    }
+}
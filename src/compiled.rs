@@ -0,0 +1,380 @@
+//! Pre-parsed SVF, as written by `svfplayer compile`, for files played over and over where
+//! parsing the text each time would be wasted.  The player, `--preflight` and the status line
+//! take it wherever they take SVF, telling the two apart by the magic number that starts it.
+//!
+//! After the magic number each command is a tag byte and its fields, little-endian, with scan
+//! vectors as the bytes the parser produced.  LOOP and ENDLOOP are records of their own and come
+//! back as the same `lattice` markers the text reader leaves, as do phase comments.
+use std::io::{self, BufRead, Read, Write};
+
+use svf::{Command, ParseError, PIOMapDirection, Pattern, RunClock, RunTestForm, RunTestTime, State, TRSTMode, VectorChar};
+
+use crate::lattice::{LoopFilter, Marker, Markers};
+
+/// "SVFC" and the format version
+const MAGIC: &[u8] = b"SVFC\x01";
+
+const END_DR: u8 = 0;
+const END_IR: u8 = 1;
+const FREQUENCY: u8 = 2;
+const HDR: u8 = 3;
+const HIR: u8 = 4;
+const SDR: u8 = 5;
+const SIR: u8 = 6;
+const TDR: u8 = 7;
+const TIR: u8 = 8;
+const RUNTEST: u8 = 9;
+const STATE: u8 = 10;
+const TRST: u8 = 11;
+const PIO: u8 = 12;
+const PIO_MAP: u8 = 13;
+const LOOP: u8 = 14;
+const END_LOOP: u8 = 15;
+const PHASE: u8 = 16;
+
+const STATES: [State; 16] = [
+    State::RESET, State::IDLE,
+    State::DRSELECT, State::DRCAPTURE, State::DRSHIFT, State::DREXIT1, State::DRPAUSE, State::DREXIT2, State::DRUPDATE,
+    State::IRSELECT, State::IRCAPTURE, State::IRSHIFT, State::IREXIT1, State::IRPAUSE, State::IREXIT2, State::IRUPDATE,
+];
+const VECTOR_CHARS: [VectorChar; 6] = [VectorChar::H, VectorChar::L, VectorChar::Z, VectorChar::U, VectorChar::D, VectorChar::X];
+const DIRECTIONS: [PIOMapDirection; 3] = [PIOMapDirection::In, PIOMapDirection::Out, PIOMapDirection::InOut];
+const TRST_MODES: [TRSTMode; 4] = [TRSTMode::On, TRSTMode::Off, TRSTMode::Z, TRSTMode::Absent];
+/// Stands for an absent state or time
+const NONE: u8 = 0xff;
+
+/// Whether `input` is compiled SVF, leaving it unread
+pub fn is_compiled(input: &mut impl BufRead) -> bool {
+    input.fill_buf().is_ok_and(|buf| buf.starts_with(MAGIC))
+}
+
+fn index<T: PartialEq>(table: &[T], value: &T) -> u8 {
+    table.iter().position(|v| v == value).unwrap() as u8
+}
+
+struct Encoder<W> {
+    out: W,
+}
+
+impl<W: Write> Encoder<W> {
+    fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.out.write_all(&[value])
+    }
+
+    fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.out.write_all(&value.to_le_bytes())
+    }
+
+    fn f64(&mut self, value: f64) -> io::Result<()> {
+        self.out.write_all(&value.to_le_bytes())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.u32(bytes.len() as u32)?;
+        self.out.write_all(bytes)
+    }
+
+    fn state(&mut self, state: Option<State>) -> io::Result<()> {
+        self.u8(state.map_or(NONE, |state| index(&STATES, &state)))
+    }
+
+    fn time(&mut self, time: &Option<RunTestTime>) -> io::Result<()> {
+        match time {
+            Some(time) => {
+                self.u8(1)?;
+                self.f64(time.min)?;
+                self.f64(time.max.unwrap_or(f64::NAN))
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn pattern(&mut self, tag: u8, pattern: &Pattern) -> io::Result<()> {
+        self.u8(tag)?;
+        self.u32(pattern.length)?;
+        let vectors = [&pattern.tdi, &pattern.tdo, &pattern.mask, &pattern.smask];
+        self.u8(vectors.iter().enumerate().fold(0, |present, (i, v)| present | (v.is_some() as u8) << i))?;
+        for vector in vectors.into_iter().flatten() {
+            self.bytes(vector)?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, cmd: &Command) -> io::Result<()> {
+        match cmd {
+            Command::EndDR(state) => {
+                self.u8(END_DR)?;
+                self.state(Some(*state))
+            }
+            Command::EndIR(state) => {
+                self.u8(END_IR)?;
+                self.state(Some(*state))
+            }
+            Command::Frequency(hz) => {
+                self.u8(FREQUENCY)?;
+                self.f64(hz.unwrap_or(f64::NAN))
+            }
+            Command::HDR(pattern) => self.pattern(HDR, pattern),
+            Command::HIR(pattern) => self.pattern(HIR, pattern),
+            Command::SDR(pattern) => self.pattern(SDR, pattern),
+            Command::SIR(pattern) => self.pattern(SIR, pattern),
+            Command::TDR(pattern) => self.pattern(TDR, pattern),
+            Command::TIR(pattern) => self.pattern(TIR, pattern),
+            Command::RunTest { run_state, form, end_state } => {
+                self.u8(RUNTEST)?;
+                self.state(*run_state)?;
+                self.state(*end_state)?;
+                match form {
+                    RunTestForm::Clocked { run_count, run_clk, time } => {
+                        self.u8(0)?;
+                        self.u32(*run_count)?;
+                        self.u8((*run_clk == RunClock::SCK) as u8)?;
+                        self.time(time)
+                    }
+                    RunTestForm::Timed(time) => {
+                        self.u8(1)?;
+                        self.time(&Some(time.clone()))
+                    }
+                }
+            }
+            Command::State { path, end } => {
+                self.u8(STATE)?;
+                self.state(Some(*end))?;
+                match path {
+                    Some(path) => {
+                        self.u8(1)?;
+                        self.bytes(&path.iter().map(|state| index(&STATES, state)).collect::<Vec<_>>())
+                    }
+                    None => self.u8(0),
+                }
+            }
+            Command::TRST(mode) => {
+                self.u8(TRST)?;
+                self.u8(index(&TRST_MODES, mode))
+            }
+            Command::PIO(vector) => {
+                self.u8(PIO)?;
+                self.bytes(&vector.iter().map(|c| index(&VECTOR_CHARS, c)).collect::<Vec<_>>())
+            }
+            Command::PIOMap(columns) => {
+                self.u8(PIO_MAP)?;
+                self.u32(columns.len() as u32)?;
+                for (direction, name) in columns {
+                    self.u8(index(&DIRECTIONS, direction))?;
+                    self.bytes(name.as_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parse the SVF in `input` once and write it to `out` compiled
+pub fn compile(input: &mut impl BufRead, out: impl Write) -> io::Result<()> {
+    let markers = Markers::default();
+    let mut input = LoopFilter::new(input, markers.clone());
+    let mut encoder = Encoder { out: io::BufWriter::new(out) };
+    encoder.out.write_all(MAGIC)?;
+    let mut commands = svf::parse_iter_bufread(&mut input).enumerate();
+    loop {
+        let next = commands.next();
+        let i = next.as_ref().map_or(usize::MAX, |(i, _)| *i);
+        while markers.borrow().front().is_some_and(|m| m.before() <= i) {
+            match markers.borrow_mut().pop_front().unwrap() {
+                Marker::Loop { count, .. } => {
+                    encoder.u8(LOOP)?;
+                    encoder.u32(count)?;
+                }
+                Marker::EndLoop { .. } => encoder.u8(END_LOOP)?,
+                Marker::Phase { name, .. } => {
+                    encoder.u8(PHASE)?;
+                    encoder.bytes(name.as_bytes())?;
+                }
+            }
+        }
+        let Some((_, cmd)) = next else {
+            break;
+        };
+        let cmd = cmd.map_err(|e| io::Error::other(e.to_string()))?;
+        encoder.command(&cmd)?;
+    }
+    encoder.out.flush()
+}
+
+/// Commands read back from compiled SVF, leaving LOOP markers in `markers` as `LoopFilter`
+/// does.  A file that is cut short or corrupt panics, as it can only come from a broken copy.
+pub struct Reader<R> {
+    inner: R,
+    markers: Markers,
+    commands: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(mut inner: R, markers: Markers) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        inner.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::other("not compiled SVF of this version"));
+        }
+        Ok(Reader { inner, markers, commands: 0 })
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        let mut buf = [0; 8];
+        self.inner.read_exact(&mut buf)?;
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        let mut bytes = vec![];
+        (&mut self.inner).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+
+    fn lookup<T: Copy>(table: &[T], index: u8) -> io::Result<T> {
+        table.get(index as usize).copied().ok_or_else(|| io::Error::other(format!("bad index {}", index)))
+    }
+
+    fn state(&mut self) -> io::Result<Option<State>> {
+        match self.u8()? {
+            NONE => Ok(None),
+            index => Self::lookup(&STATES, index).map(Some),
+        }
+    }
+
+    fn time(&mut self) -> io::Result<Option<RunTestTime>> {
+        if self.u8()? == 0 {
+            return Ok(None);
+        }
+        let min = self.f64()?;
+        let max = Some(self.f64()?).filter(|max| !max.is_nan());
+        Ok(Some(RunTestTime { min, max }))
+    }
+
+    fn pattern(&mut self) -> io::Result<Pattern> {
+        let length = self.u32()?;
+        let present = self.u8()?;
+        let mut vector = |i: u8| if present & 1 << i != 0 { self.bytes().map(Some) } else { Ok(None) };
+        Ok(Pattern { length, tdi: vector(0)?, tdo: vector(1)?, mask: vector(2)?, smask: vector(3)? })
+    }
+
+    /// The next command, None at the end of the file
+    fn command(&mut self) -> io::Result<Option<Command>> {
+        loop {
+            let mut tag = [0];
+            if self.inner.read(&mut tag)? == 0 {
+                return Ok(None);
+            }
+            let cmd = match tag[0] {
+                END_DR => Command::EndDR(self.state()?.ok_or_else(|| io::Error::other("ENDDR without a state"))?),
+                END_IR => Command::EndIR(self.state()?.ok_or_else(|| io::Error::other("ENDIR without a state"))?),
+                FREQUENCY => Command::Frequency(Some(self.f64()?).filter(|hz| !hz.is_nan())),
+                HDR => Command::HDR(self.pattern()?),
+                HIR => Command::HIR(self.pattern()?),
+                SDR => Command::SDR(self.pattern()?),
+                SIR => Command::SIR(self.pattern()?),
+                TDR => Command::TDR(self.pattern()?),
+                TIR => Command::TIR(self.pattern()?),
+                RUNTEST => {
+                    let run_state = self.state()?;
+                    let end_state = self.state()?;
+                    let form = match self.u8()? {
+                        0 => {
+                            let run_count = self.u32()?;
+                            let run_clk = if self.u8()? == 0 { RunClock::TCK } else { RunClock::SCK };
+                            RunTestForm::Clocked { run_count, run_clk, time: self.time()? }
+                        }
+                        _ => RunTestForm::Timed(self.time()?.ok_or_else(|| io::Error::other("RUNTEST without a time"))?),
+                    };
+                    Command::RunTest { run_state, form, end_state }
+                }
+                STATE => {
+                    let end = self.state()?.ok_or_else(|| io::Error::other("STATE without an end state"))?;
+                    let path = match self.u8()? {
+                        0 => None,
+                        _ => Some(self.bytes()?.into_iter().map(|i| Self::lookup(&STATES, i)).collect::<io::Result<_>>()?),
+                    };
+                    Command::State { path, end }
+                }
+                TRST => Command::TRST(Self::lookup(&TRST_MODES, self.u8()?)?),
+                PIO => Command::PIO(self.bytes()?.into_iter().map(|i| Self::lookup(&VECTOR_CHARS, i)).collect::<io::Result<_>>()?),
+                PIO_MAP => {
+                    let mut columns = vec![];
+                    for _ in 0..self.u32()? {
+                        let direction = Self::lookup(&DIRECTIONS, self.u8()?)?;
+                        let name = String::from_utf8(self.bytes()?).map_err(io::Error::other)?;
+                        columns.push((direction, name));
+                    }
+                    Command::PIOMap(columns)
+                }
+                LOOP => {
+                    let count = self.u32()?;
+                    self.markers.borrow_mut().push_back(Marker::Loop { before: self.commands, count });
+                    continue;
+                }
+                END_LOOP => {
+                    self.markers.borrow_mut().push_back(Marker::EndLoop { before: self.commands });
+                    continue;
+                }
+                PHASE => {
+                    let name = String::from_utf8(self.bytes()?).map_err(io::Error::other)?;
+                    self.markers.borrow_mut().push_back(Marker::Phase { before: self.commands, name });
+                    continue;
+                }
+                tag => return Err(io::Error::other(format!("unknown record {}", tag))),
+            };
+            self.commands += 1;
+            return Ok(Some(cmd));
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Command, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.command() {
+            Ok(cmd) => cmd.map(Ok),
+            Err(e) => panic!("compiled SVF after command {}: {}", self.commands, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_and_loops_come_back_as_written() {
+        let text = "FREQUENCY 1E6 HZ;\nTRST OFF;\nENDDR DRPAUSE;\nSIR 4 TDI (e);\n\
+                    ! LOOP 3;\nSDR 12 TDI (abc) TDO (123) MASK (0ff) SMASK (fff);\nRUNTEST IDLE 10 TCK 1E-3 SEC MAXIMUM 1 SEC ENDSTATE IDLE;\n! ENDLOOP;\n\
+                    STATE IDLE DRSELECT DRCAPTURE DREXIT1 DRPAUSE;\nRUNTEST 1E-2 SEC;\nFREQUENCY;\n";
+        let expected = svf::parse_complete(&text.replace("! LOOP 3;\n", "").replace("! ENDLOOP;\n", "")).unwrap();
+        let mut compiled = vec![];
+        compile(&mut text.replace("! ", "").as_bytes(), &mut compiled).unwrap();
+        assert!(is_compiled(&mut &compiled[..]));
+        let markers = Markers::default();
+        let commands: Vec<Command> = Reader::new(&compiled[..], markers.clone()).unwrap().map(Result::unwrap).collect();
+        assert_eq!(commands, expected);
+        let markers: Vec<(bool, usize)> = markers.borrow().iter()
+            .map(|m| (matches!(m, Marker::Loop { count: 3, .. }), m.before()))
+            .collect();
+        assert_eq!(markers, [(true, 4), (false, 6)]);
+    }
+}
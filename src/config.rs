@@ -0,0 +1,93 @@
+//! Defaults loaded from `~/.config/svfplayer.toml` (or `--config`).  Anything given on the
+//! command line takes precedence over the file.
+//!
+//! ```toml
+//! cable = "jtagkey"
+//! baud = 6000000
+//! retries = 2
+//! on_mismatch = "prompt"
+//! log_level = "warn"
+//! bit_order = "lsb-first"
+//! devices = "/etc/svfplayer/devices.toml"
+//! ```
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::bits::{BitOrder, DontCare};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, PartialOrd, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Only report errors
+    Error,
+    /// Also report warnings, e.g. for commands that are ignored
+    Warn,
+    /// Show a status line while playing, on a terminal
+    #[default]
+    Info,
+    /// Echo every command as it is played instead
+    Verbose,
+    /// Also print the TDI driven and TDO captured by every checked scan
+    Debug,
+}
+
+/// What a TDO mismatch outside a LOOP does
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OnMismatch {
+    /// Stop playback, after any --retries
+    #[default]
+    Abort,
+    /// Re-shift a failing SDR up to --retries times, 3 if not given, then stop
+    Retry,
+    /// Report the mismatch and carry on
+    Warn,
+    /// Ask whether to carry on
+    Prompt,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub cable: Option<String>,
+    pub baud: Option<u32>,
+    /// Number of times a failing SDR check is re-shifted before giving up
+    pub retries: Option<u32>,
+    pub on_mismatch: Option<OnMismatch>,
+    pub log_level: Option<LogLevel>,
+    pub bit_order: Option<BitOrder>,
+    pub dont_care_bits: Option<DontCare>,
+    /// Parts and manufacturers to know besides the built-in ones
+    pub devices: Option<PathBuf>,
+}
+
+/// `svfplayer.toml` in `$XDG_CONFIG_HOME`, or else in `%APPDATA%` on Windows and `~/.config`
+/// everywhere else, macOS included
+fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("svfplayer.toml"))
+}
+
+impl Config {
+    fn parse(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Load `path` if given (it must exist), otherwise the per-user file if there is one
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        match path {
+            Some(path) => Self::parse(path),
+            None => match default_path() {
+                Some(path) if path.exists() => Self::parse(&path),
+                _ => Ok(Config::default()),
+            },
+        }
+    }
+}
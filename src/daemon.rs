@@ -0,0 +1,756 @@
+//! Job server started by `svfplayer serve`.  Clients submit SVF over TCP and the jobs are played
+//! one after another on the daemon's cable, which is owned by a single worker thread.  A daemon
+//! drives exactly one cable and keeps one queue for it; a station with several cables runs a
+//! daemon for each, on its own port.
+//!
+//! When the daemon was given a token, a client's first line has to be `AUTH <token>`, answered
+//! with `AUTHORIZED`; anything else is answered `FAILED - not authorized` and the connection
+//! closed.  Each request is then one line, optionally followed by a payload:
+//!
+//! ```text
+//! PLAY <bytes>\n<bytes of SVF or compiled SVF>
+//! FILE <path>\n
+//! CANCEL <id>\n
+//! ```
+//!
+//! `PLAY` and `FILE` may end in `PRIORITY <n>`.  Jobs of higher priority play first, and jobs of
+//! the same priority in the order they came.  `CANCEL` answers `CANCELLED <id>`: a queued job is
+//! dropped, a running one stops after the command playing and the TAP is reset, and either way
+//! its client sees `FAILED <id> cancelled`.
+//!
+//! Otherwise the server answers with a stream of event lines ending in `DONE` or `FAILED`, after
+//! which the connection accepts the next request:
+//!
+//! ```text
+//! QUEUED <id> <jobs ahead>
+//! STARTED <id>
+//! PROGRESS <id> <commands done>/<total> <percent>% <phase playing, or -> <kind of command playing>
+//! PHASE <id> <name> <commands> <bits> <mismatches> <seconds>[ failed]
+//! DONE <id>
+//! FAILED <id> <message>
+//! ```
+//!
+//! Jobs play as `svfplayer play` plays a file, LOOPs, phase markers and all.  With `--preflight`
+//! a job that couldn't play through is failed before it touches the cable, and with `--deadline`
+//! each job has that long to finish.
+//!
+//! The `PHASE` lines total each phase the job marked, once it has ended, and a job that fails
+//! inside a phase names it in its `FAILED` message.
+//!
+//! Progress is reported at most once a percent.  A client reading slower than that is sent only
+//! the latest report when it catches up, so it never holds up the cable.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use jtag_taps::statemachine::JtagSM;
+use serde::Serialize;
+
+use crate::access::Gate;
+use crate::batch::BatchingCable;
+use crate::bits::{BitOrder, DontCare};
+use crate::cable::{self, AdapterBox};
+use crate::color;
+use crate::config::LogLevel;
+use crate::observer::Observer;
+use crate::phases::Phase;
+use crate::preflight::Preflight;
+use crate::profile::Profiler;
+use crate::{panic_message, run_svf, status, Svf};
+
+/// Largest SVF payload accepted in a PLAY request, or in the body of an HTTP POST
+pub(crate) const MAX_PAYLOAD: usize = 256 * 1024 * 1024;
+
+pub enum Event {
+    Queued(usize),
+    Started,
+    Progress { done: usize, total: usize, phase: Option<String>, command: &'static str },
+    Phase(PhaseReport),
+    Done,
+    Failed(String),
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// What is known about a job, as reported by the HTTP front-end
+#[derive(Clone, Serialize)]
+pub struct Status {
+    pub id: u64,
+    pub state: State,
+    pub priority: i32,
+    pub commands_done: usize,
+    pub commands_total: usize,
+    pub percent: f64,
+    /// Phase playing, as marked by a `! phase NAME` comment
+    pub phase: Option<String>,
+    /// Kind of the command last played
+    pub command: Option<&'static str>,
+    /// Totals of each phase, once the job has ended
+    pub phases: Vec<PhaseReport>,
+    pub error: Option<String>,
+}
+
+/// How one phase of a job went
+#[derive(Clone, Serialize)]
+pub struct PhaseReport {
+    pub name: String,
+    pub commands: u64,
+    pub bits: u64,
+    pub mismatches: u64,
+    pub seconds: f64,
+    pub failed: bool,
+}
+
+impl From<&Phase> for PhaseReport {
+    fn from(phase: &Phase) -> Self {
+        PhaseReport {
+            name: phase.name.clone(),
+            commands: phase.commands,
+            bits: phase.bits,
+            mismatches: phase.mismatches,
+            seconds: phase.elapsed.as_secs_f64(),
+            failed: phase.failed,
+        }
+    }
+}
+
+/// Events on their way to one job's client.  Only the latest progress report is kept, so the
+/// worker never waits on a client, however slowly it reads.
+#[derive(Default)]
+pub struct Feed {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Pending {
+    events: VecDeque<Event>,
+    /// Newer than everything in `events`
+    progress: Option<Event>,
+}
+
+impl Feed {
+    pub fn send(&self, event: Event) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Event::Progress { .. } = event {
+            pending.progress = Some(event);
+        } else {
+            if let Some(progress) = pending.progress.take() {
+                pending.events.push_back(progress);
+            }
+            pending.events.push_back(event);
+        }
+        self.ready.notify_one();
+    }
+
+    /// Wait for the next event
+    pub fn recv(&self) -> Event {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            if let Some(event) = pending.events.pop_front().or_else(|| pending.progress.take()) {
+                return event;
+            }
+            pending = self.ready.wait(pending).unwrap();
+        }
+    }
+}
+
+pub struct Job {
+    pub id: u64,
+    pub svf: Vec<u8>,
+    pub priority: i32,
+    pub events: Arc<Feed>,
+    /// Set to stop the job after the command playing
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// The job playing, if any, and the flag that cancels it
+type Running = Mutex<Option<(u64, Arc<AtomicBool>)>>;
+
+/// Jobs waiting for the cable
+#[derive(Default)]
+struct Waiting {
+    jobs: Mutex<Vec<Job>>,
+    ready: Condvar,
+}
+
+impl Waiting {
+    fn push(&self, job: Job) {
+        self.jobs.lock().unwrap().push(job);
+        self.ready.notify_one();
+    }
+
+    /// Wait for a job, taking the one of highest priority that came first, and mark it `running`
+    /// before it can be looked for among the waiting
+    fn pop(&self, running: &Running) -> Job {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            let next = jobs.iter().enumerate()
+                .max_by_key(|(_, job)| (job.priority, std::cmp::Reverse(job.id)))
+                .map(|(i, _)| i);
+            if let Some(i) = next {
+                let job = jobs.remove(i);
+                *running.lock().unwrap() = Some((job.id, job.cancel.clone()));
+                return job;
+            }
+            jobs = self.ready.wait(jobs).unwrap();
+        }
+    }
+}
+
+/// Settings applied to the player for every job
+pub struct Player {
+    pub cable: String,
+    pub baud: u32,
+    pub retries: u32,
+    pub log_level: LogLevel,
+    pub bit_order: BitOrder,
+    pub dont_care: DontCare,
+    /// Check each job can play through before it starts
+    pub preflight: bool,
+    /// How long each job has to finish
+    pub deadline: Option<Duration>,
+}
+
+/// Reports a job's progress to its client and to the status table, once per percent
+struct JobProgress {
+    id: u64,
+    events: Arc<Feed>,
+    status: Arc<Mutex<BTreeMap<u64, Status>>>,
+    percent: usize,
+    phase: Option<String>,
+    command: &'static str,
+    /// Commands in the job, counted before it started
+    total: Option<usize>,
+}
+
+impl Observer for JobProgress {
+    fn on_command(&mut self, cmd: &svf::Command) {
+        self.command = Profiler::kind(cmd);
+    }
+
+    fn on_phase(&mut self, name: &str) {
+        self.phase = Some(name.to_string());
+    }
+
+    fn on_progress(&mut self, done: usize, total: Option<usize>) {
+        let Some(total) = total.or(self.total) else {
+            return;
+        };
+        if done * 100 / total <= self.percent && done != total {
+            return;
+        }
+        self.percent = done * 100 / total;
+        if let Some(status) = self.status.lock().unwrap().get_mut(&self.id) {
+            status.commands_done = done;
+            status.commands_total = total;
+            status.percent = done as f64 * 100.0 / total as f64;
+            status.phase = self.phase.clone();
+            status.command = Some(self.command);
+        }
+        self.events.send(Event::Progress { done, total, phase: self.phase.clone(), command: self.command });
+    }
+
+    fn wants_tdo(&self) -> bool {
+        false
+    }
+}
+
+/// Submits jobs to the worker that owns the cable.  There is one of these per daemon, as there
+/// is one cable.
+#[derive(Clone)]
+pub struct Queue {
+    waiting: Arc<Waiting>,
+    running: Arc<Running>,
+    pending: Arc<AtomicUsize>,
+    next_id: Arc<AtomicU64>,
+    status: Arc<Mutex<BTreeMap<u64, Status>>>,
+    /// The cable spec jobs are played on
+    pub cable: Arc<str>,
+}
+
+impl Queue {
+    /// Start the worker thread.  The cable is opened on the worker and stays open between jobs;
+    /// this returns once it is open, or with the error it couldn't be opened with.
+    pub fn spawn(player: Player) -> Result<Self, String> {
+        let waiting = Arc::new(Waiting::default());
+        let worker_waiting = waiting.clone();
+        let running = Arc::new(Mutex::new(None));
+        let worker_running = running.clone();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let worker_pending = pending.clone();
+        let status = Arc::new(Mutex::new(BTreeMap::new()));
+        let worker_status: Arc<Mutex<BTreeMap<u64, Status>>> = status.clone();
+        let cable = Arc::from(player.cable.as_str());
+        let player_cable = player.cable.clone();
+        let (opened, open_result) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let update = |id, f: &dyn Fn(&mut Status)| {
+                if let Some(status) = worker_status.lock().unwrap().get_mut(&id) {
+                    f(status);
+                }
+            };
+            let cable = match cable::open(&player.cable, player.baud) {
+                Ok(cable) => cable,
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                    return;
+                }
+            };
+            let mut jtag = JtagSM::new(AdapterBox(Box::new(BatchingCable::new(cable))));
+            jtag.cable.0.flush();
+            let _ = opened.send(Ok(()));
+            let mut svf = Svf::new();
+            svf.retries = player.retries;
+            svf.log_level = player.log_level;
+            svf.bit_order = player.bit_order;
+            svf.dont_care = player.dont_care;
+            // Set once the cable stops answering, after which nothing can play on it
+            let mut lost = None;
+            loop {
+                let job = worker_waiting.pop(&worker_running);
+                let result = match &lost {
+                    Some(e) => Err(format!("the cable was lost: {}", e)),
+                    None => {
+                        update(job.id, &|status| status.state = State::Running);
+                        job.events.send(Event::Started);
+                        svf.observer = Some(Box::new(JobProgress {
+                            id: job.id,
+                            events: job.events.clone(),
+                            status: worker_status.clone(),
+                            percent: 0,
+                            phase: None,
+                            command: "",
+                            total: status::count(&mut &job.svf[..]),
+                        }));
+                        let result = run_job(&mut jtag, &mut svf, &job, &player);
+                        let phases: Vec<PhaseReport> = svf.phases.phases.iter().map(PhaseReport::from).collect();
+                        update(job.id, &|status| status.phases = phases.clone());
+                        for phase in phases {
+                            job.events.send(Event::Phase(phase));
+                        }
+                        // Every job starts from a known TAP state, whatever the last one left behind
+                        let reset = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                            svf.reset();
+                            jtag.mode_reset();
+                            jtag.cable.0.flush();
+                        }));
+                        match reset {
+                            Ok(()) => result,
+                            Err(e) => {
+                                let e = format!("resetting the TAP failed: {}", panic_message(&*e));
+                                lost = Some(e.clone());
+                                Err(e)
+                            }
+                        }
+                    }
+                };
+                *worker_running.lock().unwrap() = None;
+                worker_pending.fetch_sub(1, Ordering::SeqCst);
+                update(job.id, &|status| match &result {
+                    Ok(()) => status.state = State::Done,
+                    Err(_) if job.cancel.load(Ordering::SeqCst) => status.state = State::Cancelled,
+                    Err(e) => {
+                        status.state = State::Failed;
+                        status.error = Some(e.clone());
+                    }
+                });
+                job.events.send(match result {
+                    Ok(()) => Event::Done,
+                    Err(e) => Event::Failed(e),
+                });
+            }
+        });
+        // A cable that panics while it is first reset takes the worker down without an answer
+        open_result.recv().map_err(|_| format!("{}: the cable failed as it was reset", player_cable))??;
+        Ok(Queue {
+            waiting,
+            running,
+            pending,
+            next_id: Arc::new(AtomicU64::new(1)),
+            status,
+            cable,
+        })
+    }
+
+    /// Queue `svf` for playback, returning its job ID and the feed of its events
+    pub fn submit(&self, svf: Vec<u8>, priority: i32) -> (u64, Arc<Feed>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let ahead = self.waiting.jobs.lock().unwrap().iter().filter(|job| job.priority >= priority).count()
+            + self.running.lock().unwrap().is_some() as usize;
+        self.status.lock().unwrap().insert(id, Status {
+            id,
+            state: State::Queued,
+            priority,
+            commands_done: 0,
+            commands_total: 0,
+            percent: 0.0,
+            phase: None,
+            command: None,
+            phases: vec![],
+            error: None,
+        });
+        let events = Arc::new(Feed::default());
+        events.send(Event::Queued(ahead));
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.waiting.push(Job { id, svf, priority, events: events.clone(), cancel });
+        (id, events)
+    }
+
+    /// Drop job `id` if it is waiting, or stop it after the command playing if it is running
+    pub fn cancel(&self, id: u64) -> Result<(), String> {
+        let mut jobs = self.waiting.jobs.lock().unwrap();
+        if let Some(i) = jobs.iter().position(|job| job.id == id) {
+            let job = jobs.remove(i);
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            if let Some(status) = self.status.lock().unwrap().get_mut(&id) {
+                status.state = State::Cancelled;
+            }
+            job.events.send(Event::Failed("cancelled".into()));
+            return Ok(());
+        }
+        match &*self.running.lock().unwrap() {
+            Some((running, cancel)) if *running == id => {
+                cancel.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            _ => Err(format!("no job {} is waiting or running", id)),
+        }
+    }
+
+    pub fn status(&self, id: u64) -> Option<Status> {
+        self.status.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Status> {
+        self.status.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Number of jobs waiting or running
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+}
+
+fn run_job(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf, job: &Job, player: &Player) -> Result<(), String> {
+    if player.preflight {
+        Preflight::new(None).check(&mut &job.svf[..])?;
+    }
+    if let Some(deadline) = player.deadline {
+        svf.set_deadline(deadline);
+    }
+    svf.cancel = Some(job.cancel.clone());
+    // Each job reports the phases it marked, not those of the jobs before
+    svf.phases.phases.clear();
+    // Verification failures and cancelling still panic deep in the player, so catch them here
+    // to keep the daemon alive
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| run_svf(jtag, svf, &mut &job.svf[..], None)));
+    if result.as_ref().map_or(true, Result::is_err) {
+        // The reset after the job mustn't check what this one left in flight
+        svf.abandon();
+    }
+    let result = result.map_err(|e| panic_message(&*e)).and_then(|result| result.map_err(|e| e.to_string()));
+    let result = match (result, svf.phases.current()) {
+        (Err(e), Some(phase)) => Err(format!("{} (in phase {})", e, phase)),
+        (result, _) => result,
+    };
+    if result.is_err() {
+        svf.phases.fail();
+    } else {
+        svf.phases.end();
+    }
+    result
+}
+
+enum Request {
+    Play { svf: Vec<u8>, priority: i32 },
+    Cancel(u64),
+}
+
+/// Read the next request.  The outer error means the connection is unusable, the inner one
+/// only fails this request.
+fn read_request(reader: &mut BufReader<TcpStream>) -> std::io::Result<Option<Result<Request, String>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let line = line.trim_end();
+    let (verb, arg) = line.split_once(' ').unwrap_or((line, ""));
+    let (arg, priority) = match arg.rsplit_once(" PRIORITY ") {
+        Some((arg, priority)) => match priority.parse() {
+            Ok(priority) => (arg, priority),
+            Err(_) => return Ok(Some(Err(format!("bad priority {}", priority)))),
+        },
+        None => (arg, 0),
+    };
+    let play = |svf: Result<Vec<u8>, String>| Ok(Some(svf.map(|svf| Request::Play { svf, priority })));
+    match verb {
+        "PLAY" => {
+            let len: usize = arg.parse().map_err(|_| std::io::Error::other("bad PLAY length"))?;
+            if len > MAX_PAYLOAD {
+                return Err(std::io::Error::other("payload too large"));
+            }
+            let mut payload = vec![0; len];
+            reader.read_exact(&mut payload)?;
+            play(Ok(payload))
+        }
+        "FILE" => {
+            let mut svf = vec![];
+            let read = crate::input::open(arg).and_then(|mut input| input.read_to_end(&mut svf));
+            play(read.map(|_| svf).map_err(|e| format!("{}: {}", arg, e)))
+        }
+        "CANCEL" => Ok(Some(arg.parse().map(Request::Cancel).map_err(|_| format!("bad job ID {}", arg)))),
+        _ => Ok(Some(Err(format!("unknown request {}", verb)))),
+    }
+}
+
+fn serve_client(stream: TcpStream, queue: &Queue, gate: &Gate) -> std::io::Result<()> {
+    let mut out = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    if gate.needs_token() {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match line.trim_end().strip_prefix("AUTH ") {
+            Some(token) if gate.accepts(token.as_bytes()) => writeln!(out, "AUTHORIZED")?,
+            _ => {
+                writeln!(out, "FAILED - not authorized")?;
+                return Err(std::io::Error::other("not authorized"));
+            }
+        }
+    }
+    while let Some(request) = read_request(&mut reader)? {
+        let (svf, priority) = match request {
+            Ok(Request::Play { svf, priority }) => (svf, priority),
+            Ok(Request::Cancel(id)) => {
+                match queue.cancel(id) {
+                    Ok(()) => writeln!(out, "CANCELLED {}", id)?,
+                    Err(e) => writeln!(out, "FAILED - {}", e)?,
+                }
+                continue;
+            }
+            Err(e) => {
+                writeln!(out, "FAILED - {}", e)?;
+                continue;
+            }
+        };
+        let (id, events) = queue.submit(svf, priority);
+        loop {
+            match events.recv() {
+                Event::Queued(ahead) => writeln!(out, "QUEUED {} {}", id, ahead)?,
+                Event::Started => writeln!(out, "STARTED {}", id)?,
+                Event::Progress { done, total, phase, command } => {
+                    writeln!(out, "PROGRESS {} {}/{} {}% {} {}", id, done, total, done * 100 / total,
+                             phase.as_deref().unwrap_or("-"), command)?
+                }
+                Event::Phase(phase) => {
+                    writeln!(out, "PHASE {} {} {} {} {} {:.3}{}", id, phase.name, phase.commands, phase.bits,
+                             phase.mismatches, phase.seconds, if phase.failed { " failed" } else { "" })?
+                }
+                Event::Done => {
+                    writeln!(out, "DONE {}", id)?;
+                    break;
+                }
+                Event::Failed(e) => {
+                    writeln!(out, "FAILED {} {}", id, e.replace('\n', " "))?;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Accept clients `gate` lets in forever, each on its own thread
+pub fn serve(listener: TcpListener, queue: Queue, gate: Gate) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{} accept failed: {}", color::warning(), e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        if !stream.peer_addr().is_ok_and(|addr| gate.allows(addr.ip())) {
+            eprintln!("{} refused client {}, which --allow doesn't list", color::warning(), peer);
+            continue;
+        }
+        let queue = queue.clone();
+        let gate = gate.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = serve_client(stream, &queue, &gate) {
+                eprintln!("client {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn higher_priorities_play_first_then_in_order() {
+        let waiting = Waiting::default();
+        for (id, priority) in [(1, 0), (2, 5), (3, 0), (4, 5)] {
+            let events = Arc::new(Feed::default());
+            waiting.push(Job { id, svf: vec![], priority, events, cancel: Arc::default() });
+        }
+        let running = Mutex::new(None);
+        let order: Vec<u64> = (0..4).map(|_| waiting.pop(&running).id).collect();
+        assert_eq!(order, [2, 4, 1, 3]);
+        assert_eq!(running.lock().unwrap().as_ref().map(|(id, _)| *id), Some(3));
+    }
+
+    #[test]
+    fn a_slow_client_only_gets_the_latest_progress() {
+        let feed = Feed::default();
+        feed.send(Event::Started);
+        for done in 1..=3 {
+            feed.send(Event::Progress { done, total: 4, phase: None, command: "SDR" });
+        }
+        feed.send(Event::Done);
+        assert!(matches!(feed.recv(), Event::Started));
+        assert!(matches!(feed.recv(), Event::Progress { done: 3, .. }));
+        assert!(matches!(feed.recv(), Event::Done));
+    }
+
+    fn player(cable: &str) -> Player {
+        Player {
+            cable: cable.to_string(),
+            baud: 0,
+            retries: 0,
+            log_level: LogLevel::Error,
+            bit_order: BitOrder::default(),
+            dont_care: DontCare::default(),
+            preflight: false,
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn a_cable_that_wont_open_fails_at_startup() {
+        assert!(Queue::spawn(player("no-such-cable")).is_err());
+    }
+
+    /// Answers the reset `JtagSM` opens with, then fails every call
+    struct Dying(bool);
+
+    impl jtag_taps::cable::Cable for Dying {
+        fn change_mode(&mut self, _tms: &[usize], _tdo: bool) {
+            assert!(!std::mem::replace(&mut self.0, true), "cable unplugged");
+        }
+
+        fn read_data(&mut self, _bits: usize) -> Vec<u8> {
+            panic!("cable unplugged")
+        }
+
+        fn write_data(&mut self, _data: &[u8], _bits: u8, _pause_after: bool) {
+            panic!("cable unplugged")
+        }
+
+        fn read_write_data(&mut self, _data: &[u8], _bits: u8, _pause_after: bool) -> Vec<u8> {
+            panic!("cable unplugged")
+        }
+    }
+
+    impl cable::Adapter for Dying {}
+
+    struct DyingBackend;
+
+    impl cable::Backend for DyingBackend {
+        fn name(&self) -> &str {
+            "daemon-test-dying"
+        }
+
+        fn open(&self, _params: &str, _clock: u32) -> Result<Box<dyn cable::Adapter>, String> {
+            Ok(Box::new(Dying(false)))
+        }
+    }
+
+    #[test]
+    fn a_failed_reset_fails_the_job_and_the_ones_after_it() {
+        cable::register(DyingBackend);
+        let queue = Queue::spawn(player("daemon-test-dying")).unwrap();
+        let feeds: Vec<_> = (0..2).map(|_| queue.submit(vec![], 0).1).collect();
+        for feed in feeds {
+            let failed = loop {
+                match feed.recv() {
+                    Event::Failed(e) => break e,
+                    Event::Done => panic!("job played on a dead cable"),
+                    _ => (),
+                }
+            };
+            assert!(failed.contains("cable unplugged"), "{}", failed);
+        }
+        assert_eq!(queue.pending(), 0);
+    }
+
+    /// A queue on a simulated one-device chain, described in a file named after `test`
+    fn sim_queue(test: &str) -> Queue {
+        let chain = std::env::temp_dir().join(format!("svfplayer-{}-{}.toml", test, std::process::id()));
+        std::fs::write(&chain, "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n").unwrap();
+        let queue = Queue::spawn(player(&format!("sim:{}", chain.display()))).unwrap();
+        let _ = std::fs::remove_file(&chain);
+        queue
+    }
+
+    /// Wait for the job to end, returning why it failed if it did
+    fn outcome(feed: &Feed) -> Result<(), String> {
+        loop {
+            match feed.recv() {
+                Event::Done => return Ok(()),
+                Event::Failed(e) => return Err(e),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn jobs_play_loops_phases_and_compiled_svf() {
+        let queue = sim_queue("daemon-jobs");
+        let text = "! phase identify\nLOOP 2;\nSIR 4 TDI (e);\nSDR 32 TDI (0) TDO (12345679);\nENDLOOP;\n";
+        let mut compiled = vec![];
+        crate::compiled::compile(&mut text.as_bytes(), &mut compiled).unwrap();
+        for svf in [text.as_bytes().to_vec(), compiled] {
+            let (id, feed) = queue.submit(svf, 0);
+            assert_eq!(outcome(&feed), Ok(()));
+            let status = queue.status(id).unwrap();
+            assert_eq!(status.commands_done, 2);
+            assert_eq!((status.phase.as_deref(), status.command), (Some("identify"), Some("SDR")));
+            assert_eq!(status.phases.iter().map(|phase| (phase.name.as_str(), phase.commands)).collect::<Vec<_>>(),
+                       [("identify", 2)]);
+        }
+        let (id, feed) = queue.submit(b"! phase verify\nSIR 4 TDI (e);\nSDR 32 TDI (0) TDO (0);\n".to_vec(), 0);
+        let failed = outcome(&feed).unwrap_err();
+        assert!(failed.contains("TDO mismatch") && failed.ends_with("(in phase verify)"), "{}", failed);
+        let phases = queue.status(id).unwrap().phases;
+        assert_eq!(phases.iter().map(|phase| (phase.name.as_str(), phase.failed)).collect::<Vec<_>>(), [("verify", true)]);
+    }
+
+    #[test]
+    fn a_running_job_is_cancelled_before_its_next_command() {
+        let queue = sim_queue("daemon-cancel");
+        let (id, feed) = queue.submit("RUNTEST 0.1 SEC;\n".repeat(50).into_bytes(), 0);
+        assert!(matches!(feed.recv(), Event::Queued(_)));
+        assert!(matches!(feed.recv(), Event::Started));
+        let started = std::time::Instant::now();
+        queue.cancel(id).unwrap();
+        assert_eq!(outcome(&feed), Err("cancelled".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(matches!(queue.status(id).unwrap().state, State::Cancelled));
+    }
+}
@@ -0,0 +1,181 @@
+//! Known devices, for naming what `scan` finds and for working out a chain's instruction lengths
+//! and opcodes from its IDCODEs.  A small table is built in; a TOML file named by `devices` in
+//! the config adds to it, its entries taking precedence.
+//!
+//! ```toml
+//! [[part]]
+//! name = "myasic"
+//! idcode = 0x1234a001
+//! mask = 0x0fffffff   # the default, ignoring the version
+//! irlen = 5
+//! idcode_opcode = 0x01
+//! usercode_opcode = 0x03
+//!
+//! [[manufacturer]]
+//! name = "Example Corp"
+//! jedec = 0x7a        # bits 11:1 of the IDCODE: the bank less one, then the 7 bit ID
+//! ```
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Bits of an IDCODE that say which part it is, leaving out the version
+pub const PART_MASK: u32 = 0x0fff_ffff;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Part {
+    pub name: String,
+    pub idcode: u32,
+    #[serde(default = "part_mask")]
+    pub mask: u32,
+    pub irlen: usize,
+    pub idcode_opcode: Option<u64>,
+    pub usercode_opcode: Option<u64>,
+}
+
+fn part_mask() -> u32 {
+    PART_MASK
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manufacturer {
+    pub name: String,
+    pub jedec: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Database {
+    #[serde(default)]
+    part: Vec<Part>,
+    #[serde(default)]
+    manufacturer: Vec<Manufacturer>,
+}
+
+const MANUFACTURERS: &[(u32, &str)] = &[
+    (0x015, "NXP"),
+    (0x017, "Texas Instruments"),
+    (0x01f, "Atmel"),
+    (0x020, "STMicroelectronics"),
+    (0x034, "Cypress"),
+    (0x049, "Xilinx"),
+    (0x06e, "Altera"),
+    (0x0a6, "Lattice"),
+    (0x0c1, "Infineon"),
+    (0x23b, "ARM"),
+    (0x272, "Espressif"),
+    (0x489, "SiFive"),
+];
+
+/// Name, IDCODE without the version, IR length, IDCODE opcode and USERCODE opcode
+type Entry = (&'static str, u32, usize, Option<u64>, Option<u64>);
+
+const PARTS: &[Entry] = &[
+    ("xc6slx9", 0x0400_1093, 6, Some(0x09), Some(0x08)),
+    ("xc6slx16", 0x0400_2093, 6, Some(0x09), Some(0x08)),
+    ("xc7a35t", 0x0362_d093, 6, Some(0x09), Some(0x08)),
+    ("xc7a100t", 0x0363_1093, 6, Some(0x09), Some(0x08)),
+    ("xc7a200t", 0x0363_6093, 6, Some(0x09), Some(0x08)),
+    ("xc7k325t", 0x0365_1093, 6, Some(0x09), Some(0x08)),
+    ("xc7z010", 0x0372_2093, 6, Some(0x09), Some(0x08)),
+    ("xc7z020", 0x0372_7093, 6, Some(0x09), Some(0x08)),
+    ("EPM240", 0x020a_10dd, 10, Some(0x006), Some(0x007)),
+    ("EP4CE22", 0x020f_30dd, 10, Some(0x006), Some(0x007)),
+    ("10M50", 0x0310_50dd, 10, Some(0x006), Some(0x007)),
+    ("LFE5U-25F", 0x0111_1043, 8, Some(0xe0), Some(0xc0)),
+    ("LFE5U-45F", 0x0111_2043, 8, Some(0xe0), Some(0xc0)),
+    ("LFE5U-85F", 0x0111_3043, 8, Some(0xe0), Some(0xc0)),
+    ("JTAG-DP", 0x0ba0_0477, 4, Some(0xe), None),
+    ("STM32F10x", 0x0641_0041, 5, None, None),
+    ("STM32F40x", 0x0641_3041, 5, None, None),
+    ("ESP32", 0x0200_34e5, 5, None, None),
+    ("FE310", 0x0000_0913, 5, Some(0x01), None),
+];
+
+/// Bits 11:1 of `idcode`, naming its manufacturer
+pub fn jedec(idcode: u32) -> u32 {
+    idcode >> 1 & 0x7ff
+}
+
+impl Database {
+    /// The built-in table
+    pub fn builtin() -> Database {
+        Database {
+            part: PARTS.iter()
+                .map(|(name, idcode, irlen, idcode_opcode, usercode_opcode)| Part {
+                    name: name.to_string(),
+                    idcode: *idcode,
+                    mask: PART_MASK,
+                    irlen: *irlen,
+                    idcode_opcode: *idcode_opcode,
+                    usercode_opcode: *usercode_opcode,
+                })
+                .collect(),
+            manufacturer: MANUFACTURERS.iter()
+                .map(|(jedec, name)| Manufacturer { name: name.to_string(), jedec: *jedec })
+                .collect(),
+        }
+    }
+
+    /// The built-in table with the entries of `path`, if given, in front
+    pub fn load(path: Option<&Path>) -> Result<Database, String> {
+        let mut database = Database::builtin();
+        if let Some(path) = path {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut extra: Database = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            extra.part.append(&mut database.part);
+            extra.manufacturer.append(&mut database.manufacturer);
+            database = extra;
+        }
+        Ok(database)
+    }
+
+    pub fn part(&self, idcode: u32) -> Option<&Part> {
+        self.part.iter().find(|part| idcode & part.mask == part.idcode & part.mask)
+    }
+
+    /// A part by name, ignoring case
+    pub fn named(&self, name: &str) -> Option<&Part> {
+        self.part.iter().find(|part| part.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn manufacturer(&self, idcode: u32) -> Option<&str> {
+        self.manufacturer.iter().find(|m| m.jedec == jedec(idcode)).map(|m| m.name.as_str())
+    }
+
+    /// What is known about `idcode`, e.g. `Xilinx xc7a35t`, or None for nothing at all
+    pub fn describe(&self, idcode: u32) -> Option<String> {
+        match (self.manufacturer(idcode), self.part(idcode)) {
+            (Some(manufacturer), Some(part)) => Some(format!("{} {}", manufacturer, part.name)),
+            (manufacturer, part) => part.map(|part| part.name.clone()).or(manufacturer.map(str::to_string)),
+        }
+    }
+
+    /// The parts of a chain found by `chain::idcodes`, if every one of them is known
+    pub fn chain(&self, idcodes: &[Option<u32>]) -> Result<Vec<&Part>, String> {
+        idcodes.iter().enumerate()
+            .map(|(position, idcode)| match idcode {
+                None => Err(format!("device {} has no IDCODE to look up", position)),
+                Some(idcode) => self.part(*idcode).ok_or_else(|| format!("device {} ({:08x}) isn't known", position, idcode)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_match_whatever_their_version() {
+        let database = Database::builtin();
+        assert_eq!(database.describe(0x1362_d093).unwrap(), "Xilinx xc7a35t");
+        assert_eq!(database.describe(0x4ba0_0477).unwrap(), "ARM JTAG-DP");
+        assert_eq!(database.describe(0x0000_1093).unwrap(), "Xilinx");
+        assert_eq!(database.describe(0x0000_0001), None);
+        assert_eq!(database.chain(&[Some(0x0362_d093), Some(0x4ba0_0477)]).unwrap()[1].irlen, 4);
+        assert!(database.chain(&[None]).is_err());
+    }
+}
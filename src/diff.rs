@@ -0,0 +1,288 @@
+//! `svfplayer diff`: compare two SVF files by what they make the player do rather than how
+//! they're written.  Every scan is compared with the vectors and end state it actually uses, so
+//! leaving out a sticky TDI, MASK or ENDDR, or repeating one, makes no difference.  Neither do
+//! comments, formatting, or TDI and TDO bits that SMASK and MASK say don't matter.
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use svf::{Command, Pattern, RunTestForm, State};
+
+use crate::bits::fit;
+use crate::input;
+use crate::lattice::{LoopFilter, Marker, Markers};
+
+/// Steps read ahead of the comparison from each file
+const READ_AHEAD: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// Scan data of the given length
+    Vector(Vec<u8>, u32),
+    Text(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Vector(data, length) => write!(f, "({})", crate::svf_writer::hex(data, *length).replace("\n    ", "")),
+            Value::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// A command as the player runs it
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    /// Command number in its file, counting from 1
+    pub index: usize,
+    pub kind: &'static str,
+    pub fields: Vec<(&'static str, Value)>,
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        for (name, value) in &self.fields {
+            write!(f, " {} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    /// The first field that `other` has a different value for
+    fn differs(&self, other: &Step) -> Option<&'static str> {
+        if self.kind != other.kind {
+            return Some("command");
+        }
+        // Steps of the same kind have the same fields, except TDO that only some scans check
+        let names = self.fields.iter().chain(&other.fields).map(|(name, _)| *name);
+        names.into_iter().find(|name| self.field(name) != other.field(name))
+    }
+
+    fn field(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| *n == name).map(|(_, value)| value)
+    }
+}
+
+/// The vectors a scan command inherits from the last one of its kind
+struct Sticky {
+    length: u32,
+    tdi: Vec<u8>,
+    mask: Vec<u8>,
+    smask: Vec<u8>,
+}
+
+/// Turns commands into steps, filling in whatever they leave to earlier commands
+struct Normalizer {
+    sticky: HashMap<&'static str, Sticky>,
+    endir: State,
+    enddr: State,
+    run_state: State,
+    end_state: State,
+}
+
+fn and(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a & b).collect()
+}
+
+impl Normalizer {
+    fn new() -> Self {
+        Normalizer {
+            sticky: HashMap::new(),
+            endir: State::IDLE,
+            enddr: State::IDLE,
+            run_state: State::IDLE,
+            end_state: State::IDLE,
+        }
+    }
+
+    fn scan(&mut self, kind: &'static str, pattern: Pattern, end: Option<State>) -> Result<Step, String> {
+        let length = pattern.length;
+        let ones = fit(vec![0xff; length.div_ceil(8) as usize], length);
+        let fitted = |v: Option<Vec<u8>>| v.map(|v| fit(v, length));
+        let sticky = match self.sticky.remove(kind).filter(|s| s.length == length) {
+            Some(sticky) => sticky,
+            None if pattern.tdi.is_none() && length != 0 => {
+                return Err(format!("{} length changed to {} without a new TDI", kind, length));
+            }
+            None => Sticky { length, tdi: vec![], mask: ones.clone(), smask: ones },
+        };
+        let sticky = Sticky {
+            length,
+            tdi: fitted(pattern.tdi).unwrap_or(sticky.tdi),
+            mask: fitted(pattern.mask).unwrap_or(sticky.mask),
+            smask: fitted(pattern.smask).unwrap_or(sticky.smask),
+        };
+        let mut fields = vec![
+            ("LENGTH", Value::Text(length.to_string())),
+            ("TDI", Value::Vector(and(&sticky.tdi, &sticky.smask), length)),
+            ("SMASK", Value::Vector(sticky.smask.clone(), length)),
+        ];
+        if let Some(tdo) = fitted(pattern.tdo) {
+            fields.push(("TDO", Value::Vector(and(&tdo, &sticky.mask), length)));
+            fields.push(("MASK", Value::Vector(sticky.mask.clone(), length)));
+        }
+        if let Some(end) = end {
+            fields.push(("END", Value::Text(end.to_string())));
+        }
+        self.sticky.insert(kind, sticky);
+        Ok(Step { index: 0, kind, fields })
+    }
+
+    /// The step `cmd` takes, or None for one that only sets up later commands
+    fn step(&mut self, cmd: Command) -> Result<Option<Step>, String> {
+        let text = |s: String| Value::Text(s);
+        let step = match cmd {
+            Command::EndIR(state) => {
+                self.endir = state;
+                return Ok(None);
+            }
+            Command::EndDR(state) => {
+                self.enddr = state;
+                return Ok(None);
+            }
+            Command::SIR(pattern) => self.scan("SIR", pattern, Some(self.endir))?,
+            Command::SDR(pattern) => self.scan("SDR", pattern, Some(self.enddr))?,
+            Command::HIR(pattern) => self.scan("HIR", pattern, None)?,
+            Command::HDR(pattern) => self.scan("HDR", pattern, None)?,
+            Command::TIR(pattern) => self.scan("TIR", pattern, None)?,
+            Command::TDR(pattern) => self.scan("TDR", pattern, None)?,
+            Command::RunTest { run_state, form, end_state } => {
+                self.run_state = run_state.unwrap_or(self.run_state);
+                self.end_state = end_state.unwrap_or(self.end_state);
+                let (clocks, time) = match form {
+                    RunTestForm::Clocked { run_count, run_clk, time } => (format!("{} {:?}", run_count, run_clk), time),
+                    RunTestForm::Timed(time) => ("0 TCK".to_string(), Some(time)),
+                };
+                let mut fields = vec![("STATE", text(self.run_state.to_string())), ("CLOCKS", text(clocks))];
+                if let Some(time) = time {
+                    fields.push(("MINIMUM", text(format!("{:E} SEC", time.min))));
+                    if let Some(max) = time.max {
+                        fields.push(("MAXIMUM", text(format!("{:E} SEC", max))));
+                    }
+                }
+                fields.push(("END", text(self.end_state.to_string())));
+                Step { index: 0, kind: "RUNTEST", fields }
+            }
+            Command::State { path, end } => {
+                let mut fields = vec![];
+                if let Some(path) = path {
+                    let path: Vec<_> = path.iter().map(State::to_string).collect();
+                    fields.push(("PATH", text(path.join(" "))));
+                }
+                fields.push(("END", text(end.to_string())));
+                Step { index: 0, kind: "STATE", fields }
+            }
+            Command::Frequency(hz) => Step {
+                index: 0,
+                kind: "FREQUENCY",
+                fields: vec![("HZ", text(hz.map_or("none".to_string(), |hz| format!("{:E}", hz))))],
+            },
+            Command::TRST(mode) => Step { index: 0, kind: "TRST", fields: vec![("MODE", text(format!("{:?}", mode)))] },
+            cmd => Step { index: 0, kind: "OTHER", fields: vec![("TEXT", text(cmd.to_string()))] },
+        };
+        Ok(Some(step))
+    }
+}
+
+/// Send the steps of `input` to `tx`, ending with an error if it can't be read
+fn read(input: &mut impl BufRead, tx: &SyncSender<Result<Step, String>>) -> Result<(), String> {
+    let markers = Markers::default();
+    let mut input = LoopFilter::new(input, markers.clone());
+    let mut normalizer = Normalizer::new();
+    let mut commands = svf::parse_iter_bufread(&mut input).enumerate();
+    loop {
+        let next = commands.next();
+        let i = next.as_ref().map_or(usize::MAX, |(i, _)| *i);
+        while markers.borrow().front().is_some_and(|m| m.before() <= i) {
+            let (kind, fields) = match markers.borrow_mut().pop_front().unwrap() {
+                Marker::Loop { count, .. } => ("LOOP", vec![("COUNT", Value::Text(count.to_string()))]),
+                Marker::EndLoop { .. } => ("ENDLOOP", vec![]),
+                // Only a comment, so files that differ in nothing else are the same
+                Marker::Phase { .. } => continue,
+            };
+            if tx.send(Ok(Step { index: i.saturating_add(1), kind, fields })).is_err() {
+                return Ok(());
+            }
+        }
+        let Some((i, cmd)) = next else {
+            return Ok(());
+        };
+        let cmd = cmd.map_err(|e| format!("command {}: {}", i + 1, e))?;
+        if let Some(mut step) = normalizer.step(cmd).map_err(|e| format!("command {}: {}", i + 1, e))? {
+            step.index = i + 1;
+            if tx.send(Ok(step)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn spawn<'scope>(scope: &'scope std::thread::Scope<'scope, '_>, path: &'scope str) -> Receiver<Result<Step, String>> {
+    let (tx, rx) = sync_channel(READ_AHEAD);
+    scope.spawn(move || {
+        let result = input::open(path).map_err(|e| e.to_string()).and_then(|mut input| read(&mut input, &tx));
+        if let Err(e) = result {
+            let _ = tx.send(Err(format!("{}: {}", path, e)));
+        }
+    });
+    rx
+}
+
+/// Where two files first part ways
+pub enum Difference {
+    /// Both have a step here, but `field` differs
+    Step { a: Step, b: Step, field: &'static str },
+    /// One file ends while the other goes on with `next`; `a_ended` says which
+    Ended { a_ended: bool, next: Step },
+}
+
+/// Compare `a` and `b` step by step, returning the first difference and how many steps matched
+pub fn diff(a: &str, b: &str) -> Result<(usize, Option<Difference>), String> {
+    std::thread::scope(|scope| {
+        let (a, b) = (spawn(scope, a), spawn(scope, b));
+        let mut same = 0;
+        loop {
+            let difference = match (a.recv().ok().transpose()?, b.recv().ok().transpose()?) {
+                (None, None) => return Ok((same, None)),
+                (None, Some(next)) => Difference::Ended { a_ended: true, next },
+                (Some(next), None) => Difference::Ended { a_ended: false, next },
+                (Some(a), Some(b)) => match a.differs(&b) {
+                    Some(field) => Difference::Step { a, b, field },
+                    None => {
+                        same += 1;
+                        continue;
+                    }
+                },
+            };
+            // Dropping the receivers stops the readers
+            return Ok((same, Some(difference)));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(text: &str) -> Vec<Step> {
+        let (tx, rx) = sync_channel(READ_AHEAD);
+        read(&mut text.as_bytes(), &tx).unwrap();
+        drop(tx);
+        rx.into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn sticky_and_masked_bits_are_ignored() {
+        let a = "ENDDR DRPAUSE;\nSDR 8 TDI (5A) SMASK (0F) TDO (FF) MASK (03);\nSDR 8 TDO (03);\nRUNTEST IDLE 10 TCK;\n";
+        let b = "! generated by another tool\nENDDR DRPAUSE; ENDDR DRPAUSE;\nSDR 8 TDI (0A) SMASK (0F) TDO (03) MASK (03);\n\
+                 SDR 8 TDI (FA) TDO (07) MASK (03);\nRUNTEST 10 TCK;\n";
+        let (a, b) = (steps(a), steps(b));
+        assert_eq!(a.len(), b.len());
+        assert!(a.iter().zip(&b).all(|(a, b)| a.differs(b).is_none()));
+        let c = steps("SDR 8 TDI (5A);\n");
+        assert_eq!(a[0].differs(&c[0]), Some("TDI"));
+    }
+}
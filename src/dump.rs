@@ -0,0 +1,90 @@
+//! Scan vectors printed for people, as the TDO echoed by the REPL and at `--log-level debug`:
+//! hex or binary digits in groups, most significant bit on the left as SVF writes them, each
+//! line headed by the indices of the bits on it.
+use crate::cable::bit;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Radix {
+    #[default]
+    Hex,
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, clap::Args)]
+pub struct Dump {
+    /// Digits scan vectors are printed in
+    #[arg(long = "dump", value_enum, default_value_t, value_name = "RADIX")]
+    pub radix: Radix,
+    /// Digits between the spaces of a printed vector, 0 for none
+    #[arg(long = "dump-group", default_value_t = 8, value_name = "DIGITS")]
+    pub group: usize,
+}
+
+impl Default for Dump {
+    fn default() -> Self {
+        Dump { radix: Radix::Hex, group: 8 }
+    }
+}
+
+impl Dump {
+    /// Bits per digit, and digits per line
+    fn layout(&self) -> (usize, usize) {
+        match self.radix {
+            Radix::Hex => (4, 16),
+            Radix::Binary => (1, 32),
+        }
+    }
+
+    /// The first `length` bits of `data`, LSB first, as lines from the most significant down
+    pub fn lines(&self, data: &[u8], length: usize) -> Vec<String> {
+        let (width, per_line) = self.layout();
+        let length = length.min(data.len() * 8);
+        let digits = length.div_ceil(width).max(1);
+        let digit = |k: usize| {
+            let value = (0..width).filter(|i| k * width + i < length && bit(data, k * width + i))
+                .fold(0, |value, i| value | 1 << i);
+            std::char::from_digit(value, 16).unwrap().to_ascii_uppercase()
+        };
+        let mut lines: Vec<(String, String)> = vec![];
+        for start in (0..digits).step_by(per_line).rev() {
+            let end = (start + per_line).min(digits);
+            let mut text = String::new();
+            for k in (start..end).rev() {
+                text.push(digit(k));
+                if self.group > 0 && k > start && (k - start) % self.group == 0 {
+                    text.push(' ');
+                }
+            }
+            let high = (end * width).min(length).max(1) - 1;
+            lines.push((format!("[{}:{}]", high, start * width), text));
+        }
+        // Right align a short first line with the full ones below it
+        let full = if lines.len() > 1 { per_line + (per_line - 1).checked_div(self.group).unwrap_or(0) } else { 0 };
+        let indent = lines.first().map_or(0, |(bits, _)| bits.len());
+        lines.into_iter().map(|(bits, text)| format!("{:>indent$} {:>full$}", bits, text)).collect()
+    }
+
+    /// Print `data` under `label`, the lines after the first lined up beneath it
+    pub fn print(&self, label: &str, data: &[u8], length: usize) {
+        for (i, line) in self.lines(data, length).iter().enumerate() {
+            println!("{:<width$} {}", if i == 0 { label } else { "" }, line, width = label.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_read_msb_first_with_bit_indices() {
+        let hex = Dump { radix: Radix::Hex, group: 4 };
+        assert_eq!(hex.lines(&[0x79, 0x56, 0x34, 0x12], 32), ["[31:0] 1234 5679"]);
+        assert_eq!(hex.lines(&[0xff, 0x01], 9), ["[8:0] 1FF"]);
+        let long: Vec<u8> = (0..9).collect();
+        assert_eq!(hex.lines(&long, 72), [format!("[71:64] {:>19}", "08"),
+                                          " [63:0] 0706 0504 0302 0100".to_string()]);
+        let binary = Dump { radix: Radix::Binary, group: 4 };
+        assert_eq!(binary.lines(&[0x35], 6), ["[5:0] 11 0101"]);
+    }
+}
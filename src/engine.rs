@@ -0,0 +1,335 @@
+//! The part of playing SVF that needs neither a cable backend nor an operating system: where the
+//! TAP is and the TMS paths between states, the sticky TDI, MASK and SMASK of each register, and
+//! checking TDO.  It only uses `core` and `alloc`, and reports bad vectors as a `ScanError`
+//! rather than panicking, so firmware that clocks its own pins can reuse it; without the default
+//! `std` feature the crate is `no_std` and this and `bits` are all it builds.
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::zip;
+
+use crate::bits::{self, DontCare, Rng};
+
+/// The states of the IEEE 1149.1 TAP controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapState {
+    Reset,
+    Idle,
+    SelectDR,
+    CaptureDR,
+    ShiftDR,
+    Exit1DR,
+    PauseDR,
+    Exit2DR,
+    UpdateDR,
+    SelectIR,
+    CaptureIR,
+    ShiftIR,
+    Exit1IR,
+    PauseIR,
+    Exit2IR,
+    UpdateIR,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reg {
+    Data,
+    Instruction,
+}
+
+impl TapState {
+    /// The name SVF gives the state
+    pub fn name(self) -> &'static str {
+        use TapState::*;
+        match self {
+            Reset => "RESET",
+            Idle => "IDLE",
+            SelectDR => "DRSELECT",
+            CaptureDR => "DRCAPTURE",
+            ShiftDR => "DRSHIFT",
+            Exit1DR => "DREXIT1",
+            PauseDR => "DRPAUSE",
+            Exit2DR => "DREXIT2",
+            UpdateDR => "DRUPDATE",
+            SelectIR => "IRSELECT",
+            CaptureIR => "IRCAPTURE",
+            ShiftIR => "IRSHIFT",
+            Exit1IR => "IREXIT1",
+            PauseIR => "IRPAUSE",
+            Exit2IR => "IREXIT2",
+            UpdateIR => "IRUPDATE",
+        }
+    }
+}
+
+/// The state one TCK with `tms` leads to from `state`
+pub fn next(state: TapState, tms: usize) -> TapState {
+    use TapState::*;
+    let (low, high) = match state {
+        Reset => (Idle, Reset),
+        Idle => (Idle, SelectDR),
+        SelectDR => (CaptureDR, SelectIR),
+        CaptureDR | ShiftDR => (ShiftDR, Exit1DR),
+        Exit1DR => (PauseDR, UpdateDR),
+        PauseDR => (PauseDR, Exit2DR),
+        Exit2DR => (ShiftDR, UpdateDR),
+        UpdateDR | UpdateIR => (Idle, SelectDR),
+        SelectIR => (CaptureIR, Reset),
+        CaptureIR | ShiftIR => (ShiftIR, Exit1IR),
+        Exit1IR => (PauseIR, UpdateIR),
+        PauseIR => (PauseIR, Exit2IR),
+        Exit2IR => (ShiftIR, UpdateIR),
+    };
+    if tms == 0 { low } else { high }
+}
+
+/// The shortest TMS sequence from `from` to `to`, nothing if they are the same state
+pub fn shortest(from: TapState, to: TapState) -> Vec<usize> {
+    let mut paths = VecDeque::from([(from, vec![])]);
+    let mut seen = vec![from];
+    while let Some((state, path)) = paths.pop_front() {
+        if state == to {
+            return path;
+        }
+        for tms in [0, 1] {
+            let state = next(state, tms);
+            if !seen.contains(&state) {
+                seen.push(state);
+                paths.push_back((state, [&path[..], &[tms]].concat()));
+            }
+        }
+    }
+    unreachable!("every TAP state can reach every other")
+}
+
+/// TMS clocking from `from` through each of `states` in turn, which has to be reachable from
+/// the one before in a single TCK
+pub fn follow(from: TapState, states: &[TapState]) -> Result<Vec<usize>, String> {
+    let mut state = from;
+    let mut tms = vec![];
+    for &to in states {
+        let bit = [0, 1].into_iter().find(|&bit| next(state, bit) == to)
+            .ok_or_else(|| format!("STATE path can't move from {} to {} in one clock", state.name(), to.name()))?;
+        tms.push(bit);
+        state = to;
+    }
+    Ok(tms)
+}
+
+/// The Shift and Pause states of `reg`
+pub fn shift_and_pause(reg: Reg) -> (TapState, TapState) {
+    match reg {
+        Reg::Data => (TapState::ShiftDR, TapState::PauseDR),
+        Reg::Instruction => (TapState::ShiftIR, TapState::PauseIR),
+    }
+}
+
+/// TMS from `from` into Shift for a scan of `reg`.  From a pause state this is Exit2, Update,
+/// Select, Capture, Exit1 and Pause before Exit2 into Shift, so the register paused in is
+/// updated and the one scanned freshly captured rather than the two scans run together.
+pub fn to_shift(from: TapState, reg: Reg) -> Vec<usize> {
+    let (shift, pause) = shift_and_pause(reg);
+    match from {
+        TapState::PauseDR | TapState::PauseIR => {
+            let update = next(next(from, 1), 1);
+            [vec![1, 1], shortest(update, pause), vec![1, 0]].concat()
+        }
+        _ => shortest(from, shift),
+    }
+}
+
+/// Vectors a scan can't be played with
+#[derive(Debug, PartialEq)]
+pub enum ScanError {
+    /// Hex data for `field` of a `name` scan with set bits past its length
+    TooLong { name: String, field: String, length: u32 },
+    /// A `name` scan changed its length without giving the TDI for it
+    NoTdi { name: String, length: u32 },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::TooLong { name, field, length } =>
+                write!(f, "{} {} has more bits than the length {}", name, field, length),
+            ScanError::NoTdi { name, length } =>
+                write!(f, "{} length changed to {} without a new TDI", name, length),
+        }
+    }
+}
+
+/// `data` sized to exactly `length` bits, refusing hex data that doesn't fit
+pub fn scan_vector(name: &str, field: &str, data: Vec<u8>, length: u32) -> Result<Vec<u8>, ScanError> {
+    if !bits::fits(&data, length) {
+        return Err(ScanError::TooLong { name: name.into(), field: field.into(), length });
+    }
+    Ok(bits::fit(data, length))
+}
+
+pub fn tdo_matches(read: &[u8], tdo: &[u8], mask: &[u8]) -> bool {
+    zip(read, zip(tdo, mask)).all(|(r, (tdo, mask))| r & mask == tdo & mask)
+}
+
+/// TDI, MASK and SMASK remembered from the previous scan of one register
+#[derive(Default)]
+pub struct Sticky {
+    pub length: Option<u32>,
+    pub tdi: Vec<u8>,
+    pub mask: Vec<u8>,
+    pub smask: Vec<u8>,
+    /// TDI actually driven by the previous scan, don't-care bits included
+    pub driven: Vec<u8>,
+    /// Whether the last update kept the length, TDI and SMASK of the scan before, which then
+    /// drives the same bits again unless the don't-care ones are random
+    pub repeated: bool,
+}
+
+impl Sticky {
+    /// Take the vectors a scan of `length` bits gives.  The remembered ones only carry over while
+    /// the length stays the same; after a length change TDI is required and MASK and SMASK
+    /// default to all ones.
+    pub fn update(&mut self, name: &str, length: u32, tdi: Option<Vec<u8>>, mask: Option<Vec<u8>>,
+                  smask: Option<Vec<u8>>) -> Result<(), ScanError> {
+        let mut repeated = self.length == Some(length);
+        if self.length != Some(length) {
+            if tdi.is_none() && length != 0 {
+                return Err(ScanError::NoTdi { name: name.into(), length });
+            }
+            *self = Sticky {
+                length: Some(length),
+                mask: bits::fit(vec![0xff; bits::byte_len(length)], length),
+                smask: bits::fit(vec![0xff; bits::byte_len(length)], length),
+                ..Sticky::default()
+            };
+        }
+        if let Some(tdi) = tdi {
+            let tdi = scan_vector(name, "TDI", tdi, length)?;
+            repeated &= tdi == self.tdi;
+            self.tdi = tdi;
+        }
+        if let Some(mask) = mask {
+            self.mask = scan_vector(name, "MASK", mask, length)?;
+        }
+        if let Some(smask) = smask {
+            let smask = scan_vector(name, "SMASK", smask, length)?;
+            repeated &= smask == self.smask;
+            self.smask = smask;
+        }
+        self.repeated = repeated;
+        Ok(())
+    }
+
+    /// The TDI to drive: the remembered TDI where SMASK cares, `dont_care` where it doesn't
+    pub fn drive(&mut self, dont_care: DontCare, rng: &mut Rng) -> Vec<u8> {
+        let mut tdi = Vec::new();
+        self.drive_into(dont_care, rng, &mut tdi);
+        tdi
+    }
+
+    /// `drive` into `out`, whose allocation is kept between scans
+    pub fn drive_into(&mut self, dont_care: DontCare, rng: &mut Rng, out: &mut Vec<u8>) {
+        let len = self.tdi.len();
+        out.clear();
+        match dont_care {
+            DontCare::Zero => out.resize(len, 0),
+            DontCare::One => out.resize(len, 0xff),
+            DontCare::Previous => {
+                out.extend_from_slice(&self.driven);
+                out.resize(len, 0);
+            }
+            DontCare::Random => {
+                out.resize(len, 0);
+                rng.fill(out);
+            }
+        }
+        for (fill, (tdi, mask)) in zip(out.iter_mut(), zip(&self.tdi, &self.smask)) {
+            *fill = tdi & mask | *fill & !mask;
+        }
+        bits::clear_pad(out, self.length.unwrap_or(0));
+        self.driven.clear();
+        self.driven.extend_from_slice(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TapState::*;
+
+    const STATES: [TapState; 16] = [
+        Reset, Idle, SelectDR, CaptureDR, ShiftDR, Exit1DR, PauseDR, Exit2DR, UpdateDR,
+        SelectIR, CaptureIR, ShiftIR, Exit1IR, PauseIR, Exit2IR, UpdateIR,
+    ];
+
+    #[test]
+    fn moves_follow_the_state_diagram() {
+        assert_eq!(shortest(Reset, Idle), [0]);
+        assert_eq!(shortest(Idle, Idle), Vec::<usize>::new());
+        assert_eq!(shortest(Idle, PauseIR), [1, 1, 0, 1, 0]);
+        assert_eq!(shortest(PauseIR, Reset), [1, 1, 1, 1, 1]);
+        assert_eq!(shortest(PauseIR, PauseDR), [1, 1, 1, 0, 1, 0]);
+        assert_eq!(shortest(PauseDR, Idle), [1, 1, 0]);
+        for from in STATES {
+            for to in STATES {
+                let tms = shortest(from, to);
+                assert!(tms.iter().fold(from, |state, &tms| next(state, tms)) == to);
+            }
+        }
+    }
+
+    #[test]
+    fn scans_out_of_pause_update_and_capture_first() {
+        assert_eq!(to_shift(Idle, Reg::Data), [1, 0, 0]);
+        assert_eq!(to_shift(Reset, Reg::Instruction), [0, 1, 1, 0, 0]);
+        assert_eq!(to_shift(PauseDR, Reg::Data), [1, 1, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(to_shift(PauseIR, Reg::Instruction), [1, 1, 1, 1, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn state_paths_step_one_tck_at_a_time() {
+        assert_eq!(follow(Idle, &[SelectDR, SelectIR, CaptureIR, Exit1IR, PauseIR]), Ok(vec![1, 1, 0, 1, 0]));
+        assert!(follow(Idle, &[CaptureDR]).unwrap_err().contains("IDLE to DRCAPTURE"));
+    }
+
+    #[test]
+    fn repeated_scans_are_noticed() {
+        let mut sticky = Sticky::default();
+        sticky.update("SDR", 8, Some(vec![0xff]), None, None).unwrap();
+        assert!(!sticky.repeated);
+        sticky.update("SDR", 8, None, Some(vec![0x0f]), None).unwrap();
+        assert!(sticky.repeated);
+        sticky.update("SDR", 8, Some(vec![0xff]), None, Some(vec![0xff])).unwrap();
+        assert!(sticky.repeated);
+        sticky.update("SDR", 8, Some(vec![0xfe]), None, None).unwrap();
+        assert!(!sticky.repeated);
+        sticky.update("SDR", 16, Some(vec![0xfe, 0]), None, None).unwrap();
+        assert!(!sticky.repeated);
+    }
+
+    #[test]
+    fn bad_vectors_are_errors_rather_than_panics() {
+        let mut sticky = Sticky::default();
+        assert_eq!(sticky.update("SDR", 8, None, None, None), Err(ScanError::NoTdi { name: "SDR".into(), length: 8 }));
+        let err = sticky.update("SIR", 4, Some(vec![0x1f]), None, None).unwrap_err();
+        assert_eq!(err.to_string(), "SIR TDI has more bits than the length 4");
+        assert_eq!(scan_vector("SDR", "TDO", vec![0x0f], 4), Ok(vec![0x0f]));
+    }
+
+    #[test]
+    fn driving_into_a_used_buffer_fills_in_dont_care_bits() {
+        let mut sticky = Sticky::default();
+        let mut rng = Rng::new(0);
+        sticky.update("SDR", 12, Some(vec![0x0f, 0x0c]), None, Some(vec![0x0f, 0x0f])).unwrap();
+        let mut out = vec![0xaa; 20];
+        sticky.drive_into(DontCare::One, &mut rng, &mut out);
+        assert_eq!(out, [0xff, 0x0c]);
+        sticky.update("SDR", 12, Some(vec![0, 0]), None, Some(vec![0x0f, 0x00])).unwrap();
+        sticky.drive_into(DontCare::Previous, &mut rng, &mut out);
+        assert_eq!(out, [0xf0, 0x0c]);
+        sticky.drive_into(DontCare::Random, &mut rng, &mut out);
+        assert_eq!((out[0] & 0x0f, out.len(), out[1] & 0xf0), (0, 2, 0));
+    }
+}
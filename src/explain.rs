@@ -0,0 +1,233 @@
+//! `play --explain`: print every command as the player would execute it, without opening the
+//! cable.  Scans are written out with the TDI, MASK and SMASK they inherit from the scans before
+//! them, after --target padding and --patch, and every command that moves the TAP names the
+//! states it passes through on the way to where it ends.
+use std::io::{self, BufRead, Write};
+
+use jtag_taps::statemachine::{JtagState, Register};
+use svf::{Command, ParseError, Pattern, RunTestForm};
+
+use crate::engine::{scan_vector, Sticky};
+use crate::patch::Patch;
+use crate::player::{svf_state, Svf};
+use crate::svf_writer::hex;
+use crate::target::Padding;
+use crate::{compiled, lattice, path, preflight};
+
+pub struct Explainer<W> {
+    out: W,
+    pub padding: Option<Padding>,
+    pub patches: Vec<Patch>,
+    stable: JtagState,
+    endir: JtagState,
+    enddr: JtagState,
+    run_state: JtagState,
+    end_state: JtagState,
+    sir: Sticky,
+    sdr: Sticky,
+    sdr_count: usize,
+}
+
+fn states(route: &[JtagState]) -> String {
+    let names: Vec<_> = route.iter().map(|&state| svf_state(state).to_string()).collect();
+    names.join(" > ")
+}
+
+impl<W: Write> Explainer<W> {
+    pub fn new(out: W) -> Self {
+        Explainer {
+            out,
+            padding: None,
+            patches: vec![],
+            stable: JtagState::Idle,
+            endir: JtagState::Idle,
+            enddr: JtagState::Idle,
+            run_state: JtagState::Idle,
+            end_state: JtagState::Idle,
+            sir: Sticky::default(),
+            sdr: Sticky::default(),
+            sdr_count: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn note(&mut self, note: impl std::fmt::Display) -> io::Result<()> {
+        writeln!(self.out, "      ! {}", note)
+    }
+
+    fn moves(&mut self, route: &[JtagState]) -> io::Result<()> {
+        match route {
+            [] => self.note(format!("stays in {}", svf_state(self.stable))),
+            route => self.note(format!("{} > {}", svf_state(self.stable), states(route))),
+        }
+    }
+
+    fn scan(&mut self, number: usize, name: &'static str, pattern: Pattern) -> Result<(), String> {
+        let reg = if name == "SIR" { Register::Instruction } else { Register::Data };
+        let (header, trailer) = match (&self.padding, reg) {
+            (Some(padding), Register::Data) => padding.dr_bits(),
+            (Some(padding), Register::Instruction) => padding.ir_bits(),
+            (None, _) => (0, 0),
+        };
+        let pattern = match (&mut self.padding, reg) {
+            (Some(padding), Register::Instruction) => padding.sir(pattern),
+            (Some(padding), Register::Data) => padding.sdr(pattern),
+            (None, _) => pattern,
+        };
+        let length = pattern.length;
+        let tdo = pattern.tdo.map(|tdo| scan_vector(name, "TDO", tdo, length)).transpose()
+            .map_err(|e| e.to_string())?;
+        let sticky = if reg == Register::Instruction { &mut self.sir } else { &mut self.sdr };
+        sticky.update(name, length, pattern.tdi, pattern.mask, pattern.smask).map_err(|e| e.to_string())?;
+        if reg == Register::Data {
+            self.sdr_count += 1;
+            for patch in self.patches.iter().filter(|patch| patch.sdr == self.sdr_count) {
+                patch.apply(&mut self.sdr.tdi, length - header - trailer, header)?;
+            }
+        }
+        let end = if reg == Register::Instruction { self.endir } else { self.enddr };
+        let sticky = if reg == Register::Instruction { &self.sir } else { &self.sdr };
+        let mut text = format!("{:>5} {} {}", number, name, length);
+        if length != 0 {
+            text += &format!(" TDI ({})", hex(&sticky.tdi, length));
+            if let Some(tdo) = &tdo {
+                text += &format!(" TDO ({})", hex(tdo, length));
+            }
+            text += &format!(" MASK ({}) SMASK ({})", hex(&sticky.mask, length), hex(&sticky.smask, length));
+        }
+        writeln!(self.out, "{};", text).map_err(|e| e.to_string())?;
+        if tdo.is_none() {
+            self.note("TDO isn't checked").map_err(|e| e.to_string())?;
+        }
+        if header + trailer != 0 {
+            self.note(format!("{} header and {} trailer bits of BYPASS for --target", header, trailer))
+                .map_err(|e| e.to_string())?;
+        }
+        let route = if length == 0 { path::route(self.stable, end) } else { path::scan_route(self.stable, reg, end) };
+        self.moves(&route).map_err(|e| e.to_string())?;
+        self.stable = end;
+        Ok(())
+    }
+
+    /// Print `cmd`, the `number`th of its file, as it would be played
+    pub fn command(&mut self, number: usize, cmd: Command) -> Result<(), String> {
+        let unsupported = preflight::unsupported(&cmd);
+        let io = |e: io::Error| e.to_string();
+        match cmd {
+            Command::SIR(pattern) if unsupported.is_none() => return self.scan(number, "SIR", pattern),
+            Command::SDR(pattern) if unsupported.is_none() => return self.scan(number, "SDR", pattern),
+            Command::EndIR(state) => {
+                self.endir = Svf::to_jtag_state(state);
+                writeln!(self.out, "{:>5} {}", number, cmd).map_err(io)?;
+            }
+            Command::EndDR(state) => {
+                self.enddr = Svf::to_jtag_state(state);
+                writeln!(self.out, "{:>5} {}", number, cmd).map_err(io)?;
+            }
+            Command::State { path: Some(ref given), end } => {
+                writeln!(self.out, "{:>5} {}", number, cmd).map_err(io)?;
+                let route: Vec<_> = given.iter().chain([&end]).map(|&state| Svf::to_jtag_state(state)).collect();
+                path::follow(self.stable, &route)?;
+                self.moves(&route).map_err(io)?;
+                self.stable = Svf::to_jtag_state(end);
+            }
+            Command::State { path: None, end } => {
+                writeln!(self.out, "{:>5} {}", number, cmd).map_err(io)?;
+                let end = Svf::to_jtag_state(end);
+                self.moves(&path::route(self.stable, end)).map_err(io)?;
+                self.stable = end;
+            }
+            Command::RunTest { run_state, ref form, end_state } if unsupported.is_none() => {
+                if let Some(state) = run_state {
+                    self.run_state = Svf::to_jtag_state(state);
+                }
+                if let Some(state) = end_state {
+                    self.end_state = Svf::to_jtag_state(state);
+                }
+                writeln!(self.out, "{:>5} RUNTEST {} {} ENDSTATE {};", number, svf_state(self.run_state), form,
+                         svf_state(self.end_state)).map_err(io)?;
+                if let RunTestForm::Clocked { time: Some(time), .. } | RunTestForm::Timed(time) = form {
+                    self.note(format!("waits at least {} s", time.min)).map_err(io)?;
+                }
+                let mut route = path::route(self.stable, self.run_state);
+                route.extend(path::route(self.run_state, self.end_state));
+                self.moves(&route).map_err(io)?;
+                self.stable = self.end_state;
+            }
+            cmd => {
+                writeln!(self.out, "{:>5} {}", number, cmd).map_err(io)?;
+                if let Command::TRST(svf::TRSTMode::On) = cmd {
+                    self.stable = JtagState::Reset;
+                }
+            }
+        }
+        if let Some(why) = unsupported {
+            self.note(format!("not played: {}", why)).map_err(io)?;
+        }
+        Ok(())
+    }
+
+    /// Explain a whole file, LOOP bodies once each
+    pub fn explain(&mut self, input: &mut impl BufRead) -> Result<(), String> {
+        let markers = lattice::Markers::default();
+        if compiled::is_compiled(input) {
+            let commands = compiled::Reader::new(input, markers.clone()).map_err(|e| e.to_string())?;
+            return self.explain_commands(commands, markers);
+        }
+        let mut input = lattice::LoopFilter::new(input, markers.clone());
+        self.explain_commands(svf::parse_iter_bufread(&mut input), markers)
+    }
+
+    fn explain_commands(&mut self, commands: impl Iterator<Item = Result<Command, ParseError>>,
+                        markers: lattice::Markers) -> Result<(), String> {
+        let mut commands = commands.enumerate();
+        loop {
+            let next = commands.next();
+            let i = next.as_ref().map_or(usize::MAX, |(i, _)| *i);
+            while markers.borrow().front().is_some_and(|m| m.before() <= i) {
+                match markers.borrow_mut().pop_front().unwrap() {
+                    lattice::Marker::Loop { count, .. } => writeln!(self.out, "      LOOP {};", count),
+                    lattice::Marker::EndLoop { .. } => writeln!(self.out, "      ENDLOOP;"),
+                    lattice::Marker::Phase { name, .. } => writeln!(self.out, "      ! phase {}", name),
+                }.map_err(|e| e.to_string())?;
+            }
+            let Some((i, cmd)) = next else {
+                return Ok(());
+            };
+            let cmd = cmd.map_err(|e| e.to_string())?;
+            self.command(i + 1, cmd).map_err(|e| format!("command {}: {}", i + 1, e))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explain(text: &str, padding: Option<Padding>) -> String {
+        let mut explainer = Explainer::new(vec![]);
+        explainer.padding = padding;
+        explainer.explain(&mut text.as_bytes()).unwrap();
+        String::from_utf8(explainer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn scans_show_what_they_inherit_and_where_they_go() {
+        let text = "ENDDR DRPAUSE;\nSDR 8 TDI (a5) MASK (0f);\nSDR 8 TDO (05);\nRUNTEST 10 TCK;\n";
+        let out = explain(text, None);
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "    1 ENDDR DRPAUSE;");
+        assert_eq!(lines[4], "    3 SDR 8 TDI (A5) TDO (05) MASK (0F) SMASK (FF);");
+        assert_eq!(lines[5], concat!("      ! DRPAUSE > DREXIT2 > DRUPDATE > DRSELECT > DRCAPTURE > DREXIT1 > DRPAUSE > DREXIT2 > ",
+                                   "DRSHIFT > DREXIT1 > DRPAUSE"));
+        assert_eq!(lines[6], "    4 RUNTEST IDLE 10 TCK ENDSTATE IDLE;");
+
+        let out = explain("SIR 6 TDI (09);\n", Some(Padding::new(&[4, 6, 5], 1).unwrap()));
+        assert!(out.contains("SIR 15 TDI (7C9F)"));
+        assert!(out.contains("4 header and 5 trailer bits"));
+        assert!(explain("HIR 8 TDI (ff);\n", None).contains("not played: headers and trailers"));
+    }
+}
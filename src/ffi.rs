@@ -0,0 +1,148 @@
+//! C API built into the shared library by `cargo build --release --manifest-path cdylib/Cargo.toml`
+//! and declared in `include/svfplayer.h`.  Functions that can fail return NULL or -1 and leave a
+//! message for `svfplayer_last_error` on the calling thread.
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::AssertUnwindSafe;
+
+use jtag_taps::statemachine::JtagSM;
+
+use crate::cable::{self, AdapterBox};
+use crate::config::LogLevel;
+use crate::observer::Observer;
+use crate::{panic_message, run_svf, Svf};
+
+pub type ProgressFn = extern "C" fn(done: usize, total: usize, user: *mut c_void);
+
+pub struct Player {
+    jtag: JtagSM<AdapterBox>,
+    svf: Svf,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+/// # Safety
+/// `s` must be NULL or a NUL-terminated string
+unsafe fn string<'a>(s: *const c_char, what: &str) -> Option<&'a str> {
+    if s.is_null() {
+        fail(format!("{} is NULL", what));
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            fail(format!("{} is not UTF-8", what));
+            None
+        }
+    }
+}
+
+struct Progress {
+    callback: ProgressFn,
+    user: *mut c_void,
+}
+
+impl Observer for Progress {
+    fn on_progress(&mut self, done: usize, total: Option<usize>) {
+        (self.callback)(done, total.unwrap_or(0), self.user);
+    }
+
+    fn wants_tdo(&self) -> bool {
+        false
+    }
+}
+
+/// Open the cable described by `cable` (a --cable spec) at `baud`
+///
+/// # Safety
+/// `cable` must be a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn svfplayer_open(cable: *const c_char, baud: u32) -> *mut Player {
+    let Some(cable) = string(cable, "cable") else {
+        return std::ptr::null_mut();
+    };
+    match std::panic::catch_unwind(|| cable::open(cable, baud)) {
+        Ok(Ok(adapter)) => {
+            let mut svf = Svf::new();
+            svf.log_level = LogLevel::Warn;
+            Box::into_raw(Box::new(Player { jtag: JtagSM::new(AdapterBox(adapter)), svf }))
+        }
+        Ok(Err(e)) => {
+            fail(e);
+            std::ptr::null_mut()
+        }
+        Err(e) => {
+            fail(panic_message(&*e));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Play the SVF file at `path`, calling `progress` (if not NULL) after every command with the
+/// number done and 0 for the total, which isn't known up front
+///
+/// # Safety
+/// `player` must come from `svfplayer_open` and `path` must be a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn svfplayer_play(player: *mut Player, path: *const c_char,
+                                        progress: Option<ProgressFn>, user: *mut c_void) -> c_int {
+    let Some(player) = player.as_mut() else {
+        fail("player is NULL".into());
+        return -1;
+    };
+    let Some(path) = string(path, "path") else {
+        return -1;
+    };
+    let mut input = match crate::input::open(path) {
+        Ok(input) => input,
+        Err(e) => {
+            fail(format!("{}: {}", path, e));
+            return -1;
+        }
+    };
+    player.svf.observer = progress.map(|callback| Box::new(Progress { callback, user }) as Box<dyn Observer>);
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        run_svf(&mut player.jtag, &mut player.svf, &mut input, None)
+    }));
+    player.svf.observer = None;
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(e) => Some(panic_message(&*e)),
+    };
+    // The next file starts from a known TAP state, whatever this one left behind
+    player.svf.abandon();
+    player.svf.reset();
+    player.jtag.mode_reset();
+    match error {
+        Some(e) => {
+            fail(e);
+            -1
+        }
+        None => 0,
+    }
+}
+
+/// Why the last call on this thread failed.  Valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn svfplayer_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+/// Close the cable and free `player`
+///
+/// # Safety
+/// `player` must come from `svfplayer_open` and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn svfplayer_close(player: *mut Player) {
+    if !player.is_null() {
+        drop(Box::from_raw(player));
+    }
+}
@@ -0,0 +1,190 @@
+//! `--fields`: name pieces of what particular scans read back, and print them decoded when the
+//! run ends, rather than leaving the user to pick bits out of hex TDO dumps.
+//!
+//! ```toml
+//! [[field]]
+//! name = "USERCODE"
+//! sdr = 4          # the 4th SDR played; or sir = N
+//! width = 32
+//!
+//! [[field]]
+//! name = "DONE"
+//! sir = 2
+//! offset = 5
+//! width = 1
+//! format = "bin"   # hex (the default), dec or bin
+//! ```
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde::Deserialize;
+use svf::Command;
+
+use crate::cable::bit;
+use crate::observer::Observer;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Hex,
+    Dec,
+    Bin,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    pub name: String,
+    /// Which SIR or SDR, counting every one played from 1
+    pub sir: Option<usize>,
+    pub sdr: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+    pub width: usize,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldMap {
+    field: Vec<Field>,
+}
+
+pub fn load(path: &Path) -> Result<Vec<Field>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn parse(text: &str) -> Result<Vec<Field>, String> {
+    let map: FieldMap = toml::from_str(text).map_err(|e| e.to_string())?;
+    for field in &map.field {
+        if field.sir.is_some() == field.sdr.is_some() {
+            return Err(format!("{}: give one of sir or sdr", field.name));
+        }
+        if field.width == 0 || field.format == Format::Dec && field.width > 64 {
+            return Err(format!("{}: width must be 1 to 64 bits for dec, at least 1 otherwise", field.name));
+        }
+    }
+    Ok(map.field)
+}
+
+impl Field {
+    fn decode(&self, tdo: &[u8]) -> String {
+        let bits: Vec<bool> = (self.offset..self.offset + self.width).map(|i| bit(tdo, i)).collect();
+        let value = |bits: &[bool]| bits.iter().rev().fold(0u64, |v, b| v << 1 | *b as u64);
+        match self.format {
+            Format::Dec => value(&bits).to_string(),
+            Format::Bin => bits.iter().rev().map(|b| if *b { '1' } else { '0' }).collect(),
+            Format::Hex => {
+                let digits: String = bits.chunks(4).rev().map(|nibble| format!("{:x}", value(nibble))).collect();
+                format!("0x{}", digits)
+            }
+        }
+    }
+}
+
+/// Keeps the readback of the scans that `fields` take their values from
+pub struct Capture {
+    fields: Vec<Field>,
+    sirs: usize,
+    sdrs: usize,
+    /// Kind of the scan running now, if a field wants it
+    wanted: Option<&'static str>,
+    /// TDO by field, for each field whose scan has run
+    values: Vec<Option<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new(fields: Vec<Field>) -> Rc<RefCell<Capture>> {
+        let values = vec![None; fields.len()];
+        Rc::new(RefCell::new(Capture { fields, sirs: 0, sdrs: 0, wanted: None, values }))
+    }
+
+    fn field_wants(field: &Field, kind: &str, index: usize) -> bool {
+        match kind {
+            "SIR" => field.sir == Some(index),
+            _ => field.sdr == Some(index),
+        }
+    }
+
+    fn on_command(&mut self, cmd: &Command) {
+        let (kind, index) = match cmd {
+            Command::SIR(_) => {
+                self.sirs += 1;
+                ("SIR", self.sirs)
+            }
+            Command::SDR(_) => {
+                self.sdrs += 1;
+                ("SDR", self.sdrs)
+            }
+            _ => {
+                self.wanted = None;
+                return;
+            }
+        };
+        self.wanted = self.fields.iter().any(|f| Self::field_wants(f, kind, index)).then_some(kind);
+    }
+
+    fn on_tdo(&mut self, kind: &'static str, tdo: &[u8]) {
+        if self.wanted != Some(kind) {
+            return;
+        }
+        let index = if kind == "SIR" { self.sirs } else { self.sdrs };
+        for (field, value) in self.fields.iter().zip(&mut self.values) {
+            if Self::field_wants(field, kind, index) {
+                *value = Some(tdo.to_vec());
+            }
+        }
+    }
+
+    /// Every field as `NAME = value`, or a note that its scan didn't run
+    pub fn report(&self) -> Vec<String> {
+        self.fields.iter().zip(&self.values)
+            .map(|(field, value)| match value {
+                Some(tdo) if field.offset + field.width > tdo.len() * 8 => {
+                    format!("{}: beyond the {} bits captured", field.name, tdo.len() * 8)
+                }
+                Some(tdo) => format!("{} = {}", field.name, field.decode(tdo)),
+                None => format!("{}: not captured", field.name),
+            })
+            .collect()
+    }
+}
+
+/// Hands the player's callbacks to a shared `Capture`
+pub struct CaptureObserver(pub Rc<RefCell<Capture>>);
+
+impl Observer for CaptureObserver {
+    fn on_command(&mut self, cmd: &Command) {
+        self.0.borrow_mut().on_command(cmd);
+    }
+
+    fn on_tdo(&mut self, kind: &'static str, tdo: &[u8]) {
+        self.0.borrow_mut().on_tdo(kind, tdo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_cut_from_their_scans() {
+        let fields = parse("[[field]]\nname = \"ID\"\nsdr = 2\nwidth = 12\n\n\
+                            [[field]]\nname = \"DONE\"\nsir = 1\noffset = 1\nwidth = 2\nformat = \"bin\"\n\n\
+                            [[field]]\nname = \"COUNT\"\nsdr = 1\noffset = 4\nwidth = 8\nformat = \"dec\"\n\n\
+                            [[field]]\nname = \"LATER\"\nsdr = 9\nwidth = 1\n").unwrap();
+        let capture = Capture::new(fields);
+        let mut observer = CaptureObserver(capture.clone());
+        for (kind, cmd, tdo) in [("SIR", "SIR 4 TDI (0);", [0x04, 0x00]), ("SDR", "SDR 12 TDI (0);", [0x50, 0x01]),
+                                 ("SDR", "SDR 12 TDI (0);", [0xbc, 0x0a])] {
+            observer.on_command(&svf::parse_complete(cmd).unwrap()[0]);
+            observer.on_tdo(kind, &tdo);
+        }
+        assert_eq!(capture.borrow().report(), ["ID = 0xabc", "DONE = 10", "COUNT = 21", "LATER: not captured"]);
+        assert!(parse("[[field]]\nname = \"X\"\nwidth = 1\n").is_err());
+    }
+}
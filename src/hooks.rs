@@ -0,0 +1,23 @@
+//! `--pre-cmd` and `--post-cmd`: shell commands run around playback, e.g. to power-cycle the
+//! board.  The post command sees the outcome in `SVFPLAYER_RESULT` (`passed` or `failed`) and
+//! `SVFPLAYER_ERROR`, and both see the files being played in `SVFPLAYER_INPUT`.
+use std::process::{Command, ExitStatus};
+
+fn shell(command: &str, inputs: &[String]) -> Command {
+    let mut c = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    c.args([if cfg!(windows) { "/C" } else { "-c" }, command]);
+    c.env("SVFPLAYER_INPUT", inputs.join(" "));
+    c
+}
+
+pub fn pre(command: &str, inputs: &[String]) -> std::io::Result<ExitStatus> {
+    shell(command, inputs).status()
+}
+
+/// Run the post command for a run that failed with `error`, or passed if there is none
+pub fn post(command: &str, inputs: &[String], error: Option<&str>) -> std::io::Result<ExitStatus> {
+    shell(command, inputs)
+        .env("SVFPLAYER_RESULT", if error.is_some() { "failed" } else { "passed" })
+        .env("SVFPLAYER_ERROR", error.unwrap_or(""))
+        .status()
+}
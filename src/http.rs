@@ -0,0 +1,222 @@
+//! REST front-end to the job daemon, enabled with `svfplayer serve --http ADDRESS:PORT`.
+//!
+//! - `POST /jobs` queues the SVF in the request body, or the file named by a JSON body of the form
+//!   `{"path": "..."}`, and answers `201` with the job's status, or `413` for a body over the
+//!   daemon's payload limit.  `?priority=N` queues it ahead of jobs of lower priority.
+//! - `GET /jobs` lists every job, `GET /jobs/{id}` reports one: its progress, the phase and kind
+//!   of command playing, and once it has ended the totals of each phase it marked
+//! - `DELETE /jobs/{id}` cancels a job, stopping it after the command playing if it has started
+//! - `GET /cables` shows the cable jobs run on, how many jobs are pending and which adapters are
+//!   attached
+//!
+//! Clients `--allow` doesn't list are answered `403`.  With a token, every request has to carry
+//! it as `Authorization: Bearer <token>` or is answered `401`.
+use std::io::Read;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::access::Gate;
+use crate::color;
+use crate::daemon::{Queue, MAX_PAYLOAD};
+
+/// Threads answering requests, so a client that is slow to send its body doesn't hold up the rest
+const WORKERS: usize = 4;
+
+#[derive(Deserialize)]
+struct FileJob {
+    path: String,
+}
+
+#[derive(Serialize)]
+struct Attached {
+    spec: String,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct Cables {
+    active: String,
+    pending: usize,
+    attached: Vec<Attached>,
+}
+
+#[derive(Serialize)]
+struct Error {
+    error: String,
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_data(serde_json::to_vec(body).unwrap())
+        .with_status_code(status)
+        .with_header(header)
+}
+
+fn error(status: u16, message: impl Into<String>) -> Response<std::io::Cursor<Vec<u8>>> {
+    json(status, &Error { error: message.into() })
+}
+
+fn is_json(request: &Request) -> bool {
+    request.headers().iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"))
+}
+
+fn post_job(request: &mut Request, queue: &Queue, query: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let priority = match query.split('&').find_map(|param| param.strip_prefix("priority=")) {
+        Some(priority) => match priority.parse() {
+            Ok(priority) => priority,
+            Err(_) => return error(400, format!("bad priority {}", priority)),
+        },
+        None => 0,
+    };
+    let too_large = || error(413, format!("the body is larger than {} bytes", MAX_PAYLOAD));
+    if request.body_length().is_some_and(|length| length > MAX_PAYLOAD) {
+        return too_large();
+    }
+    let mut body = vec![];
+    if let Err(e) = request.as_reader().take(MAX_PAYLOAD as u64 + 1).read_to_end(&mut body) {
+        return error(400, e.to_string());
+    }
+    if body.len() > MAX_PAYLOAD {
+        return too_large();
+    }
+    let svf = if is_json(request) {
+        let job: FileJob = match serde_json::from_slice(&body) {
+            Ok(job) => job,
+            Err(e) => return error(400, e.to_string()),
+        };
+        let mut svf = vec![];
+        if let Err(e) = crate::input::open(&job.path).and_then(|mut input| input.read_to_end(&mut svf)) {
+            return error(400, format!("{}: {}", job.path, e));
+        }
+        svf
+    } else {
+        body
+    };
+
+    // Progress is polled through GET /jobs/{id}, so nobody listens to the events
+    let (id, _) = queue.submit(svf, priority);
+    json(201, &queue.status(id))
+}
+
+fn authorized(request: &Request, gate: &Gate) -> bool {
+    !gate.needs_token() || request.headers().iter().any(|h| {
+        h.field.equiv("Authorization")
+            && h.value.as_str().strip_prefix("Bearer ").is_some_and(|token| gate.accepts(token.as_bytes()))
+    })
+}
+
+fn handle(request: &mut Request, queue: &Queue, gate: &Gate) -> Response<std::io::Cursor<Vec<u8>>> {
+    if !request.remote_addr().is_some_and(|addr| gate.allows(addr.ip())) {
+        return error(403, "this address is not allowed");
+    }
+    if !authorized(request, gate) {
+        return error(401, "a valid bearer token is required");
+    }
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let path: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    match (request.method(), path.as_slice()) {
+        (Method::Post, ["jobs"]) => post_job(request, queue, query),
+        (Method::Get, ["jobs"]) => json(200, &queue.all()),
+        (Method::Get, ["jobs", id]) => match id.parse().ok().and_then(|id| queue.status(id)) {
+            Some(status) => json(200, &status),
+            None => error(404, format!("no job {}", id)),
+        },
+        (Method::Delete, ["jobs", id]) => match id.parse() {
+            Ok(id) => match queue.cancel(id) {
+                Ok(()) => json(200, &queue.status(id)),
+                Err(e) => error(404, e),
+            },
+            Err(_) => error(404, format!("no job {}", id)),
+        },
+        (Method::Get, ["cables"]) => json(200, &Cables {
+            active: queue.cable.to_string(),
+            pending: queue.pending(),
+            attached: crate::cable::detect().into_iter()
+                .map(|(spec, description)| Attached { spec, description })
+                .collect(),
+        }),
+        (_, ["jobs"] | ["jobs", _] | ["cables"]) => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    }
+}
+
+/// Answer requests forever
+pub fn serve(server: Server, queue: Queue, gate: Gate) {
+    let server = Arc::new(server);
+    let workers: Vec<_> = (0..WORKERS).map(|_| {
+        let (server, queue, gate) = (server.clone(), queue.clone(), gate.clone());
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let response = handle(&mut request, &queue, &gate);
+                if let Err(e) = request.respond(response) {
+                    eprintln!("{} HTTP response failed: {}", color::warning(), e);
+                }
+            }
+        })
+    }).collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::daemon::Player;
+
+    fn start() -> std::net::SocketAddr {
+        let chain = std::env::temp_dir().join(format!("svfplayer-http-{}.toml", std::process::id()));
+        std::fs::write(&chain, "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n").unwrap();
+        let queue = Queue::spawn(Player {
+            cable: format!("sim:{}", chain.display()),
+            baud: 0,
+            retries: 0,
+            log_level: crate::config::LogLevel::Error,
+            bit_order: Default::default(),
+            dont_care: Default::default(),
+            preflight: false,
+            deadline: None,
+        }).unwrap();
+        // The cable is open by now
+        std::fs::remove_file(&chain).unwrap();
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        std::thread::spawn(move || serve(server, queue, Gate::default()));
+        addr
+    }
+
+    /// Send `head` and as much of the body as `body`, returning the status line of the answer
+    fn request(addr: std::net::SocketAddr, head: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        write!(stream, "{}\r\nConnection: close\r\n\r\n{}", head, body).unwrap();
+        let mut answer = String::new();
+        let _ = stream.read_to_string(&mut answer);
+        answer.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn oversized_bodies_are_refused_and_a_slow_one_holds_nobody_up() {
+        let addr = start();
+        // Never sends the body it promises, which is too long for tiny_http to read before handing
+        // the request over
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        write!(stalled, "POST /jobs HTTP/1.1\r\nContent-Length: 100000\r\n\r\nSIR").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let too_large = format!("POST /jobs HTTP/1.1\r\nContent-Length: {}", MAX_PAYLOAD + 1);
+        assert!(request(addr, &too_large, "").contains(" 413 "));
+        let svf = "SIR 4 TDI (e);\n";
+        let head = format!("POST /jobs HTTP/1.1\r\nContent-Length: {}", svf.len());
+        assert!(request(addr, &head, svf).contains(" 201 "));
+        drop(stalled);
+    }
+}
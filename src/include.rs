@@ -0,0 +1,139 @@
+//! The `! include "file.svf"` extension, enabled with `--includes`, which plays another file in
+//! place of the directive.  Being a comment, the directive is harmless to tools that don't know
+//! it.  Paths are relative to the file doing the including, and a file that includes itself,
+//! directly or not, is an error rather than a hang.
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+
+use crate::input;
+
+struct Open {
+    /// Canonical path, None for standard input
+    path: Option<PathBuf>,
+    reader: Box<dyn BufRead>,
+}
+
+impl Open {
+    fn dir(&self) -> PathBuf {
+        match &self.path {
+            Some(path) => path.parent().unwrap_or(Path::new("/")).to_path_buf(),
+            None => PathBuf::from("."),
+        }
+    }
+}
+
+/// An input with its includes spliced in, a line at a time
+pub struct Includes {
+    stack: Vec<Open>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+/// The file named by an include directive on `line`, if it is one
+fn directive(line: &str) -> Option<Result<&str, String>> {
+    let rest = line.trim_start().strip_prefix('!')?.trim_start();
+    let rest = rest.strip_prefix("include")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let name = rest.trim().strip_prefix('"').and_then(|r| r.strip_suffix('"'));
+    Some(name.ok_or_else(|| format!("include needs a quoted file name: {}", line.trim())))
+}
+
+impl Includes {
+    /// Open `path` ("-" being standard input) with includes enabled
+    pub fn open(path: &str) -> io::Result<Includes> {
+        Includes::new(path, input::open(path)?)
+    }
+
+    /// Read `reader`, already opened from `path`, with includes enabled
+    pub fn new(path: &str, reader: Box<dyn BufRead>) -> io::Result<Includes> {
+        let canonical = if path == "-" { None } else { Some(Path::new(path).canonicalize()?) };
+        Ok(Includes { stack: vec![Open { path: canonical, reader }], line: vec![], pos: 0 })
+    }
+
+    fn include(&mut self, name: &str) -> io::Result<()> {
+        let path = self.stack.last().unwrap().dir().join(name);
+        let canonical = path.canonicalize().map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        if self.stack.iter().any(|open| open.path.as_ref() == Some(&canonical)) {
+            return Err(io::Error::other(format!("{} includes itself", canonical.display())));
+        }
+        let reader = input::open(&canonical.to_string_lossy())
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.stack.push(Open { path: Some(canonical), reader });
+        Ok(())
+    }
+
+    fn next_line(&mut self) -> io::Result<()> {
+        // The parser only passes on the kind of an I/O error, so say what it was here
+        self.splice().inspect_err(|e| eprintln!("error: {}", e))
+    }
+
+    fn splice(&mut self) -> io::Result<()> {
+        self.line.clear();
+        self.pos = 0;
+        while let Some(open) = self.stack.last_mut() {
+            if open.reader.read_until(b'\n', &mut self.line)? == 0 {
+                self.stack.pop();
+                continue;
+            }
+            let text = String::from_utf8_lossy(&self.line).into_owned();
+            match directive(&text) {
+                None => return Ok(()),
+                Some(Ok(name)) => {
+                    // Keep the directive's line break so the included file starts on a line of its own
+                    self.line = b"\n".to_vec();
+                    self.include(name)?;
+                    return Ok(());
+                }
+                Some(Err(e)) => return Err(io::Error::other(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for Includes {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Includes {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.line.len() {
+            self.next_line()?;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes_are_spliced_relative_to_the_includer() {
+        let dir = std::env::temp_dir().join(format!("svfplayer-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        std::fs::write(dir.join("main.svf"), "SIR 4 TDI (1);\n! include \"parts/erase.svf\"\nRUNTEST 1 TCK;\n").unwrap();
+        std::fs::write(dir.join("parts/erase.svf"), "SDR 8 TDI (ff);\n").unwrap();
+        std::fs::write(dir.join("loop.svf"), "!include \"loop.svf\"\n").unwrap();
+
+        let mut text = String::new();
+        Includes::open(dir.join("main.svf").to_str().unwrap()).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "SIR 4 TDI (1);\n\nSDR 8 TDI (ff);\nRUNTEST 1 TCK;\n");
+        let mut text = String::new();
+        let looped = Includes::open(dir.join("loop.svf").to_str().unwrap()).unwrap().read_to_string(&mut text);
+        assert!(looped.unwrap_err().to_string().contains("includes itself"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+//! Opening SVF inputs for streaming into the parser.  Compressed inputs are recognized by their
+//! magic bytes, so this works for standard input as well as for `.svf.gz` / `.svf.zst` files.
+use std::io::{BufRead, BufReader};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Open an SVF input.  "-" is standard input.
+pub fn open(path: &str) -> std::io::Result<Box<dyn BufRead>> {
+    let reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(std::fs::File::open(path)?))
+    };
+    decompress(reader)
+}
+
+fn decompress(mut reader: Box<dyn BufRead>) -> std::io::Result<Box<dyn BufRead>> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(GZIP_MAGIC) {
+        let decoder = flate2::bufread::MultiGzDecoder::new(reader);
+        Ok(Box::new(BufReader::new(decoder)))
+    } else if head.starts_with(ZSTD_MAGIC) {
+        let decoder = ruzstd::decoding::StreamingDecoder::new(reader)
+            .map_err(std::io::Error::other)?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        Ok(reader)
+    }
+}
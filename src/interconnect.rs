@@ -0,0 +1,178 @@
+//! `svfplayer interconnect`: a walking-ones test of the nets between BSDL-described devices.
+//! Every net is driven by one of its pins while the others listen.  With all nets low first and
+//! then each one high in turn, a listener that misses its own net's one is an open, one that
+//! picks up another net's is a short, and one that is high throughout is stuck.
+//!
+//! The netlist has a net per line, its name followed by its pins:
+//!
+//! ```text
+//! # name  pins as DEVICE.PORT
+//! D0      cpu.PA(0) fpga.IO(12)
+//! RESET_N cpu.NRST fpga.IO(3)
+//! ```
+use jtag_taps::statemachine::JtagSM;
+
+use crate::bscan::{drive, receiver, Drive, Target};
+use crate::cable::AdapterBox;
+use crate::Svf;
+
+pub struct Net {
+    pub name: String,
+    /// (device, port)
+    pub pins: Vec<(String, String)>,
+}
+
+pub fn parse_netlist(text: &str) -> Result<Vec<Net>, String> {
+    let mut nets = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let pins = words
+            .map(|pin| pin.split_once('.').map(|(d, p)| (d.to_string(), p.to_string()))
+                 .ok_or_else(|| format!("line {}: {} should look like DEVICE.PORT", i + 1, pin)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if pins.len() < 2 {
+            return Err(format!("line {}: net {} needs at least two pins", i + 1, name));
+        }
+        nets.push(Net { name: name.to_string(), pins });
+    }
+    Ok(nets)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Fault {
+    /// `pin` didn't see its net driven high
+    Open { net: String, pin: String },
+    Short { net: String, other: String },
+    StuckHigh { net: String },
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Fault::Open { net, pin } => write!(f, "{}: open at {}", net, pin),
+            Fault::Short { net, other } => write!(f, "{}: shorted to {}", net, other),
+            Fault::StuckHigh { net } => write!(f, "{}: stuck high", net),
+        }
+    }
+}
+
+/// Where a net is driven from and read back, as (device index, cell, pin)
+struct Plan {
+    driver: (usize, usize),
+    receivers: Vec<(usize, usize, String)>,
+}
+
+fn plan(target: &Target, names: &[String], nets: &[Net], cells: &mut [Vec<bool>]) -> Result<Vec<Plan>, String> {
+    let device = |name: &str| names.iter().position(|n| n == name).ok_or_else(|| format!("no device {}", name));
+    let mut plans = vec![];
+    for net in nets {
+        let mut driver = None;
+        let mut receivers = vec![];
+        for (name, port) in &net.pins {
+            let index = device(name)?;
+            let bsdl = target.devices[index].1;
+            let pin = format!("{}.{}", name, port);
+            if driver.is_none() {
+                if let Ok(cell) = drive(bsdl, &mut cells[index], port, Drive::Low) {
+                    driver = Some((index, cell));
+                    continue;
+                }
+            }
+            let cell = receiver(bsdl, port).ok_or_else(|| format!("{}: {} can't be read", net.name, pin))?;
+            // A listener must not drive the net too
+            if bsdl.cells.iter().any(|c| c.function.drives() && c.port.as_deref() == Some(port.as_str())) {
+                drive(bsdl, &mut cells[index], port, Drive::HighZ).map_err(|e| format!("{}: {}", net.name, e))?;
+            }
+            receivers.push((index, cell, pin));
+        }
+        let driver = driver.ok_or_else(|| format!("{}: no pin can drive it", net.name))?;
+        plans.push(Plan { driver, receivers });
+    }
+    Ok(plans)
+}
+
+/// Work out the faults from `reads[pattern][net]`, what the receivers of each of `nets` (its
+/// name and receiving pins) captured with all nets low (pattern 0) and then with net
+/// `pattern - 1` high
+fn diagnose(nets: &[(&str, Vec<&str>)], reads: &[Vec<Vec<bool>>]) -> Vec<Fault> {
+    let mut faults = vec![];
+    let stuck: Vec<bool> = reads[0].iter().map(|net| net.iter().any(|v| *v)).collect();
+    for (j, (net, _)) in nets.iter().enumerate() {
+        if stuck[j] {
+            faults.push(Fault::StuckHigh { net: net.to_string() });
+        }
+    }
+    for (driven, pattern) in reads.iter().enumerate().skip(1) {
+        let driven = driven - 1;
+        for (j, values) in pattern.iter().enumerate() {
+            if j == driven {
+                for (pin, value) in nets[j].1.iter().zip(values) {
+                    if !value {
+                        faults.push(Fault::Open { net: nets[j].0.to_string(), pin: pin.to_string() });
+                    }
+                }
+            } else if !stuck[j] && values.iter().any(|v| *v) {
+                let (a, b) = (driven.min(j), driven.max(j));
+                let short = Fault::Short { net: nets[a].0.to_string(), other: nets[b].0.to_string() };
+                if !faults.contains(&short) {
+                    faults.push(short);
+                }
+            }
+        }
+    }
+    faults
+}
+
+/// Run the test on the devices of `target`, called `names` in the netlist
+pub fn test(target: &Target, names: &[String], nets: &[Net], svf: &mut Svf, sm: &mut JtagSM<AdapterBox>)
+            -> Result<Vec<Fault>, String> {
+    let mut low = target.safe();
+    let plans = plan(target, names, nets, &mut low)?;
+    // Every capture reflects the pattern loaded by the scan before it
+    target.enter_extest(svf, sm, &low)?;
+    let mut patterns = vec![];
+    for plan in &plans {
+        let mut cells = low.clone();
+        let (index, cell) = plan.driver;
+        cells[index][cell] = true;
+        patterns.push(cells);
+    }
+    patterns.push(low);
+    let mut reads = vec![];
+    for cells in &patterns {
+        let captured = target.exchange(svf, sm, cells);
+        reads.push(plans.iter()
+            .map(|plan| plan.receivers.iter().map(|(index, cell, _)| captured[*index][*cell]).collect())
+            .collect());
+    }
+    let listeners: Vec<_> = nets.iter().zip(&plans)
+        .map(|(net, plan)| (net.name.as_str(), plan.receivers.iter().map(|(_, _, pin)| pin.as_str()).collect()))
+        .collect();
+    Ok(diagnose(&listeners, &reads))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_and_shorts_are_told_apart() {
+        let nets = [("A", vec!["u2.A"]), ("B", vec!["u2.B", "u2.C"]), ("C", vec!["u2.D"])];
+        // Net A is open, B and C are shorted together
+        let reads = vec![
+            vec![vec![false], vec![false, false], vec![false]],
+            vec![vec![false], vec![false, false], vec![false]],
+            vec![vec![false], vec![true, true], vec![true]],
+            vec![vec![false], vec![true, true], vec![true]],
+        ];
+        assert_eq!(diagnose(&nets, &reads), [
+            Fault::Open { net: "A".into(), pin: "u2.A".into() },
+            Fault::Short { net: "B".into(), other: "C".into() },
+        ]);
+        assert!(parse_netlist("A u1.Y\n").is_err());
+    }
+}
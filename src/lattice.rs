@@ -0,0 +1,196 @@
+//! Lattice Diamond's `LOOP n; ... ENDLOOP;` extension, which repeats the enclosed commands until
+//! their TDO checks pass.  The svf parser doesn't know these statements, so `LoopFilter` takes
+//! them out of the input and reports where they were.  Their newlines are kept so parse errors
+//! still point at the right line.
+//!
+//! The filter also picks out `! phase NAME` comments (or `// phase: NAME`), which mark where a
+//! named phase of the file such as an erase or a verify starts, for reports grouped by phase.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, Read};
+use std::rc::Rc;
+
+pub enum Marker {
+    /// A loop of up to `count` iterations starts before command `before`
+    Loop { before: usize, count: u32 },
+    /// The innermost loop ends before command `before`
+    EndLoop { before: usize },
+    /// Phase `name` starts with command `before`
+    Phase { before: usize, name: String },
+}
+
+impl Marker {
+    pub fn before(&self) -> usize {
+        match self {
+            Marker::Loop { before, .. } | Marker::EndLoop { before } | Marker::Phase { before, .. } => *before,
+        }
+    }
+}
+
+pub type Markers = Rc<RefCell<VecDeque<Marker>>>;
+
+/// Hands the svf parser one statement at a time, which is how much it reads per command
+pub struct LoopFilter<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    statements: usize,
+    markers: Markers,
+}
+
+/// The words of `statement` outside comments, up to its `;`
+pub fn words(statement: &str) -> Vec<String> {
+    statement.lines()
+        .map(|l| l.find('!').map_or(l, |i| &l[..i]))
+        .map(|l| l.find("//").map_or(l, |i| &l[..i]))
+        .flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ';'))
+        .filter(|w| !w.is_empty())
+        .map(str::to_uppercase)
+        .collect()
+}
+
+/// The phase names given in the comments of `statement`
+pub fn phases(statement: &str) -> Vec<String> {
+    statement.lines()
+        .filter_map(|l| l.find('!').map(|i| &l[i + 1..]).or_else(|| l.find("//").map(|i| &l[i + 2..])))
+        .filter_map(|comment| {
+            let comment = comment.trim_start();
+            let rest = comment.get(..5).filter(|word| word.eq_ignore_ascii_case("phase")).map(|_| &comment[5..])?;
+            let name = rest.strip_prefix(':').unwrap_or(rest);
+            (rest.starts_with([':', ' ', '\t']) && !name.trim().is_empty()).then(|| name.trim().to_string())
+        })
+        .collect()
+}
+
+impl<R: BufRead> LoopFilter<R> {
+    pub fn new(inner: R, markers: Markers) -> Self {
+        LoopFilter { inner, buf: vec![], pos: 0, statements: 0, markers }
+    }
+
+    fn next_statement(&mut self) -> std::io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        loop {
+            let mut statement = vec![];
+            if self.inner.read_until(b';', &mut statement)? == 0 {
+                return Ok(());
+            }
+            let text = String::from_utf8_lossy(&statement);
+            for name in phases(&text) {
+                self.markers.borrow_mut().push_back(Marker::Phase { before: self.statements, name });
+            }
+            let marker = match words(&text).as_slice() {
+                [keyword, count] if keyword == "LOOP" => {
+                    let count = count.parse()
+                        .map_err(|_| std::io::Error::other(format!("bad LOOP count {}", count)))?;
+                    Some(Marker::Loop { before: self.statements, count })
+                }
+                [keyword] if keyword == "ENDLOOP" => Some(Marker::EndLoop { before: self.statements }),
+                _ => None,
+            };
+            match marker {
+                Some(marker) => {
+                    self.markers.borrow_mut().push_back(marker);
+                    self.buf.extend(std::iter::repeat_n(b'\n', text.matches('\n').count()));
+                }
+                None => {
+                    // A comment after the last command has no `;` and isn't a command
+                    if !words(&text).is_empty() {
+                        self.statements += 1;
+                    }
+                    self.buf.extend(statement);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for LoopFilter<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for LoopFilter<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos >= self.buf.len() {
+            self.next_statement()?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cable::script::{self, Call::{ChangeMode, ReadWrite}, IDLE_TO_SHIFT_DR, PAUSE_TO_IDLE};
+
+    const FILE: &str = "! phase erase\nSIR 4 TDI (e);\nLOOP 3;\nSDR 8 TDI (00) TDO (5a);\n\
+                        RUNTEST 10 TCK;\nENDLOOP;\n// phase: verify\nSDR 8 TDI (00);\n! the end\n";
+
+    /// The commands the parser finds in `text` through the filter, and the markers it left
+    fn filter(text: &str) -> (Vec<svf::Command>, Vec<Marker>) {
+        let markers = Markers::default();
+        let mut input = LoopFilter::new(text.as_bytes(), markers.clone());
+        let commands: Vec<_> = svf::parse_iter_bufread(&mut input).collect::<Result<_, _>>().unwrap();
+        // The comment after the last command isn't counted as one
+        assert_eq!(input.statements, commands.len());
+        let markers = markers.borrow_mut().drain(..).collect();
+        (commands, markers)
+    }
+
+    #[test]
+    fn markers_are_numbered_by_the_commands_the_parser_sees() {
+        let (commands, markers) = filter(FILE);
+        assert_eq!(commands.len(), 4);
+        let markers: Vec<(usize, String)> = markers.iter().map(|marker| (marker.before(), match marker {
+            Marker::Loop { count, .. } => format!("LOOP {}", count),
+            Marker::EndLoop { .. } => "ENDLOOP".to_string(),
+            Marker::Phase { name, .. } => name.clone(),
+        })).collect();
+        assert_eq!(markers, [(0, "erase".to_string()), (1, "LOOP 3".to_string()), (3, "ENDLOOP".to_string()),
+                             (3, "verify".to_string())]);
+        assert!(matches!(commands[1], svf::Command::SDR(_)));
+        assert!(matches!(commands[2], svf::Command::RunTest { .. }));
+    }
+
+    #[test]
+    fn commands_stay_on_their_lines() {
+        let mut filtered = String::new();
+        LoopFilter::new(FILE.as_bytes(), Markers::default()).read_to_string(&mut filtered).unwrap();
+        let line = |text: &str, command: &str| text.lines().position(|l| l.starts_with(command));
+        for command in ["SIR", "SDR 8 TDI (00) TDO", "RUNTEST", "SDR 8 TDI (00);"] {
+            assert_eq!(line(&filtered, command), line(FILE, command), "{}", command);
+        }
+        assert_eq!(filtered.lines().count(), FILE.lines().count());
+    }
+
+    #[test]
+    fn words_and_phases_skip_comments() {
+        assert_eq!(words("! LOOP 3;\n  loop 3 // ENDLOOP\n;"), ["LOOP", "3"]);
+        assert!(words("\n! the end\n").is_empty());
+        assert_eq!(phases("! Phase program\n// phase:verify\n! phased out\n! phase\n"), ["program", "verify"]);
+    }
+
+    #[test]
+    fn loops_stop_once_tdo_matches() {
+        let scan = |tdo| vec![
+            ChangeMode(IDLE_TO_SHIFT_DR.to_vec(), true),
+            ReadWrite { data: vec![0x00], bits: 8, pause_after: true, tdo: vec![tdo] },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ];
+        // The third iteration isn't in the script, so playing it would fail
+        let mut calls = [scan(0x5b), scan(0x5a)].concat();
+        calls[0] = ChangeMode([&[0], &IDLE_TO_SHIFT_DR[..]].concat(), true);
+        script::play("LOOP 3;\nSDR 8 TDI (00) TDO (5a);\nENDLOOP;\n", calls);
+    }
+}
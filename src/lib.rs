@@ -0,0 +1,119 @@
+//! SVF player for the adapters supported by jtag_taps and the cables in `cable`.  The
+//! `svfplayer` binary is a command line front-end to this library; `Svf` plays commands on a
+//! `JtagSM` and `run_svf` plays a whole file.  Without the default `std` feature only the
+//! `no_std` core in `engine` and `bits` is built.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod access;
+#[cfg(feature = "std")]
+pub mod batch;
+pub mod bits;
+#[cfg(feature = "std")]
+pub mod bscan;
+#[cfg(feature = "std")]
+pub mod bsdl;
+#[cfg(feature = "std")]
+pub mod cable;
+#[cfg(feature = "std")]
+pub mod cable_profile;
+#[cfg(feature = "std")]
+pub mod chain;
+#[cfg(feature = "std")]
+pub mod checks;
+#[cfg(feature = "std")]
+pub mod cli;
+#[cfg(feature = "std")]
+pub mod color;
+#[cfg(feature = "std")]
+pub mod compiled;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod daemon;
+#[cfg(feature = "std")]
+pub mod devices;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod dump;
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod explain;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod fields;
+#[cfg(feature = "std")]
+pub mod hooks;
+#[cfg(feature = "std")]
+pub mod http;
+#[cfg(feature = "std")]
+pub mod include;
+#[cfg(feature = "std")]
+pub mod input;
+#[cfg(feature = "std")]
+pub mod interconnect;
+#[cfg(feature = "std")]
+pub mod lattice;
+#[cfg(feature = "std")]
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod optimize;
+#[cfg(feature = "std")]
+pub mod pacing;
+#[cfg(feature = "std")]
+pub mod patch;
+#[cfg(feature = "std")]
+pub mod path;
+#[cfg(feature = "std")]
+pub mod phases;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+mod player;
+#[cfg(feature = "std")]
+pub mod postmortem;
+#[cfg(feature = "std")]
+pub mod preflight;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "pyo3")]
+mod python;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod stapl;
+#[cfg(feature = "std")]
+pub mod session_log;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod status;
+#[cfg(feature = "std")]
+pub mod svf_writer;
+#[cfg(feature = "std")]
+pub mod target;
+#[cfg(feature = "std")]
+pub mod telemetry;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod tune;
+#[cfg(feature = "std")]
+pub mod watch;
+#[cfg(feature = "std")]
+pub mod watchdog;
+#[cfg(feature = "std")]
+pub mod xvc_server;
+
+#[cfg(feature = "std")]
+pub use player::*;
@@ -0,0 +1,83 @@
+//! `--max-shift-bits` and `--max-total-tck`, guards against a corrupt or wrong file driving
+//! absurd scans into a fragile target.  The files are checked by the pre-flight pass before the
+//! cable is opened, as if every LOOP ran all of its iterations, and again as each command is
+//! played.
+use svf::{Command, RunClock, RunTestForm, RunTestTime};
+
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// Longest SIR or SDR allowed
+    pub max_shift_bits: Option<u32>,
+    /// Most TCK cycles the whole run may take, counting scanned bits and RUNTEST clocks
+    pub max_total_tck: Option<u64>,
+    tck: u64,
+    /// Rate set by the last FREQUENCY, to turn RUNTEST times into clocks
+    frequency: Option<f64>,
+}
+
+impl Limits {
+    pub fn new(max_shift_bits: Option<u32>, max_total_tck: Option<u64>) -> Limits {
+        Limits { max_shift_bits, max_total_tck, ..Limits::default() }
+    }
+
+    /// TCK cycles counted so far
+    pub fn tck(&self) -> u64 {
+        self.tck
+    }
+
+    fn runtest_clocks(&self, form: &RunTestForm) -> u64 {
+        let timed = |time: &Option<RunTestTime>| match (time, self.frequency) {
+            (Some(time), Some(hz)) => (time.min * hz).ceil() as u64,
+            _ => 0,
+        };
+        match form {
+            RunTestForm::Clocked { run_count, run_clk: RunClock::TCK, time } => (*run_count as u64).max(timed(time)),
+            RunTestForm::Clocked { time, .. } => timed(time),
+            RunTestForm::Timed(time) => timed(&Some(time.clone())),
+        }
+    }
+
+    /// Count `cmd` against the limits
+    pub fn check(&mut self, cmd: &Command) -> Result<(), String> {
+        let clocks = match cmd {
+            Command::SIR(pattern) | Command::SDR(pattern) => {
+                if let Some(max) = self.max_shift_bits.filter(|max| pattern.length > *max) {
+                    return Err(format!("{} bit scan is longer than --max-shift-bits {}", pattern.length, max));
+                }
+                pattern.length as u64
+            }
+            Command::RunTest { form, .. } => self.runtest_clocks(form),
+            Command::Frequency(hz) => {
+                self.frequency = *hz;
+                0
+            }
+            _ => 0,
+        };
+        self.add(clocks)
+    }
+
+    /// Count `clocks` more cycles, e.g. for a LOOP body repeating
+    pub fn add(&mut self, clocks: u64) -> Result<(), String> {
+        self.tck = self.tck.saturating_add(clocks);
+        match self.max_total_tck {
+            Some(max) if self.tck > max => Err(format!("run would take more than --max-total-tck {} clocks", max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtest_times_count_at_the_file_frequency() {
+        let mut limits = Limits::new(Some(8), Some(1008));
+        for cmd in svf::parse_complete("SDR 8 TDI (00);\nRUNTEST 1E-3 SEC;\nFREQUENCY 1E6 HZ;\nRUNTEST 1E-3 SEC;\n").unwrap() {
+            limits.check(&cmd).unwrap();
+        }
+        assert_eq!(limits.tck(), 8 + 1000);
+        assert!(limits.check(&svf::parse_complete("SIR 9 TDI (0);").unwrap()[0]).is_err());
+        assert!(limits.check(&svf::parse_complete("RUNTEST 1 TCK;").unwrap()[0]).is_err());
+    }
+}
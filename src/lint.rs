@@ -0,0 +1,211 @@
+//! Checks for SVF that parses but is unlikely to do what its author meant.  Every problem is
+//! reported with the line its statement starts on.
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use svf::{Command, ParseError, Pattern, RunTestForm, State, TRSTMode};
+
+use crate::bits::fits;
+use crate::engine::next;
+use crate::lattice::words;
+use crate::{path, state_named, Svf};
+
+pub struct Issue {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Line within `statement` holding its first token, skipping blank lines and comments
+fn first_token_line(statement: &str) -> Option<usize> {
+    statement.lines().position(|l| {
+        let l = l.trim_start();
+        !l.is_empty() && !l.starts_with('!') && !l.starts_with("//")
+    })
+}
+
+fn adjacent(from: State, to: State) -> bool {
+    let (from, to) = (path::tap(Svf::to_jtag_state(from)), path::tap(Svf::to_jtag_state(to)));
+    next(from, 0) == to || next(from, 1) == to
+}
+
+#[derive(Default)]
+struct Linter {
+    issues: Vec<Issue>,
+    line: usize,
+    /// TAP state after the previous statement, when it can be known without hardware
+    state: Option<State>,
+    endir: Option<State>,
+    enddr: Option<State>,
+    run_state: Option<State>,
+    end_state: Option<State>,
+    /// Length of the last MASK given to each scan command
+    masks: HashMap<&'static str, u32>,
+}
+
+impl Linter {
+    fn report(&mut self, message: String) {
+        self.issues.push(Issue { line: self.line, message });
+    }
+
+    fn pattern(&mut self, name: &'static str, pattern: &Pattern) {
+        let vectors = [("TDI", &pattern.tdi), ("TDO", &pattern.tdo), ("MASK", &pattern.mask),
+                       ("SMASK", &pattern.smask)];
+        for (field, data) in vectors {
+            if let Some(data) = data {
+                if !fits(data, pattern.length) {
+                    self.report(format!("{} {} has more bits than the length {}", name, field, pattern.length));
+                }
+            }
+        }
+        if pattern.mask.is_some() {
+            self.masks.insert(name, pattern.length);
+        } else if pattern.tdo.is_some() && self.masks.get(name) != Some(&pattern.length) {
+            self.report(format!("{} TDO without a MASK for {} bits", name, pattern.length));
+        }
+    }
+
+    fn command(&mut self, cmd: &Command) {
+        match cmd {
+            Command::EndIR(state) => self.endir = Some(*state),
+            Command::EndDR(state) => self.enddr = Some(*state),
+            Command::SIR(pattern) => {
+                self.pattern("SIR", pattern);
+                self.state = Some(self.endir.unwrap_or(State::IDLE));
+            }
+            Command::SDR(pattern) => {
+                self.pattern("SDR", pattern);
+                self.state = Some(self.enddr.unwrap_or(State::IDLE));
+            }
+            Command::HIR(pattern) => self.pattern("HIR", pattern),
+            Command::HDR(pattern) => self.pattern("HDR", pattern),
+            Command::TIR(pattern) => self.pattern("TIR", pattern),
+            Command::TDR(pattern) => self.pattern("TDR", pattern),
+            Command::RunTest { run_state, form, end_state } => {
+                if let RunTestForm::Clocked { run_count: 0, .. } = form {
+                    self.report("RUNTEST with zero clocks".into());
+                }
+                if run_state.is_some() {
+                    self.run_state = *run_state;
+                }
+                if end_state.is_some() {
+                    self.end_state = *end_state;
+                }
+                self.state = Some(self.end_state.or(self.run_state).unwrap_or(State::IDLE));
+            }
+            Command::State { path: None, end } => self.state = Some(*end),
+            Command::State { path: Some(path), end } => {
+                let mut from = self.state;
+                for to in path.iter().chain(std::iter::once(end)) {
+                    if let Some(from) = from {
+                        if !adjacent(from, *to) {
+                            self.report(format!("STATE path can't move from {} to {} in one clock", from, to));
+                        }
+                    }
+                    from = Some(*to);
+                }
+                self.state = Some(*end);
+            }
+            Command::TRST(TRSTMode::On) => self.state = Some(State::RESET),
+            _ => (),
+        }
+    }
+}
+
+/// Check every statement of `input`.  Statements that don't parse are reported and skipped.
+pub fn lint(input: &mut impl BufRead) -> std::io::Result<Vec<Issue>> {
+    let mut linter = Linter::default();
+    let mut line = 1;
+    loop {
+        let mut buf = vec![];
+        if input.read_until(b';', &mut buf)? == 0 {
+            break;
+        }
+        let statement = String::from_utf8_lossy(&buf);
+        let first = first_token_line(&statement);
+        linter.line = line + first.unwrap_or(0);
+        line += statement.matches('\n').count();
+        if !statement.ends_with(';') {
+            // Only trailing comments and whitespace are allowed after the last statement
+            if first.is_some() {
+                linter.report("statement is missing its terminating ';'".into());
+            }
+            break;
+        }
+
+        if matches!(words(&statement).first().map(String::as_str), Some("LOOP" | "ENDLOOP")) {
+            continue;
+        }
+        match svf::parse_iter(&statement).next() {
+            Some(Ok(cmd)) => linter.command(&cmd),
+            Some(Err(e)) => {
+                let mut words = statement.lines().skip(first.unwrap_or(0))
+                    .flat_map(|l| l.trim_end_matches(';').split_whitespace())
+                    .map(str::to_uppercase);
+                let keyword = words.next().unwrap_or_default();
+                let unstable = match e {
+                    ParseError::NotStableState(_, state) => Some(state),
+                    // The parser only knows stable state names after ENDIR and ENDDR
+                    _ if keyword == "ENDIR" || keyword == "ENDDR" => words.next().and_then(|w| state_named(&w)),
+                    _ => None,
+                };
+                match unstable {
+                    Some(state) => linter.report(format!("{} to {}, which is not a stable state", keyword, state)),
+                    None => {
+                        // The line number is relative to the statement, so drop it
+                        let message = e.to_string();
+                        let message = message.rsplit_once(" at L").map_or(message.as_str(), |(m, _)| m);
+                        linter.report(message.to_string());
+                    }
+                }
+                // Where the TAP ends up is anyone's guess now
+                linter.state = None;
+            }
+            None => (),
+        };
+    }
+    Ok(linter.issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines and messages of the issues in `text`
+    fn issues(text: &str) -> Vec<(usize, String)> {
+        lint(&mut text.as_bytes()).unwrap().into_iter().map(|issue| (issue.line, issue.message)).collect()
+    }
+
+    #[test]
+    fn end_states_have_to_be_stable() {
+        let found = issues("! set up\nENDDR DRPAUSE;\n\nENDIR IRSHIFT;\n");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, 4);
+        assert!(found[0].1.contains("ENDIR to IRSHIFT"), "{}", found[0].1);
+    }
+
+    #[test]
+    fn tdo_needs_a_mask_of_its_length() {
+        let found = issues("SDR 8 TDI (00) TDO (12) MASK (ff);\nSDR 8 TDO (12);\nSDR 16 TDI (0000) TDO (1234);\n");
+        assert_eq!(found, [(3, "SDR TDO without a MASK for 16 bits".to_string())]);
+    }
+
+    #[test]
+    fn vectors_fit_their_length() {
+        let found = issues("SIR 4 TDI (e);\nSIR 4\n  TDI (1f);\n");
+        assert_eq!(found, [(2, "SIR TDI has more bits than the length 4".to_string())]);
+    }
+
+    #[test]
+    fn runtest_clocks_something() {
+        let found = issues("RUNTEST 10 TCK;\n// nothing\nRUNTEST 0 TCK;\n");
+        assert_eq!(found, [(3, "RUNTEST with zero clocks".to_string())]);
+    }
+
+    #[test]
+    fn state_paths_follow_the_state_diagram() {
+        // Update-IR goes on to Select-DR, never Select-IR
+        let found = issues("STATE IDLE;\nSTATE DRSELECT IRSELECT IRCAPTURE IREXIT1 IRUPDATE DRSELECT DRCAPTURE DREXIT1 \
+                            DRPAUSE;\nSTATE IRPAUSE;\nSTATE IREXIT2 IRUPDATE IRSELECT IRCAPTURE IREXIT1 IRPAUSE;\n");
+        assert_eq!(found, [(4, "STATE path can't move from IRUPDATE to IRSELECT in one clock".to_string())]);
+    }
+}
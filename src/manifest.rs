@@ -0,0 +1,138 @@
+//! `svfplayer jobs`: play different files on different cables at the same time, as listed in a
+//! TOML manifest.  Every job is a separate `svfplayer` process, so an error on one board can't
+//! stop the others, and each line it prints is passed on prefixed with the job's name.
+//!
+//! ```toml
+//! [[job]]
+//! name = "cpu"
+//! cable = "ftdi:serial=FTX1A2B,interface=A"
+//! baud = 6000000
+//! files = ["cpu.svf"]
+//!
+//! [[job]]
+//! name = "fpga"
+//! cable = "jlink:000123456789"
+//! baud = 12000000
+//! files = ["erase.svf", "program.svf"]
+//! retries = 2
+//! args = ["--on-error", "trst"]
+//! ```
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+use crate::config::LogLevel;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub job: Vec<Job>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Prefix for the job's output, the cable spec if not given
+    pub name: Option<String>,
+    pub cable: String,
+    pub baud: u32,
+    pub files: Vec<String>,
+    pub retries: Option<u32>,
+    pub log_level: Option<LogLevel>,
+    /// Any other options for playing this job's files
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl Job {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.cable)
+    }
+
+    fn command(&self) -> std::io::Result<Command> {
+        let mut command = Command::new(std::env::current_exe()?);
+        command.args(["play", "--cable", &self.cable, "--baud", &self.baud.to_string()]);
+        if let Some(retries) = self.retries {
+            command.args(["--retries", &retries.to_string()]);
+        }
+        if let Some(level) = self.log_level {
+            let level = clap::ValueEnum::to_possible_value(&level).unwrap();
+            command.args(["--log-level", level.get_name()]);
+        }
+        command.args(&self.args).arg("--").args(&self.files);
+        Ok(command)
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Copy every line from `from` to standard output (or error), starting with `[name]`
+fn relay(name: String, from: impl Read + Send + 'static, stderr: bool) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(from).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if stderr {
+                eprintln!("[{}] {}", name, line);
+            } else {
+                println!("[{}] {}", name, line);
+            }
+        }
+    })
+}
+
+/// Run every job in `manifest` concurrently and report how each one ended.  Returns whether all
+/// of them passed.
+pub fn run(manifest: &Manifest) -> bool {
+    let mut running = vec![];
+    for job in &manifest.job {
+        let child = job.command().and_then(|mut command| {
+            command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        });
+        match child {
+            Ok(mut child) => {
+                let relays = [
+                    relay(job.name().to_string(), child.stdout.take().unwrap(), false),
+                    relay(job.name().to_string(), child.stderr.take().unwrap(), true),
+                ];
+                running.push((job.name(), Some((child, relays))));
+            }
+            Err(e) => {
+                eprintln!("[{}] unable to start: {}", job.name(), e);
+                running.push((job.name(), None));
+            }
+        }
+    }
+
+    let mut passed = true;
+    let mut results = vec![];
+    for (name, child) in running {
+        let status = child.map(|(mut child, relays)| {
+            let status = child.wait();
+            for relay in relays {
+                let _ = relay.join();
+            }
+            status
+        });
+        let result = match status {
+            Some(Ok(status)) if status.success() => "passed".to_string(),
+            Some(Ok(status)) => format!("failed ({})", status),
+            Some(Err(e)) => format!("failed ({})", e),
+            None => "failed (not started)".to_string(),
+        };
+        passed &= result == "passed";
+        results.push((name, result));
+    }
+    for (name, result) in results {
+        println!("{}: {}", name, result);
+    }
+    passed
+}
@@ -0,0 +1,27 @@
+//! Callbacks for code that drives the player and wants to follow along without parsing its
+//! output, e.g. to draw a progress bar or keep the readback.  The callbacks do nothing unless
+//! overridden.
+use svf::Command;
+
+pub trait Observer {
+    /// `cmd` is about to run
+    fn on_command(&mut self, _cmd: &Command) {}
+
+    /// A scan of `kind` ("SIR" or "SDR") shifted out `tdo`
+    fn on_tdo(&mut self, _kind: &'static str, _tdo: &[u8]) {}
+
+    /// Whether `on_tdo` should see every scan.  Scans without a TDO check then wait for the cable
+    /// to send TDO back, which is slower.
+    fn wants_tdo(&self) -> bool {
+        true
+    }
+
+    /// A TDO check failed.  Playback stops after this unless a retry or LOOP is pending.
+    fn on_mismatch(&mut self, _read: &[u8], _expected: &[u8], _mask: &[u8]) {}
+
+    /// A `! phase NAME` marker started phase `name`
+    fn on_phase(&mut self, _name: &str) {}
+
+    /// `done` commands have been played, of `total` when the whole file was known up front
+    fn on_progress(&mut self, _done: usize, _total: Option<usize>) {}
+}
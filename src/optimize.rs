@@ -0,0 +1,129 @@
+//! `svfplayer optimize`: on top of what `svf_writer` leaves out, drop commands that don't change
+//! what the target sees.  Back-to-back RUNTESTs that stay in one state become a single one, and a
+//! STATE for the state the TAP is already in is removed.
+use svf::{Command, RunClock, RunTestForm, State, TRSTMode};
+
+pub struct Optimizer {
+    /// A RUNTEST held back in case the next command can be folded into it
+    pending: Option<Command>,
+    /// Where the TAP is, if known
+    at: Option<State>,
+    endir: Option<State>,
+    enddr: Option<State>,
+    run_state: Option<State>,
+    end_state: Option<State>,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        // The player's defaults before any ENDIR, ENDDR or RUNTEST says otherwise
+        Optimizer {
+            pending: None,
+            at: None,
+            endir: Some(State::IDLE),
+            enddr: Some(State::IDLE),
+            run_state: Some(State::IDLE),
+            end_state: Some(State::IDLE),
+        }
+    }
+}
+
+/// TCK count of a RUNTEST that only clocks, with no timing requirement
+fn clocks(form: &RunTestForm) -> Option<u32> {
+    match form {
+        RunTestForm::Clocked { run_count, run_clk: RunClock::TCK, time: None } => Some(*run_count),
+        _ => None,
+    }
+}
+
+impl Optimizer {
+    /// Take the next command, returning the ones that can be written out now
+    pub fn push(&mut self, cmd: Command) -> Vec<Command> {
+        if let Command::RunTest { run_state, form, end_state } = &cmd {
+            let run = run_state.or(self.run_state);
+            let end = end_state.or(self.end_state);
+            let steady = run.is_some() && run == end && run == self.run_state && end == self.end_state;
+            if let (true, Some(extra), Some(Command::RunTest { form: RunTestForm::Clocked { run_count, .. }, .. })) =
+                (steady, clocks(form), &mut self.pending)
+            {
+                if let Some(total) = run_count.checked_add(extra) {
+                    *run_count = total;
+                    return vec![];
+                }
+            }
+        }
+
+        let mut out: Vec<Command> = self.pending.take().into_iter().collect();
+        match &cmd {
+            Command::RunTest { run_state, form, end_state } => {
+                self.run_state = run_state.or(self.run_state);
+                self.end_state = end_state.or(self.end_state);
+                self.at = self.end_state;
+                if clocks(form).is_some() && self.run_state.is_some() && self.run_state == self.end_state {
+                    self.pending = Some(cmd);
+                    return out;
+                }
+            }
+            Command::State { path: None, end } => {
+                if self.at == Some(*end) {
+                    return out;
+                }
+                self.at = Some(*end);
+            }
+            Command::State { end, .. } => self.at = Some(*end),
+            Command::EndIR(state) => self.endir = Some(*state),
+            Command::EndDR(state) => self.enddr = Some(*state),
+            Command::SIR(_) => self.at = self.endir,
+            Command::SDR(_) => self.at = self.enddr,
+            Command::TRST(TRSTMode::On) => self.at = Some(State::RESET),
+            _ => (),
+        }
+        out.push(cmd);
+        out
+    }
+
+    /// Return whatever is held back, and forget what is known about the TAP, for the end of the
+    /// file or a statement such as LOOP that the commands around it may not simply follow
+    pub fn finish(&mut self) -> Vec<Command> {
+        let pending = self.pending.take().into_iter().collect();
+        *self = Optimizer {
+            endir: None,
+            enddr: None,
+            run_state: None,
+            end_state: None,
+            ..Optimizer::default()
+        };
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimized(svf: &str) -> Vec<Command> {
+        let mut optimizer = Optimizer::default();
+        let mut out: Vec<Command> = svf::parse_complete(svf).unwrap().into_iter()
+            .flat_map(|cmd| optimizer.push(cmd))
+            .collect();
+        out.extend(optimizer.finish());
+        out
+    }
+
+    #[test]
+    fn runtests_in_one_state_are_merged() {
+        assert_eq!(optimized("RUNTEST IDLE 10 TCK;\nRUNTEST 5 TCK;\nRUNTEST IDLE 1 TCK ENDSTATE IDLE;\n"),
+                   svf::parse_complete("RUNTEST IDLE 16 TCK;\n").unwrap());
+        // Leaving for DRPAUSE in between is an excursion the target sees
+        let moving = "RUNTEST IDLE 10 TCK ENDSTATE DRPAUSE;\nRUNTEST 5 TCK;\n";
+        assert_eq!(optimized(moving), svf::parse_complete(moving).unwrap());
+        let timed = "RUNTEST 10 TCK;\nRUNTEST 5 TCK 1E-3 SEC;\n";
+        assert_eq!(optimized(timed), svf::parse_complete(timed).unwrap());
+    }
+
+    #[test]
+    fn state_already_reached_is_dropped() {
+        assert_eq!(optimized("SIR 6 TDI (09);\nSTATE IDLE;\nSTATE DRPAUSE;\nSTATE DRPAUSE;\n"),
+                   svf::parse_complete("SIR 6 TDI (09);\nSTATE DRPAUSE;\n").unwrap());
+    }
+}
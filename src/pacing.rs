@@ -0,0 +1,38 @@
+//! `--max-duty N%`: keep a bus-powered adapter and its target below a share of the time spent
+//! scanning, by idling after each SIR and SDR until the scan's own time is at most N% of the
+//! whole.  TCK stops in the gaps, so the average current drawn falls with it.  `--phase-duty`
+//! sets a different share for a phase marked in the files.
+use std::time::Duration;
+
+/// A duty cycle as `N%` (or just `N`), returned as a fraction
+pub fn parse_duty(s: &str) -> Result<f64, String> {
+    let percent: f64 = s.strip_suffix('%').unwrap_or(s).trim().parse().map_err(|_| format!("bad duty cycle {}", s))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!("duty cycle {} isn't above 0% and at most 100%", s));
+    }
+    Ok(percent / 100.0)
+}
+
+pub fn parse_phase_duty(s: &str) -> Result<(String, f64), String> {
+    let (name, duty) = s.split_once('=').ok_or("expected PHASE=N%")?;
+    Ok((name.to_string(), parse_duty(duty)?))
+}
+
+/// How long to idle after `active` time scanning to keep to `duty`
+pub fn gap(active: Duration, duty: f64) -> Duration {
+    active.mul_f64(1.0 / duty - 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_keep_scanning_to_the_duty_cycle() {
+        assert_eq!(parse_duty("25%").unwrap(), 0.25);
+        assert_eq!(parse_phase_duty("program=50").unwrap(), ("program".to_string(), 0.5));
+        assert!(parse_duty("0%").is_err() && parse_duty("150%").is_err() && parse_duty("half").is_err());
+        assert_eq!(gap(Duration::from_millis(10), 0.25), Duration::from_millis(30));
+        assert_eq!(gap(Duration::from_millis(10), 1.0), Duration::ZERO);
+    }
+}
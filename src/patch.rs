@@ -0,0 +1,77 @@
+//! `--patch N:OFFSET:LENGTH:DATA`, which overwrites LENGTH bits of the TDI of the Nth SDR from
+//! bit OFFSET up, so one programming file can carry a serial number or key that differs per
+//! unit.  DATA is hex written like an SVF vector (`0x0000BEEF`), or a file whose bytes are the
+//! bits least significant first.
+use crate::bits::{fit, fits};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patch {
+    /// SDR to patch, counting every one played from 1, over all the files
+    pub sdr: usize,
+    pub offset: u32,
+    pub length: u32,
+    /// `length` bits, least significant first
+    pub data: Vec<u8>,
+}
+
+fn hex(digits: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = digits.chars().rev()
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or_else(|| format!("bad hex digit {}", c)))
+        .collect::<Result<_, _>>()?;
+    Ok(digits.chunks(2).map(|pair| pair[0] | pair.get(1).copied().unwrap_or(0) << 4).collect())
+}
+
+pub fn parse_patch(s: &str) -> Result<Patch, String> {
+    let mut fields = s.splitn(4, ':');
+    let mut number = |what: &str| {
+        let field = fields.next().ok_or("expected N:OFFSET:LENGTH:DATA")?;
+        field.parse::<u32>().map_err(|_| format!("bad {} {}", what, field))
+    };
+    let (sdr, offset, length) = (number("SDR number")? as usize, number("offset")?, number("length")?);
+    if sdr == 0 {
+        return Err("SDRs are numbered from 1".into());
+    }
+    let data = fields.next().ok_or("expected N:OFFSET:LENGTH:DATA")?;
+    let data = match data.strip_prefix("0x").or_else(|| data.strip_prefix("0X")) {
+        Some(digits) => hex(digits)?,
+        None => std::fs::read(data).map_err(|e| format!("{}: {}", data, e))?,
+    };
+    if !fits(&data, length) {
+        return Err(format!("{} has more than {} bits", s, length));
+    }
+    Ok(Patch { sdr, offset, length, data: fit(data, length) })
+}
+
+impl Patch {
+    /// Overwrite the patched bits of `tdi`, a vector of `length` bits after `shift` bits of
+    /// padding for other devices
+    pub fn apply(&self, tdi: &mut [u8], length: u32, shift: u32) -> Result<(), String> {
+        if self.offset + self.length > length {
+            return Err(format!("patch of bits {}..{} doesn't fit in {} bit SDR {}", self.offset,
+                               self.offset + self.length, length, self.sdr));
+        }
+        for i in 0..self.length as usize {
+            let bit = self.data[i / 8] >> (i % 8) & 1;
+            let j = (shift + self.offset) as usize + i;
+            tdi[j / 8] = tdi[j / 8] & !(1 << (j % 8)) | bit << (j % 8);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_overwrites_only_its_bits() {
+        let patch = parse_patch("2:4:12:0xABC").unwrap();
+        assert_eq!(patch, Patch { sdr: 2, offset: 4, length: 12, data: vec![0xbc, 0x0a] });
+        let mut tdi = vec![0xff, 0x00, 0xff];
+        patch.apply(&mut tdi, 24, 0).unwrap();
+        assert_eq!(tdi, [0xcf, 0xab, 0xff]);
+        assert!(patch.apply(&mut tdi, 15, 0).is_err());
+        assert!(parse_patch("1:0:4:0x1F").is_err());
+        assert!(parse_patch("0:0:4:0x1").is_err());
+    }
+}
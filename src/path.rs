@@ -0,0 +1,103 @@
+//! TMS paths planned by the player instead of `JtagSM::change_mode`, whose search never takes a
+//! one-step path and believes TMS high in Update-IR leads to Select-IR.  The paths come from
+//! `engine`: moves follow the IEEE 1149.1 state diagram, scans out of a pause state take the SVF
+//! specification's path through Update and Capture, and STATE path lists are clocked exactly as
+//! written.  `JtagSM` is then told where the TAP is without clocking it again, so the backends
+//! and `write_reg` carry on from the right state.
+use jtag_taps::cable::Cable;
+use jtag_taps::statemachine::{JtagSM, JtagState, Register};
+
+use crate::cable::{Adapter, AdapterBox};
+use crate::engine::{self, Reg, TapState};
+
+const STATES: [(JtagState, TapState); 16] = [
+    (JtagState::Reset, TapState::Reset), (JtagState::Idle, TapState::Idle),
+    (JtagState::SelectDR, TapState::SelectDR), (JtagState::CaptureDR, TapState::CaptureDR),
+    (JtagState::ShiftDR, TapState::ShiftDR), (JtagState::Exit1DR, TapState::Exit1DR),
+    (JtagState::PauseDR, TapState::PauseDR), (JtagState::Exit2DR, TapState::Exit2DR),
+    (JtagState::UpdateDR, TapState::UpdateDR), (JtagState::SelectIR, TapState::SelectIR),
+    (JtagState::CaptureIR, TapState::CaptureIR), (JtagState::ShiftIR, TapState::ShiftIR),
+    (JtagState::Exit1IR, TapState::Exit1IR), (JtagState::PauseIR, TapState::PauseIR),
+    (JtagState::Exit2IR, TapState::Exit2IR), (JtagState::UpdateIR, TapState::UpdateIR),
+];
+
+pub(crate) fn tap(state: JtagState) -> TapState {
+    STATES.iter().find(|(jtag, _)| *jtag == state).unwrap().1
+}
+
+fn jtag(state: TapState) -> JtagState {
+    STATES.iter().find(|(_, tap)| *tap == state).unwrap().0
+}
+
+fn reg(reg: Register) -> Reg {
+    match reg {
+        Register::Data => Reg::Data,
+        Register::Instruction => Reg::Instruction,
+    }
+}
+
+/// TMS clocking from `from` through each of `states` in turn, see `engine::follow`
+pub fn follow(from: JtagState, states: &[JtagState]) -> Result<Vec<usize>, String> {
+    engine::follow(tap(from), &states.iter().map(|&state| tap(state)).collect::<Vec<_>>())
+}
+
+/// Stands in for the cable while `JtagSM` is told where the TAP already is
+struct Unclocked;
+
+impl Cable for Unclocked {
+    fn change_mode(&mut self, _tms: &[usize], _tdi: bool) {}
+    fn read_data(&mut self, _bits: usize) -> Vec<u8> { vec![] }
+    fn write_data(&mut self, _data: &[u8], _bits: u8, _pause_after: bool) {}
+    fn read_write_data(&mut self, _data: &[u8], _bits: u8, _pause_after: bool) -> Vec<u8> { vec![] }
+}
+
+impl Adapter for Unclocked {}
+
+/// Clock out `tms`, which leaves the TAP in `to`, and bring `sm` along without clocking again
+pub fn walk(sm: &mut JtagSM<AdapterBox>, tms: &[usize], to: JtagState) {
+    if !tms.is_empty() {
+        sm.cable.change_mode(tms, true);
+    }
+    let cable = std::mem::replace(&mut sm.cable.0, Box::new(Unclocked));
+    sm.change_mode(to);
+    sm.cable.0 = cable;
+}
+
+/// Move the TAP from `from` to `to` by the shortest path
+pub fn move_to(sm: &mut JtagSM<AdapterBox>, from: JtagState, to: JtagState) {
+    walk(sm, &engine::shortest(tap(from), tap(to)), to);
+}
+
+/// Move the TAP from `from` into Shift for a scan of `reg`
+pub fn enter_shift(sm: &mut JtagSM<AdapterBox>, from: JtagState, reg: Register) {
+    let (shift, _) = engine::shift_and_pause(self::reg(reg));
+    walk(sm, &engine::to_shift(tap(from), self::reg(reg)), jtag(shift));
+}
+
+/// Leave the Pause a scan of `reg` ends in for `end`
+pub fn leave_scan(sm: &mut JtagSM<AdapterBox>, reg: Register, end: JtagState) {
+    let (_, pause) = engine::shift_and_pause(self::reg(reg));
+    move_to(sm, jtag(pause), end);
+}
+
+fn visit(from: TapState, tms: &[usize]) -> Vec<JtagState> {
+    tms.iter().scan(from, |state, &tms| {
+        *state = engine::next(*state, tms);
+        Some(jtag(*state))
+    }).collect()
+}
+
+/// The states a move from `from` to `to` passes through, ending with `to`
+pub fn route(from: JtagState, to: JtagState) -> Vec<JtagState> {
+    visit(tap(from), &engine::shortest(tap(from), tap(to)))
+}
+
+/// The states a scan of `reg` from `from` passes through on its way to `end`
+pub fn scan_route(from: JtagState, reg: Register, end: JtagState) -> Vec<JtagState> {
+    let (shift, pause) = engine::shift_and_pause(self::reg(reg));
+    let mut states = visit(tap(from), &engine::to_shift(tap(from), self::reg(reg)));
+    // The last bit is shifted on the way to Exit1, and the cable pauses after it
+    states.extend(visit(shift, &[1, 0]));
+    states.extend(route(jtag(pause), end));
+    states
+}
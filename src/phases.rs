@@ -0,0 +1,160 @@
+//! Time, commands, bits and TDO mismatches per named phase of a run, the phases being marked by
+//! `! phase NAME` comments (see `lattice`).  A phase that comes round again, in the next file or
+//! the next --repeat, adds to the same totals.  `--phase-budget` limits how long one pass through
+//! a phase may take.
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Phase {
+    pub name: String,
+    pub commands: u64,
+    pub bits: u64,
+    pub mismatches: u64,
+    pub elapsed: Duration,
+    /// Whether a run failed during the phase
+    pub failed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Phases {
+    pub phases: Vec<Phase>,
+    /// The phase playing, as an index into `phases`, and when it started this time
+    current: Option<(usize, Instant)>,
+    /// Longest each named phase may take
+    pub budgets: Vec<(String, Duration)>,
+    /// Share of the time each named phase may spend scanning, see `pacing`
+    pub duties: Vec<(String, f64)>,
+}
+
+pub fn parse_budget(s: &str) -> Result<(String, Duration), String> {
+    let (name, budget) = s.split_once('=').ok_or("expected PHASE=DURATION")?;
+    let budget = humantime::parse_duration(budget).map_err(|e| format!("{}: {}", budget, e))?;
+    Ok((name.to_string(), budget))
+}
+
+impl Phases {
+    /// End whichever phase is playing and start `name`
+    pub fn begin(&mut self, name: &str) {
+        self.end();
+        let i = self.phases.iter().position(|phase| phase.name == name).unwrap_or_else(|| {
+            self.phases.push(Phase { name: name.to_string(), ..Phase::default() });
+            self.phases.len() - 1
+        });
+        self.current = Some((i, Instant::now()));
+    }
+
+    pub fn end(&mut self) {
+        if let Some((i, start)) = self.current.take() {
+            self.phases[i].elapsed += start.elapsed();
+        }
+    }
+
+    fn playing(&mut self) -> Option<&mut Phase> {
+        self.current.map(|(i, _)| &mut self.phases[i])
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.current.map(|(i, _)| self.phases[i].name.as_str())
+    }
+
+    /// The duty cycle the phase playing is held to, if it has one of its own
+    pub fn duty(&self) -> Option<f64> {
+        let name = self.current()?;
+        self.duties.iter().find(|(phase, _)| phase == name).map(|(_, duty)| *duty)
+    }
+
+    /// Why the phase playing has to stop, if it has used up its budget
+    pub fn overrun(&self) -> Option<String> {
+        let (i, start) = self.current?;
+        let name = &self.phases[i].name;
+        let (_, budget) = self.budgets.iter().find(|(phase, _)| phase == name)?;
+        (start.elapsed() >= *budget)
+            .then(|| format!("phase {} took longer than its budget of {}", name, humantime::format_duration(*budget)))
+    }
+
+    /// How much of its budget the phase playing has left, if it has one
+    pub fn time_left(&self) -> Option<Duration> {
+        let (i, start) = self.current?;
+        let (_, budget) = self.budgets.iter().find(|(phase, _)| *phase == self.phases[i].name)?;
+        Some(budget.saturating_sub(start.elapsed()))
+    }
+
+    /// A command scanning `bits` has played
+    pub fn command(&mut self, bits: u64) {
+        if let Some(phase) = self.playing() {
+            phase.commands += 1;
+            phase.bits += bits;
+        }
+    }
+
+    pub fn mismatch(&mut self) {
+        if let Some(phase) = self.playing() {
+            phase.mismatches += 1;
+        }
+    }
+
+    /// The run failed in the phase playing, which ends it
+    pub fn fail(&mut self) {
+        if let Some(phase) = self.playing() {
+            phase.failed = true;
+        }
+        self.end();
+    }
+
+    pub fn report(&self) -> Vec<String> {
+        let mut lines = vec![format!("{:<16} {:>10} {:>12} {:>10} {:>10}", "phase", "commands", "bits", "mismatches",
+                                     "time")];
+        for phase in &self.phases {
+            lines.push(format!("{:<16} {:>10} {:>12} {:>10} {:>8.3} s{}", phase.name, phase.commands, phase.bits,
+                               phase.mismatches, phase.elapsed.as_secs_f64(),
+                               if phase.failed { "  failed" } else { "" }));
+        }
+        lines
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        self.phases.iter().map(|phase| serde_json::json!({
+            "name": phase.name,
+            "commands": phase.commands,
+            "bits_shifted": phase.bits,
+            "tdo_mismatches": phase.mismatches,
+            "elapsed_seconds": phase.elapsed.as_secs_f64(),
+            "result": if phase.failed { "failed" } else { "passed" },
+        })).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lattice::phases;
+
+    #[test]
+    fn phases_come_from_comments_and_add_up_by_name() {
+        assert_eq!(phases("! phase erase\n// Phase: program \n! phased out\nSDR 8 TDI (00);"), ["erase", "program"]);
+        assert!(phases("! phase\nSIR 4 TDI (1);").is_empty());
+
+        let mut totals = Phases::default();
+        totals.begin("erase");
+        totals.command(8);
+        totals.begin("verify");
+        totals.mismatch();
+        totals.begin("erase");
+        totals.command(4);
+        assert_eq!(totals.current(), Some("erase"));
+        totals.fail();
+        assert_eq!(totals.current(), None);
+        assert_eq!(totals.phases.len(), 2);
+        assert_eq!((totals.phases[0].commands, totals.phases[0].bits, totals.phases[0].failed), (2, 12, true));
+        assert_eq!(totals.phases[1].mismatches, 1);
+        assert_eq!(totals.json()[1]["result"], "passed");
+
+        totals.budgets = vec![parse_budget("erase=0s").unwrap(), parse_budget("verify=1h").unwrap()];
+        totals.begin("verify");
+        assert_eq!(totals.overrun(), None);
+        totals.begin("erase");
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(totals.overrun().unwrap().contains("budget of 0s"));
+        assert!(parse_budget("erase").is_err());
+    }
+}
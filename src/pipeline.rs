@@ -0,0 +1,158 @@
+//! Pipelined cable I/O.  The cable is owned by a worker thread which executes requests in
+//! order as fast as the adapter allows.  Normal `Cable` calls from the player stay synchronous,
+//! but the writes of a scan can be captured with `PipelineHandle::capture_writes` so that their
+//! TDO is delivered later through a channel.  The player keeps issuing the following commands while the worker is
+//! still shifting, and verifies the captured TDO once it arrives.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use jtag_taps::cable::Cable;
+
+use crate::cable::{Adapter, Capabilities};
+
+type Control = Box<dyn FnOnce(&mut dyn Adapter) + Send>;
+
+enum Request {
+    ChangeMode(Vec<usize>, bool),
+    Read(usize, Sender<Vec<u8>>),
+    Write(Vec<u8>, u8, bool),
+    ReadWrite(Vec<u8>, u8, bool, Sender<Vec<u8>>),
+    /// Any of the `Adapter` controls, which reply through their own channel
+    Control(Control),
+}
+
+pub struct PipelinedCable {
+    requests: Option<Sender<Request>>,
+    capture: Rc<RefCell<Option<Sender<Vec<u8>>>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Player side handle used to turn `write_data` calls into reads whose results are delivered
+/// asynchronously
+#[derive(Clone)]
+pub struct PipelineHandle {
+    capture: Rc<RefCell<Option<Sender<Vec<u8>>>>>,
+}
+
+impl PipelineHandle {
+    /// Capture every write from now until `end_capture`; the receiver gets their TDO in order and
+    /// disconnects after the last
+    pub fn capture_writes(&self) -> Receiver<Vec<u8>> {
+        let (tx, rx) = channel();
+        *self.capture.borrow_mut() = Some(tx);
+        rx
+    }
+
+    pub fn end_capture(&self) {
+        self.capture.borrow_mut().take();
+    }
+}
+
+impl PipelinedCable {
+    /// Start the worker thread.  `open` runs on the worker, so the cable itself never has to
+    /// cross threads.
+    pub fn spawn<F: FnOnce() -> Box<dyn Adapter> + Send + 'static>(open: F) -> Self {
+        let (tx, rx) = channel::<Request>();
+        let worker = std::thread::spawn(move || {
+            let mut cable = open();
+            for req in rx {
+                match req {
+                    Request::ChangeMode(tms, tdi) => cable.change_mode(&tms, tdi),
+                    Request::Read(bits, reply) => {
+                        let _ = reply.send(cable.read_data(bits));
+                    }
+                    Request::Write(data, bits, pause_after) => cable.write_data(&data, bits, pause_after),
+                    Request::ReadWrite(data, bits, pause_after, reply) => {
+                        let _ = reply.send(cable.read_write_data(&data, bits, pause_after));
+                    }
+                    Request::Control(f) => f(&mut *cable),
+                }
+            }
+        });
+
+        PipelinedCable {
+            requests: Some(tx),
+            capture: Rc::new(RefCell::new(None)),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn handle(&self) -> PipelineHandle {
+        PipelineHandle {
+            capture: self.capture.clone(),
+        }
+    }
+
+    fn send(&self, req: Request) {
+        self.requests.as_ref().unwrap().send(req).expect("cable worker exited");
+    }
+
+    fn control<R: Send + 'static>(&self, f: impl FnOnce(&mut dyn Adapter) -> R + Send + 'static) -> R {
+        let (tx, rx) = channel();
+        self.send(Request::Control(Box::new(move |cable| {
+            let _ = tx.send(f(cable));
+        })));
+        rx.recv().expect("cable worker exited")
+    }
+}
+
+impl Cable for PipelinedCable {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        self.send(Request::ChangeMode(tms.to_vec(), tdo));
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        let (tx, rx) = channel();
+        self.send(Request::Read(bits, tx));
+        rx.recv().expect("cable worker exited")
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        let capture = self.capture.borrow().clone();
+        match capture {
+            Some(reply) => self.send(Request::ReadWrite(data.to_vec(), bits, pause_after, reply)),
+            None => self.send(Request::Write(data.to_vec(), bits, pause_after)),
+        }
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        let (tx, rx) = channel();
+        self.send(Request::ReadWrite(data.to_vec(), bits, pause_after, tx));
+        rx.recv().expect("cable worker exited")
+    }
+}
+
+impl Adapter for PipelinedCable {
+    fn capabilities(&mut self) -> Capabilities {
+        self.control(|cable| cable.capabilities())
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.control(move |cable| cable.set_frequency(hz))
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.control(move |cable| cable.set_trst(asserted))
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.control(move |cable| cable.set_srst(asserted))
+    }
+
+    fn flush(&mut self) {
+        // Requests run in order, so the reply means everything before it is done
+        self.control(|cable| cable.flush())
+    }
+}
+
+impl Drop for PipelinedCable {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker drain the queue and exit
+        self.requests.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
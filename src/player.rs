@@ -0,0 +1,1359 @@
+//! Playing SVF on a `JtagSM`: `Svf` holds the state carried from one command to the next and
+//! plays each command, `run_svf` plays a whole file.
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use jtag_taps::statemachine::{JtagSM, JtagState, Register};
+use svf::{Command, ParseError, Pattern, RunClock, RunTestForm, State, TRSTMode};
+
+use crate::{bits, checks, color, compiled, lattice, pacing, path, preflight, svf_writer, tune, watchdog};
+use crate::bits::{BitOrder, DontCare, Rng};
+use crate::engine::{scan_vector, tdo_matches, Sticky};
+use crate::cable::{AdapterBox, Capabilities};
+use crate::checks::Rule;
+use crate::config::{LogLevel, OnMismatch};
+use crate::dump::Dump;
+use crate::limits::Limits;
+use crate::pipeline::PipelineHandle;
+use crate::observer::Observer;
+use crate::patch::Patch;
+use crate::phases::Phases;
+use crate::postmortem::{Failure, Postmortem};
+use crate::profile::Profiler;
+use crate::session_log::SessionLog;
+use crate::status::StatusLine;
+use crate::target::Padding;
+use crate::telemetry::Telemetry;
+
+/// An SDR whose TDO is still being shifted by the pipelined cable
+struct InFlight {
+    /// The TDO of each of the scan's writes
+    read: Receiver<Vec<u8>>,
+    length: u32,
+    tdo: Vec<u8>,
+    mask: Vec<u8>,
+    on_mismatch: OnMismatch,
+    /// Number of the SDR's command, and its TDI if a post-mortem would want it
+    index: usize,
+    tdi: Option<Vec<u8>>,
+}
+
+pub struct Svf {
+    endir: JtagState,
+    enddr: JtagState,
+    end_state: JtagState,
+    run_state: JtagState,
+    sir: Sticky,
+    sdr: Sticky,
+    pub pipeline: Option<PipelineHandle>,
+    pub pipeline_depth: usize,
+    in_flight: VecDeque<InFlight>,
+    pub retries: u32,
+    pub on_mismatch: OnMismatch,
+    pub log_level: LogLevel,
+    /// How scan vectors are printed
+    pub dump: Dump,
+    pub bit_order: BitOrder,
+    pub dont_care: DontCare,
+    /// Source of the don't-care bits for `DontCare::Random`
+    pub rng: Rng,
+    /// TCK rate the cable confirmed for the last FREQUENCY
+    frequency: Option<f64>,
+    /// Rate the last FREQUENCY in the file asked for
+    file_frequency: Option<f64>,
+    /// Rate used in place of every FREQUENCY in the file
+    pub freq_override: Option<f64>,
+    /// Highest rate any FREQUENCY may ask for
+    pub max_freq: Option<f64>,
+    /// Most bytes of scan data, or eighths of RUNTEST clocks, handed to the cable at once
+    pub chunk_size: usize,
+    /// Playing the body of a LOOP, where a TDO mismatch means another iteration
+    in_loop: bool,
+    loop_mismatch: bool,
+    /// Read back and print TDO for every scan, including ones with nothing to verify
+    pub echo_tdo: bool,
+    /// Pause before every command
+    pub step: bool,
+    /// Pause before these commands, numbered from 1 in each file
+    pub breakpoints: Vec<usize>,
+    /// Stop a file after this command, numbered the same way
+    pub stop_after: Option<usize>,
+    /// Whether the last file was stopped short by `stop_after`
+    pub stopped: bool,
+    /// Set from another thread to stop playback before the next command
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Where every command that ran without error is written back out as SVF
+    pub recorder: Option<svf_writer::Writer<Box<dyn Write>>>,
+    /// Read back every scan and record what came out as its expected TDO, turning a run against
+    /// a known-good board into a verification file
+    pub capture_tdo: bool,
+    /// What the last scan read, while capturing
+    captured: Option<Vec<u8>>,
+    /// Changes to the TDI of particular SDRs
+    pub patches: Vec<Patch>,
+    /// SDRs played so far
+    sdr_count: usize,
+    /// TDI of the last SDR as it went to the cable, and which SDR that was, for the next one to
+    /// shift again when it repeats it
+    packed: Option<(usize, Vec<u8>)>,
+    /// Buffer the TDI of an SIR is packed into, kept to save allocating one for each
+    sir_packed: Vec<u8>,
+    /// SDR checks that are soft or hard whatever `on_mismatch` says
+    pub checks: Vec<Rule>,
+    /// Number of the command being played in its file, and the instruction the last SIR loaded
+    index: usize,
+    opcode: Option<u64>,
+    /// Totals for each phase the files mark
+    pub phases: Phases,
+    /// Where to write what led up to a mismatch that stops playback
+    pub postmortem: Option<Postmortem>,
+    /// When playback has to have finished by, and the --deadline that set it
+    deadline: Option<(Instant, Duration)>,
+    /// Share of the time spent scanning, outside phases with a duty cycle of their own
+    pub max_duty: Option<f64>,
+    /// BYPASS added around every scan when the files are for one device of the chain
+    pub padding: Option<Padding>,
+    /// Scan length and TCK guards, over the whole run
+    pub limits: Option<Limits>,
+    /// Warn about and skip commands that can't be played instead of failing
+    pub ignore_unsupported: bool,
+    /// Refuse what the SVF specification doesn't allow, instead of tolerating common quirks
+    pub strict: bool,
+    pub log_file: Option<SessionLog>,
+    /// Drawn after every command of a file, in place of echoing it
+    pub status: Option<StatusLine>,
+    pub telemetry: Option<Telemetry>,
+    pub observer: Option<Box<dyn Observer>>,
+    /// Play a command again, from the TAP state before it, when the cable is reopened under it
+    pub resume: bool,
+    /// Where the TAP was left by the last command that completed
+    stable: JtagState,
+    /// Whether the last TRST command asserted or released the line
+    trst: Option<bool>,
+    /// What the cable can do, once asked
+    capabilities: Option<Capabilities>,
+}
+
+/// Format scan data the way SVF writes it: most significant byte first
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().rev().map(|b| format!("{:02X}", b)).collect()
+}
+
+const STATES: [State; 16] = {
+    use State::*;
+    [RESET, IDLE, DRSELECT, DRCAPTURE, DRSHIFT, DREXIT1, DRPAUSE, DREXIT2, DRUPDATE, IRSELECT,
+     IRCAPTURE, IRSHIFT, IREXIT1, IRPAUSE, IREXIT2, IRUPDATE]
+};
+
+/// The state SVF (and STAPL) call `name`
+pub(crate) fn state_named(name: &str) -> Option<State> {
+    STATES.into_iter().find(|s| s.to_string() == name)
+}
+
+/// The SVF name for `state`
+pub(crate) fn svf_state(state: JtagState) -> State {
+    STATES.into_iter().find(|s| Svf::to_jtag_state(*s) == state).unwrap()
+}
+
+/// The message a caught panic was raised with
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else if payload.is::<watchdog::Reconnected>() {
+        "the cable was reopened in the middle of a command".into()
+    } else {
+        "playback panicked".into()
+    }
+}
+
+/// Clock `cycles` TCKs while holding the TAP in `state`, which it is already in, at most `chunk` per call into the cable
+pub(crate) fn clock(sm: &mut JtagSM<AdapterBox>, state: JtagState, mut cycles: u64, chunk: u64) {
+    // Test-Logic-Reset is the one stable state held with TMS high
+    let tms = vec![(state == JtagState::Reset) as usize; cycles.min(chunk) as usize];
+    while cycles > 0 {
+        let n = cycles.min(chunk);
+        sm.cable.change_mode(&tms[..n as usize], true);
+        cycles -= n;
+    }
+}
+
+/// Shift `data` into `reg` at most `chunk` bytes per call into the cable, ending in Pause
+fn write_reg(sm: &mut JtagSM<AdapterBox>, reg: Register, data: &[u8], bits: u8, chunk: usize) {
+    if data.len() <= chunk {
+        return sm.write_reg(reg, data, bits, true);
+    }
+    let count = data.len().div_ceil(chunk);
+    for (i, part) in data.chunks(chunk).enumerate() {
+        let last = i == count - 1;
+        sm.write_reg(reg, part, if last { bits } else { 8 }, last);
+    }
+}
+
+/// Like `write_reg`, returning what was shifted out
+pub(crate) fn read_write_reg(sm: &mut JtagSM<AdapterBox>, reg: Register, data: &[u8], bits: u8, chunk: usize) -> Vec<u8> {
+    if data.len() <= chunk {
+        return sm.read_write_reg(reg, data, bits, true);
+    }
+    let count = data.len().div_ceil(chunk);
+    let mut read = Vec::with_capacity(data.len());
+    for (i, part) in data.chunks(chunk).enumerate() {
+        let last = i == count - 1;
+        read.extend(sm.read_write_reg(reg, part, if last { bits } else { 8 }, last));
+    }
+    read
+}
+
+/// Take the vectors `pattern` gives into `sticky`
+fn remember(sticky: &mut Sticky, name: &str, pattern: Pattern) {
+    sticky.update(name, pattern.length, pattern.tdi, pattern.mask, pattern.smask)
+        .unwrap_or_else(|e| panic!("{}", e));
+}
+
+impl Default for Svf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Svf {
+    pub fn new() -> Self {
+        Svf {
+            endir: JtagState::Idle,
+            enddr: JtagState::Idle,
+            end_state: JtagState::Idle,
+            run_state: JtagState::Idle,
+            sir: Sticky::default(),
+            sdr: Sticky::default(),
+            pipeline: None,
+            pipeline_depth: 0,
+            in_flight: VecDeque::new(),
+            retries: 0,
+            on_mismatch: OnMismatch::default(),
+            log_level: LogLevel::default(),
+            dump: Dump::default(),
+            bit_order: BitOrder::default(),
+            dont_care: DontCare::default(),
+            rng: Rng::new(0),
+            frequency: None,
+            file_frequency: None,
+            freq_override: None,
+            max_freq: None,
+            chunk_size: tune::DEFAULT_CHUNK_SIZE,
+            in_loop: false,
+            loop_mismatch: false,
+            echo_tdo: false,
+            step: false,
+            breakpoints: vec![],
+            stop_after: None,
+            stopped: false,
+            cancel: None,
+            recorder: None,
+            capture_tdo: false,
+            captured: None,
+            patches: vec![],
+            sdr_count: 0,
+            packed: None,
+            sir_packed: vec![],
+            checks: vec![],
+            index: 0,
+            opcode: None,
+            phases: Phases::default(),
+            postmortem: None,
+            deadline: None,
+            max_duty: None,
+            padding: None,
+            limits: None,
+            ignore_unsupported: false,
+            strict: false,
+            log_file: None,
+            status: None,
+            telemetry: None,
+            observer: None,
+            resume: false,
+            stable: JtagState::Reset,
+            trst: None,
+            capabilities: None,
+        }
+    }
+
+    /// Forget everything learned from previous commands (end states, remembered TDI/SMASK/MASK),
+    /// keeping only the playback configuration
+    pub fn reset(&mut self) {
+        self.settle(0);
+        *self = Svf {
+            pipeline: self.pipeline.take(),
+            pipeline_depth: self.pipeline_depth,
+            retries: self.retries,
+            on_mismatch: self.on_mismatch,
+            log_level: self.log_level,
+            dump: self.dump,
+            bit_order: self.bit_order,
+            dont_care: self.dont_care,
+            rng: self.rng.clone(),
+            frequency: self.frequency,
+            file_frequency: self.file_frequency,
+            freq_override: self.freq_override,
+            max_freq: self.max_freq,
+            chunk_size: self.chunk_size,
+            echo_tdo: self.echo_tdo,
+            step: self.step,
+            breakpoints: std::mem::take(&mut self.breakpoints),
+            stop_after: self.stop_after,
+            cancel: self.cancel.take(),
+            recorder: self.recorder.take(),
+            capture_tdo: self.capture_tdo,
+            patches: std::mem::take(&mut self.patches),
+            sdr_count: self.sdr_count,
+            packed: None,
+            sir_packed: vec![],
+            checks: std::mem::take(&mut self.checks),
+            phases: std::mem::take(&mut self.phases),
+            postmortem: self.postmortem.take(),
+            deadline: self.deadline,
+            max_duty: self.max_duty,
+            padding: self.padding.as_ref().map(Padding::restart),
+            limits: self.limits.take(),
+            ignore_unsupported: self.ignore_unsupported,
+            strict: self.strict,
+            log_file: self.log_file.take(),
+            status: self.status.take(),
+            telemetry: self.telemetry.take(),
+            observer: self.observer.take(),
+            resume: self.resume,
+            ..Svf::new()
+        };
+    }
+
+    /// SVF setting up what the commands so far have left in effect, to put in front of the rest
+    /// of a file that stopped early
+    pub fn prologue(&self) -> String {
+        let empty = || Pattern { length: 0, tdi: None, tdo: None, mask: None, smask: None };
+        let mut cmds = vec![];
+        if let Some(hz) = self.file_frequency {
+            cmds.push(Command::Frequency(Some(hz)));
+        }
+        cmds.extend([Command::HIR(empty()), Command::HDR(empty()), Command::TIR(empty()), Command::TDR(empty())]);
+        cmds.push(Command::EndIR(svf_state(self.endir)));
+        cmds.push(Command::EndDR(svf_state(self.enddr)));
+        cmds.push(Command::RunTest {
+            run_state: Some(svf_state(self.run_state)),
+            form: RunTestForm::Clocked { run_count: 0, run_clk: RunClock::TCK, time: None },
+            end_state: Some(svf_state(self.end_state)),
+        });
+        match self.trst {
+            Some(true) => cmds.push(Command::TRST(TRSTMode::On)),
+            Some(false) => cmds.push(Command::TRST(TRSTMode::Off)),
+            None => (),
+        }
+        cmds.iter().map(|cmd| format!("{}\n", cmd)).collect()
+    }
+
+    /// Fail playback that goes on longer than `budget` from now
+    pub fn set_deadline(&mut self, budget: Duration) {
+        self.deadline = Some((Instant::now() + budget, budget));
+    }
+
+    /// How long until the run's deadline or the end of the playing phase's budget, whichever
+    /// comes first
+    fn time_left(&self) -> Option<Duration> {
+        let deadline = self.deadline.map(|(deadline, _)| deadline.saturating_duration_since(Instant::now()));
+        match (deadline, self.phases.time_left()) {
+            (Some(deadline), Some(phase)) => Some(deadline.min(phase)),
+            (deadline, phase) => deadline.or(phase),
+        }
+    }
+
+    /// `clock`, failing as soon as the run's deadline or the phase's budget is reached rather than
+    /// once every cycle is clocked
+    fn clock_in_time(&self, sm: &mut JtagSM<AdapterBox>, state: JtagState, cycles: u64, chunk: u64) {
+        let Some(left) = self.time_left() else {
+            return clock(sm, state, cycles, chunk);
+        };
+        // At a known rate, clocks that would take longer than the time left aren't started
+        let fit = self.frequency.map_or(cycles, |hz| cycles.min((left.as_secs_f64() * hz) as u64));
+        let mut done = 0;
+        while done < fit {
+            let n = (fit - done).min(chunk);
+            clock(sm, state, n, chunk);
+            done += n;
+            self.check_time();
+        }
+        if fit < cycles {
+            sm.cable.0.flush();
+            self.sleep_in_time(left);
+        }
+    }
+
+    /// Sleep for `time`, or fail once the run's deadline or the phase's budget is reached
+    fn sleep_in_time(&self, time: Duration) {
+        std::thread::sleep(self.time_left().map_or(time, |left| time.min(left)));
+        self.check_time();
+    }
+
+    /// `write_reg`, stopping between chunks once the run's deadline or the phase's budget is
+    /// reached, for `check_time` to fail after
+    fn write_in_time(&self, sm: &mut JtagSM<AdapterBox>, reg: Register, data: &[u8], bits: u8, chunk: usize) {
+        if self.time_left().is_none() || data.len() <= chunk {
+            return write_reg(sm, reg, data, bits, chunk);
+        }
+        let count = data.len().div_ceil(chunk);
+        for (i, part) in data.chunks(chunk).enumerate() {
+            if self.time_left() == Some(Duration::ZERO) {
+                return;
+            }
+            let last = i == count - 1;
+            sm.write_reg(reg, part, if last { bits } else { 8 }, last);
+        }
+    }
+
+    /// Fail if the run has reached its deadline or the phase playing its budget
+    fn check_time(&self) {
+        if let Some((_, budget)) = self.deadline.filter(|(deadline, _)| Instant::now() >= *deadline) {
+            panic!("playback took longer than its deadline of {}", humantime::format_duration(budget));
+        }
+        if let Some(why) = self.phases.overrun() {
+            panic!("{}", why);
+        }
+    }
+
+    /// Start counting towards phase `name`, once the checks of the phase before are done
+    pub fn begin_phase(&mut self, name: &str) {
+        self.settle(0);
+        self.log(format!("Phase {}", name));
+        if self.log_level >= LogLevel::Verbose {
+            println!("Phase {}", name);
+        }
+        if let Some(status) = &mut self.status {
+            status.phase(name);
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.on_phase(name);
+        }
+        self.phases.begin(name);
+    }
+
+    /// Forget about checks still in flight and any LOOP being played, after playback failed
+    pub fn abandon(&mut self) {
+        self.in_flight.clear();
+        self.in_loop = false;
+    }
+
+    /// Outside strict mode, drop the bits of vectors beyond the length, which some generators
+    /// leave set
+    fn tolerate(&mut self, name: &str, mut pattern: Pattern) -> Pattern {
+        if self.strict {
+            return pattern;
+        }
+        let length = pattern.length;
+        let vectors = [("TDI", &mut pattern.tdi), ("TDO", &mut pattern.tdo), ("MASK", &mut pattern.mask),
+                       ("SMASK", &mut pattern.smask)];
+        for (field, data) in vectors {
+            if let Some(data) = data.as_mut().filter(|data| !bits::fits(data, length)) {
+                self.log(format!("{} {} has more bits than the length {}, ignoring them", name, field, length));
+                if self.log_level >= LogLevel::Warn {
+                    eprintln!("{} {} {} has more bits than the length {}, ignoring them (--strict refuses this)",
+                              color::warning(), name, field, length);
+                }
+                *data = bits::fit(std::mem::take(data), length);
+            }
+        }
+        pattern
+    }
+
+    /// Pass over an unsupported command under `ignore_unsupported`
+    fn skip(&mut self, cmd: impl std::fmt::Display, why: &str) {
+        self.log(format!("Skipped {} ({})", cmd, why));
+        if self.log_level >= LogLevel::Warn {
+            eprintln!("{} skipping {} ({})", color::warning(), cmd, why);
+        }
+    }
+
+    /// Verify SDR captures from the pipeline until at most `depth` are outstanding
+    pub fn settle(&mut self, depth: usize) {
+        while self.in_flight.len() > depth {
+            let check = self.in_flight.pop_front().unwrap();
+            let read: Vec<u8> = check.read.iter().flatten().collect();
+            let read = bits::from_cable(read, check.length, self.bit_order);
+            if self.reads_tdo() {
+                self.show_tdo("SDR", &read);
+            }
+            self.verify(&read, &check.tdo, &check.mask, check.on_mismatch, check.index, check.tdi.as_deref());
+        }
+    }
+
+    /// Shift `length` bits of `tdi` through `reg`, move to `end` and return what came out of TDO
+    pub fn scan(&mut self, sm: &mut JtagSM<AdapterBox>, reg: Register, tdi: Vec<u8>, length: u32,
+            end: JtagState) -> Vec<u8> {
+        let buf = bits::to_cable(tdi, length, self.bit_order);
+        let chunk = self.chunk(sm);
+        path::enter_shift(sm, self.stable, reg);
+        let read = read_write_reg(sm, reg, &buf, bits::last_bits(length), chunk);
+        path::leave_scan(sm, reg, end);
+        self.stable = end;
+        bits::from_cable(read, length, self.bit_order)
+    }
+
+    fn reads_tdo(&self) -> bool {
+        self.echo_tdo || self.capture_tdo || self.observer.as_ref().is_some_and(|o| o.wants_tdo())
+    }
+
+    fn show_tdo(&mut self, kind: &'static str, read: &[u8]) {
+        let sticky = if kind == "SIR" { &self.sir } else { &self.sdr };
+        let length = sticky.length.unwrap_or(0) as usize;
+        if self.log_level >= LogLevel::Debug {
+            self.dump.print("TDI", &sticky.driven, length);
+        }
+        if self.echo_tdo || self.log_level >= LogLevel::Debug {
+            self.dump.print("TDO", read, length);
+        }
+        if let Some(observer) = &mut self.observer {
+            match &self.padding {
+                Some(padding) => observer.on_tdo(kind, &padding.unpad(kind, read)),
+                None => observer.on_tdo(kind, read),
+            }
+        }
+        if self.capture_tdo {
+            self.captured = Some(read.to_vec());
+        }
+    }
+
+    pub(crate) fn progress(&mut self, done: usize, total: Option<usize>) {
+        if let Some(observer) = &mut self.observer {
+            observer.on_progress(done, total);
+        }
+    }
+
+    pub fn log(&mut self, message: impl std::fmt::Display) {
+        if let Some(log) = &mut self.log_file {
+            log.entry(message);
+        }
+    }
+
+    /// Fail on a TDO mismatch, or inside a LOOP only note it so the body is repeated
+    fn verify(&mut self, read: &[u8], tdo: &[u8], mask: &[u8], on_mismatch: OnMismatch, index: usize,
+              tdi: Option<&[u8]>) {
+        if !tdo_matches(read, tdo, mask) {
+            if let Some(telemetry) = &mut self.telemetry {
+                telemetry.tdo_mismatches += 1;
+            }
+            if let Some(observer) = &mut self.observer {
+                observer.on_mismatch(read, tdo, mask);
+            }
+            self.phases.mismatch();
+            tracing::error!(read = hex(read), expected = hex(tdo), mask = hex(mask), "TDO mismatch");
+            self.log(format!("TDO mismatch: read {}, expected {}, mask {}", hex(read), hex(tdo), hex(mask)));
+        }
+        if self.in_loop {
+            self.loop_mismatch |= !tdo_matches(read, tdo, mask);
+            return;
+        }
+        if !tdo_matches(read, tdo, mask) {
+            if let Some(status) = &mut self.status {
+                status.finish();
+            }
+            let carry_on = match on_mismatch {
+                OnMismatch::Abort | OnMismatch::Retry => false,
+                OnMismatch::Warn => {
+                    if self.log_level >= LogLevel::Warn {
+                        eprintln!("{} {}", color::warning(), color::tdo_mismatch(read, tdo, mask));
+                    }
+                    true
+                }
+                OnMismatch::Prompt => {
+                    eprintln!("{}", color::tdo_mismatch(read, tdo, mask));
+                    eprint!("Carry on anyway? [y/N] ");
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+                }
+            };
+            if carry_on {
+                self.log("TDO mismatch ignored");
+                return;
+            }
+            if on_mismatch != OnMismatch::Prompt && self.log_level >= LogLevel::Error {
+                eprintln!("{}", color::tdo_mismatch(read, tdo, mask));
+            }
+            self.write_postmortem(Failure { number: index, tdi, read, tdo, mask, state: self.stable, phase: None,
+                                            settings: String::new() });
+            panic!("TDO mismatch: read {}, expected {}, mask {}", hex(read), hex(tdo), hex(mask));
+        }
+    }
+
+    fn write_postmortem<'a>(&'a self, mut failure: Failure<'a>) {
+        let Some(postmortem) = &self.postmortem else {
+            return;
+        };
+        // A scan fails once it has left the TAP in its end state
+        failure.state = match postmortem.current() {
+            Some(Command::SIR(_)) => self.endir,
+            Some(Command::SDR(_)) => self.enddr,
+            _ => self.stable,
+        };
+        failure.phase = self.phases.current();
+        failure.settings = self.prologue();
+        match postmortem.write(&failure) {
+            Ok(()) => eprintln!("Post-mortem written to {}", postmortem.path),
+            Err(e) => eprintln!("{} post-mortem: {}", color::warning(), e),
+        }
+    }
+
+    pub(crate) fn to_jtag_state(state: State) -> JtagState {
+        match state {
+            State::RESET => JtagState::Reset,
+            State::IDLE => JtagState::Idle,
+            State::DRSELECT => JtagState::SelectDR,
+            State::DRCAPTURE => JtagState::CaptureDR,
+            State::DRSHIFT => JtagState::ShiftDR,
+            State::DREXIT1 => JtagState::Exit1DR,
+            State::DRPAUSE => JtagState::PauseDR,
+            State::DREXIT2 => JtagState::Exit2DR,
+            State::DRUPDATE => JtagState::UpdateDR,
+            State::IRSELECT => JtagState::SelectIR,
+            State::IRCAPTURE => JtagState::CaptureIR,
+            State::IRSHIFT => JtagState::ShiftIR,
+            State::IREXIT1 => JtagState::Exit1IR,
+            State::IRPAUSE => JtagState::PauseIR,
+            State::IREXIT2 => JtagState::Exit2IR,
+            State::IRUPDATE => JtagState::UpdateIR,
+        }
+    }
+
+    pub fn run_command(&mut self, cmd: Command, sm: &mut JtagSM<AdapterBox>) {
+        if let Some(limits) = &mut self.limits {
+            limits.check(&cmd).unwrap_or_else(|e| panic!("{}", e));
+        }
+        if self.ignore_unsupported {
+            if let Some(why) = preflight::unsupported(&cmd) {
+                self.skip(&cmd, &why);
+                return;
+            }
+        }
+        if let Command::SIR(Pattern { length, tdi: Some(tdi), .. }) = &cmd {
+            self.opcode = Some(checks::opcode(&bits::fit(tdi.clone(), *length)));
+        }
+        let cmd = match cmd {
+            Command::SIR(pattern) => Command::SIR(self.tolerate("SIR", pattern)),
+            Command::SDR(pattern) => Command::SDR(self.tolerate("SDR", pattern)),
+            cmd => cmd,
+        };
+        let cmd = match (cmd, &mut self.padding) {
+            (Command::SIR(pattern), Some(padding)) => Command::SIR(padding.sir(pattern)),
+            (Command::SDR(pattern), Some(padding)) => Command::SDR(padding.sdr(pattern)),
+            (cmd, _) => cmd,
+        };
+        self.log(&cmd);
+        if let Some(observer) = &mut self.observer {
+            observer.on_command(&cmd);
+        }
+        let span = tracing::info_span!("command", kind = Profiler::kind(&cmd), length = tracing::field::Empty,
+                                       end_state = tracing::field::Empty, duration_us = tracing::field::Empty);
+        let _entered = span.enter();
+        let end_state = match &cmd {
+            Command::SIR(pattern) | Command::SDR(pattern) => {
+                span.record("length", pattern.length);
+                None
+            }
+            Command::State { end, .. } => Some(*end),
+            Command::TRST(TRSTMode::On) => Some(State::RESET),
+            _ => None,
+        };
+        if let Some(telemetry) = &mut self.telemetry {
+            telemetry.commands += 1;
+            match &cmd {
+                Command::SIR(pattern) => telemetry.sir_bits += pattern.length as u64,
+                Command::SDR(pattern) => telemetry.sdr_bits += pattern.length as u64,
+                _ => (),
+            }
+        }
+        let start = std::time::Instant::now();
+        let mut recorded = self.recorder.is_some().then(|| cmd.clone());
+        self.captured = None;
+        let kind = Profiler::kind(&cmd);
+        if kind == "SDR" {
+            self.sdr_count += 1;
+        }
+        if self.resume {
+            self.execute_resuming(cmd, sm);
+        } else {
+            self.execute(cmd, sm);
+        }
+        let end_state = match kind {
+            "SIR" => Some(svf_state(self.endir)),
+            "SDR" => Some(svf_state(self.enddr)),
+            "RUNTEST" => Some(svf_state(self.end_state)),
+            _ => end_state,
+        };
+        if let Some(state) = end_state {
+            self.stable = Self::to_jtag_state(state);
+            span.record("end_state", tracing::field::display(state));
+        }
+        span.record("duration_us", start.elapsed().as_micros() as u64);
+        if let (Some(Command::SIR(pattern) | Command::SDR(pattern)), Some(read)) = (&mut recorded, self.captured.take()) {
+            // Expect exactly what came back, under the mask the file gave this scan
+            let sticky = if kind == "SIR" { &self.sir } else { &self.sdr };
+            pattern.tdo = Some(read);
+            pattern.mask = Some(sticky.mask.clone());
+        }
+        if let (Some(cmd), Some(recorder)) = (recorded, &mut self.recorder) {
+            recorder.command(&cmd).expect("write recording");
+        }
+    }
+
+    /// Run `cmd`, and if the cable is reopened part way through, set the TAP up again and run
+    /// it from the start
+    fn execute_resuming(&mut self, cmd: Command, sm: &mut JtagSM<AdapterBox>) {
+        let mut restore = false;
+        loop {
+            let attempt = cmd.clone();
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                if restore {
+                    self.restore(sm);
+                }
+                self.execute(attempt, sm);
+                // A batching cable may still hold some of the command, which the watchdog could
+                // lose after the command counts as done
+                sm.cable.0.flush();
+            }));
+            match result {
+                Ok(()) => return,
+                Err(e) if e.is::<watchdog::Reconnected>() => restore = true,
+                Err(e) => std::panic::resume_unwind(e),
+            }
+        }
+    }
+
+    /// Put a freshly opened cable's TAP back where the last completed command left it
+    fn restore(&mut self, sm: &mut JtagSM<AdapterBox>) {
+        self.log(format!("Cable reopened, resuming from {}", svf_state(self.stable)));
+        if self.log_level >= LogLevel::Warn {
+            eprintln!("{} cable reopened, playing the current command again", color::warning());
+        }
+        tracing::warn!("cable reopened");
+        self.capabilities = None;
+        if let Some(asserted) = self.trst.filter(|_| self.capabilities(sm).trst) {
+            sm.cable.0.set_trst(asserted);
+        }
+        sm.mode_reset();
+        path::move_to(sm, JtagState::Reset, self.stable);
+    }
+
+    fn execute(&mut self, cmd: Command, sm: &mut JtagSM<AdapterBox>) {
+        match cmd {
+            Command::TRST(mode) => {
+                let trst = self.capabilities(sm).trst;
+                if mode == TRSTMode::On {
+                    if !trst || !sm.cable.0.set_trst(true) {
+                        if self.ignore_unsupported {
+                            self.skip("TRST ON;", "the cable has no TRST line");
+                            return;
+                        }
+                        eprintln!("TRST control not implemented");
+                        unimplemented!();
+                    }
+                    // The TAP is held in Test-Logic-Reset, so that's where the state machine is too
+                    sm.mode_reset();
+                    self.trst = Some(true);
+                } else if mode == TRSTMode::Off {
+                    if trst {
+                        sm.cable.0.set_trst(false);
+                    }
+                    self.trst = Some(false);
+                } else if trst {
+                    sm.cable.0.set_trst(false);
+                }
+            }
+            Command::EndDR(state) => self.enddr = Self::to_jtag_state(state),
+            Command::EndIR(state) => self.endir = Self::to_jtag_state(state),
+            Command::State{path: Some(states), end} => {
+                let end = Self::to_jtag_state(end);
+                let states: Vec<_> = states.into_iter().map(Self::to_jtag_state).chain([end]).collect();
+                let tms = path::follow(self.stable, &states).unwrap_or_else(|e| panic!("{}", e));
+                path::walk(sm, &tms, end);
+            }
+            Command::State{path: None, end} => path::move_to(sm, self.stable, Self::to_jtag_state(end)),
+            Command::HIR(pattern) => {
+                if pattern.length != 0 {
+                    eprintln!("HIR not implemented");
+                    unimplemented!();
+                }
+            }
+            Command::HDR(pattern) => {
+                if pattern.length != 0 {
+                    eprintln!("HDR not implemented");
+                    unimplemented!();
+                }
+            }
+            Command::TIR(pattern) => {
+                if pattern.length != 0 {
+                    eprintln!("TIR not implemented");
+                    unimplemented!();
+                }
+            }
+            Command::TDR(pattern) => {
+                if pattern.length != 0 {
+                    eprintln!("TDR not implemented");
+                    unimplemented!();
+                }
+            }
+            // A scan of no bits shifts nothing, but still leaves the TAP in the end state
+            Command::SIR(pattern) if pattern.length == 0 => {
+                remember(&mut self.sir, "SIR", pattern);
+                path::move_to(sm, self.stable, self.endir);
+            }
+            Command::SDR(pattern) if pattern.length == 0 => {
+                remember(&mut self.sdr, "SDR", pattern);
+                path::move_to(sm, self.stable, self.enddr);
+            }
+            Command::SIR(mut pattern) => {
+                let length = pattern.length;
+                let tdo = pattern.tdo.take()
+                    .map(|tdo| scan_vector("SIR", "TDO", tdo, length).unwrap_or_else(|e| panic!("{}", e)));
+                remember(&mut self.sir, "SIR", pattern);
+                let len = bits::last_bits(length);
+
+                if tdo.is_some() || self.reads_tdo() {
+                    let tdi = self.sir.drive(self.dont_care, &mut self.rng);
+                    let read = self.scan(sm, Register::Instruction, tdi, length, self.endir);
+                    self.show_tdo("SIR", &read);
+                    if let Some(tdo) = &tdo {
+                        let mask = self.sir.mask.clone();
+                        let tdi = self.postmortem.is_some().then(|| self.sir.driven.clone());
+                        self.verify(&read, tdo, &mask, self.on_mismatch, self.index, tdi.as_deref());
+                    }
+                } else {
+                    // Nothing to verify, so don't wait for the cable to send TDO back
+                    let mut buf = std::mem::take(&mut self.sir_packed);
+                    self.sir.drive_into(self.dont_care, &mut self.rng, &mut buf);
+                    let buf = bits::to_cable(buf, length, self.bit_order);
+                    let chunk = self.chunk(sm);
+                    path::enter_shift(sm, self.stable, Register::Instruction);
+                    write_reg(sm, Register::Instruction, &buf, len, chunk);
+                    path::leave_scan(sm, Register::Instruction, self.endir);
+                    self.sir_packed = buf;
+                }
+            }
+            Command::SDR(mut pattern) => {
+                let length = pattern.length;
+                let tdo = pattern.tdo.take()
+                    .map(|tdo| scan_vector("SDR", "TDO", tdo, length).unwrap_or_else(|e| panic!("{}", e)));
+                remember(&mut self.sdr, "SDR", pattern);
+                let (header, trailer) = self.padding.as_ref().map_or((0, 0), Padding::dr_bits);
+                for patch in self.patches.iter().filter(|patch| patch.sdr == self.sdr_count) {
+                    patch.apply(&mut self.sdr.tdi, length - header - trailer, header)
+                        .unwrap_or_else(|e| panic!("{}", e));
+                }
+                let len = bits::last_bits(length);
+
+                // Fill and blank-check sequences shift the same TDI over and over, so don't pack it
+                // again each time
+                let buf = match self.packed.take() {
+                    Some((sdr, buf)) if sdr + 1 == self.sdr_count && self.sdr.repeated && self.dont_care != DontCare::Random
+                        && !self.patches.iter().any(|patch| patch.sdr == self.sdr_count) => buf,
+                    packed => {
+                        let mut buf = packed.map(|(_, buf)| buf).unwrap_or_default();
+                        self.sdr.drive_into(self.dont_care, &mut self.rng, &mut buf);
+                        bits::to_cable(buf, length, self.bit_order)
+                    }
+                };
+                let chunk = self.chunk(sm);
+                let on_mismatch = checks::on_mismatch(checks::severity(&self.checks, self.index, self.opcode),
+                                                      self.on_mismatch);
+                path::enter_shift(sm, self.stable, Register::Data);
+                if let (Some(tdo), Some(pipeline)) = (&tdo, &self.pipeline) {
+                    let read = pipeline.capture_writes();
+                    self.write_in_time(sm, Register::Data, &buf, len, chunk);
+                    pipeline.end_capture();
+                    self.check_time();
+                    path::leave_scan(sm, Register::Data, self.enddr);
+                    self.in_flight.push_back(InFlight {
+                        read,
+                        length,
+                        tdo: tdo.clone(),
+                        mask: self.sdr.mask.clone(),
+                        on_mismatch,
+                        index: self.index,
+                        tdi: self.postmortem.is_some().then(|| self.sdr.driven.clone()),
+                    });
+                    // What was read is shown with the TDI that was driven, so it can't wait for
+                    // a later SDR
+                    self.settle(if self.reads_tdo() { 0 } else { self.pipeline_depth });
+                } else if let Some(tdo) = tdo {
+                    let mut attempt = 0;
+                    loop {
+                        let read = read_write_reg(sm, Register::Data, &buf, len, chunk);
+                        let read = bits::from_cable(read, length, self.bit_order);
+                        path::leave_scan(sm, Register::Data, self.enddr);
+                        self.show_tdo("SDR", &read);
+                        if attempt >= self.retries || tdo_matches(&read, &tdo, &self.sdr.mask) {
+                            let mask = self.sdr.mask.clone();
+                            let tdi = self.postmortem.is_some().then(|| self.sdr.driven.clone());
+                            self.verify(&read, &tdo, &mask, on_mismatch, self.index, tdi.as_deref());
+                            break;
+                        }
+                        attempt += 1;
+                        path::enter_shift(sm, self.enddr, Register::Data);
+                        self.log(format!("TDO mismatch, retrying ({}/{})", attempt, self.retries));
+                        tracing::warn!(attempt, retries = self.retries, "TDO mismatch, retrying");
+                        if let Some(telemetry) = &mut self.telemetry {
+                            telemetry.retries += 1;
+                        }
+                        if self.log_level >= LogLevel::Warn {
+                            eprintln!("{} TDO mismatch, retrying ({}/{})", color::warning(), attempt, self.retries);
+                        }
+                    }
+                } else if self.reads_tdo() {
+                    let read = read_write_reg(sm, Register::Data, &buf, len, chunk);
+                    let read = bits::from_cable(read, length, self.bit_order);
+                    path::leave_scan(sm, Register::Data, self.enddr);
+                    self.show_tdo("SDR", &read);
+                } else {
+                    self.write_in_time(sm, Register::Data, &buf, len, chunk);
+                    self.check_time();
+                    path::leave_scan(sm, Register::Data, self.enddr);
+                }
+                self.packed = Some((self.sdr_count, buf));
+            }
+            Command::RunTest{run_state, form, end_state} => {
+                if let Some(end_state) = end_state {
+                    self.end_state = Self::to_jtag_state(end_state);
+                }
+                if let Some(run_state) = run_state {
+                    self.run_state = Self::to_jtag_state(run_state);
+                }
+                let (run_count, time) = match form {
+                    RunTestForm::Clocked { run_count, run_clk, time } => {
+                        assert_eq!(run_clk, RunClock::TCK);
+                        (run_count, time)
+                    }
+                    RunTestForm::Timed(time) => (0, Some(time)),
+                };
+                path::move_to(sm, self.stable, self.run_state);
+                let chunk = self.chunk(sm) as u64 * 8;
+                let start = std::time::Instant::now();
+                self.clock_in_time(sm, self.run_state, run_count as u64, chunk);
+                if let Some(time) = time {
+                    let min = std::time::Duration::from_secs_f64(time.min);
+                    // Keep TCK running for the rest of the minimum time if the rate is known,
+                    // then make up any shortfall once the clocks have actually left the cable
+                    if let (Some(hz), Some(remaining)) = (self.frequency, min.checked_sub(start.elapsed())) {
+                        let cycles = (remaining.as_secs_f64() * hz).ceil() as u64;
+                        self.clock_in_time(sm, self.run_state, cycles, chunk);
+                    }
+                    sm.cable.0.flush();
+                    if let Some(remaining) = min.checked_sub(start.elapsed()) {
+                        self.sleep_in_time(remaining);
+                    }
+                    let elapsed = start.elapsed().as_secs_f64();
+                    if let Some(max) = time.max.filter(|max| elapsed > *max) {
+                        panic!("RUNTEST took {:.6} s, more than its MAXIMUM of {} s", elapsed, max);
+                    }
+                }
+                path::move_to(sm, self.run_state, self.end_state);
+            }
+            Command::Frequency(Some(hz)) => {
+                self.file_frequency = Some(hz);
+                self.set_frequency(sm, self.freq_override.unwrap_or(hz));
+            }
+            Command::Frequency(None) => self.file_frequency = None,
+            _ => {
+                eprintln!("unimplemented command: {}", cmd);
+                unimplemented!();
+            }
+        }
+    }
+
+    /// What the cable can do, asked once per cable
+    fn capabilities(&mut self, sm: &mut JtagSM<AdapterBox>) -> Capabilities {
+        *self.capabilities.get_or_insert_with(|| sm.cable.0.capabilities())
+    }
+
+    /// `chunk_size`, or less if the cable takes less at once
+    fn chunk(&mut self, sm: &mut JtagSM<AdapterBox>) -> usize {
+        match self.capabilities(sm).max_transfer {
+            Some(max) => self.chunk_size.min(max.max(1)),
+            None => self.chunk_size,
+        }
+    }
+
+    /// Ask the cable for `hz`, or `max_freq` or the cable's fastest TCK if that is lower
+    pub fn set_frequency(&mut self, sm: &mut JtagSM<AdapterBox>, hz: f64) {
+        let mut hz = self.max_freq.map_or(hz, |max| hz.min(max));
+        if let Some(max) = self.capabilities(sm).max_hz.filter(|max| hz > *max) {
+            if self.log_level >= LogLevel::Warn {
+                eprintln!("{} the cable runs TCK at most at {} Hz, not {} Hz", color::warning(), max, hz);
+            }
+            hz = max;
+        }
+        match sm.cable.0.set_frequency(hz) {
+            Some(actual) => {
+                if self.frequency != Some(actual) && self.log_level >= LogLevel::Info {
+                    println!("TCK: {} Hz", actual);
+                }
+                self.frequency = Some(actual);
+            }
+            None => {
+                if self.log_level >= LogLevel::Warn {
+                    eprintln!("{} this cable can't change frequency", color::warning());
+                }
+            }
+        }
+    }
+}
+
+/// Parse a rate such as `6MHz`, `400 kHz` or `1e6`
+pub fn parse_frequency(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let scale = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "hz" => 1.0,
+        "khz" => 1e3,
+        "mhz" => 1e6,
+        "ghz" => 1e9,
+        _ => return Err(format!("unknown unit {}", unit)),
+    };
+    let number: f64 = number.trim().parse().map_err(|_| format!("bad frequency {}", s))?;
+    if number <= 0.0 {
+        return Err("frequency must be positive".into());
+    }
+    Ok(number * scale)
+}
+
+/// Wait at a breakpoint until the user decides how to go on.  Everything before `cmd` has
+/// been clocked out by the time the prompt appears.
+fn pause(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, index: usize, cmd: &Command) {
+    svf.settle(0);
+    sm.cable.0.flush();
+    eprintln!("[{}] {}", index, cmd);
+    eprint!("Enter to run it, c to continue to the next breakpoint, q to stop: ");
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() || answer.is_empty() {
+        return;
+    }
+    match answer.trim() {
+        "c" => svf.step = false,
+        // Stopping is a failure, so it goes through the same abort sequence and hooks as one
+        "q" => panic!("stopped before command {}", index),
+        _ => svf.step = true,
+    }
+}
+
+/// Run one command from a file, where it is command number `index`
+fn play(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, index: usize, cmd: Command,
+        profiler: &mut Option<&mut Profiler>) {
+    if svf.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::SeqCst)) {
+        // Commands only stop in stable states, so a reset parks the TAP cleanly after this
+        panic!("cancelled");
+    }
+    if svf.step || svf.breakpoints.contains(&index) {
+        pause(sm, svf, index, &cmd);
+    }
+    if svf.log_level >= LogLevel::Verbose {
+        println!("{}", cmd);
+    }
+    if let Some(profiler) = profiler.as_deref_mut() {
+        profiler.begin(&cmd);
+    }
+    let kind = Profiler::kind(&cmd);
+    let bits = match &cmd {
+        Command::SIR(pattern) | Command::SDR(pattern) => pattern.length as u64,
+        _ => 0,
+    };
+    if let Some(postmortem) = &mut svf.postmortem {
+        postmortem.command(index, &cmd);
+    }
+    svf.index = index;
+    let duty = svf.phases.duty().or(svf.max_duty).filter(|_| bits > 0);
+    let start = Instant::now();
+    svf.run_command(cmd, sm);
+    if let Some(duty) = duty {
+        // Time the scan as it reaches the target, not as it's queued
+        svf.settle(0);
+        sm.cable.0.flush();
+        std::thread::sleep(pacing::gap(start.elapsed(), duty));
+    }
+    svf.phases.command(bits);
+    svf.check_time();
+    if let Some(profiler) = profiler.as_deref_mut() {
+        profiler.end();
+    }
+    if let Some(status) = &mut svf.status {
+        status.update(kind, index, bits);
+    }
+}
+
+/// Repeat a LOOP body until all of its TDO checks pass
+fn play_loop(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, count: u32, body: Vec<(usize, Command)>,
+             profiler: &mut Option<&mut Profiler>) {
+    svf.in_loop = true;
+    for attempt in 1..=count {
+        svf.loop_mismatch = false;
+        for (index, cmd) in &body {
+            play(sm, svf, *index, cmd.clone(), profiler);
+        }
+        svf.settle(0);
+        if !svf.loop_mismatch {
+            break;
+        }
+        if attempt == count {
+            svf.in_loop = false;
+            panic!("TDO still doesn't match after {} LOOP iterations", count);
+        }
+        svf.log(format!("LOOP iteration {} of {} didn't match, repeating", attempt, count));
+        if svf.log_level >= LogLevel::Debug {
+            println!("LOOP iteration {} of {} didn't match, repeating", attempt, count);
+        }
+    }
+    svf.in_loop = false;
+}
+
+/// Play SVF text, or SVF compiled by `svfplayer compile`
+pub fn run_svf(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, input: &mut impl BufRead,
+           profiler: Option<&mut Profiler>) -> Result<(),ParseError> {
+    let markers = lattice::Markers::default();
+    if compiled::is_compiled(input) {
+        let commands = compiled::Reader::new(input, markers.clone()).unwrap_or_else(|e| panic!("compiled SVF: {}", e));
+        return run_commands(sm, svf, commands, markers, profiler);
+    }
+    let mut input = lattice::LoopFilter::new(input, markers.clone());
+    run_commands(sm, svf, svf::parse_iter_bufread(&mut input), markers, profiler)
+}
+
+fn run_commands(sm: &mut JtagSM<AdapterBox>, svf: &mut Svf, commands: impl Iterator<Item = Result<Command, ParseError>>,
+                markers: lattice::Markers, mut profiler: Option<&mut Profiler>) -> Result<(),ParseError> {
+    let mut body: Option<(u32, Vec<(usize, Command)>)> = None;
+    let mut commands = commands.enumerate();
+    loop {
+        let next = commands.next();
+        let i = next.as_ref().map_or(usize::MAX, |(i, _)| *i);
+        while markers.borrow().front().is_some_and(|m| m.before() <= i) {
+            match markers.borrow_mut().pop_front().unwrap() {
+                lattice::Marker::Loop { count, .. } => {
+                    assert!(!svf.strict, "LOOP is a Lattice extension, not part of the SVF specification");
+                    assert!(body.is_none(), "LOOP can't be nested");
+                    body = Some((count, vec![]));
+                }
+                lattice::Marker::EndLoop { before } => {
+                    let (count, cmds) = body.take().expect("ENDLOOP without LOOP");
+                    play_loop(sm, svf, count, cmds, &mut profiler);
+                    svf.progress(before, None);
+                }
+                lattice::Marker::Phase { name, .. } => svf.begin_phase(&name),
+            }
+        }
+        let Some((i, cmd)) = next else {
+            break;
+        };
+        if body.is_none() && svf.stop_after.is_some_and(|n| i >= n) {
+            svf.stopped = true;
+            break;
+        }
+        let cmd = cmd?;
+        match &mut body {
+            Some((_, cmds)) => cmds.push((i + 1, cmd)),
+            None => {
+                play(sm, svf, i + 1, cmd, &mut profiler);
+                svf.progress(i + 1, None);
+            }
+        }
+    }
+    assert!(body.is_none(), "LOOP without ENDLOOP");
+    svf.settle(0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cable;
+
+    fn sdr(text: &str) -> Pattern {
+        match svf::parse_complete(text).unwrap().remove(0) {
+            Command::SDR(pattern) => pattern,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tdo_without_mask_compares_every_bit() {
+        let mut sticky = Sticky::default();
+        remember(&mut sticky, "SDR", sdr("SDR 12 TDI (000) TDO (abc);"));
+        assert_eq!(sticky.mask, [0xff, 0x0f]);
+        assert!(tdo_matches(&[0xbc, 0x0a], &[0xbc, 0x0a], &sticky.mask));
+        assert!(!tdo_matches(&[0xbc, 0x0b], &[0xbc, 0x0a], &sticky.mask));
+        assert!(!tdo_matches(&[0xbd, 0x0a], &[0xbc, 0x0a], &sticky.mask));
+    }
+
+    #[test]
+    fn given_mask_is_remembered_for_the_same_length() {
+        let mut sticky = Sticky::default();
+        remember(&mut sticky, "SDR", sdr("SDR 8 TDI (00) TDO (0f) MASK (0f);"));
+        remember(&mut sticky, "SDR", sdr("SDR 8 TDO (ff);"));
+        assert_eq!(sticky.mask, [0x0f]);
+        assert!(tdo_matches(&[0x0f], &[0xff], &sticky.mask));
+    }
+
+    #[test]
+    fn mask_defaults_to_all_ones_after_a_length_change() {
+        let mut sticky = Sticky::default();
+        remember(&mut sticky, "SDR", sdr("SDR 8 TDI (00) MASK (0f);"));
+        remember(&mut sticky, "SDR", sdr("SDR 9 TDI (000) TDO (1ff);"));
+        assert_eq!(sticky.mask, [0xff, 0x01]);
+        assert!(!tdo_matches(&[0x0f, 0x01], &[0xff, 0x01], &sticky.mask));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn masked_compare_matches_a_bitwise_model(
+            bits in proptest::collection::vec(proptest::prelude::any::<(bool, bool, bool)>(), 1..100)) {
+            let tdo: Vec<bool> = bits.iter().map(|b| b.0).collect();
+            let mask: Vec<bool> = bits.iter().map(|b| b.1).collect();
+            let read: Vec<bool> = bits.iter().map(|b| b.2).collect();
+            let length = bits.len() as u32;
+            let mut sticky = Sticky::default();
+            remember(&mut sticky, "SDR", sdr(&format!("SDR {} TDI (0) TDO ({}) MASK ({});", length,
+                                              bits::tests::hex(&tdo), bits::tests::hex(&mask))));
+            let expected = bits.iter().all(|(tdo, mask, read)| !mask || tdo == read);
+            let tdo = bits::fit(cable::pack(&tdo), length);
+            proptest::prop_assert_eq!(tdo_matches(&cable::pack(&read), &tdo, &sticky.mask), expected);
+        }
+    }
+
+    /// Fails the first write it's given the way the watchdog does once it has reopened the cable
+    struct Flaky {
+        inner: cable::script::Script,
+        failed: bool,
+    }
+
+    impl jtag_taps::cable::Cable for Flaky {
+        fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+            self.inner.change_mode(tms, tdo)
+        }
+
+        fn read_data(&mut self, bits: usize) -> Vec<u8> {
+            self.inner.read_data(bits)
+        }
+
+        fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+            if !self.failed {
+                self.failed = true;
+                std::panic::resume_unwind(Box::new(watchdog::Reconnected));
+            }
+            self.inner.write_data(data, bits, pause_after)
+        }
+
+        fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+            self.inner.read_write_data(data, bits, pause_after)
+        }
+    }
+
+    impl cable::Adapter for Flaky {
+        fn capabilities(&mut self) -> Capabilities {
+            self.inner.capabilities()
+        }
+    }
+
+    #[test]
+    fn writes_lost_in_a_batch_flush_are_played_again() {
+        use cable::script::{Call::*, IDLE_TO_SHIFT_IR, PAUSE_TO_IDLE};
+        // The reset and the way to Shift-IR reach the cable, then the write doesn't, so the TAP is
+        // reset again and the whole SIR played once more
+        let to_shift_ir = ChangeMode([&[1, 1, 1, 1, 1, 0, 0][..], &IDLE_TO_SHIFT_IR[..]].concat(), true);
+        let script = cable::script::Script::new(vec![
+            to_shift_ir.clone(),
+            to_shift_ir,
+            Write { data: vec![0x0e], bits: 4, pause_after: true },
+            ChangeMode(PAUSE_TO_IDLE.to_vec(), true),
+        ]);
+        let flaky = Flaky { inner: script, failed: false };
+        let mut sm = JtagSM::new(AdapterBox(Box::new(crate::batch::BatchingCable::new(Box::new(flaky)))));
+        let mut player = Svf::new();
+        player.resume = true;
+        crate::run_svf(&mut sm, &mut player, &mut "SIR 4 TDI (e);\n".as_bytes(), None).unwrap();
+    }
+
+    const CHAIN: &str = "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n";
+
+    struct Shown(std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>);
+
+    impl Observer for Shown {
+        fn on_tdo(&mut self, _kind: &'static str, tdo: &[u8]) {
+            self.0.borrow_mut().push(tdo.to_vec());
+        }
+    }
+
+    fn pipelined(svf: &str, observe: bool) -> Vec<Vec<u8>> {
+        let cable = crate::pipeline::PipelinedCable::spawn(|| {
+            Box::new(cable::ShiftCable(cable::sim::Sim::parse(CHAIN).unwrap()))
+        });
+        let mut player = Svf::new();
+        player.pipeline = Some(cable.handle());
+        player.pipeline_depth = 2;
+        player.chunk_size = 1;
+        player.bit_order = BitOrder::MsbFirst;
+        let shown = std::rc::Rc::default();
+        if observe {
+            player.observer = Some(Box::new(Shown(std::rc::Rc::clone(&shown))));
+        }
+        let mut sm = JtagSM::new(AdapterBox(Box::new(cable)));
+        crate::run_svf(&mut sm, &mut player, &mut svf.as_bytes(), None).unwrap();
+        drop(player);
+        std::rc::Rc::try_unwrap(shown).unwrap().into_inner()
+    }
+
+    #[test]
+    fn pipelined_sdrs_are_chunked_and_checked_in_svf_bit_order() {
+        // The IDCODE comes out least significant bit first, which MSB-first files write reversed
+        let svf = "SDR 32 TDI (0) TDO (9e6a2c48);\nRUNTEST 1 TCK;\nSDR 32 TDI (0) TDO (9e6a2c48);\n";
+        assert!(pipelined(svf, false).is_empty());
+        assert_eq!(pipelined(svf, true), [[0x48, 0x2c, 0x6a, 0x9e]; 2]);
+        let unreversed = "SDR 32 TDI (0) TDO (12345679);\nRUNTEST 1 TCK;\n";
+        assert!(std::panic::catch_unwind(|| pipelined(unreversed, false)).is_err());
+    }
+
+    /// Why playing `svf` on the simulated chain failed, and how long it took to
+    fn overtime(svf: &str, setup: impl FnOnce(&mut Svf)) -> (String, Duration) {
+        let mut player = Svf::new();
+        setup(&mut player);
+        let mut sm = JtagSM::new(AdapterBox(Box::new(cable::ShiftCable(cable::sim::Sim::parse(CHAIN).unwrap()))));
+        let start = Instant::now();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            crate::run_svf(&mut sm, &mut player, &mut svf.as_bytes(), None)
+        }));
+        (crate::panic_message(&*result.unwrap_err()), start.elapsed())
+    }
+
+    #[test]
+    fn long_runtests_stop_at_the_deadline_or_phase_budget() {
+        let deadline = |svf: &mut Svf| svf.set_deadline(Duration::from_millis(100));
+        let slow = |svf: &mut Svf| {
+            deadline(svf);
+            svf.frequency = Some(1e3);
+        };
+        let budget = |svf: &mut Svf| svf.phases.budgets = vec![("erase".into(), Duration::from_millis(100))];
+        for (svf, setup, why) in [
+            ("RUNTEST 10 SEC;\n", &deadline as &dyn Fn(&mut Svf), "deadline"),
+            // A million clocks at 1 kHz would take over a quarter of an hour
+            ("RUNTEST 1000000 TCK;\n", &slow, "deadline"),
+            ("! phase erase\nRUNTEST 10 SEC;\n", &budget, "phase erase took longer"),
+        ] {
+            let (error, took) = overtime(svf, setup);
+            assert!(error.contains(why), "{}", error);
+            assert!(took < Duration::from_secs(2), "{:?}", took);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "without a new TDI")]
+    fn length_change_requires_tdi() {
+        let mut sticky = Sticky::default();
+        remember(&mut sticky, "SDR", sdr("SDR 8 TDI (00);"));
+        remember(&mut sticky, "SDR", sdr("SDR 16 TDO (0000);"));
+    }
+}
@@ -0,0 +1,112 @@
+//! `--postmortem PATH`: when a TDO mismatch stops playback, write what it takes to look into it
+//! later to one JSON file to attach to a bug report.  The bundle has the commands leading up to
+//! the failing check, the check's TDI, expected TDO, MASK and what was read, which bits differ,
+//! the state the TAP was left in and the settings in effect.
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use jtag_taps::statemachine::JtagState;
+use svf::Command;
+
+use crate::player::{hex, svf_state};
+
+/// How many commands before the failure a bundle has, unless --postmortem-commands says
+pub const DEFAULT_COMMANDS: usize = 20;
+
+pub struct Postmortem {
+    pub path: String,
+    keep: usize,
+    /// The latest commands, with their numbers in their file
+    recent: VecDeque<(usize, Command)>,
+}
+
+/// The check that failed, vectors as they were compared
+pub struct Failure<'a> {
+    pub number: usize,
+    pub tdi: Option<&'a [u8]>,
+    pub read: &'a [u8],
+    pub tdo: &'a [u8],
+    pub mask: &'a [u8],
+    pub state: JtagState,
+    pub phase: Option<&'a str>,
+    /// The settings a file would need to put the player where it was, see `Svf::prologue`
+    pub settings: String,
+}
+
+impl Postmortem {
+    pub fn new(path: String, keep: usize) -> Self {
+        Postmortem { path, keep, recent: VecDeque::new() }
+    }
+
+    /// Command `number` is about to be played
+    pub fn command(&mut self, number: usize, cmd: &Command) {
+        if self.recent.len() == self.keep.max(1) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((number, cmd.clone()));
+    }
+
+    /// The command playing
+    pub fn current(&self) -> Option<&Command> {
+        self.recent.back().map(|(_, cmd)| cmd)
+    }
+
+    pub fn bundle(&self, failure: &Failure) -> serde_json::Value {
+        let differs: Vec<u8> = failure.read.iter().zip(failure.tdo).zip(failure.mask)
+            .map(|((read, tdo), mask)| (read ^ tdo) & mask)
+            .collect();
+        let recent: Vec<_> = self.recent.iter()
+            .map(|(number, cmd)| serde_json::json!({ "number": number, "command": cmd.to_string() }))
+            .collect();
+        serde_json::json!({
+            "time": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "command": failure.number,
+            "phase": failure.phase,
+            "tap_state": svf_state(failure.state).to_string(),
+            "settings": failure.settings,
+            "scan": {
+                "tdi": failure.tdi.map(hex),
+                "tdo": hex(failure.tdo),
+                "mask": hex(failure.mask),
+                "read": hex(failure.read),
+                "differs": hex(&differs),
+            },
+            "recent_commands": recent,
+        })
+    }
+
+    pub fn write(&self, failure: &Failure) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(&self.bundle(failure)).unwrap();
+        std::fs::write(&self.path, text + "\n").map_err(|e| format!("{}: {}", self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_keep_the_latest_commands_and_the_failing_bits() {
+        let mut postmortem = Postmortem::new(String::new(), 2);
+        for (number, cmd) in svf::parse_iter("SIR 4 TDI (e);\nRUNTEST 10 TCK;\nSDR 8 TDI (00) TDO (5a);\n").enumerate() {
+            postmortem.command(number + 1, &cmd.unwrap());
+        }
+        assert!(matches!(postmortem.current(), Some(Command::SDR(_))));
+        let failure = Failure {
+            number: 3,
+            tdi: Some(&[0x00]),
+            read: &[0x5b],
+            tdo: &[0x5a],
+            mask: &[0xff],
+            state: JtagState::Idle,
+            phase: Some("verify"),
+            settings: String::new(),
+        };
+        let bundle = postmortem.bundle(&failure);
+        assert_eq!(bundle["scan"]["differs"], "01");
+        assert_eq!(bundle["tap_state"], "IDLE");
+        assert_eq!(bundle["recent_commands"].as_array().unwrap().len(), 2);
+        assert_eq!(bundle["recent_commands"][0]["number"], 2);
+    }
+}
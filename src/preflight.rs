@@ -0,0 +1,154 @@
+//! `--preflight`: read every file through once before the cable is opened, and refuse to start
+//! if any command is one the player would stop at, so an unsupported command fails fast rather
+//! than halfway through an erase.  The --max-shift-bits and --max-total-tck limits are checked
+//! in the same pass.
+use std::io::BufRead;
+
+use svf::{Command, ParseError, Pattern, RunClock, RunTestForm};
+
+use crate::bits::fits;
+use crate::compiled;
+use crate::lattice;
+use crate::limits::Limits;
+
+/// Why the player can't run `cmd`, if it can't
+pub fn unsupported(cmd: &Command) -> Option<String> {
+    match cmd {
+        Command::PIO(_) | Command::PIOMap(_) => Some("PIO isn't supported".into()),
+        Command::HIR(pattern) | Command::HDR(pattern) | Command::TIR(pattern) | Command::TDR(pattern)
+            if pattern.length != 0 => Some("headers and trailers other than 0 bits aren't supported".into()),
+        Command::RunTest { form: RunTestForm::Clocked { run_clk: RunClock::SCK, .. }, .. } => {
+            Some("RUNTEST on SCK isn't supported".into())
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Preflight {
+    pub limits: Option<Limits>,
+    /// Let through commands the player will skip under --ignore-unsupported
+    pub ignore_unsupported: bool,
+    /// Refuse what the player only tolerates outside --strict
+    pub strict: bool,
+    /// Length of the last SIR and SDR
+    sir: Option<u32>,
+    sdr: Option<u32>,
+}
+
+impl Preflight {
+    pub fn new(limits: Option<Limits>) -> Preflight {
+        Preflight { limits, ..Preflight::default() }
+    }
+
+    fn pattern(strict: bool, last: &mut Option<u32>, name: &str, pattern: &Pattern) -> Result<(), String> {
+        let vectors = [("TDI", &pattern.tdi), ("TDO", &pattern.tdo), ("MASK", &pattern.mask),
+                       ("SMASK", &pattern.smask)];
+        for (field, data) in vectors {
+            if strict && data.as_ref().is_some_and(|data| !fits(data, pattern.length)) {
+                return Err(format!("{} {} has more bits than the length {}", name, field, pattern.length));
+            }
+        }
+        if *last != Some(pattern.length) && pattern.tdi.is_none() && pattern.length != 0 {
+            return Err(format!("{} length changed to {} without a new TDI", name, pattern.length));
+        }
+        *last = Some(pattern.length);
+        Ok(())
+    }
+
+    fn command(&mut self, cmd: &Command) -> Result<(), String> {
+        if let Some(why) = unsupported(cmd).filter(|_| !self.ignore_unsupported) {
+            return Err(why);
+        }
+        match cmd {
+            Command::SIR(pattern) => Self::pattern(self.strict, &mut self.sir, "SIR", pattern)?,
+            Command::SDR(pattern) => Self::pattern(self.strict, &mut self.sdr, "SDR", pattern)?,
+            _ => (),
+        }
+        match &mut self.limits {
+            Some(limits) => limits.check(cmd),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a whole file, counting LOOP bodies as many times as they may run
+    pub fn check(&mut self, input: &mut impl BufRead) -> Result<(), String> {
+        let markers = lattice::Markers::default();
+        if compiled::is_compiled(input) {
+            let commands = compiled::Reader::new(input, markers.clone()).map_err(|e| e.to_string())?;
+            return self.check_commands(commands, markers);
+        }
+        let mut input = lattice::LoopFilter::new(input, markers.clone());
+        self.check_commands(svf::parse_iter_bufread(&mut input), markers)
+    }
+
+    fn check_commands(&mut self, commands: impl Iterator<Item = Result<Command, ParseError>>,
+                      markers: lattice::Markers) -> Result<(), String> {
+        // Clocks counted when each open LOOP started, and its count
+        let mut loops: Vec<(u64, u32)> = vec![];
+        let mut commands = commands.enumerate();
+        loop {
+            let next = commands.next();
+            let i = next.as_ref().map_or(usize::MAX, |(i, _)| *i);
+            while markers.borrow().front().is_some_and(|m| m.before() <= i) {
+                let tck = self.limits.as_ref().map_or(0, Limits::tck);
+                match markers.borrow_mut().pop_front().unwrap() {
+                    lattice::Marker::Loop { .. } if self.strict => {
+                        return Err("LOOP is a Lattice extension, not part of the SVF specification".into());
+                    }
+                    lattice::Marker::Loop { .. } if !loops.is_empty() => return Err("LOOP can't be nested".into()),
+                    lattice::Marker::Loop { count, .. } => loops.push((tck, count)),
+                    lattice::Marker::EndLoop { .. } => {
+                        let (start, count) = loops.pop().ok_or("ENDLOOP without LOOP")?;
+                        if let Some(limits) = &mut self.limits {
+                            limits.add((tck - start).saturating_mul(count.saturating_sub(1) as u64))?;
+                        }
+                    }
+                    lattice::Marker::Phase { .. } => (),
+                }
+            }
+            let Some((i, cmd)) = next else {
+                break;
+            };
+            let cmd = cmd.map_err(|e| e.to_string())?;
+            self.command(&cmd).map_err(|e| format!("command {} ({}): {}", i + 1, cmd, e))?;
+        }
+        if !loops.is_empty() {
+            return Err("LOOP without ENDLOOP".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(text: &str, limits: Option<Limits>) -> Result<Preflight, String> {
+        let mut preflight = Preflight::new(limits);
+        preflight.check(&mut text.as_bytes()).map(|()| preflight)
+    }
+
+    #[test]
+    fn whole_file_is_checked_before_playing() {
+        let text = "FREQUENCY 1E6 HZ;\nSDR 8 TDI (00);\nLOOP 10;\nRUNTEST 1E-3 SEC;\nENDLOOP;\n";
+        let preflight = check(text, Some(Limits::new(Some(8), None))).unwrap();
+        assert_eq!(preflight.limits.unwrap().tck(), 8 + 10 * 1000);
+        assert!(check(text, Some(Limits::new(None, Some(10_000)))).is_err());
+        assert!(check(text, Some(Limits::new(Some(7), None))).is_err());
+
+        assert!(check("SIR 4 TDI (1);\nSDR 8 TDO (00);\n", None).unwrap_err().contains("without a new TDI"));
+        assert!(check("SDR 8 TDI (00);\nRUNTEST 10 SCK;\n", None).unwrap_err().starts_with("command 2"));
+        assert!(check("HIR 0;\nSDR 8 TDI (00);\nSDR 8 TDO (ff);\n", None).is_ok());
+        assert!(check("LOOP 2;\nSDR 8 TDI (00);\n", None).is_err());
+    }
+
+    #[test]
+    fn strict_refuses_what_is_otherwise_tolerated() {
+        let strict = |text: &str| Preflight { strict: true, ..Preflight::default() }.check(&mut text.as_bytes());
+        assert!(check("SIR 4 TDI (1f);\n", None).is_ok());
+        assert!(strict("SIR 4 TDI (1f);\n").unwrap_err().contains("more bits than the length"));
+        assert!(check("LOOP 2;\nSDR 8 TDI (00);\nENDLOOP;\n", None).is_ok());
+        assert!(strict("LOOP 2;\nSDR 8 TDI (00);\nENDLOOP;\n").unwrap_err().contains("Lattice"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Per-command profiling of SVF playback.  A `ProfilingCable` sits between the state machine and
+//! the real adapter and counts every call into it, which is a good approximation of USB round
+//! trips for the adapters supported by jtag_taps.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use jtag_taps::cable::Cable;
+use svf::Command;
+
+use crate::cable::{Adapter, Capabilities};
+
+#[derive(Clone, Copy, Default)]
+pub struct CableStats {
+    pub round_trips: u64,
+    pub tms_clocks: u64,
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+}
+
+impl CableStats {
+    fn since(&self, earlier: &CableStats) -> CableStats {
+        CableStats {
+            round_trips: self.round_trips - earlier.round_trips,
+            tms_clocks: self.tms_clocks - earlier.tms_clocks,
+            bytes_out: self.bytes_out - earlier.bytes_out,
+            bytes_in: self.bytes_in - earlier.bytes_in,
+        }
+    }
+
+    fn add(&mut self, other: &CableStats) {
+        self.round_trips += other.round_trips;
+        self.tms_clocks += other.tms_clocks;
+        self.bytes_out += other.bytes_out;
+        self.bytes_in += other.bytes_in;
+    }
+}
+
+pub struct ProfilingCable {
+    inner: Box<dyn Adapter>,
+    stats: Rc<RefCell<CableStats>>,
+}
+
+impl ProfilingCable {
+    pub fn new(inner: Box<dyn Adapter>, stats: Rc<RefCell<CableStats>>) -> Self {
+        ProfilingCable { inner, stats }
+    }
+}
+
+impl Cable for ProfilingCable {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        {
+            let mut stats = self.stats.borrow_mut();
+            stats.round_trips += 1;
+            stats.tms_clocks += tms.len() as u64;
+        }
+        self.inner.change_mode(tms, tdo)
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        let data = self.inner.read_data(bits);
+        let mut stats = self.stats.borrow_mut();
+        stats.round_trips += 1;
+        stats.bytes_in += data.len() as u64;
+        data
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        {
+            let mut stats = self.stats.borrow_mut();
+            stats.round_trips += 1;
+            stats.bytes_out += data.len() as u64;
+            if pause_after {
+                stats.tms_clocks += 2;
+            }
+        }
+        self.inner.write_data(data, bits, pause_after)
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        let read = self.inner.read_write_data(data, bits, pause_after);
+        let mut stats = self.stats.borrow_mut();
+        stats.round_trips += 1;
+        stats.bytes_out += data.len() as u64;
+        stats.bytes_in += read.len() as u64;
+        if pause_after {
+            stats.tms_clocks += 2;
+        }
+        read
+    }
+}
+
+impl Adapter for ProfilingCable {
+    fn capabilities(&mut self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.inner.set_frequency(hz)
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.inner.set_trst(asserted)
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.inner.set_srst(asserted)
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    count: u64,
+    time: Duration,
+    cable: CableStats,
+}
+
+pub struct Profiler {
+    stats: Rc<RefCell<CableStats>>,
+    entries: BTreeMap<&'static str, Entry>,
+    started: Option<(&'static str, Instant, CableStats)>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            stats: Rc::new(RefCell::new(CableStats::default())),
+            entries: BTreeMap::new(),
+            started: None,
+        }
+    }
+
+    /// Wrap `cable` so that its traffic is attributed to the command currently being profiled
+    pub fn wrap(&self, cable: Box<dyn Adapter>) -> Box<dyn Adapter> {
+        Box::new(ProfilingCable::new(cable, self.stats.clone()))
+    }
+
+    pub fn kind(cmd: &Command) -> &'static str {
+        match cmd {
+            Command::EndDR(_) => "ENDDR",
+            Command::EndIR(_) => "ENDIR",
+            Command::Frequency(_) => "FREQUENCY",
+            Command::HDR(_) => "HDR",
+            Command::HIR(_) => "HIR",
+            Command::PIO(_) => "PIO",
+            Command::PIOMap(_) => "PIOMAP",
+            Command::RunTest { .. } => "RUNTEST",
+            Command::SDR(_) => "SDR",
+            Command::SIR(_) => "SIR",
+            Command::State { .. } => "STATE",
+            Command::TDR(_) => "TDR",
+            Command::TIR(_) => "TIR",
+            Command::TRST(_) => "TRST",
+        }
+    }
+
+    pub fn begin(&mut self, cmd: &Command) {
+        self.started = Some((Self::kind(cmd), Instant::now(), *self.stats.borrow()));
+    }
+
+    pub fn end(&mut self) {
+        if let Some((kind, start, before)) = self.started.take() {
+            let entry = self.entries.entry(kind).or_default();
+            entry.count += 1;
+            entry.time += start.elapsed();
+            entry.cable.add(&self.stats.borrow().since(&before));
+        }
+    }
+
+    pub fn report(&self) {
+        eprintln!("{:<10} {:>10} {:>12} {:>12} {:>12} {:>12} {:>12}",
+                  "command", "count", "time (ms)", "round trips", "TMS clocks", "bytes out", "bytes in");
+        let mut total = Entry::default();
+        for (kind, entry) in &self.entries {
+            Self::report_line(kind, entry);
+            total.count += entry.count;
+            total.time += entry.time;
+            total.cable.add(&entry.cable);
+        }
+        Self::report_line("total", &total);
+    }
+
+    fn report_line(kind: &str, entry: &Entry) {
+        eprintln!("{:<10} {:>10} {:>12.3} {:>12} {:>12} {:>12} {:>12}",
+                  kind, entry.count, entry.time.as_secs_f64() * 1000.0, entry.cable.round_trips,
+                  entry.cable.tms_clocks, entry.cable.bytes_out, entry.cable.bytes_in);
+    }
+}
@@ -0,0 +1,71 @@
+//! Python module built from the crate in cdylib/ with `--features pyo3` (e.g. by
+//! `maturin build -m cdylib/Cargo.toml --features pyo3`), so test scripts can play SVF in-process:
+//!
+//! ```python
+//! import svfplayer
+//! svfplayer.play_file("board.svf", "jtagkey", 1000000, on_tdo=lambda kind, tdo: print(kind, tdo.hex()))
+//! print(svfplayer.scan_chain("jtagkey", 1000000))
+//! ```
+//!
+//! Playback errors, TDO mismatches included, raise `RuntimeError`.
+use std::panic::AssertUnwindSafe;
+
+use jtag_taps::statemachine::JtagSM;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::cable::{self, AdapterBox};
+use crate::config::LogLevel;
+use crate::observer::Observer;
+use crate::{chain, panic_message, run_svf, Svf};
+
+/// Hands every TDO read back to a Python callable
+struct Callback(Py<PyAny>);
+
+impl Observer for Callback {
+    fn on_tdo(&mut self, kind: &'static str, tdo: &[u8]) {
+        Python::attach(|py| {
+            // Least significant byte first, so int.from_bytes(tdo, "little") gives the SVF value
+            if let Err(e) = self.0.call1(py, (kind, PyBytes::new(py, tdo))) {
+                panic!("on_tdo callback failed: {}", e);
+            }
+        });
+    }
+}
+
+fn open(cable: &str, baud: u32) -> PyResult<JtagSM<AdapterBox>> {
+    let adapter = cable::open(cable, baud).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(JtagSM::new(AdapterBox(adapter)))
+}
+
+/// Play the SVF file at `path` on `cable`, optionally calling `on_tdo(kind, bytes)` for every scan
+#[pyfunction]
+#[pyo3(signature = (path, cable, baud, on_tdo = None))]
+fn play_file(path: &str, cable: &str, baud: u32, on_tdo: Option<Py<PyAny>>) -> PyResult<()> {
+    let mut input = crate::input::open(path).map_err(|e| PyRuntimeError::new_err(format!("{}: {}", path, e)))?;
+    let mut jtag = open(cable, baud)?;
+    let mut svf = Svf::new();
+    // The script has the callbacks, so don't echo every command to its stdout too
+    svf.log_level = LogLevel::Warn;
+    svf.observer = on_tdo.map(|f| Box::new(Callback(f)) as Box<dyn Observer>);
+    std::panic::catch_unwind(AssertUnwindSafe(|| run_svf(&mut jtag, &mut svf, &mut input, None)))
+        .map_err(|e| panic_message(&*e))
+        .and_then(|result| result.map_err(|e| e.to_string()))
+        .map_err(PyRuntimeError::new_err)
+}
+
+/// IDCODEs of the devices on `cable`'s chain, nearest TDO first, with None for BYPASS-only ones
+#[pyfunction]
+fn scan_chain(cable: &str, baud: u32) -> PyResult<Vec<Option<u32>>> {
+    let mut jtag = open(cable, baud)?;
+    std::panic::catch_unwind(AssertUnwindSafe(|| chain::idcodes(&mut jtag)))
+        .map_err(|e| PyRuntimeError::new_err(panic_message(&*e)))
+}
+
+#[pymodule]
+fn svfplayer(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(play_file, m)?)?;
+    m.add_function(wrap_pyfunction!(scan_chain, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Interactive prompt started by `svfplayer repl`.  SVF commands typed at the prompt run on the
+//! cable as soon as their terminating `;` is entered, and the TDO of every scan is printed.  A
+//! TDO mismatch is reported and the session carries on.
+use std::io::{BufRead, Write};
+use std::panic::AssertUnwindSafe;
+
+use jtag_taps::statemachine::JtagSM;
+
+use crate::cable::AdapterBox;
+use crate::{panic_message, Svf};
+
+pub fn run(jtag: &mut JtagSM<AdapterBox>, svf: &mut Svf) {
+    svf.echo_tdo = true;
+    // Errors are printed at the prompt, without the panic location and backtrace note
+    std::panic::set_hook(Box::new(|_| {}));
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut pending = String::new();
+    loop {
+        print!("{}", if pending.is_empty() { "svf> " } else { "...> " });
+        let _ = std::io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        if pending.is_empty() && matches!(line.trim(), "quit" | "exit") {
+            break;
+        }
+        pending.push_str(&line);
+        pending.push('\n');
+        if !line.contains(';') {
+            continue;
+        }
+
+        // Anything after the last ';' belongs to a command that isn't finished yet
+        let split = pending.rfind(';').unwrap() + 1;
+        let rest = pending.split_off(split);
+        for cmd in svf::parse_iter(&pending) {
+            let cmd = match cmd {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    break;
+                }
+            };
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| svf.run_command(cmd, jtag)));
+            if let Err(e) = result {
+                eprintln!("error: {}", panic_message(&*e));
+            }
+        }
+        pending = rest.trim_start().to_string();
+    }
+}
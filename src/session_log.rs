@@ -0,0 +1,26 @@
+//! `--log-file`: one timestamped line per command, retry and mismatch, kept apart from the
+//! console output so it can be filed with the board's records.
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::time::SystemTime;
+
+pub struct SessionLog {
+    file: File,
+}
+
+impl SessionLog {
+    /// Append to the log at `path`, so several runs on the same target share one file
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(SessionLog { file })
+    }
+
+    pub fn entry(&mut self, message: impl Display) {
+        let time = humantime::format_rfc3339_micros(SystemTime::now());
+        // Long scans are wrapped over several lines, which would split the entry
+        let message = message.to_string().split_whitespace().collect::<Vec<_>>().join(" ");
+        // The log is a record, not the playback, so a full disk shouldn't stop the run
+        let _ = writeln!(self.file, "{} {}", time, message);
+    }
+}
@@ -0,0 +1,1071 @@
+//! Player for STAPL (JESD71, Altera's Jam) source files, run with `svfplayer stapl --action NAME
+//! FILE.jam`.  Scans go through the same cable path as SVF.  Covered are the statements CPLD
+//! programming files mostly use: ACTION, PROCEDURE and CALL, BOOLEAN and INTEGER variables and
+//! arrays, LET, IF, FOR/NEXT, GOTO, IRSCAN/DRSCAN with CAPTURE and COMPARE, PREIR/PREDR/POSTIR/
+//! POSTDR, IRSTOP/DRSTOP, STATE, WAIT, FREQUENCY, PRINT, EXPORT and EXIT.  The byte-code form
+//! (.jbc) and compressed `@` array data are not supported.
+use std::collections::HashMap;
+
+use jtag_taps::statemachine::{JtagSM, JtagState, Register};
+
+use crate::cable::{bit, pack, AdapterBox};
+use crate::{clock, state_named, Svf};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(i64),
+    Str(String),
+    Bits(Vec<bool>),
+    Sym(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "..", "(", ")", "[",
+                           "]", ",", "=", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "!",
+                           "~", ":", ";"];
+
+/// Split `text` into statements of tokens, each with the line it starts on
+fn lex(text: &str) -> Result<Vec<(usize, Vec<Tok>)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut statements = vec![];
+    let mut current = vec![];
+    let mut start_line = 1;
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if current.is_empty() {
+            start_line = line;
+        }
+        if c == '\'' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '"' {
+            let end = chars[i + 1..].iter().position(|c| *c == '"')
+                .ok_or(format!("line {}: unterminated string", line))?;
+            current.push(Tok::Str(chars[i + 1..i + 1 + end].iter().collect()));
+            i += end + 2;
+            continue;
+        }
+        if c == '#' || c == '$' {
+            let digits: String = chars[i + 1..].iter()
+                .take_while(|c| c.is_ascii_hexdigit())
+                .collect();
+            i += 1 + digits.len();
+            // The rightmost digit holds element 0
+            let mut bits = vec![];
+            for d in digits.chars().rev() {
+                let d = d.to_digit(16).unwrap();
+                match c {
+                    '#' if d <= 1 => bits.push(d == 1),
+                    '#' => return Err(format!("line {}: {} in a binary literal", line, d)),
+                    _ => bits.extend((0..4).map(|b| d & (1 << b) != 0)),
+                }
+            }
+            current.push(Tok::Bits(bits));
+            continue;
+        }
+        if c == '@' {
+            return Err(format!("line {}: compressed array data isn't supported", line));
+        }
+        if c.is_ascii_digit() {
+            let digits: String = chars[i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+            i += digits.len();
+            current.push(Tok::Num(digits.parse().map_err(|_| format!("line {}: number too large", line))?));
+            continue;
+        }
+        if c.is_alphabetic() || c == '_' {
+            let word: String = chars[i..].iter()
+                .take_while(|c| c.is_alphanumeric() || **c == '_' || **c == '$')
+                .collect();
+            i += word.chars().count();
+            current.push(Tok::Ident(word.to_uppercase()));
+            continue;
+        }
+        let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+        let sym = SYMBOLS.iter().find(|s| rest.starts_with(**s))
+            .ok_or(format!("line {}: unexpected {}", line, c))?;
+        i += sym.len();
+        if *sym == ";" {
+            statements.push((start_line, std::mem::take(&mut current)));
+        } else {
+            current.push(Tok::Sym(sym));
+        }
+    }
+    if !current.is_empty() {
+        return Err(format!("line {}: statement is missing its terminating ';'", start_line));
+    }
+    Ok(statements)
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(i64),
+    Bits(Vec<bool>),
+    Var(String),
+    Index(String, Box<Expr>),
+    /// `name[from..to]`
+    Slice(String, Box<Expr>, Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Int(i64),
+    Bits(Vec<bool>),
+}
+
+impl Value {
+    fn int(&self) -> Result<i64, String> {
+        match self {
+            Value::Int(i) => Ok(*i),
+            Value::Bits(b) if b.len() == 1 => Ok(b[0] as i64),
+            Value::Bits(_) => Err("expected a number, got an array".into()),
+        }
+    }
+
+    /// The value as `length` bits, element 0 first
+    fn bits(&self, length: usize) -> Vec<bool> {
+        let mut bits = match self {
+            Value::Int(i) => (0..64).map(|b| i & (1 << b) != 0).collect(),
+            Value::Bits(b) => b.clone(),
+        };
+        bits.resize(length, false);
+        bits
+    }
+}
+
+const BINARY: &[&[&str]] = &[&["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="],
+                             &["<", "<=", ">", ">="], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser<'a> {
+    toks: &'a [Tok],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(toks: &'a [Tok]) -> Self {
+        Parser { toks, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Tok, String> {
+        let tok = self.peek().cloned().ok_or("statement ends too early")?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.toks.len()
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        if self.peek() == Some(&Tok::Sym(SYMBOLS.iter().find(|s| **s == sym).unwrap())) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<(), String> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            Err(format!("expected {}", sym))
+        }
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Ident(w)) if w == word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Tok::Ident(name) => Ok(name),
+            tok => Err(format!("expected a name, got {:?}", tok)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Tok::Sym(op)) = self.peek() {
+            let Some(op) = BINARY[level].iter().find(|o| *o == op) else {
+                break;
+            };
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ["-", "!", "~"] {
+            if self.eat(op) {
+                let op = SYMBOLS.iter().find(|s| **s == op).unwrap();
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next()? {
+            Tok::Num(n) => Ok(Expr::Num(n)),
+            Tok::Bits(b) => Ok(Expr::Bits(b)),
+            Tok::Sym("(") => {
+                let e = self.expr()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Tok::Ident(name) => {
+                if self.eat("(") {
+                    let arg = self.expr()?;
+                    self.expect(")")?;
+                    return Ok(Expr::Call(name, Box::new(arg)));
+                }
+                if !self.eat("[") {
+                    return Ok(Expr::Var(name));
+                }
+                let index = self.expr()?;
+                let e = if self.eat("..") {
+                    Expr::Slice(name, Box::new(index), Box::new(self.expr()?))
+                } else {
+                    Expr::Index(name, Box::new(index))
+                };
+                self.expect("]")?;
+                Ok(e)
+            }
+            tok => Err(format!("unexpected {:?}", tok)),
+        }
+    }
+
+    fn state(&mut self) -> Result<JtagState, String> {
+        let name = self.ident()?;
+        state_named(&name).map(Svf::to_jtag_state).ok_or(format!("unknown state {}", name))
+    }
+}
+
+/// Where an assignment or CAPTURE stores its value
+#[derive(Clone, Debug)]
+struct Target {
+    name: String,
+    index: Option<Expr>,
+    slice: Option<(Expr, Expr)>,
+}
+
+fn target(p: &mut Parser) -> Result<Target, String> {
+    match p.primary()? {
+        Expr::Var(name) => Ok(Target { name, index: None, slice: None }),
+        Expr::Index(name, i) => Ok(Target { name, index: Some(*i), slice: None }),
+        Expr::Slice(name, a, b) => Ok(Target { name, index: None, slice: Some((*a, *b)) }),
+        e => Err(format!("can't assign to {:?}", e)),
+    }
+}
+
+#[derive(Clone)]
+struct Scan {
+    reg: Register,
+    length: Expr,
+    data: Expr,
+    capture: Option<Target>,
+    /// Expected data, mask and the BOOLEAN that records whether they matched
+    compare: Option<(Expr, Expr, Target)>,
+}
+
+#[derive(Clone)]
+enum Stmt {
+    Nothing,
+    Label(String),
+    Action(String, Vec<(String, bool)>),
+    Procedure(String),
+    EndProc,
+    Declare { name: String, boolean: bool, size: Option<Expr>, init: Vec<Expr> },
+    Let(Target, Expr),
+    If(Expr, Box<Stmt>),
+    For { var: String, from: Expr, to: Expr, step: Expr },
+    Next(String),
+    Goto(String),
+    Call(String),
+    Exit(Expr),
+    Print(Vec<Expr>, Vec<String>),
+    Export(String, Expr),
+    Scan(Box<Scan>),
+    /// PREIR, PREDR, POSTIR or POSTDR
+    Pad { pre: bool, reg: Register, length: Expr, data: Option<Expr> },
+    Stop(Register, JtagState),
+    State(JtagState),
+    Wait { state: JtagState, cycles: Option<Expr>, usec: Option<Expr>, end: JtagState },
+    Frequency(Expr),
+}
+
+/// Split the rest of the statement on commas
+fn list(p: &mut Parser) -> Result<Vec<Expr>, String> {
+    let mut items = vec![p.expr()?];
+    while p.eat(",") {
+        items.push(p.expr()?);
+    }
+    Ok(items)
+}
+
+fn scan(p: &mut Parser, reg: Register) -> Result<Stmt, String> {
+    let length = p.expr()?;
+    p.expect(",")?;
+    let data = p.expr()?;
+    let mut scan = Scan { reg, length, data, capture: None, compare: None };
+    while p.eat(",") {
+        if p.keyword("CAPTURE") {
+            scan.capture = Some(target(p)?);
+        } else if p.keyword("COMPARE") {
+            let expected = p.expr()?;
+            p.expect(",")?;
+            let mask = p.expr()?;
+            p.expect(",")?;
+            scan.compare = Some((expected, mask, target(p)?));
+        } else {
+            return Err("expected CAPTURE or COMPARE".into());
+        }
+    }
+    Ok(Stmt::Scan(Box::new(scan)))
+}
+
+fn statement(p: &mut Parser) -> Result<Stmt, String> {
+    if let [Tok::Ident(label), Tok::Sym(":"), ..] = &p.toks[p.pos..] {
+        p.pos += 2;
+        return Ok(Stmt::Label(label.clone()));
+    }
+    let keyword = p.ident()?;
+    let stmt = match keyword.as_str() {
+        "NOTE" | "CRC" | "DATA" | "ENDDATA" => {
+            p.pos = p.toks.len();
+            Stmt::Nothing
+        }
+        "ACTION" => {
+            let name = p.ident()?;
+            if let Some(Tok::Str(_)) = p.peek() {
+                p.pos += 1;
+            }
+            p.expect("=")?;
+            let mut procedures = vec![];
+            loop {
+                let procedure = p.ident()?;
+                let optional = p.keyword("OPTIONAL");
+                p.keyword("RECOMMENDED");
+                procedures.push((procedure, optional));
+                if !p.eat(",") {
+                    break;
+                }
+            }
+            Stmt::Action(name, procedures)
+        }
+        "PROCEDURE" => {
+            let name = p.ident()?;
+            // USES only matters to players that load DATA blocks lazily
+            p.pos = p.toks.len();
+            Stmt::Procedure(name)
+        }
+        "ENDPROC" => Stmt::EndProc,
+        "BOOLEAN" | "INTEGER" => {
+            let name = p.ident()?;
+            let size = if p.eat("[") {
+                let size = p.expr()?;
+                p.expect("]")?;
+                Some(size)
+            } else {
+                None
+            };
+            let init = if p.eat("=") { list(p)? } else { vec![] };
+            Stmt::Declare { name, boolean: keyword == "BOOLEAN", size, init }
+        }
+        "LET" => {
+            let target = target(p)?;
+            p.expect("=")?;
+            Stmt::Let(target, p.expr()?)
+        }
+        "IF" => {
+            let cond = p.expr()?;
+            if !p.keyword("THEN") {
+                return Err("expected THEN".into());
+            }
+            Stmt::If(cond, Box::new(statement(p)?))
+        }
+        "FOR" => {
+            let var = p.ident()?;
+            p.expect("=")?;
+            let from = p.expr()?;
+            if !p.keyword("TO") {
+                return Err("expected TO".into());
+            }
+            let to = p.expr()?;
+            let step = if p.keyword("STEP") { p.expr()? } else { Expr::Num(1) };
+            Stmt::For { var, from, to, step }
+        }
+        "NEXT" => Stmt::Next(p.ident()?),
+        "GOTO" => Stmt::Goto(p.ident()?),
+        "CALL" => Stmt::Call(p.ident()?),
+        "EXIT" => Stmt::Exit(p.expr()?),
+        "PRINT" | "EXPORT" => {
+            let mut exprs = vec![];
+            let mut strings = vec![];
+            loop {
+                match p.peek() {
+                    Some(Tok::Str(s)) => {
+                        strings.push(s.clone());
+                        exprs.push(Expr::Num(0));
+                        p.pos += 1;
+                    }
+                    _ => {
+                        strings.push(String::new());
+                        exprs.push(p.expr()?);
+                    }
+                }
+                if !p.eat(",") {
+                    break;
+                }
+            }
+            if keyword == "EXPORT" {
+                if strings.len() != 2 || strings[0].is_empty() {
+                    return Err("expected EXPORT \"key\", value".into());
+                }
+                Stmt::Export(strings[0].clone(), exprs.pop().unwrap())
+            } else {
+                Stmt::Print(exprs, strings)
+            }
+        }
+        "IRSCAN" => scan(p, Register::Instruction)?,
+        "DRSCAN" => scan(p, Register::Data)?,
+        "PREIR" | "PREDR" | "POSTIR" | "POSTDR" => {
+            let length = p.expr()?;
+            let data = if p.eat(",") { Some(p.expr()?) } else { None };
+            let reg = if keyword.ends_with("IR") { Register::Instruction } else { Register::Data };
+            Stmt::Pad { pre: keyword.starts_with("PRE"), reg, length, data }
+        }
+        "IRSTOP" => Stmt::Stop(Register::Instruction, p.state()?),
+        "DRSTOP" => Stmt::Stop(Register::Data, p.state()?),
+        "STATE" => {
+            // jtag_taps finds its own way there, so only the last state of a path matters
+            let mut state = p.state()?;
+            while !p.done() {
+                state = p.state()?;
+            }
+            Stmt::State(state)
+        }
+        "WAIT" => {
+            let mut states = vec![];
+            let (mut cycles, mut usec) = (None, None);
+            loop {
+                if let Some(Tok::Ident(name)) = p.peek() {
+                    if let Some(state) = state_named(name) {
+                        p.pos += 1;
+                        states.push(Svf::to_jtag_state(state));
+                        if !p.eat(",") {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+                let n = p.expr()?;
+                if p.keyword("CYCLES") || p.keyword("CYCLE") {
+                    cycles = Some(n);
+                } else if p.keyword("USEC") {
+                    usec = Some(n);
+                } else {
+                    return Err("expected CYCLES or USEC".into());
+                }
+                if !p.eat(",") {
+                    break;
+                }
+            }
+            let state = states.first().copied().unwrap_or(JtagState::Idle);
+            Stmt::Wait { state, cycles, usec, end: states.get(1).copied().unwrap_or(state) }
+        }
+        "FREQUENCY" => Stmt::Frequency(p.expr()?),
+        _ => return Err(format!("unsupported statement {}", keyword)),
+    };
+    Ok(stmt)
+}
+
+#[derive(Clone, Debug)]
+enum Var {
+    Int(i64),
+    Ints(Vec<i64>),
+    Bool(bool),
+    Bools(Vec<bool>),
+}
+
+/// What to do after a statement
+enum Flow {
+    Next,
+    Jump(usize),
+    Return,
+    Exit(i64),
+}
+
+/// Padding added around every IR or DR scan
+#[derive(Default)]
+struct Padding {
+    pre: Vec<bool>,
+    post: Vec<bool>,
+}
+
+pub struct Program {
+    stmts: Vec<(usize, Stmt)>,
+    labels: HashMap<String, usize>,
+    procedures: HashMap<String, usize>,
+    /// Index of the NEXT closing each FOR
+    loops: HashMap<usize, usize>,
+    pub actions: Vec<(String, Vec<(String, bool)>)>,
+}
+
+pub fn parse(text: &str) -> Result<Program, String> {
+    let mut program = Program {
+        stmts: vec![],
+        labels: HashMap::new(),
+        procedures: HashMap::new(),
+        loops: HashMap::new(),
+        actions: vec![],
+    };
+    let mut open_loops = vec![];
+    for (line, toks) in lex(text)? {
+        let mut p = Parser::new(&toks);
+        // A label shares its statement with whatever follows it
+        while !p.done() {
+            let stmt = statement(&mut p).map_err(|e| format!("line {}: {}", line, e))?;
+            let index = program.stmts.len();
+            match &stmt {
+                Stmt::Label(label) => {
+                    program.labels.insert(label.clone(), index);
+                }
+                Stmt::Procedure(name) => {
+                    program.procedures.insert(name.clone(), index + 1);
+                }
+                Stmt::Action(name, procedures) => program.actions.push((name.clone(), procedures.clone())),
+                Stmt::For { .. } => open_loops.push(index),
+                Stmt::Next(_) => {
+                    let start = open_loops.pop().ok_or(format!("line {}: NEXT without FOR", line))?;
+                    program.loops.insert(start, index);
+                }
+                _ => (),
+            }
+            program.stmts.push((line, stmt));
+            if !p.done() && !matches!(program.stmts.last(), Some((_, Stmt::Label(_)))) {
+                return Err(format!("line {}: unexpected text after the statement", line));
+            }
+        }
+    }
+    Ok(program)
+}
+
+pub struct Interpreter<'a> {
+    program: &'a Program,
+    vars: HashMap<String, Var>,
+    ir: Padding,
+    dr: Padding,
+    irstop: JtagState,
+    drstop: JtagState,
+    /// Active FOR loops: variable, limit, step and the index of the FOR
+    fors: Vec<(String, i64, i64, usize)>,
+}
+
+/// `n` as an index, a size or a length, none of which can be negative
+fn size(n: i64) -> Result<usize, String> {
+    usize::try_from(n).map_err(|_| format!("{} is negative", n))
+}
+
+fn slice_range(from: i64, to: i64) -> Result<Vec<usize>, String> {
+    let (from, to) = (size(from)?, size(to)?);
+    // a[7..0] keeps the array's order, a[0..7] reverses it
+    Ok(if from >= to {
+        (to..=from).collect()
+    } else {
+        (from..=to).rev().collect()
+    })
+}
+
+fn overflow(a: i64, op: &str, b: i64) -> String {
+    format!("{} {} {} overflows", a, op, b)
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Interpreter {
+            program,
+            vars: HashMap::new(),
+            ir: Padding::default(),
+            dr: Padding::default(),
+            irstop: JtagState::Idle,
+            drstop: JtagState::Idle,
+            fors: vec![],
+        }
+    }
+
+    fn eval(&self, e: &Expr) -> Result<Value, String> {
+        Ok(match e {
+            Expr::Num(n) => Value::Int(*n),
+            Expr::Bits(b) => Value::Bits(b.clone()),
+            Expr::Var(name) => match self.vars.get(name).ok_or(format!("{} is not declared", name))? {
+                Var::Int(i) => Value::Int(*i),
+                Var::Bool(b) => Value::Int(*b as i64),
+                Var::Bools(b) => Value::Bits(b.clone()),
+                Var::Ints(_) => return Err(format!("{} is an INTEGER array", name)),
+            },
+            Expr::Index(name, i) => {
+                let i = size(self.eval(i)?.int()?)?;
+                match self.vars.get(name).ok_or(format!("{} is not declared", name))? {
+                    Var::Bools(b) => Value::Int(*b.get(i).ok_or(format!("{}[{}] is out of range", name, i))? as i64),
+                    Var::Ints(v) => Value::Int(*v.get(i).ok_or(format!("{}[{}] is out of range", name, i))?),
+                    _ => return Err(format!("{} is not an array", name)),
+                }
+            }
+            Expr::Slice(name, from, to) => {
+                let Some(Var::Bools(b)) = self.vars.get(name) else {
+                    return Err(format!("{} is not a BOOLEAN array", name));
+                };
+                let range = slice_range(self.eval(from)?.int()?, self.eval(to)?.int()?)?;
+                Value::Bits(range.into_iter()
+                    .map(|i| b.get(i).copied().ok_or(format!("{}[{}] is out of range", name, i)))
+                    .collect::<Result<_, _>>()?)
+            }
+            Expr::Unary(op, e) => match (*op, self.eval(e)?) {
+                ("-", v) => {
+                    let v = v.int()?;
+                    Value::Int(v.checked_neg().ok_or(format!("-({}) overflows", v))?)
+                }
+                ("!", v) => Value::Int((v.int()? == 0) as i64),
+                ("~", Value::Bits(b)) => Value::Bits(b.iter().map(|b| !b).collect()),
+                ("~", v) => Value::Int(!v.int()?),
+                _ => unreachable!(),
+            },
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.eval(a)?, self.eval(b)?);
+                if let (Value::Bits(x), Value::Bits(y)) = (&a, &b) {
+                    if x.len() > 1 || y.len() > 1 {
+                        let n = x.len().max(y.len());
+                        let (x, y) = (a.bits(n), b.bits(n));
+                        let zip = x.iter().zip(&y);
+                        return Ok(match *op {
+                            "&" => Value::Bits(zip.map(|(x, y)| x & y).collect()),
+                            "|" => Value::Bits(zip.map(|(x, y)| x | y).collect()),
+                            "^" => Value::Bits(zip.map(|(x, y)| x ^ y).collect()),
+                            "==" => Value::Int((x == y) as i64),
+                            "!=" => Value::Int((x != y) as i64),
+                            _ => return Err(format!("{} isn't defined on arrays", op)),
+                        });
+                    }
+                }
+                let (a, b) = (a.int()?, b.int()?);
+                Value::Int(match *op {
+                    "||" => (a != 0 || b != 0) as i64,
+                    "&&" => (a != 0 && b != 0) as i64,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "&" => a & b,
+                    "==" => (a == b) as i64,
+                    "!=" => (a != b) as i64,
+                    "<" => (a < b) as i64,
+                    "<=" => (a <= b) as i64,
+                    ">" => (a > b) as i64,
+                    ">=" => (a >= b) as i64,
+                    "<<" | ">>" if !(0..64).contains(&b) => return Err(format!("can't shift by {}", b)),
+                    "<<" => a << b,
+                    ">>" => a >> b,
+                    "/" | "%" if b == 0 => return Err("division by zero".into()),
+                    "+" => a.checked_add(b).ok_or(overflow(a, op, b))?,
+                    "-" => a.checked_sub(b).ok_or(overflow(a, op, b))?,
+                    "*" => a.checked_mul(b).ok_or(overflow(a, op, b))?,
+                    "/" => a.checked_div(b).ok_or(overflow(a, op, b))?,
+                    "%" => a.checked_rem(b).ok_or(overflow(a, op, b))?,
+                    _ => unreachable!(),
+                })
+            }
+            Expr::Call(f, arg) => {
+                let x = self.eval(arg)?.int()?;
+                Value::Int(match f.as_str() {
+                    "ABS" => x.checked_abs().ok_or(format!("ABS({}) overflows", x))?,
+                    "INT" | "CEIL" | "FLOOR" => x,
+                    "LOG2" if x > 0 => (x as u64).next_power_of_two().trailing_zeros() as i64,
+                    "SQRT" if x >= 0 => (x as f64).sqrt() as i64,
+                    _ => return Err(format!("can't evaluate {}({})", f, x)),
+                })
+            }
+        })
+    }
+
+    fn assign(&mut self, target: &Target, value: Value) -> Result<(), String> {
+        let index = target.index.as_ref().map(|i| self.eval(i)).transpose()?.map(|v| v.int()).transpose()?;
+        let index = index.map(size).transpose()?;
+        let range = match &target.slice {
+            Some((from, to)) => Some(slice_range(self.eval(from)?.int()?, self.eval(to)?.int()?)?),
+            None => None,
+        };
+        let var = self.vars.get_mut(&target.name).ok_or(format!("{} is not declared", target.name))?;
+        let out_of_range = |i: usize| format!("{}[{}] is out of range", target.name, i);
+        match (var, index, range) {
+            (Var::Int(i), None, None) => *i = value.int()?,
+            (Var::Bool(b), None, None) => *b = value.int()? != 0,
+            (Var::Bools(b), None, None) => *b = value.bits(b.len()),
+            (Var::Bools(b), Some(i), None) => *b.get_mut(i).ok_or(out_of_range(i))? = value.int()? != 0,
+            (Var::Ints(v), Some(i), None) => *v.get_mut(i).ok_or(out_of_range(i))? = value.int()?,
+            (Var::Bools(b), None, Some(range)) => {
+                for (bit, i) in value.bits(range.len()).into_iter().zip(range) {
+                    *b.get_mut(i).ok_or(out_of_range(i))? = bit;
+                }
+            }
+            _ => return Err(format!("can't assign to {}", target.name)),
+        }
+        Ok(())
+    }
+
+    fn declare(&mut self, name: &str, boolean: bool, size: &Option<Expr>, init: &[Expr]) -> Result<(), String> {
+        let size = size.as_ref().map(|s| self.eval(s)).transpose()?.map(|v| v.int()).transpose()?;
+        let size = size.map(self::size).transpose()?;
+        let values = init.iter().map(|e| self.eval(e)).collect::<Result<Vec<_>, _>>()?;
+        let var = match (boolean, size) {
+            (true, None) => Var::Bool(values.first().map(|v| v.int()).transpose()?.unwrap_or(0) != 0),
+            (false, None) => Var::Int(values.first().map(|v| v.int()).transpose()?.unwrap_or(0)),
+            (true, Some(n)) => Var::Bools(match values.as_slice() {
+                [] => vec![false; n],
+                [v] => v.bits(n),
+                _ => values.iter().map(|v| v.int().map(|i| i != 0)).collect::<Result<_, _>>()?,
+            }),
+            (false, Some(n)) => {
+                let mut ints = values.iter().map(|v| v.int()).collect::<Result<Vec<_>, _>>()?;
+                ints.resize(n, 0);
+                Var::Ints(ints)
+            }
+        };
+        self.vars.insert(name.to_string(), var);
+        Ok(())
+    }
+
+    fn scan(&mut self, scan: &Scan, sm: &mut JtagSM<AdapterBox>, svf: &mut Svf) -> Result<(), String> {
+        let length = size(self.eval(&scan.length)?.int()?)?;
+        let data = self.eval(&scan.data)?.bits(length);
+        let (padding, end) = match scan.reg {
+            Register::Instruction => (&self.ir, self.irstop),
+            Register::Data => (&self.dr, self.drstop),
+        };
+        let offset = padding.pre.len();
+        let mut tdi = padding.pre.clone();
+        tdi.extend(&data);
+        tdi.extend(&padding.post);
+        let read = svf.scan(sm, scan.reg, pack(&tdi), tdi.len() as u32, end);
+        let captured: Vec<bool> = (offset..offset + length).map(|i| bit(&read, i)).collect();
+        if let Some(target) = &scan.capture {
+            self.assign(target, Value::Bits(captured.clone()))?;
+        }
+        if let Some((expected, mask, result)) = &scan.compare {
+            let expected = self.eval(expected)?.bits(length);
+            let mask = self.eval(mask)?.bits(length);
+            let matched = (0..length).all(|i| !mask[i] || captured[i] == expected[i]);
+            self.assign(result, Value::Int(matched as i64))?;
+        }
+        Ok(())
+    }
+
+    fn exec(&mut self, index: usize, stmt: &Stmt, sm: &mut JtagSM<AdapterBox>, svf: &mut Svf)
+            -> Result<Flow, String> {
+        match stmt {
+            Stmt::Nothing | Stmt::Label(_) | Stmt::Action(..) | Stmt::Procedure(_) => (),
+            Stmt::EndProc => return Ok(Flow::Return),
+            Stmt::Declare { name, boolean, size, init } => self.declare(name, *boolean, size, init)?,
+            Stmt::Let(target, e) => {
+                let value = self.eval(e)?;
+                self.assign(target, value)?;
+            }
+            Stmt::If(cond, stmt) => {
+                if self.eval(cond)?.int()? != 0 {
+                    return self.exec(index, stmt, sm, svf);
+                }
+            }
+            Stmt::For { var, from, to, step } => {
+                let (from, to, step) = (self.eval(from)?.int()?, self.eval(to)?.int()?, self.eval(step)?.int()?);
+                self.assign(&Target { name: var.clone(), index: None, slice: None }, Value::Int(from))?;
+                if (step > 0 && from > to) || (step < 0 && from < to) {
+                    return Ok(Flow::Jump(self.program.loops[&index] + 1));
+                }
+                self.fors.push((var.clone(), to, step, index));
+            }
+            Stmt::Next(var) => {
+                let Some((name, to, step, start)) = self.fors.last().cloned() else {
+                    return Err("NEXT without FOR".into());
+                };
+                if name != *var {
+                    return Err(format!("NEXT {} closes FOR {}", var, name));
+                }
+                let target = Target { name, index: None, slice: None };
+                let i = self.eval(&Expr::Var(var.clone()))?.int()?;
+                let i = i.checked_add(step).ok_or(overflow(i, "+", step))?;
+                self.assign(&target, Value::Int(i))?;
+                if (step > 0 && i <= to) || (step < 0 && i >= to) {
+                    return Ok(Flow::Jump(start + 1));
+                }
+                self.fors.pop();
+            }
+            Stmt::Goto(label) => {
+                return Ok(Flow::Jump(*self.program.labels.get(label).ok_or(format!("no label {}", label))?));
+            }
+            Stmt::Call(procedure) => {
+                if let Flow::Exit(code) = self.call(procedure, sm, svf)? {
+                    return Ok(Flow::Exit(code));
+                }
+            }
+            Stmt::Exit(code) => return Ok(Flow::Exit(self.eval(code)?.int()?)),
+            Stmt::Print(exprs, strings) => {
+                let mut line = String::new();
+                for (e, s) in exprs.iter().zip(strings) {
+                    if !s.is_empty() {
+                        line.push_str(s);
+                        continue;
+                    }
+                    match self.eval(e)? {
+                        Value::Int(i) => line.push_str(&i.to_string()),
+                        Value::Bits(b) => line.extend(b.iter().rev().map(|b| if *b { '1' } else { '0' })),
+                    }
+                }
+                println!("{}", line);
+            }
+            Stmt::Export(key, e) => match self.eval(e)? {
+                Value::Int(i) => println!("Export {} = {}", key, i),
+                Value::Bits(b) => println!("Export {} = {}", key, crate::hex(&pack(&b))),
+            },
+            Stmt::Scan(scan) => self.scan(scan, sm, svf)?,
+            Stmt::Pad { pre, reg, length, data } => {
+                let length = size(self.eval(length)?.int()?)?;
+                // Pad with ones by default, which selects BYPASS on the IR of other devices
+                let bits = match data {
+                    Some(data) => self.eval(data)?.bits(length),
+                    None => vec![*reg == Register::Instruction; length],
+                };
+                let padding = if *reg == Register::Instruction { &mut self.ir } else { &mut self.dr };
+                if *pre {
+                    padding.pre = bits;
+                } else {
+                    padding.post = bits;
+                }
+            }
+            Stmt::Stop(Register::Instruction, state) => self.irstop = *state,
+            Stmt::Stop(Register::Data, state) => self.drstop = *state,
+            Stmt::State(state) => sm.change_mode(*state),
+            Stmt::Wait { state, cycles, usec, end } => {
+                let cycles = cycles.as_ref().map(|c| self.eval(c)).transpose()?.map(|v| v.int()).transpose()?;
+                let cycles = cycles.map(size).transpose()?;
+                let usec = usec.as_ref().map(|u| self.eval(u)).transpose()?.map(|v| v.int()).transpose()?;
+                let usec = usec.map(size).transpose()?;
+                let start = std::time::Instant::now();
+                clock(sm, *state, cycles.unwrap_or(0) as u64, svf.chunk_size as u64 * 8);
+                if let Some(usec) = usec {
+                    sm.cable.0.flush();
+                    let min = std::time::Duration::from_micros(usec as u64);
+                    if let Some(remaining) = min.checked_sub(start.elapsed()) {
+                        std::thread::sleep(remaining);
+                    }
+                }
+                sm.change_mode(*end);
+            }
+            Stmt::Frequency(hz) => {
+                let hz = self.eval(hz)?.int()?;
+                svf.set_frequency(sm, hz as f64);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Run `procedure` until its ENDPROC, or until EXIT
+    fn call(&mut self, procedure: &str, sm: &mut JtagSM<AdapterBox>, svf: &mut Svf) -> Result<Flow, String> {
+        let mut pc = *self.program.procedures.get(procedure).ok_or(format!("no procedure {}", procedure))?;
+        let depth = self.fors.len();
+        let program = self.program;
+        loop {
+            let Some((line, stmt)) = program.stmts.get(pc) else {
+                return Err(format!("{} has no ENDPROC", procedure));
+            };
+            match self.exec(pc, stmt, sm, svf).map_err(|e| format!("line {}: {}", line, e))? {
+                Flow::Next => pc += 1,
+                Flow::Jump(to) => pc = to,
+                Flow::Return => {
+                    self.fors.truncate(depth);
+                    return Ok(Flow::Return);
+                }
+                Flow::Exit(code) => return Ok(Flow::Exit(code)),
+            }
+        }
+    }
+
+    /// Run the statements outside any procedure, which declare the program's data
+    fn globals(&mut self, sm: &mut JtagSM<AdapterBox>, svf: &mut Svf) -> Result<(), String> {
+        let program = self.program;
+        let mut in_procedure = false;
+        for (i, (line, stmt)) in program.stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Procedure(_) => in_procedure = true,
+                Stmt::EndProc => in_procedure = false,
+                Stmt::Declare { .. } if !in_procedure => {
+                    self.exec(i, stmt, sm, svf).map_err(|e| format!("line {}: {}", line, e))?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `action` and return its exit code
+    pub fn run(&mut self, action: &str, sm: &mut JtagSM<AdapterBox>, svf: &mut Svf) -> Result<i64, String> {
+        let (_, procedures) = self.program.actions.iter()
+            .find(|(name, _)| name == action)
+            .ok_or(format!("no action {}", action))?;
+        self.globals(sm, svf)?;
+        for (procedure, optional) in procedures {
+            if *optional {
+                continue;
+            }
+            if let Flow::Exit(code) = self.call(procedure, sm, svf)? {
+                return Ok(code);
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cable::sim::Sim;
+    use crate::cable::ShiftCable;
+
+    /// The device nearest TDO first, so its bits come out first and its data is shifted in first
+    const CHAIN: &str = "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n\n\
+                         [[device]]\nirlen = 6\nidcode = 0x0362d093\ninstructions = { idcode = 0x09 }\n";
+
+    /// Run `action` of `text` on the simulated chain, returning its exit code and the variables
+    /// it left behind
+    fn run(text: &str, action: &str) -> Result<(i64, HashMap<String, Var>), String> {
+        let program = parse(text)?;
+        let mut jtag = JtagSM::new(AdapterBox(Box::new(ShiftCable(Sim::parse(CHAIN).unwrap()))));
+        let mut interpreter = Interpreter::new(&program);
+        let code = interpreter.run(action, &mut jtag, &mut Svf::new())?;
+        Ok((code, interpreter.vars))
+    }
+
+    fn word(var: &Var) -> u32 {
+        match var {
+            Var::Bools(bits) => bits.iter().rev().fold(0, |word, bit| word << 1 | *bit as u32),
+            var => panic!("{:?} isn't a BOOLEAN array", var),
+        }
+    }
+
+    #[test]
+    fn array_literals_hold_element_0_in_their_rightmost_digit() {
+        let (_, toks) = &lex("LET A = #0011 & $A5;").unwrap()[0];
+        assert_eq!(toks[3], Tok::Bits(vec![true, true, false, false]));
+        assert_eq!(toks[5], Tok::Bits(vec![true, false, true, false, false, true, false, true]));
+        assert!(lex("LET A = #0012;").unwrap_err().contains("binary literal"));
+        assert!(lex("LET A = 1").unwrap_err().contains("';'"));
+    }
+
+    #[test]
+    fn statements_start_on_their_own_lines() {
+        let lines: Vec<usize> = lex("' comment\nACTION A = P;\nPROCEDURE P;\n  LET X =\n    1;\nENDPROC;\n")
+            .unwrap().into_iter().map(|(line, _)| line).collect();
+        assert_eq!(lines, [2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn actions_call_procedures_that_scan_through_padding() {
+        let text = "ACTION READ = MAIN, EXTRA OPTIONAL;\n\
+                    BOOLEAN IR[4];\nBOOLEAN ID_A[32];\nBOOLEAN ID_B[32];\n\
+                    BOOLEAN IR_OK;\nBOOLEAN IR_BAD = 1;\n\
+                    PROCEDURE MAIN;\n  CALL READ_A;\n  CALL READ_B;\nENDPROC;\n\
+                    PROCEDURE READ_A;\n  POSTIR 6;\n  POSTDR 1;\n\
+                    IRSCAN 4, #1110, CAPTURE IR, COMPARE #0001, #1111, IR_OK;\n\
+                    IRSCAN 4, #1110, COMPARE #0010, #1111, IR_BAD;\n\
+                    DRSCAN 32, $00000000, CAPTURE ID_A;\n  POSTIR 0;\n  POSTDR 0;\nENDPROC;\n\
+                    PROCEDURE READ_B;\n  PREIR 4;\n  PREDR 1;\n  IRSCAN 6, $09;\n\
+                    DRSCAN 32, $00000000, CAPTURE ID_B;\nENDPROC;\n\
+                    PROCEDURE EXTRA;\n  EXIT 3;\nENDPROC;\n";
+        let (code, vars) = run(text, "READ").unwrap();
+        assert_eq!(code, 0);
+        assert_eq!(word(&vars["IR"]), 0b0001);
+        assert!(matches!(vars["IR_OK"], Var::Bool(true)));
+        assert!(matches!(vars["IR_BAD"], Var::Bool(false)));
+        assert_eq!(word(&vars["ID_A"]), 0x12345679);
+        assert_eq!(word(&vars["ID_B"]), 0x0362d093);
+        assert!(run(text, "WRITE").unwrap_err().contains("no action WRITE"));
+    }
+
+    #[test]
+    fn loops_waits_slices_and_exit() {
+        let text = "ACTION RUN = MAIN;\nINTEGER SUM = 0;\nINTEGER I;\nBOOLEAN BITS[8] = $c5;\nBOOLEAN LOW[4];\n\
+                    PROCEDURE MAIN;\n  FOR I = 1 TO 5;\n    LET SUM = SUM + I;\n  NEXT I;\n\
+                    FOR I = 10 TO 0 STEP -5;\n    LET SUM = SUM * 2;\n  NEXT I;\n\
+                    WAIT IDLE, 10 CYCLES, 5 USEC, IDLE;\n  LET LOW[3..0] = BITS[3..0];\n\
+                    IF SUM == 120 THEN EXIT 7;\n  EXIT 1;\nENDPROC;\n";
+        let (code, vars) = run(text, "RUN").unwrap();
+        assert_eq!(code, 7);
+        assert!(matches!(vars["I"], Var::Int(-5)));
+        assert_eq!(word(&vars["LOW"]), 0x5);
+    }
+
+    fn eval(expr: &str) -> Result<i64, String> {
+        let text = format!("ACTION RUN = MAIN;\nINTEGER X;\nPROCEDURE MAIN;\n  LET X = {};\nENDPROC;\n", expr);
+        let (_, vars) = run(&text, "RUN")?;
+        match vars["X"] {
+            Var::Int(x) => Ok(x),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn arithmetic_errors_instead_of_panicking() {
+        assert_eq!(eval("1 << 62"), Ok(1 << 62));
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+        for expr in ["1 << 64", "1 >> -1", "9223372036854775807 + 1", "-9223372036854775807 - 2",
+                     "4611686018427387904 * 2", "-(-9223372036854775807 - 1)", "(-9223372036854775807 - 1) / -1",
+                     "ABS(-9223372036854775807 - 1)", "1 / 0"] {
+            assert!(eval(expr).is_err(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn negative_sizes_and_indices_are_errors() {
+        let program = |body: &str| format!("ACTION RUN = MAIN;\nBOOLEAN B[8];\nINTEGER V[4];\n\
+                                            PROCEDURE MAIN;\n  {}\nENDPROC;\n", body);
+        for body in ["BOOLEAN N[-1];", "LET B[-1] = 1;", "LET V[0 - 1] = 1;", "LET B[0] = B[-1];",
+                     "LET B[3..0] = B[-1..0];", "DRSCAN -1, $0;", "PREDR -2;", "WAIT IDLE, -1 CYCLES;",
+                     "WAIT IDLE, -1 USEC;"] {
+            let e = run(&program(body), "RUN").unwrap_err();
+            assert!(e.contains("is negative"), "{}: {}", body, e);
+            assert!(e.starts_with("line 5:"), "{}: {}", body, e);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Dry pass over an SVF file that estimates how long playback will take.  State transitions
+//! are costed by running a real `JtagSM` against a cable that only counts TMS clocks, so the
+//! numbers match what the player would actually drive.
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::rc::Rc;
+
+use jtag_taps::cable::Cable;
+use jtag_taps::statemachine::{JtagSM, JtagState, Register};
+use svf::{Command, ParseError, RunTestForm};
+
+use crate::bits::fit;
+use crate::lattice::LoopFilter;
+use crate::Svf;
+
+struct TckCounter {
+    clocks: Rc<Cell<u64>>,
+}
+
+impl Cable for TckCounter {
+    fn change_mode(&mut self, tms: &[usize], _tdo: bool) {
+        self.clocks.set(self.clocks.get() + tms.len() as u64);
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        vec![0; bits.div_ceil(8)]
+    }
+
+    fn write_data(&mut self, _data: &[u8], _bits: u8, pause_after: bool) {
+        // The final data bit moves to Exit1, then one more clock reaches Pause
+        if pause_after {
+            self.clocks.set(self.clocks.get() + 1);
+        }
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        self.write_data(data, bits, pause_after);
+        vec![0; data.len()]
+    }
+}
+
+#[derive(Default)]
+pub struct Estimate {
+    pub commands: u64,
+    pub sir_count: u64,
+    pub sir_bits: u64,
+    pub sdr_count: u64,
+    pub sdr_bits: u64,
+    pub runtest_count: u64,
+    pub runtest_clocks: u64,
+    pub state_clocks: u64,
+    /// Wall-clock time RUNTEST commands spend waiting beyond their clock count
+    pub wait_seconds: f64,
+    /// Time spent clocking TCK at the FREQUENCY in effect for each command
+    pub clock_seconds: f64,
+    /// Scans of each length
+    pub sir_lengths: BTreeMap<u32, u64>,
+    pub sdr_lengths: BTreeMap<u32, u64>,
+    /// SIRs of each length and TDI, for how often each instruction is loaded
+    pub opcodes: BTreeMap<(u32, Vec<u8>), u64>,
+    /// What each command asks of the cable, for predicting playback on other cables
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Step {
+    /// The FREQUENCY in effect, if the file declared one
+    pub hz: Option<f64>,
+    pub scan_bits: u64,
+    pub run_clocks: u64,
+    pub state_clocks: u64,
+    /// Least time a RUNTEST has to take
+    pub min_time: f64,
+    /// Whether the player waits for the cable before going on, to check TDO or time a RUNTEST
+    pub round_trip: bool,
+}
+
+/// Width of the longest bar in a histogram
+const BAR: u64 = 40;
+
+fn histogram(name: &str, lengths: &BTreeMap<u32, u64>) {
+    let Some(most) = lengths.values().max() else {
+        return;
+    };
+    println!("{} lengths:", name);
+    for (length, count) in lengths {
+        let bar = "#".repeat((count * BAR).div_ceil(*most) as usize);
+        println!("  {:>8} bits {:>8}  {}", length, count, bar);
+    }
+}
+
+impl Estimate {
+    pub fn total_clocks(&self) -> u64 {
+        self.sir_bits + self.sdr_bits + self.runtest_clocks + self.state_clocks
+    }
+
+    pub fn total_seconds(&self) -> f64 {
+        self.clock_seconds + self.wait_seconds
+    }
+
+    pub fn report(&self) {
+        println!("commands:          {}", self.commands);
+        println!("SIR:               {} scans, {} bits", self.sir_count, self.sir_bits);
+        println!("SDR:               {} scans, {} bits", self.sdr_count, self.sdr_bits);
+        println!("RUNTEST:           {} commands, {} clocks", self.runtest_count, self.runtest_clocks);
+        println!("state transitions: {} clocks", self.state_clocks);
+        println!("total TCK:         {}", self.total_clocks());
+        println!("expected duration: {:.3} s ({:.3} s clocking, {:.3} s waiting)",
+                 self.total_seconds(), self.clock_seconds, self.wait_seconds);
+        histogram("SIR", &self.sir_lengths);
+        histogram("SDR", &self.sdr_lengths);
+        if !self.opcodes.is_empty() {
+            println!("IR opcodes:");
+        }
+        for ((length, tdi), count) in &self.opcodes {
+            let hex: String = tdi.iter().rev().map(|b| format!("{:02X}", b)).collect();
+            println!("  {:>8} bits {:>8}  {}", length, count, hex);
+        }
+    }
+}
+
+/// Estimate playback of `contents`.  `default_hz` is used until the file declares a FREQUENCY.
+pub fn estimate(input: &mut impl BufRead, default_hz: f64) -> Result<Estimate, ParseError> {
+    let clocks = Rc::new(Cell::new(0));
+    let cable: Box<dyn Cable> = Box::new(TckCounter { clocks: clocks.clone() });
+    let mut sm = JtagSM::new(cable);
+
+    let mut est = Estimate::default();
+    let mut hz = default_hz;
+    let mut declared = None;
+    let mut endir = JtagState::Idle;
+    let mut enddr = JtagState::Idle;
+    let mut run_state = JtagState::Idle;
+    let mut end_state = JtagState::Idle;
+    let mut ir = vec![];
+
+    // Each LOOP body is costed once, as if it passed on the first iteration
+    let mut input = LoopFilter::new(input, Default::default());
+    for cmd in svf::parse_iter_bufread(&mut input) {
+        let cmd = cmd?;
+        est.commands += 1;
+        let before = clocks.get();
+        let mut scan_bits = 0;
+        let mut run_clocks = 0;
+        let mut min_time = 0.0;
+        let mut round_trip = false;
+
+        match cmd {
+            Command::Frequency(freq) => {
+                hz = freq.unwrap_or(default_hz);
+                declared = freq;
+            }
+            Command::EndIR(state) => endir = Svf::to_jtag_state(state),
+            Command::EndDR(state) => enddr = Svf::to_jtag_state(state),
+            Command::State { end, .. } => sm.change_mode(Svf::to_jtag_state(end)),
+            Command::SIR(pattern) => {
+                est.sir_count += 1;
+                est.sir_bits += pattern.length as u64;
+                scan_bits = pattern.length as u64;
+                round_trip = pattern.tdo.is_some();
+                *est.sir_lengths.entry(pattern.length).or_default() += 1;
+                if let Some(tdi) = pattern.tdi {
+                    ir = fit(tdi, pattern.length);
+                }
+                *est.opcodes.entry((pattern.length, ir.clone())).or_default() += 1;
+                sm.write_reg(Register::Instruction, &[0], 8, true);
+                sm.change_mode(endir);
+            }
+            Command::SDR(pattern) => {
+                est.sdr_count += 1;
+                est.sdr_bits += pattern.length as u64;
+                scan_bits = pattern.length as u64;
+                round_trip = pattern.tdo.is_some();
+                *est.sdr_lengths.entry(pattern.length).or_default() += 1;
+                sm.write_reg(Register::Data, &[0], 8, true);
+                sm.change_mode(enddr);
+            }
+            Command::RunTest { run_state: run, form, end_state: end } => {
+                est.runtest_count += 1;
+                if let Some(end) = end {
+                    end_state = Svf::to_jtag_state(end);
+                }
+                if let Some(run) = run {
+                    run_state = Svf::to_jtag_state(run);
+                }
+                sm.change_mode(run_state);
+                let time = match form {
+                    RunTestForm::Clocked { run_count, time, .. } => {
+                        run_clocks = run_count as u64;
+                        time
+                    }
+                    RunTestForm::Timed(time) => Some(time),
+                };
+                min_time = time.as_ref().map_or(0.0, |time| time.min);
+                round_trip = time.is_some();
+                est.runtest_clocks += run_clocks;
+                let clock_time = run_clocks as f64 / hz;
+                if min_time > clock_time {
+                    est.wait_seconds += min_time - clock_time;
+                }
+                sm.change_mode(end_state);
+            }
+            _ => (),
+        }
+
+        let state_clocks = clocks.get() - before;
+        est.state_clocks += state_clocks;
+        est.clock_seconds += (scan_bits + run_clocks + state_clocks) as f64 / hz;
+        est.steps.push(Step { hz: declared, scan_bits, run_clocks, state_clocks, min_time, round_trip });
+    }
+    Ok(est)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_are_counted_by_length_and_opcode() {
+        let text = "SIR 4 TDI (e);\nSDR 32 TDI (0);\nSIR 4 TDI (1);\nSDR 6 TDI (0);\nSIR 4;\nSDR 6;\n";
+        let est = estimate(&mut text.as_bytes(), 1e6).unwrap();
+        assert_eq!(est.sir_lengths, BTreeMap::from([(4, 3)]));
+        assert_eq!(est.sdr_lengths, BTreeMap::from([(6, 2), (32, 1)]));
+        assert_eq!(est.opcodes, BTreeMap::from([((4, vec![0x1]), 2), ((4, vec![0xe]), 1)]));
+    }
+}
@@ -0,0 +1,94 @@
+//! The status line drawn on a terminal in place of echoing every command: what is playing, how
+//! far through the file, the scan rate and the time taken and left.
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant};
+
+use crate::{compiled, lattice};
+
+/// Shortest time between redraws
+const REDRAW: Duration = Duration::from_millis(100);
+
+pub struct StatusLine {
+    /// Commands in the file playing, when known up front
+    total: Option<usize>,
+    /// When the file started, and the bits scanned since
+    start: Instant,
+    bits: u64,
+    drawn: Option<Instant>,
+    /// Phase playing, shown ahead of the command
+    phase: Option<String>,
+}
+
+/// Number of commands in `input`, or None if it doesn't parse
+pub fn count(input: &mut impl BufRead) -> Option<usize> {
+    if compiled::is_compiled(input) {
+        return compiled::Reader::new(input, lattice::Markers::default()).ok().map(Iterator::count);
+    }
+    let mut input = lattice::LoopFilter::new(input, lattice::Markers::default());
+    let mut commands = 0;
+    for cmd in svf::parse_iter_bufread(&mut input) {
+        cmd.ok()?;
+        commands += 1;
+    }
+    Some(commands)
+}
+
+fn minutes(time: Duration) -> String {
+    format!("{}:{:02}", time.as_secs() / 60, time.as_secs() % 60)
+}
+
+impl StatusLine {
+    pub fn new() -> StatusLine {
+        StatusLine { total: None, start: Instant::now(), bits: 0, drawn: None, phase: None }
+    }
+
+    /// Start over for a file of `total` commands
+    pub fn start(&mut self, total: Option<usize>) {
+        self.finish();
+        *self = StatusLine { total, ..StatusLine::new() };
+    }
+
+    pub fn phase(&mut self, name: &str) {
+        self.phase = Some(name.to_string());
+    }
+
+    /// Command `index`, of `kind`, has played, scanning `bits`
+    pub fn update(&mut self, kind: &str, index: usize, bits: u64) {
+        self.bits += bits;
+        let now = Instant::now();
+        if self.drawn.is_some_and(|drawn| now - drawn < REDRAW) && Some(index) != self.total {
+            return;
+        }
+        self.drawn = Some(now);
+        let elapsed = now - self.start;
+        let rate = self.bits as f64 / elapsed.as_secs_f64().max(1e-3) / 1e3;
+        let line = match self.total {
+            Some(total) => {
+                let left = elapsed.mul_f64(total.saturating_sub(index) as f64 / index.max(1) as f64);
+                format!("{:<8} {}/{}  {:.1} kbit/s  {} / ETA {}", kind, index, total, rate, minutes(elapsed),
+                        minutes(left))
+            }
+            None => format!("{:<8} {}  {:.1} kbit/s  {}", kind, index, rate, minutes(elapsed)),
+        };
+        let line = match &self.phase {
+            Some(phase) => format!("[{}] {}", phase, line),
+            None => line,
+        };
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+
+    /// End the line, leaving the last status on screen
+    pub fn finish(&mut self) {
+        if self.drawn.take().is_some() {
+            eprintln!();
+        }
+    }
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,247 @@
+//! Writes SVF from `svf::Command`s, the model the player runs.  Vectors come out as exactly
+//! `length.div_ceil(4)` hex digits, wrapped so no line gets near the 256 characters SVF allows,
+//! and parameters that the previous commands already made sticky are left out.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use svf::{Command, Pattern, State};
+
+use crate::bits::fit;
+use crate::lattice::{LoopFilter, Marker, Markers};
+use crate::optimize::Optimizer;
+
+/// Hex digits per line of a wrapped vector
+const DIGITS_PER_LINE: usize = 64;
+
+/// What a scan command inherits when the next one leaves a parameter out
+struct Sticky {
+    length: u32,
+    tdi: Option<Vec<u8>>,
+    mask: Vec<u8>,
+    smask: Vec<u8>,
+}
+
+pub struct Writer<W> {
+    out: W,
+    patterns: HashMap<&'static str, Sticky>,
+    endir: Option<State>,
+    enddr: Option<State>,
+    run_state: Option<State>,
+    end_state: Option<State>,
+    frequency: Option<Option<f64>>,
+}
+
+/// `data` as `length` bits of hex, most significant digit first
+pub fn hex(data: &[u8], length: u32) -> String {
+    let digits = (length.div_ceil(4) as usize).max(1);
+    let hex: String = fit(data.to_vec(), length).iter().rev().map(|b| format!("{:02X}", b)).collect();
+    let hex = format!("{:0>width$}", hex, width = digits);
+    let hex = &hex[hex.len() - digits..];
+    if hex.len() <= DIGITS_PER_LINE {
+        return hex.to_string();
+    }
+    // Keep the first line short so the later ones line up on whole bytes from the right
+    let first = hex.len() % DIGITS_PER_LINE;
+    let mut lines = vec![&hex[..first]];
+    lines.extend(hex.as_bytes()[first..].chunks(DIGITS_PER_LINE).map(|l| std::str::from_utf8(l).unwrap()));
+    lines.retain(|l| !l.is_empty());
+    lines.join("\n    ")
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Self {
+        Writer {
+            out,
+            patterns: HashMap::new(),
+            endir: None,
+            enddr: None,
+            run_state: None,
+            end_state: None,
+            frequency: None,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn pattern(&mut self, name: &'static str, pattern: &Pattern) -> io::Result<()> {
+        let length = pattern.length;
+        let ones = fit(vec![0xff; length.div_ceil(8) as usize], length);
+        let fitted = |v: &Option<Vec<u8>>| v.as_ref().map(|v| fit(v.clone(), length));
+        let (tdi, tdo, mask, smask) = (fitted(&pattern.tdi), fitted(&pattern.tdo), fitted(&pattern.mask),
+                                       fitted(&pattern.smask));
+        let previous = self.patterns.get(name).filter(|p| p.length == length);
+        let same = |given: &Option<Vec<u8>>, sticky: Option<&Vec<u8>>| given.is_some() && given.as_ref() == sticky;
+        let write_tdi = tdi.is_some() && !same(&tdi, previous.and_then(|p| p.tdi.as_ref()));
+        // A MASK is kept after a length change even when it's all ones, so TDO checks stay explicit
+        let write_mask = mask.is_some() && !same(&mask, previous.map(|p| &p.mask));
+        let write_smask = smask.is_some() && !same(&smask, Some(previous.map_or(&ones, |p| &p.smask)));
+
+        write!(self.out, "{} {}", name, length)?;
+        for (field, data, write) in [("TDI", &tdi, write_tdi), ("TDO", &tdo, true), ("MASK", &mask, write_mask),
+                                     ("SMASK", &smask, write_smask)] {
+            if let (Some(data), true) = (data, write) {
+                write!(self.out, " {} ({})", field, hex(data, length))?;
+            }
+        }
+        writeln!(self.out, ";")?;
+
+        let sticky = match self.patterns.remove(name) {
+            Some(p) if p.length == length => Sticky {
+                length,
+                tdi: tdi.or(p.tdi),
+                mask: mask.unwrap_or(p.mask),
+                smask: smask.unwrap_or(p.smask),
+            },
+            _ => Sticky { length, tdi, mask: mask.unwrap_or(ones.clone()), smask: smask.unwrap_or(ones) },
+        };
+        self.patterns.insert(name, sticky);
+        Ok(())
+    }
+
+    /// Write `cmd`, leaving out whatever it only repeats
+    pub fn command(&mut self, cmd: &Command) -> io::Result<()> {
+        match cmd {
+            Command::EndIR(state) => {
+                if self.endir.replace(*state) != Some(*state) {
+                    writeln!(self.out, "ENDIR {};", state)?;
+                }
+            }
+            Command::EndDR(state) => {
+                if self.enddr.replace(*state) != Some(*state) {
+                    writeln!(self.out, "ENDDR {};", state)?;
+                }
+            }
+            Command::Frequency(hz) => {
+                if self.frequency.replace(*hz) != Some(*hz) {
+                    writeln!(self.out, "{}", cmd)?;
+                }
+            }
+            Command::SIR(pattern) => self.pattern("SIR", pattern)?,
+            Command::SDR(pattern) => self.pattern("SDR", pattern)?,
+            Command::HIR(pattern) => self.pattern("HIR", pattern)?,
+            Command::HDR(pattern) => self.pattern("HDR", pattern)?,
+            Command::TIR(pattern) => self.pattern("TIR", pattern)?,
+            Command::TDR(pattern) => self.pattern("TDR", pattern)?,
+            Command::RunTest { run_state, form, end_state } => {
+                write!(self.out, "RUNTEST")?;
+                if let Some(state) = run_state {
+                    if self.run_state.replace(*state) != Some(*state) {
+                        write!(self.out, " {}", state)?;
+                    }
+                }
+                write!(self.out, " {}", form)?;
+                if let Some(state) = end_state {
+                    if self.end_state.replace(*state) != Some(*state) {
+                        write!(self.out, " ENDSTATE {}", state)?;
+                    }
+                }
+                writeln!(self.out, ";")?;
+            }
+            _ => writeln!(self.out, "{}", cmd)?,
+        }
+        Ok(())
+    }
+
+    /// Write `text` as it is, for statements outside the command model such as `LOOP 3;`.
+    /// Sticky parameters are forgotten, since the commands after it may not always follow the
+    /// ones before.
+    pub fn statement(&mut self, text: &str) -> io::Result<()> {
+        self.patterns.clear();
+        self.endir = None;
+        self.enddr = None;
+        self.run_state = None;
+        self.end_state = None;
+        self.frequency = None;
+        writeln!(self.out, "{}", text)
+    }
+
+    /// Write `text` as an SVF comment
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.out, "! {}", text)
+    }
+}
+
+fn finish<W: Write>(writer: &mut Writer<W>, optimizer: &mut Option<Optimizer>) -> io::Result<()> {
+    for cmd in optimizer.as_mut().map(Optimizer::finish).unwrap_or_default() {
+        writer.command(&cmd)?;
+    }
+    Ok(())
+}
+
+/// Copy `input` to `out` as SVF this module writes, keeping LOOP blocks, and if `optimize` is
+/// set without the commands an `Optimizer` drops
+pub fn rewrite(input: &mut impl BufRead, out: impl Write, optimize: bool) -> io::Result<()> {
+    let markers = Markers::default();
+    let mut input = LoopFilter::new(input, markers.clone());
+    let mut writer = Writer::new(out);
+    let mut optimizer = optimize.then(Optimizer::default);
+    let mut commands = svf::parse_iter_bufread(&mut input).enumerate();
+    loop {
+        let next = commands.next();
+        let i = next.as_ref().map_or(usize::MAX, |(i, _)| *i);
+        while markers.borrow().front().is_some_and(|m| m.before() <= i) {
+            finish(&mut writer, &mut optimizer)?;
+            match markers.borrow_mut().pop_front().unwrap() {
+                Marker::Loop { count, .. } => writer.statement(&format!("LOOP {};", count))?,
+                Marker::EndLoop { .. } => writer.statement("ENDLOOP;")?,
+                Marker::Phase { name, .. } => writer.comment(&format!("phase {}", name))?,
+            }
+        }
+        let Some((_, cmd)) = next else {
+            break;
+        };
+        let cmd = cmd.map_err(|e| io::Error::other(e.to_string()))?;
+        match &mut optimizer {
+            Some(optimizer) => {
+                for cmd in optimizer.push(cmd) {
+                    writer.command(&cmd)?;
+                }
+            }
+            None => writer.command(&cmd)?,
+        }
+    }
+    finish(&mut writer, &mut optimizer)?;
+    writer.into_inner().flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewritten(svf: &str) -> String {
+        let mut out = vec![];
+        rewrite(&mut svf.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn hex_has_a_digit_per_four_bits() {
+        assert_eq!(hex(&[0x01], 1), "1");
+        assert_eq!(hex(&[0x01], 9), "001");
+        assert_eq!(hex(&[0xbc, 0x0a], 12), "ABC");
+        assert_eq!(hex(&[], 0), "0");
+        let long = hex(&[0xff; 40], 320);
+        assert!(long.lines().all(|l| l.trim().len() <= DIGITS_PER_LINE));
+        assert_eq!(long.split_whitespace().collect::<String>(), "F".repeat(80));
+    }
+
+    #[test]
+    fn sticky_parameters_are_left_out() {
+        let svf = "ENDDR IDLE;\nENDDR IDLE;\nSDR 8 TDI (5A) MASK (0F) SMASK (FF);\nSDR 8 TDI (5A) TDO (0A) MASK (0F);\n\
+                   SDR 4 TDI (5) SMASK (F);\nRUNTEST IDLE 10 TCK;\nRUNTEST IDLE 10 TCK;\n";
+        assert_eq!(rewritten(svf), "ENDDR IDLE;\nSDR 8 TDI (5A) MASK (0F);\nSDR 8 TDO (0A);\nSDR 4 TDI (5);\n\
+                                    RUNTEST IDLE 10 TCK;\nRUNTEST 10 TCK;\n");
+    }
+
+    #[test]
+    fn output_parses_back_to_the_same_commands() {
+        let svf = "SIR 6 TDI (09);\nSDR 300 TDI (123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789AB);\n\
+                   LOOP 2;\nSDR 300 TDO (0);\nENDLOOP;\nSTATE RESET IDLE;\n";
+        let again = rewritten(svf);
+        assert_eq!(rewritten(&again), again);
+        let sdr = svf::parse_complete(&again.replace("LOOP 2;", "").replace("ENDLOOP;", "")).unwrap();
+        assert_eq!(sdr, svf::parse_complete(&svf.replace("LOOP 2;", "").replace("ENDLOOP;", "")).unwrap());
+    }
+}
@@ -0,0 +1,166 @@
+//! `--target`: play a file written for one device against that device in a longer chain, by
+//! padding every scan with BYPASS for the others, the way HIR, TIR, HDR and TDR would.  The
+//! devices nearer TDO are the header, shifted first; the ones nearer TDI are the trailer.
+use svf::Pattern;
+
+use crate::bits::{byte_len, fit};
+use crate::cable::{bit, pack};
+use crate::devices::Database;
+
+/// Bits added to one register's scans, and what the unpadded scans last asked to compare
+#[derive(Clone, Debug, Default)]
+struct Side {
+    header: u32,
+    trailer: u32,
+    /// TDI for the padding: all ones loads BYPASS, and BYPASS itself doesn't care
+    fill: bool,
+    length: Option<u32>,
+    mask: Vec<u8>,
+}
+
+impl Side {
+    fn wrap(&self, data: &[u8], length: u32, fill: bool) -> Vec<u8> {
+        let bits: Vec<bool> = std::iter::repeat_n(fill, self.header as usize)
+            .chain((0..length as usize).map(|i| bit(data, i)))
+            .chain(std::iter::repeat_n(fill, self.trailer as usize))
+            .collect();
+        pack(&bits)
+    }
+
+    fn pad(&mut self, mut pattern: Pattern) -> Pattern {
+        let length = pattern.length;
+        if length == 0 || self.header + self.trailer == 0 {
+            return pattern;
+        }
+        // Track MASK as the player would, so a TDO check always carries one that ignores the
+        // padding rather than the all ones default
+        if self.length != Some(length) {
+            self.length = Some(length);
+            self.mask = fit(vec![0xff; byte_len(length)], length);
+        }
+        if let Some(mask) = &pattern.mask {
+            self.mask = fit(mask.clone(), length);
+        }
+        pattern.tdi = pattern.tdi.map(|tdi| self.wrap(&tdi, length, self.fill));
+        if pattern.tdo.is_some() {
+            pattern.mask = Some(self.wrap(&self.mask, length, false));
+        }
+        pattern.tdo = pattern.tdo.map(|tdo| self.wrap(&tdo, length, false));
+        pattern.smask = pattern.smask.map(|smask| self.wrap(&smask, length, true));
+        pattern.length = self.header + length + self.trailer;
+        pattern
+    }
+
+    fn unpad(&self, read: &[u8]) -> Vec<u8> {
+        match self.length {
+            Some(length) if read.len() == byte_len(self.header + length + self.trailer) => {
+                pack(&(0..length).map(|i| bit(read, (self.header + i) as usize)).collect::<Vec<_>>())
+            }
+            _ => read.to_vec(),
+        }
+    }
+}
+
+/// Padding for the scans of one device in a chain
+#[derive(Clone, Debug)]
+pub struct Padding {
+    ir: Side,
+    dr: Side,
+}
+
+impl Padding {
+    /// For the device at `position` (counting from 0, nearest TDO first) of a chain with `irlens`
+    pub fn new(irlens: &[usize], position: usize) -> Result<Padding, String> {
+        if position >= irlens.len() {
+            return Err(format!("there's no device {} in a chain of {}", position, irlens.len()));
+        }
+        let bits = |lens: &[usize]| lens.iter().sum::<usize>() as u32;
+        Ok(Padding {
+            ir: Side { header: bits(&irlens[..position]), trailer: bits(&irlens[position + 1..]), fill: true,
+                       ..Side::default() },
+            dr: Side { header: position as u32, trailer: (irlens.len() - position - 1) as u32, ..Side::default() },
+        })
+    }
+
+    /// The same padding with the remembered masks forgotten
+    pub fn restart(&self) -> Padding {
+        let side = |side: &Side| Side { length: None, mask: vec![], ..side.clone() };
+        Padding { ir: side(&self.ir), dr: side(&self.dr) }
+    }
+
+    pub fn sir(&mut self, pattern: Pattern) -> Pattern {
+        self.ir.pad(pattern)
+    }
+
+    pub fn sdr(&mut self, pattern: Pattern) -> Pattern {
+        self.dr.pad(pattern)
+    }
+
+    /// Bits of the whole chain's IR scan before and after the target's own
+    pub fn ir_bits(&self) -> (u32, u32) {
+        (self.ir.header, self.ir.trailer)
+    }
+
+    /// Bits of the whole chain's DR scan before and after the target's own
+    pub fn dr_bits(&self) -> (u32, u32) {
+        (self.dr.header, self.dr.trailer)
+    }
+
+    /// The target's own bits of what the last scan of `kind` read from the whole chain
+    pub fn unpad(&self, kind: &str, read: &[u8]) -> Vec<u8> {
+        match kind {
+            "SIR" => self.ir.unpad(read),
+            _ => self.dr.unpad(read),
+        }
+    }
+}
+
+/// Position of `target` in a chain with `idcodes`: the position itself, or a part name to look
+/// up in `database`, which has to match exactly one device
+pub fn find(target: &str, idcodes: &[Option<u32>], database: &Database) -> Result<usize, String> {
+    if let Ok(position) = target.parse() {
+        return Ok(position);
+    }
+    let part = database.named(target).ok_or_else(|| format!("{} isn't a known part", target))?;
+    let matches: Vec<usize> = idcodes.iter().enumerate()
+        .filter(|(_, idcode)| idcode.is_some_and(|idcode| idcode & part.mask == part.idcode & part.mask))
+        .map(|(position, _)| position)
+        .collect();
+    match matches[..] {
+        [position] => Ok(position),
+        [] => Err(format!("there's no {} on the chain", part.name)),
+        _ => Err(format!("there's more than one {} on the chain, at {:?}; give its position", part.name, matches)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sir(text: &str) -> Pattern {
+        match svf::parse_complete(text).unwrap().remove(0) {
+            svf::Command::SIR(pattern) => pattern,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn scans_are_padded_with_bypass() {
+        let mut padding = Padding::new(&[4, 6, 5], 1).unwrap();
+        let padded = padding.sir(sir("SIR 6 TDI (09) TDO (01);"));
+        assert_eq!(padded.length, 15);
+        // Four ones for device 0, 001001, five ones for device 2
+        assert_eq!(padded.tdi.unwrap(), [0x9f, 0x7c]);
+        assert_eq!(padded.tdo.unwrap(), [0x10, 0x00]);
+        assert_eq!(padded.mask.unwrap(), [0xf0, 0x03]);
+        assert_eq!(padding.unpad("SIR", &[0x9f, 0x7c]), [0x09]);
+        assert_eq!(padding.dr_bits(), (1, 1));
+
+        let database = Database::builtin();
+        let idcodes = [Some(0x4ba0_0477), Some(0x1362_d093)];
+        assert_eq!(find("xc7a35t", &idcodes, &database).unwrap(), 1);
+        assert_eq!(find("0", &idcodes, &database).unwrap(), 0);
+        assert!(find("xc7a100t", &idcodes, &database).is_err());
+        assert!(Padding::new(&[4], 1).is_err());
+    }
+}
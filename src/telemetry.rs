@@ -0,0 +1,64 @@
+//! `--summary`: a JSON file written at the end of a run with what was shifted, how fast, and
+//! what went wrong, for programming-station dashboards to collect.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::phases::Phases;
+use crate::profile::CableStats;
+
+pub struct Telemetry {
+    start: Instant,
+    /// Filled in by a `ProfilingCable` around the adapter
+    pub cable: Rc<RefCell<CableStats>>,
+    pub commands: u64,
+    pub sir_bits: u64,
+    pub sdr_bits: u64,
+    pub retries: u64,
+    pub tdo_mismatches: u64,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Telemetry {
+            start: Instant::now(),
+            cable: Rc::default(),
+            commands: 0,
+            sir_bits: 0,
+            sdr_bits: 0,
+            retries: 0,
+            tdo_mismatches: 0,
+        }
+    }
+
+    /// Write the summary to `path`, with `error` being why the run failed, if it did, and the
+    /// totals of each phase
+    pub fn write(&self, path: &str, error: Option<&str>, phases: &Phases) -> std::io::Result<()> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let bits = self.sir_bits + self.sdr_bits;
+        let cable = self.cable.borrow();
+        let summary = serde_json::json!({
+            "result": if error.is_some() { "failed" } else { "passed" },
+            "error": error,
+            "errors": error.is_some() as u32,
+            "elapsed_seconds": elapsed,
+            "commands": self.commands,
+            "bits_shifted": bits,
+            "sir_bits": self.sir_bits,
+            "sdr_bits": self.sdr_bits,
+            "throughput_bits_per_second": bits as f64 / elapsed,
+            "state_transitions": cable.tms_clocks,
+            "round_trips": cable.round_trips,
+            "retries": self.retries,
+            "tdo_mismatches": self.tdo_mismatches,
+            "phases": phases.json(),
+        });
+        std::fs::write(path, serde_json::to_string_pretty(&summary)? + "\n")
+    }
+}
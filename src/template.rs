@@ -0,0 +1,92 @@
+//! `${NAME}` substitution in SVF input, enabled by giving `--define NAME=VALUE`, so one file can
+//! carry a serial number or MAC address that differs per board.  A name that isn't defined is
+//! an error rather than being left in for the parser to trip over.  Comments are left alone.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+
+/// Parse `NAME=VALUE`
+pub fn parse_define(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s.split_once('=').ok_or_else(|| format!("{} should look like NAME=VALUE", s))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("{}: names are letters, digits and _", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// `line` with every `${NAME}` before any comment replaced by its value
+pub fn substitute(line: &str, defines: &HashMap<String, String>) -> Result<String, String> {
+    let comment = [line.find('!'), line.find("//")].into_iter().flatten().min().unwrap_or(line.len());
+    let mut out = String::new();
+    let mut rest = &line[..comment];
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| format!("unterminated ${{ in {}", line.trim()))?;
+        let name = &rest[start + 2..start + end];
+        out.push_str(defines.get(name).ok_or_else(|| format!("{} isn't defined", name))?);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out.push_str(&line[comment..]);
+    Ok(out)
+}
+
+/// An input with its `${NAME}`s substituted, a line at a time
+pub struct Template<R> {
+    inner: R,
+    defines: HashMap<String, String>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> Template<R> {
+    pub fn new(inner: R, defines: HashMap<String, String>) -> Self {
+        Template { inner, defines, line: vec![], pos: 0 }
+    }
+}
+
+impl<R: BufRead> Read for Template<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Template<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.line.len() {
+            let mut line = vec![];
+            self.inner.read_until(b'\n', &mut line)?;
+            // The parser only passes on the kind of an I/O error, so say what it was here
+            let line = substitute(&String::from_utf8_lossy(&line), &self.defines).map_err(|e| {
+                eprintln!("error: {}", e);
+                io::Error::other(e)
+            })?;
+            self.line = line.into_bytes();
+            self.pos = 0;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_replaced_and_unknown_ones_rejected() {
+        let defines = HashMap::from([parse_define("SERIAL=0000BEEF").unwrap(), parse_define("N=32").unwrap()]);
+        let mut text = String::new();
+        Template::new("SDR ${N} TDI (${SERIAL}); ! ${OTHER}\n".as_bytes(), defines.clone())
+            .read_to_string(&mut text).unwrap();
+        assert_eq!(text, "SDR 32 TDI (0000BEEF); ! ${OTHER}\n");
+        assert!(substitute("SDR ${M} TDI (0);", &defines).is_err());
+        assert!(parse_define("A B=1").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+//! Picks how much data goes to the cable per call.  Small transfers pay the adapter's round trip
+//! latency over and over, while ones beyond its buffer gain nothing, so `--chunk-size auto`
+//! times a few sizes and keeps the smallest that runs close to the best throughput.
+use std::time::Instant;
+
+use jtag_taps::statemachine::{JtagSM, JtagState};
+
+use crate::cable::AdapterBox;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 8192;
+
+/// Candidate transfer sizes in bytes
+const SIZES: &[usize] = &[64, 256, 1024, 4096, 16384, 65536];
+
+#[derive(Clone, Copy, Debug)]
+pub enum ChunkSize {
+    Auto,
+    Bytes(usize),
+}
+
+pub fn parse_chunk_size(s: &str) -> Result<ChunkSize, String> {
+    if s == "auto" {
+        return Ok(ChunkSize::Auto);
+    }
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("expected a positive number of bytes or auto, not {}", s)),
+        Ok(bytes) => Ok(ChunkSize::Bytes(bytes)),
+    }
+}
+
+/// Time TMS-low clocks in Run-Test/Idle, which leaves every register on the chain alone, at each
+/// candidate size
+pub fn measure(sm: &mut JtagSM<AdapterBox>) -> usize {
+    sm.change_mode(JtagState::Idle);
+    sm.cable.0.flush();
+    let max = sm.cable.0.capabilities().max_transfer.unwrap_or(usize::MAX);
+    let mut rates = vec![];
+    for &size in SIZES.iter().filter(|size| **size <= max) {
+        let tms = vec![0; size * 8];
+        let start = Instant::now();
+        sm.cable.change_mode(&tms, true);
+        sm.cable.0.flush();
+        rates.push((size, size as f64 / start.elapsed().as_secs_f64()));
+    }
+    let best = rates.iter().map(|(_, rate)| *rate).fold(0.0, f64::max);
+    rates.iter()
+        .find(|(_, rate)| *rate >= best * 0.9)
+        .map(|(size, _)| *size)
+        .unwrap_or(DEFAULT_CHUNK_SIZE)
+}
@@ -0,0 +1,42 @@
+//! `--watch` support: block until one of the input files has been rewritten.
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+/// How long the files must stay untouched before a change counts as finished, so a generator
+/// writing in several steps triggers a single replay
+const QUIET: Duration = Duration::from_millis(300);
+
+pub fn wait_for_change(inputs: &[String]) {
+    let paths: Vec<PathBuf> = inputs.iter()
+        .map(|input| std::fs::canonicalize(input).unwrap_or_else(|_| PathBuf::from(input)))
+        .collect();
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx).expect("watch");
+    // Watch the directories rather than the files, since generators often write a new file and
+    // rename it over the old one
+    for path in &paths {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        watcher.watch(dir, RecursiveMode::NonRecursive).expect("watch");
+    }
+
+    let relevant = |event: &notify::Event| {
+        matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event.paths.iter().any(|p| paths.contains(p))
+    };
+    loop {
+        match rx.recv() {
+            Ok(Ok(event)) if relevant(&event) => break,
+            Ok(_) => (),
+            Err(_) => return,
+        }
+    }
+    loop {
+        match rx.recv_timeout(QUIET) {
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => return,
+            Ok(_) => (),
+        }
+    }
+}
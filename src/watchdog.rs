@@ -0,0 +1,190 @@
+//! `--cable-timeout` and `--reconnects`.  The cable runs on a worker thread and every transaction
+//! has to finish within the timeout.  A cable that doesn't, or that fails (a USB adapter dropping
+//! off the bus and re-enumerating, say), is abandoned along with its thread and opened again, up
+//! to a fixed number of reconnects per run.  The transaction is then unwound with `Reconnected`
+//! so that `Svf` can set the TAP up again and replay the command it was in.
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use jtag_taps::cable::Cable;
+
+use crate::cable::{Adapter, Capabilities};
+use crate::color;
+use crate::panic_message;
+
+/// Panic payload of a transaction that was abandoned because the cable was reopened
+pub struct Reconnected;
+
+pub type Open = Arc<dyn Fn() -> Result<Box<dyn Adapter>, String> + Send + Sync>;
+
+type Job = Box<dyn FnOnce(&mut dyn Adapter) + Send>;
+
+struct Worker {
+    jobs: Sender<Job>,
+    done: Receiver<()>,
+}
+
+fn recv<T>(rx: &Receiver<T>, timeout: Option<Duration>) -> Result<T, RecvTimeoutError> {
+    match timeout {
+        Some(timeout) => rx.recv_timeout(timeout),
+        None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+impl Worker {
+    /// Open the cable on a new thread, setting it to `hz` if a frequency has been chosen
+    fn start(open: &Open, hz: Option<f64>, timeout: Option<Duration>) -> Result<Self, String> {
+        let (jobs, rx) = channel::<Job>();
+        let (ready_tx, ready) = channel();
+        let (done_tx, done) = channel();
+        let open = open.clone();
+        std::thread::spawn(move || {
+            let mut cable = match open() {
+                Ok(cable) => cable,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            if let Some(hz) = hz {
+                cable.set_frequency(hz);
+            }
+            let _ = ready_tx.send(Ok(()));
+            for job in rx {
+                job(&mut *cable);
+            }
+            drop(cable);
+            let _ = done_tx.send(());
+        });
+        match recv(&ready, timeout) {
+            Ok(Ok(())) => Ok(Worker { jobs, done }),
+            Ok(Err(e)) => Err(e),
+            Err(RecvTimeoutError::Timeout) => Err(format!("opening the cable took longer than {:?}", timeout.unwrap())),
+            Err(RecvTimeoutError::Disconnected) => Err("opening the cable panicked".into()),
+        }
+    }
+}
+
+pub struct WatchdogCable {
+    open: Open,
+    worker: Option<Worker>,
+    timeout: Option<Duration>,
+    reconnects: u32,
+    frequency: Option<f64>,
+}
+
+impl WatchdogCable {
+    /// Open the cable with `open`, allowing each transaction `timeout` (or as long as it takes)
+    /// and reopening the cable at most `reconnects` times
+    pub fn spawn(open: Open, timeout: Option<Duration>, reconnects: u32) -> Result<Self, String> {
+        let worker = Worker::start(&open, None, timeout)?;
+        Ok(WatchdogCable {
+            open,
+            worker: Some(worker),
+            timeout,
+            reconnects,
+            frequency: None,
+        })
+    }
+
+    /// Replace the cable, which stopped working because of `why`, and abandon the transaction
+    fn reconnect(&mut self, why: String) -> ! {
+        // The old thread may never come back, so it is left to finish on its own
+        self.worker = None;
+        loop {
+            if self.reconnects == 0 {
+                panic!("{} and no reconnects are left", why);
+            }
+            self.reconnects -= 1;
+            eprintln!("{} {}, reopening it", color::warning(), why);
+            match Worker::start(&self.open, self.frequency, self.timeout) {
+                Ok(worker) => {
+                    self.worker = Some(worker);
+                    std::panic::resume_unwind(Box::new(Reconnected));
+                }
+                Err(e) => eprintln!("{} unable to reopen cable: {}", color::warning(), e),
+            }
+        }
+    }
+
+    fn call<R, F>(&mut self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn Adapter) -> R + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let Some(worker) = &self.worker else {
+            panic!("the cable was lost and couldn't be reopened");
+        };
+        let sent = worker.jobs.send(Box::new(move |cable: &mut dyn Adapter| {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(cable)));
+            let _ = tx.send(result.map_err(|e| panic_message(&*e)));
+        }));
+        if sent.is_err() {
+            panic!("cable worker exited");
+        }
+        match recv(&rx, self.timeout) {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => self.reconnect(format!("cable failed: {}", e)),
+            Err(RecvTimeoutError::Timeout) => {
+                self.reconnect(format!("cable didn't respond within {:?}", self.timeout.unwrap()))
+            }
+            Err(RecvTimeoutError::Disconnected) => panic!("cable worker exited"),
+        }
+    }
+}
+
+impl Cable for WatchdogCable {
+    fn change_mode(&mut self, tms: &[usize], tdo: bool) {
+        let tms = tms.to_vec();
+        self.call(move |cable| cable.change_mode(&tms, tdo))
+    }
+
+    fn read_data(&mut self, bits: usize) -> Vec<u8> {
+        self.call(move |cable| cable.read_data(bits))
+    }
+
+    fn write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) {
+        let data = data.to_vec();
+        self.call(move |cable| cable.write_data(&data, bits, pause_after))
+    }
+
+    fn read_write_data(&mut self, data: &[u8], bits: u8, pause_after: bool) -> Vec<u8> {
+        let data = data.to_vec();
+        self.call(move |cable| cable.read_write_data(&data, bits, pause_after))
+    }
+}
+
+impl Adapter for WatchdogCable {
+    fn capabilities(&mut self) -> Capabilities {
+        self.call(|cable| cable.capabilities())
+    }
+
+    fn set_frequency(&mut self, hz: f64) -> Option<f64> {
+        self.frequency = Some(hz);
+        self.call(move |cable| cable.set_frequency(hz))
+    }
+
+    fn set_trst(&mut self, asserted: bool) -> bool {
+        self.call(move |cable| cable.set_trst(asserted))
+    }
+
+    fn set_srst(&mut self, asserted: bool) -> bool {
+        self.call(move |cable| cable.set_srst(asserted))
+    }
+
+    fn flush(&mut self) {
+        self.call(|cable| cable.flush())
+    }
+}
+
+impl Drop for WatchdogCable {
+    fn drop(&mut self) {
+        // Give the cable the usual time to finish whatever it does on close, but no longer
+        if let Some(worker) = self.worker.take() {
+            drop(worker.jobs);
+            let _ = recv(&worker.done, self.timeout);
+        }
+    }
+}
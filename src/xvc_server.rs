@@ -0,0 +1,276 @@
+//! Xilinx Virtual Cable server exposing a local cable, as started by `svfplayer serve-xvc`.
+//!
+//! XVC clients send arbitrary TMS/TDI vectors, but the `Cable` trait only knows how to change
+//! state and how to shift through Shift-xR.  `Translator` follows the TAP state the client is
+//! driving and maps each run of bits onto the matching cable call.  Leaving Shift-xR always goes
+//! through Pause-xR, since that is the only exit the cable offers; the extra clocks are spent in
+//! states that don't change any register.
+//!
+//! XVC has no authentication of its own.  With a token, a client has to open with `auth:`, the
+//! token's length as 4 bytes little-endian and the token, answered with the single byte 1, before
+//! any other command.  Vivado and OpenOCD can't do that, so for them rely on `--allow`, or put
+//! the server behind a tunnel that checks the token.
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use jtag_taps::cable::Cable;
+
+use crate::access::Gate;
+use crate::color;
+use crate::engine::{next, TapState};
+
+/// Largest vector (TMS and TDI together) accepted in a single shift
+const MAX_VECTOR_BYTES: usize = 32768;
+
+fn is_shift(state: TapState) -> bool {
+    state == TapState::ShiftDR || state == TapState::ShiftIR
+}
+
+fn bit(data: &[u8], i: usize) -> bool {
+    data[i / 8] & (1 << (i % 8)) != 0
+}
+
+fn pack(bits: &[bool]) -> (Vec<u8>, u8) {
+    let mut out = vec![0; bits.len().div_ceil(8)];
+    for (i, b) in bits.iter().enumerate() {
+        if *b {
+            out[i / 8] |= 1 << (i % 8);
+        }
+    }
+    let last = match bits.len() % 8 {
+        0 => 8,
+        n => n as u8,
+    };
+    (out, last)
+}
+
+pub struct Translator<'a> {
+    cable: &'a mut dyn Cable,
+    /// The state the client believes the TAP is in
+    state: TapState,
+    /// The client left Shift-xR and is in Exit1, but the cable went on to Pause
+    parked: bool,
+}
+
+impl<'a> Translator<'a> {
+    pub fn new(cable: &'a mut dyn Cable) -> Self {
+        cable.change_mode(&[1, 1, 1, 1, 1], true);
+        Translator {
+            cable,
+            state: TapState::Reset,
+            parked: false,
+        }
+    }
+
+    /// Clock out one XVC vector and return the TDO bits
+    pub fn shift(&mut self, tms: &[bool], tdi: &[bool]) -> Vec<bool> {
+        let mut tdo = vec![false; tms.len()];
+        let mut i = 0;
+        while i < tms.len() {
+            if self.parked {
+                // Client is in Exit1, the cable is in Pause
+                self.parked = false;
+                if tms[i] {
+                    self.cable.change_mode(&[1, 1], true);
+                }
+                self.state = next(self.state, tms[i] as usize);
+                i += 1;
+            } else if is_shift(self.state) {
+                let start = i;
+                while i < tms.len() && !tms[i] {
+                    i += 1;
+                }
+                let exit = i < tms.len();
+                if exit {
+                    i += 1;
+                }
+                if i == start {
+                    continue;
+                }
+                let (data, bits) = pack(&tdi[start..i]);
+                let read = self.cable.read_write_data(&data, bits, exit);
+                for (j, out) in tdo[start..i].iter_mut().enumerate() {
+                    *out = bit(&read, j);
+                }
+                if exit {
+                    self.state = next(self.state, 1);
+                    self.parked = true;
+                }
+            } else {
+                let start = i;
+                let mut moves = vec![];
+                while i < tms.len() && !is_shift(self.state) {
+                    moves.push(tms[i] as usize);
+                    self.state = next(self.state, tms[i] as usize);
+                    i += 1;
+                }
+                self.cable.change_mode(&moves, tdi[start]);
+            }
+        }
+        tdo
+    }
+}
+
+fn read_u32(stream: &mut TcpStream) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a command name, up to and including its `:`, or None at the end of the stream
+fn read_command(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut cmd = vec![];
+    let mut byte = [0];
+    while byte[0] != b':' {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        cmd.push(byte[0]);
+        if cmd.len() > 16 {
+            return Err(std::io::Error::other("unknown XVC command"));
+        }
+    }
+    Ok(Some(cmd))
+}
+
+fn authenticate(stream: &mut TcpStream, gate: &Gate) -> std::io::Result<()> {
+    if read_command(stream)?.as_deref() != Some(b"auth:") {
+        return Err(std::io::Error::other("not authorized"));
+    }
+    let len = read_u32(stream)? as usize;
+    if len > 1024 {
+        return Err(std::io::Error::other("not authorized"));
+    }
+    let mut token = vec![0; len];
+    stream.read_exact(&mut token)?;
+    if !gate.accepts(&token) {
+        return Err(std::io::Error::other("not authorized"));
+    }
+    stream.write_all(&[1])
+}
+
+fn serve_client(stream: &mut TcpStream, cable: &mut dyn Cable, clock: u32, gate: &Gate) -> std::io::Result<()> {
+    if gate.needs_token() {
+        authenticate(stream, gate)?;
+    }
+    let mut tap = Translator::new(cable);
+    loop {
+        let Some(cmd) = read_command(stream)? else {
+            return Ok(());
+        };
+        match &cmd[..] {
+            b"getinfo:" => stream.write_all(format!("xvcServer_v1.0:{}\n", MAX_VECTOR_BYTES).as_bytes())?,
+            b"settck:" => {
+                // The cable's clock is fixed when it is opened, so report what it actually runs at
+                read_u32(stream)?;
+                let period = 1_000_000_000 / clock.max(1);
+                stream.write_all(&period.to_le_bytes())?;
+            }
+            b"shift:" => {
+                let bits = read_u32(stream)? as usize;
+                let bytes = bits.div_ceil(8);
+                if bytes * 2 > MAX_VECTOR_BYTES {
+                    return Err(std::io::Error::other("XVC vector too long"));
+                }
+                let mut tms = vec![0; bytes];
+                let mut tdi = vec![0; bytes];
+                stream.read_exact(&mut tms)?;
+                stream.read_exact(&mut tdi)?;
+                let tms: Vec<bool> = (0..bits).map(|i| bit(&tms, i)).collect();
+                let tdi: Vec<bool> = (0..bits).map(|i| bit(&tdi, i)).collect();
+                let (tdo, _) = pack(&tap.shift(&tms, &tdi));
+                stream.write_all(&tdo)?;
+            }
+            _ => return Err(std::io::Error::other("unknown XVC command")),
+        }
+    }
+}
+
+/// Accept XVC clients `gate` lets in one at a time, forever
+pub fn serve(listener: TcpListener, cable: &mut dyn Cable, clock: u32, gate: &Gate) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{} accept failed: {}", color::warning(), e);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        if !stream.peer_addr().is_ok_and(|addr| gate.allows(addr.ip())) {
+            eprintln!("{} refused XVC client {}, which --allow doesn't list", color::warning(), peer);
+            continue;
+        }
+        eprintln!("XVC client {} connected", peer);
+        match serve_client(&mut stream, cable, clock, gate) {
+            Ok(()) => eprintln!("XVC client {} disconnected", peer),
+            Err(e) => eprintln!("XVC client {}: {}", peer, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cable::sim::Sim;
+    use crate::cable::ShiftCable;
+
+    const CHAIN: &str = "[[device]]\nirlen = 4\nidcode = 0x12345679\ninstructions = { idcode = 0xe }\n";
+
+    /// `tms` written first bit first, with TDI low throughout
+    fn moves(tms: &str) -> (Vec<bool>, Vec<bool>) {
+        let tms: Vec<bool> = tms.chars().map(|c| c == '1').collect();
+        let tdi = vec![false; tms.len()];
+        (tms, tdi)
+    }
+
+    /// Shift the `length` bits of `value` from Shift-xR, leaving to Exit1 on the last, and return
+    /// what came out
+    fn scan(tap: &mut Translator, value: u64, length: usize) -> u64 {
+        let tms: Vec<bool> = (0..length).map(|i| i == length - 1).collect();
+        let tdi: Vec<bool> = (0..length).map(|i| value & 1 << i != 0).collect();
+        tap.shift(&tms, &tdi).iter().enumerate().fold(0, |out, (i, bit)| out | (*bit as u64) << i)
+    }
+
+    fn go(tap: &mut Translator, tms: &str) {
+        let (tms, tdi) = moves(tms);
+        tap.shift(&tms, &tdi);
+    }
+
+    #[test]
+    fn ir_scans_lead_into_dr_scans_and_into_more_ir_scans() {
+        let mut cable = ShiftCable(Sim::parse(CHAIN).unwrap());
+        let mut tap = Translator::new(&mut cable);
+        // Reset > Idle > Select-DR > Select-IR > Capture-IR > Shift-IR
+        go(&mut tap, "01100");
+        assert_eq!(scan(&mut tap, 0xe, 4), 0b0001);
+        // Exit1-IR > Update-IR > Select-DR > Capture-DR > Shift-DR
+        go(&mut tap, "1100");
+        assert_eq!(tap.state, TapState::ShiftDR);
+        assert_eq!(scan(&mut tap, 0, 32), 0x12345679);
+        // Exit1-DR > Update-DR > Select-DR > Select-IR > Capture-IR > Shift-IR, then Update-IR
+        // straight into another IR scan
+        go(&mut tap, "11100");
+        assert_eq!(scan(&mut tap, 0xf, 4), 0b0001);
+        go(&mut tap, "11100");
+        assert_eq!(tap.state, TapState::ShiftIR);
+        assert_eq!(scan(&mut tap, 0xe, 4), 0b0001);
+    }
+
+    #[test]
+    fn scans_carry_on_after_a_pause() {
+        let mut cable = ShiftCable(Sim::parse(CHAIN).unwrap());
+        let mut tap = Translator::new(&mut cable);
+        // IDCODE is selected from reset: Idle > Select-DR > Capture-DR > Shift-DR
+        go(&mut tap, "0100");
+        assert_eq!(scan(&mut tap, 0, 16), 0x5679);
+        // Exit1-DR > Pause-DR > Pause-DR > Exit2-DR > Shift-DR
+        go(&mut tap, "0010");
+        assert_eq!(tap.state, TapState::ShiftDR);
+        assert_eq!(scan(&mut tap, 0, 16), 0x1234);
+        // Exit1-DR > Update-DR > Idle
+        go(&mut tap, "10");
+        assert_eq!(tap.state, TapState::Idle);
+    }
+}